@@ -0,0 +1,88 @@
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use repro_threshold::attestation::{self, Attestation, Tree};
+use repro_threshold::inspect;
+use repro_threshold::signing;
+use std::hint::black_box;
+use tokio::runtime::Runtime;
+
+const DEB_PATH: &str = "test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.deb";
+const DEB_ATTESTATION: &[u8] =
+    include_bytes!("../test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.in-toto.link");
+const DEB_KEYRING: &[u8] = include_bytes!("../test_data/reproduce-debian-net-amd64.pub");
+const DEB_SHA256: &str = "32be954941cdb42bce44c19ece910af04ece3a1acc9b79fa3e8ff735ffb511cd";
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn hashing(c: &mut Criterion) {
+    let rt = runtime();
+    let data = vec![0x42u8; 16 * 1024 * 1024];
+
+    let mut group = c.benchmark_group("hashing");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("sha256_file/16MiB", |b| {
+        b.iter(|| rt.block_on(attestation::sha256_file(black_box(data.as_slice()))))
+    });
+
+    let path = std::env::temp_dir().join(format!("repro-threshold-bench-{}", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+    group.bench_function("sha256_path/16MiB", |b| {
+        b.iter(|| rt.block_on(attestation::sha256_path(black_box(&path))))
+    });
+    group.finish();
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn deb_inspection(c: &mut Criterion) {
+    let rt = runtime();
+    let deb = std::fs::read(DEB_PATH).unwrap();
+
+    c.bench_function("inspect_deb", |b| {
+        b.iter(|| rt.block_on(inspect::deb::inspect(black_box(deb.as_slice()))))
+    });
+}
+
+fn attestations(c: &mut Criterion) {
+    let key = signing::pem_to_pubkeys(DEB_KEYRING)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let sha256 = data_encoding::HEXLOWER
+        .decode(DEB_SHA256.as_bytes())
+        .unwrap();
+
+    c.bench_function("attestation_parse", |b| {
+        b.iter(|| Attestation::parse(black_box(DEB_ATTESTATION)))
+    });
+
+    let attestation = Attestation::parse(DEB_ATTESTATION).unwrap();
+    c.bench_function("attestation_verify_sha256", |b| {
+        b.iter(|| attestation.verify_sha256(black_box(&sha256), &key))
+    });
+
+    // A mismatching hash is the worst case, every attestation of the key is checked
+    let mismatch = vec![0u8; 32];
+    let mut group = c.benchmark_group("tree_verify");
+    for size in [10, 100, 1000, 5000] {
+        let mut tree = Tree::default();
+        for i in 0..size {
+            let attestation = Attestation::parse(DEB_ATTESTATION).unwrap();
+            tree.insert(format!("https://rebuilder{i}.example.com/"), attestation);
+        }
+        group.bench_with_input(BenchmarkId::new("match", size), &tree, |b, tree| {
+            b.iter(|| tree.verify(black_box(&sha256), [&key]))
+        });
+        group.bench_with_input(BenchmarkId::new("mismatch", size), &tree, |b, tree| {
+            b.iter(|| tree.verify(black_box(&mismatch), [&key]))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hashing, deb_inspection, attestations);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use repro_threshold::attestation::Attestation;
+use repro_threshold::signing;
+
+const KEYRING: &[u8] = include_bytes!("../../test_data/reproducible-archlinux.pub");
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(attestation) = Attestation::parse(data) else {
+        return;
+    };
+    let key = signing::pem_to_pubkeys(KEYRING)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    for sha256 in attestation.product_sha256s() {
+        let _ = attestation.verify_sha256(&sha256, &key);
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use repro_threshold::rebuilder;
+
+fuzz_target!(|text: &str| {
+    let _ = rebuilder::parse(text);
+});
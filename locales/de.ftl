@@ -0,0 +1,66 @@
+# German translations
+#
+# Placeholders like { $name } are replaced with values and must be kept as-is.
+
+# apt transport
+fail-download = Download fehlgeschlagen: { $err }
+fail-hash-mismatch = Prüfsummenfehler, der Mirror hat beschädigte Daten gesendet: { $err }
+fail-rebuilders-unreachable = Rebuilder nicht erreichbar: { $unreachable } vertrauenswürdige Rebuilder konnten nicht abgefragt werden, nur { $confirms }/{ $threshold } erforderliche Signaturen
+fail-deferred = Zurückgestellt: keiner der { $unreachable } vertrauenswürdigen Rebuilder war erreichbar, möglicherweise ist das Netzwerk offline oder hinter einem Captive Portal, `repro-threshold cron` prüft dieses Paket später erneut
+fail-conflicting-attestations = Widersprüchliche Attestierungen: { $rebuilders } haben eine andere Binärdatei reproduziert, nur { $confirms }/{ $threshold } erforderliche Signaturen
+fail-deadline-reached = Frist abgelaufen: { $pending } vertrauenswürdige Rebuilder haben nicht rechtzeitig geantwortet, nur { $confirms }/{ $threshold } erforderliche Signaturen
+fail-no-attestations = Keine Attestierungen gefunden: keiner der vertrauenswürdigen Rebuilder hat dieses Paket bisher reproduziert, { $threshold } erforderliche Signaturen
+fail-threshold-not-met = Schwellenwert nicht erreicht: nur { $confirms }/{ $threshold } erforderliche Signaturen
+fail-not-enough-verified = Zu wenige geprüfte Rebuilder: nur { $verified }/{ $required } erforderliche Signaturen von auf separatem Weg geprüften Rebuildern
+fail-timeout = Zeitüberschreitung bei der Überprüfung: die Rebuilder haben nicht innerhalb von { $secs } Sekunden geantwortet
+fail-layout = Überprüfung des in-toto-Layouts fehlgeschlagen: { $err }
+fail-disk-space = Nicht genügend Speicherplatz: { $needed } MiB benötigt, { $available } MiB verfügbar
+fail-grace-period-refused = Karenzzeit verweigert: { $problem }
+fail-key-changed = SIGNATURSCHLÜSSEL DER REBUILDER GEÄNDERT: { $rebuilders } liefern andere Schlüssel als beim ersten Kontakt, es wird nichts überprüft
+
+# TUI
+confirm-title = Sind Sie sicher?
+confirm-disable-enforcement = Durchsetzung deaktivieren und alle Pakete ohne Prüfung der Attestierungen zulassen? (y/n)
+home-threshold = Erforderlicher Reproduktions-Schwellenwert:{" "}
+home-threshold-disabled = deaktiviert
+home-menu-rebuilders = Vertrauenswürdige Rebuilder konfigurieren ({ $count } ausgewählt)
+home-menu-blindly = Pakete zur 'blindly-trust'-Liste hinzufügen/entfernen ({ $count } Einträge)
+home-menu-activity = Letzte Aktivität der Transporte ({ $count } Entscheidungen)
+home-menu-verify = Eine Paketdatei prüfen
+home-profile = Aktives Profil:{" "}
+home-profile-none-hint = keins, speichere eins mit `repro-threshold plumbing save-profile <name>`
+home-profile-none = keins
+home-menu-quit = Beenden
+blindly-hint = Mit `repro-threshold plumbing [add-blindly-trust|remove-blindly-trust] <package>` bearbeiten
+blindly-package = Immer blind vertrauen: { $package }
+blindly-source = Quellpaket immer blind vertrauen: { $package }
+banner-key-changed = SIGNATURSCHLÜSSEL GEÄNDERT: { $rebuilders } (mit `repro-threshold plumbing forget-key <url>` bestätigen, falls das erwartet ist)
+activity-empty = Bisher keine Aktivität der Transporte aufgezeichnet
+outcome-reproduced = reproduziert
+outcome-blindly-trusted = blind vertraut
+outcome-grace-period = Karenzzeit
+outcome-rejected = abgelehnt
+activity-details-title = Details der Entscheidung
+detail-time = Zeit: { $at }
+detail-outcome = Ergebnis:{" "}
+detail-package = Paket: { $package }
+detail-source = Quellpaket: { $source } { $version }
+detail-url = URL: { $url }
+detail-sha256 = SHA256: { $sha256 }
+detail-confirmed-by = Bestätigt von: { $rebuilders }
+detail-reason = Grund: { $reason }
+activity-close-hint = Enter oder Esc zum Schließen
+outcome-not-reproduced-yet = noch nicht reproduziert
+detail-signatures = Signaturen: { $confirms }/{ $threshold }
+detail-disagreed = Eine andere Datei reproduziert: { $rebuilders }
+detail-unreachable = Nicht erreichbar: { $rebuilders }
+detail-file = Datei: { $path }
+verify-checking = Prüfe bei vertrauenswürdigen Rebuildern...
+verify-result-hint = Strg-R zum erneuten Prüfen, Esc zum Zurückgehen
+verify-input-hint = Enter zum Prüfen, Esc zum Abbrechen
+verify-input-title = Pfad der Paketdatei
+rebuilders-verified = auf separatem Weg geprüft
+clipboard-copied = In die Zwischenablage kopiert: { $text }
+clipboard-sent-osc52 = An die Zwischenablage des Terminals gesendet: { $text }
+clipboard-failed = Kopieren in die Zwischenablage fehlgeschlagen: { $err }
+rebuilders-empty = Keine Rebuilder konfiguriert, ctrl-R lädt die Community-Liste, oder `repro-threshold plumbing add-rebuilder <url>` fügt einen hinzu
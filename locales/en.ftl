@@ -0,0 +1,66 @@
+# English messages, the fallback for untranslated ones
+#
+# Placeholders like { $name } are replaced with values.
+
+# apt transport
+fail-download = Download failed: { $err }
+fail-hash-mismatch = Hash mismatch, the mirror sent corrupted data: { $err }
+fail-rebuilders-unreachable = Rebuilders unreachable: { $unreachable } trusted rebuilders could not be queried, only { $confirms }/{ $threshold } required signatures
+fail-deferred = Deferred: none of the { $unreachable } trusted rebuilders could be reached, the network may be offline or behind a captive portal, `repro-threshold cron` checks this package again later
+fail-conflicting-attestations = Conflicting attestations: { $rebuilders } reproduced a different binary, only { $confirms }/{ $threshold } required signatures
+fail-deadline-reached = Deadline reached: { $pending } trusted rebuilders did not answer in time, only { $confirms }/{ $threshold } required signatures
+fail-no-attestations = No attestations found: none of the trusted rebuilders reproduced this package yet, { $threshold } required signatures
+fail-threshold-not-met = Threshold not met: only { $confirms }/{ $threshold } required signatures
+fail-not-enough-verified = Not enough verified rebuilders: only { $verified }/{ $required } required signatures from rebuilders verified out-of-band
+fail-timeout = Verification timed out: rebuilders did not respond within { $secs } seconds
+fail-layout = Layout verification failed: { $err }
+fail-disk-space = Not enough disk space: { $needed } MiB needed, { $available } MiB available
+fail-grace-period-refused = Refusing grace period: { $problem }
+fail-key-changed = REBUILDER SIGNING KEY CHANGED: { $rebuilders } serve a different keyring than the one trusted on first use, refusing to verify anything
+
+# TUI
+confirm-title = Are you sure?
+confirm-disable-enforcement = Disable enforcement and admit all packages without checking attestations? (y/n)
+home-threshold = Required reproduction threshold:{" "}
+home-threshold-disabled = disabled
+home-menu-rebuilders = Configure trusted rebuilders ({ $count } selected)
+home-menu-blindly = Add/remove packages from 'blindly-trust' set ({ $count } entries)
+home-menu-activity = Recent transport activity ({ $count } decisions)
+home-menu-verify = Verify a package file
+home-profile = Active profile:{" "}
+home-profile-none-hint = none, save one with `repro-threshold plumbing save-profile <name>`
+home-profile-none = none
+home-menu-quit = Quit
+blindly-hint = Use `repro-threshold plumbing [add-blindly-trust|remove-blindly-trust] <package>` to update
+blindly-package = Always blindly trust: { $package }
+blindly-source = Always blindly trust source: { $package }
+banner-key-changed = SIGNING KEY CHANGED: { $rebuilders } (run `repro-threshold plumbing forget-key <url>` if this is expected)
+activity-empty = No transport activity recorded yet
+outcome-reproduced = reproduced
+outcome-blindly-trusted = blindly trusted
+outcome-grace-period = grace period
+outcome-rejected = rejected
+activity-details-title = Decision details
+detail-time = Time: { $at }
+detail-outcome = Outcome:{" "}
+detail-package = Package: { $package }
+detail-source = Source: { $source } { $version }
+detail-url = URL: { $url }
+detail-sha256 = SHA256: { $sha256 }
+detail-confirmed-by = Confirmed by: { $rebuilders }
+detail-reason = Reason: { $reason }
+activity-close-hint = Press Enter or Esc to close
+outcome-not-reproduced-yet = not reproduced yet
+detail-signatures = Signatures: { $confirms }/{ $threshold }
+detail-disagreed = Reproduced a different file: { $rebuilders }
+detail-unreachable = Not reachable: { $rebuilders }
+detail-file = File: { $path }
+verify-checking = Checking with trusted rebuilders...
+verify-result-hint = Press Ctrl-R to check again, Esc to go back
+verify-input-hint = Press Enter to verify, Esc to cancel
+verify-input-title = Path of the package file
+rebuilders-verified = verified out-of-band
+clipboard-copied = Copied to clipboard: { $text }
+clipboard-sent-osc52 = Sent to the clipboard of the terminal: { $text }
+clipboard-failed = Failed to copy to clipboard: { $err }
+rebuilders-empty = No rebuilders configured, press ctrl-R to load community set, or run `repro-threshold plumbing add-rebuilder <url>` to add one
@@ -0,0 +1,440 @@
+//! A verification API for Kubernetes admission webhooks
+//!
+//! Workloads list the packages in their images in an annotation, the webhook denies them unless
+//! every package reached the threshold. Workloads without the annotation, or with an empty list,
+//! are denied too. The API is only served over HTTPS and requires a bearer token, so it can be
+//! exposed to the cluster.
+use crate::attestation;
+use crate::config::Config;
+use crate::errors::*;
+use crate::http;
+use crate::inspect::deb::Deb;
+use crate::policy;
+use crate::serve::{self, Head};
+use futures::future;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::io::{AsyncBufRead, AsyncReadExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{self, ServerConfig};
+
+/// The annotation listing the packages of a workload, as json array of `Query`
+pub const ANNOTATION: &str = "repro-threshold/packages";
+/// AdmissionReview objects of large workloads can get big, but not this big
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// A package to verify, the sha256 is of the .deb file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Query {
+    pub name: String,
+    pub version: String,
+    pub architecture: String,
+    pub sha256: String,
+    /// The source package, defaults to the binary package name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl Query {
+    fn deb(&self) -> Deb {
+        Deb {
+            name: self.name.clone(),
+            version: self.version.clone(),
+            architecture: self.architecture.clone(),
+            source: self.source.clone().unwrap_or_else(|| self.name.clone()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Verdict {
+    pub allowed: bool,
+    pub confirms: usize,
+    pub threshold: usize,
+    pub message: String,
+    /// Machine-readable reasons of the policy engine
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<policy::Reason>,
+}
+
+pub struct Verifier {
+    config: Config,
+    http: http::Client,
+    /// Stop waiting for rebuilders after this long, webhooks time out after 10s by default
+    ///
+    /// All packages of a review are verified concurrently and share this deadline.
+    timeout: Duration,
+}
+
+impl Verifier {
+    pub fn new(config: Config, http: http::Client, timeout: Duration) -> Self {
+        Verifier {
+            config,
+            http,
+            timeout,
+        }
+    }
+
+    pub async fn verify(&self, query: &Query, deadline: Instant) -> Result<Verdict> {
+        let sha256 = attestation::parse_sha256(&query.sha256)?;
+
+        let deb = query.deb();
+        let engine = policy::Engine::new(&self.config);
+        let threshold = engine.threshold(Some(&deb));
+        let label = format!("{} {} {}", deb.name, deb.version, deb.architecture);
+        if engine.blindly_trusts(&deb) {
+            return Ok(Verdict {
+                allowed: true,
+                confirms: 0,
+                threshold,
+                message: format!("{label} is blindly trusted"),
+                reasons: Vec::new(),
+            });
+        }
+
+        let rebuilders = self
+            .config
+            .trusted_rebuilders
+            .iter()
+            .map(|r| r.url.clone())
+            .collect::<Vec<_>>();
+        let remote = attestation::fetch_remote_until(
+            &self.http,
+            rebuilders,
+            deb.clone(),
+            Some(deadline),
+            |tree| engine.threshold_reached(Some(&deb), &sha256, tree),
+        )
+        .await;
+
+        let decision = engine.decide(Some(&deb), &sha256, &remote);
+        let confirms = decision.confirms();
+        let allowed = decision.verified();
+        let message = if allowed {
+            format!("{label} reproduced by {confirms}/{threshold} rebuilders")
+        } else {
+            format!("{label} only reproduced by {confirms}/{threshold} rebuilders")
+        };
+        Ok(Verdict {
+            allowed,
+            confirms,
+            threshold,
+            message,
+            reasons: decision.reasons,
+        })
+    }
+
+    /// Answer an `admission.k8s.io/v1` AdmissionReview
+    pub async fn review(&self, review: &serde_json::Value) -> Result<serde_json::Value> {
+        let request = review
+            .get("request")
+            .context("AdmissionReview has no request")?;
+        let uid = request
+            .get("uid")
+            .and_then(|uid| uid.as_str())
+            .context("AdmissionReview has no uid")?;
+
+        let mut response = json!({ "uid": uid, "allowed": true });
+        let denied = match packages(request.get("object"))? {
+            None => Some(format!(
+                "No {ANNOTATION} annotation, packages can't be verified"
+            )),
+            Some(queries) if queries.is_empty() => {
+                Some(format!("The {ANNOTATION} annotation lists no packages"))
+            }
+            Some(queries) => {
+                let deadline = Instant::now() + self.timeout;
+                let verdicts =
+                    future::try_join_all(queries.iter().map(|query| self.verify(query, deadline)))
+                        .await?;
+                debug!("Verdicts for admission {uid}: {verdicts:?}");
+                verdicts
+                    .into_iter()
+                    .find(|verdict| !verdict.allowed)
+                    .map(|verdict| verdict.message)
+            }
+        };
+        if let Some(message) = denied {
+            response["allowed"] = json!(false);
+            response["status"] = json!({ "code": 403, "message": message });
+        }
+
+        Ok(json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "response": response,
+        }))
+    }
+}
+
+/// Read the packages from the annotations of the object, or of its pod template
+fn packages(object: Option<&serde_json::Value>) -> Result<Option<Vec<Query>>> {
+    let Some(object) = object else {
+        return Ok(None);
+    };
+    let annotation = [&object["metadata"], &object["spec"]["template"]["metadata"]]
+        .into_iter()
+        .find_map(|metadata| metadata["annotations"][ANNOTATION].as_str());
+    let Some(annotation) = annotation else {
+        return Ok(None);
+    };
+    let queries = serde_json::from_str(annotation)
+        .with_context(|| format!("Failed to parse {ANNOTATION} annotation"))?;
+    Ok(Some(queries))
+}
+
+/// Compare hashes of the tokens, so the comparison doesn't leak how much of the token matched
+fn authorized(token: &str, header: Option<&str>) -> bool {
+    let Some(provided) = header.and_then(|header| header.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    Sha256::digest(token.as_bytes()) == Sha256::digest(provided.trim().as_bytes())
+}
+
+/// Read the body of the request, this is only done for authorized clients
+async fn read_body<R: AsyncBufRead + Unpin>(reader: &mut R, head: &Head) -> Result<Vec<u8>> {
+    let content_length = match head.header("content-length") {
+        Some(value) => value
+            .parse::<usize>()
+            .with_context(|| format!("Invalid content-length: {value:?}"))?,
+        None => 0,
+    };
+    if content_length > MAX_BODY_SIZE {
+        bail!("Request body too large: {content_length} bytes");
+    }
+    let mut body = vec![0; content_length];
+    time::timeout(serve::READ_TIMEOUT, reader.read_exact(&mut body))
+        .await
+        .context("Timed out reading request body")??;
+    Ok(body)
+}
+
+/// Returns the HTTP status and the json body
+async fn handle<R: AsyncBufRead + Unpin>(
+    verifier: &Verifier,
+    token: &str,
+    head: &Head,
+    reader: &mut R,
+) -> Result<(&'static str, Vec<u8>)> {
+    if !authorized(token, head.header("authorization")) {
+        return Ok(("401 Unauthorized", b"{}".to_vec()));
+    }
+    if head.method != "POST" {
+        return Ok(("405 Method Not Allowed", b"{}".to_vec()));
+    }
+    let body = read_body(reader, head).await?;
+
+    let result = match head.target.as_str() {
+        "/api/v1/verify" => match serde_json::from_slice::<Query>(&body) {
+            Ok(query) => verifier
+                .verify(&query, Instant::now() + verifier.timeout)
+                .await
+                .and_then(|verdict| Ok(serde_json::to_value(verdict)?)),
+            Err(err) => Err(Error::from(err).context("Failed to parse query")),
+        },
+        "/validate" => match serde_json::from_slice(&body) {
+            Ok(review) => verifier.review(&review).await,
+            Err(err) => Err(Error::from(err).context("Failed to parse AdmissionReview")),
+        },
+        _ => return Ok(("404 Not Found", b"{}".to_vec())),
+    };
+
+    match result {
+        Ok(value) => Ok(("200 OK", value.to_string().into_bytes())),
+        Err(err) => {
+            warn!("Failed to answer request: {err:#}");
+            let body = json!({ "error": format!("{err:#}") });
+            Ok(("400 Bad Request", body.to_string().into_bytes()))
+        }
+    }
+}
+
+async fn tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let buf = fs::read(cert)
+        .await
+        .with_context(|| format!("Failed to read certificate: {cert:?}"))?;
+    let certs = CertificateDer::pem_slice_iter(&buf)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse certificate: {cert:?}"))?;
+    if certs.is_empty() {
+        bail!("No certificate found in file: {cert:?}");
+    }
+
+    let buf = fs::read(key)
+        .await
+        .with_context(|| format!("Failed to read private key: {key:?}"))?;
+    let key = PrivateKeyDer::from_pem_slice(&buf)
+        .with_context(|| format!("Failed to parse private key: {key:?}"))?;
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Failed to setup TLS")?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub async fn run(
+    bind: SocketAddr,
+    token: String,
+    cert: &Path,
+    key: &Path,
+    verifier: Verifier,
+) -> Result<()> {
+    let acceptor = tls_acceptor(cert, key).await?;
+    let listener = TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("Failed to bind to address: {bind}"))?;
+    info!(
+        "Serving admission webhook on https://{}",
+        listener.local_addr()?
+    );
+
+    let verifier = Arc::new(verifier);
+    let token = Arc::new(token);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let verifier = Arc::clone(&verifier);
+        let token = Arc::clone(&token);
+        tokio::spawn(async move {
+            let result = async {
+                let stream = time::timeout(Duration::from_secs(10), acceptor.accept(stream))
+                    .await
+                    .context("TLS handshake timed out")??;
+                let (reader, writer) = tokio::io::split(stream);
+                let mut reader = BufReader::new(reader);
+                let Some(head) = serve::read_head(&mut reader).await? else {
+                    return serve::write_response(
+                        writer,
+                        "400 Bad Request",
+                        "application/json",
+                        b"{}",
+                    )
+                    .await;
+                };
+                info!("{addr} - {} {:?}", head.method, head.target);
+                let (status, body) = handle(&verifier, &token, &head, &mut reader).await?;
+                serve::write_response(writer, status, "application/json", &body).await
+            };
+            if let Err(err) = result.await {
+                warn!("Failed to serve request from {addr}: {err:#}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verifier(threshold: usize) -> Verifier {
+        let mut config = Config::default();
+        config.rules.required_threshold = threshold;
+        config.rules.blindly_trust.insert("base-files".to_string());
+        Verifier::new(config, http::client(), Duration::from_secs(1))
+    }
+
+    fn query(name: &str) -> serde_json::Value {
+        json!({
+            "name": name,
+            "version": "1.0-1",
+            "architecture": "amd64",
+            "sha256": "00".repeat(32),
+        })
+    }
+
+    fn admission_review(packages: Option<serde_json::Value>) -> serde_json::Value {
+        let mut annotations = json!({});
+        if let Some(packages) = packages {
+            annotations[ANNOTATION] = json!(packages.to_string());
+        }
+        json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {
+                "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+                "object": {
+                    "kind": "Deployment",
+                    "spec": { "template": { "metadata": { "annotations": annotations } } },
+                },
+            },
+        })
+    }
+
+    #[test]
+    fn test_authorized() {
+        assert!(authorized("hunter2", Some("Bearer hunter2")));
+        assert!(!authorized("hunter2", Some("Bearer hunter3")));
+        assert!(!authorized("hunter2", Some("hunter2")));
+        assert!(!authorized("hunter2", None));
+    }
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let raw = b"POST /validate HTTP/1.1\r\nAuthorization: Bearer hunter2\r\nContent-Length: 2\r\n\r\n{}";
+        let mut reader = &raw[..];
+        let head = serve::read_head(&mut reader).await.unwrap().unwrap();
+        assert_eq!(head.method, "POST");
+        assert_eq!(head.target, "/validate");
+        assert_eq!(head.header("authorization"), Some("Bearer hunter2"));
+        assert_eq!(read_body(&mut reader, &head).await.unwrap(), b"{}");
+    }
+
+    #[tokio::test]
+    async fn test_review() {
+        let verifier = verifier(1);
+
+        let review = admission_review(None);
+        let response = verifier.review(&review).await.unwrap();
+        assert_eq!(response["response"]["allowed"], false);
+        assert_eq!(
+            response["response"]["uid"],
+            "705ab4f5-6393-11e8-b7cc-42010a800002"
+        );
+
+        let review = admission_review(Some(json!([])));
+        let response = verifier.review(&review).await.unwrap();
+        assert_eq!(response["response"]["allowed"], false);
+
+        let review = admission_review(Some(json!([query("base-files")])));
+        let response = verifier.review(&review).await.unwrap();
+        assert_eq!(response["response"]["allowed"], true);
+
+        let review = admission_review(Some(json!([query("base-files"), query("curl")])));
+        let response = verifier.review(&review).await.unwrap();
+        assert_eq!(response["response"]["allowed"], false);
+        assert_eq!(response["response"]["status"]["code"], 403);
+        assert_eq!(
+            response["response"]["status"]["message"],
+            "curl 1.0-1 amd64 only reproduced by 0/1 rebuilders"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_unauthorized() {
+        let head = Head {
+            method: "POST".to_string(),
+            target: "/api/v1/verify".to_string(),
+            headers: vec![("Content-Length".to_string(), MAX_BODY_SIZE.to_string())],
+        };
+        // The body is never read, the client has to authenticate first
+        let (_tx, rx) = tokio::io::duplex(64);
+        let (status, _) = handle(&verifier(1), "hunter2", &head, &mut BufReader::new(rx))
+            .await
+            .unwrap();
+        assert_eq!(status, "401 Unauthorized");
+    }
+}
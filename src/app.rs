@@ -1,18 +1,49 @@
+use crate::attestation;
+use crate::audit;
+use crate::clipboard;
 use crate::config::Config;
+use crate::cron;
 use crate::errors::*;
-use crate::event::Event;
+use crate::event::{Bus, Event, Message};
 use crate::http;
+use crate::i18n::tr;
+use crate::inspect::deb::Deb;
+use crate::policy::{self, Decision};
 use crate::rebuilder::{self, Rebuilder, Selectable};
-use crossterm::event::EventStream;
+use crate::signing;
+use crate::state::{KeyChanges, Store};
+use crate::stats::Stats;
+use crate::tofu;
+use crate::ui::Theme;
+use chrono::Utc;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use ratatui::{DefaultTerminal, widgets::ListState};
 use std::iter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs;
 use tokio::task::JoinSet;
+use url::Url;
 
 #[derive(Debug)]
 pub enum View {
     Home,
-    Rebuilders { scroll: ListState },
-    BlindlyTrust { scroll: ListState },
+    Rebuilders {
+        scroll: ListState,
+    },
+    BlindlyTrust {
+        scroll: ListState,
+    },
+    /// Recent decisions of the transports, `details` shows the selected one in a popup
+    Activity {
+        scroll: ListState,
+        details: bool,
+    },
+    /// A single package file, `result` is `None` while it's being checked
+    Verify {
+        path: PathBuf,
+        result: Option<Result<Box<Verification>>>,
+    },
 }
 
 impl View {
@@ -31,194 +62,540 @@ impl View {
         scroll.select_first();
         View::BlindlyTrust { scroll }
     }
+
+    pub fn activity() -> Self {
+        let mut scroll = ListState::default();
+        scroll.select_first();
+        View::Activity {
+            scroll,
+            details: false,
+        }
+    }
+}
+
+/// An action that has to be confirmed with `y` before it's applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirm {
+    /// Lowering the threshold below 1 admits every package
+    DisableEnforcement,
 }
 
+/// How many decisions of the audit log are shown in the activity view
+const ACTIVITY_ENTRIES: usize = 500;
+
 #[derive(Debug)]
 pub struct App {
     pub view: Option<View>,
     // Keep this state even when switching views
     pub home_scroll: ListState,
-    pub confirm: bool,
+    pub confirm: Option<Confirm>,
     pub config: Config,
     pub rebuilders: Vec<Selectable<Rebuilder>>,
+    pub stats: Stats,
+    /// Trusted rebuilders that serve a different keyring than the one trusted on first use
+    pub key_changes: KeyChanges,
+    pub theme: Theme,
+    /// A reload is running in the background
+    pub reloading: bool,
+    /// The most recent decisions of the transports, newest first
+    pub activity: Vec<audit::Record>,
+    activity_modified: Option<SystemTime>,
+    /// The path typed into the prompt of the verification view
+    pub prompt: Option<String>,
+    /// Feedback for the last action, cleared on the next key press
+    pub status: Option<String>,
+}
+
+/// The result of a background reload of the rebuilder list
+pub struct Reloaded {
+    pub community: Vec<Rebuilder>,
+    pub keyrings: Vec<(Url, String)>,
+}
+
+impl Reloaded {
+    pub async fn fetch(http: &http::Client, custom: Vec<Rebuilder>) -> Result<Self> {
+        // Invalid entries are skipped, we can't render errors in the TUI
+        let community = rebuilder::fetch_rebuilderd_community(http)
+            .await?
+            .rebuilders;
+
+        let mut tasks = JoinSet::new();
+        for rebuilder in custom.iter().chain(&community) {
+            let http = http.clone();
+            let url = rebuilder.url.clone();
+            tasks.spawn(async move {
+                let keyring = http.fetch_signing_keyring(&url).await;
+                (url, keyring)
+            });
+        }
+
+        let mut keyrings = Vec::new();
+        while let Some((url, keyring)) = tasks.join_next().await.transpose()? {
+            match keyring {
+                Ok(keyring) => keyrings.push((url, keyring)),
+                Err(_err) => {
+                    // Can't render errors in TUI apps like this
+                    // warn!("Failed to fetch signing keyring for {}: {:#}", url, err);
+                }
+            }
+        }
+
+        Ok(Reloaded {
+            community,
+            keyrings,
+        })
+    }
+}
+
+/// The package file and rebuilder answers of a verification started from the TUI
+pub struct Lookup {
+    pub package: Deb,
+    pub sha256: Vec<u8>,
+    pub remote: attestation::Remote,
+}
+
+impl Lookup {
+    pub async fn fetch(http: &http::Client, rebuilders: Vec<Url>, path: &Path) -> Result<Self> {
+        let pkg = cron::Package::load(path, Duration::ZERO).await?;
+        let remote = attestation::fetch_remote(http, rebuilders, pkg.inspect.clone()).await;
+        Ok(Lookup {
+            package: pkg.inspect,
+            sha256: pkg.sha256,
+            remote,
+        })
+    }
+}
+
+/// The decision for a package file, the same the transports would make
+#[derive(Debug)]
+pub struct Verification {
+    pub package: Deb,
+    pub sha256: Vec<u8>,
+    pub decision: Decision,
+    /// Trusted rebuilders that couldn't be queried
+    pub unreachable: Vec<Url>,
+}
+
+impl Verification {
+    pub fn new(config: &Config, lookup: Lookup) -> Self {
+        let decision = policy::Engine::new(config).decide(
+            Some(&lookup.package),
+            &lookup.sha256,
+            &lookup.remote,
+        );
+        Verification {
+            package: lookup.package,
+            sha256: lookup.sha256,
+            decision,
+            unreachable: lookup.remote.unreachable,
+        }
+    }
 }
 
 impl App {
     pub fn new(config: Config) -> Self {
         let mut home_scroll = ListState::default();
         home_scroll.select_first();
+        let theme = Theme::new(&config.ui);
         let mut app = Self {
             view: Some(View::home()),
             home_scroll,
-            confirm: false,
+            confirm: None,
             config,
             rebuilders: vec![],
+            stats: Stats::default(),
+            key_changes: KeyChanges::default(),
+            theme,
+            reloading: false,
+            activity: vec![],
+            activity_modified: None,
+            prompt: None,
+            status: None,
         };
         app.rebuilders = app.config.resolve_rebuilder_view();
         app
     }
 
+    /// Start in the verification view of `path`, instead of the home screen
+    pub fn verify_file(mut self, path: PathBuf) -> Self {
+        self.view = Some(View::Verify { path, result: None });
+        self
+    }
+
     pub fn scroll(&mut self) -> &mut ListState {
         match &mut self.view {
             Some(View::Rebuilders { scroll }) => scroll,
             Some(View::BlindlyTrust { scroll }) => scroll,
+            Some(View::Activity { scroll, .. }) => scroll,
             _ => &mut self.home_scroll,
         }
     }
 
     pub async fn run(mut self, mut terminal: DefaultTerminal) -> Result<()> {
-        let mut events = EventStream::new();
+        let mut bus = Bus::new();
+        // Statistics are only informational, they may be unreadable for non-root users
+        self.stats = Stats::load().await.unwrap_or_default();
+        self.key_changes = KeyChanges::load().await.unwrap_or_default();
+        if let Some(View::Verify { path, result: None }) = &self.view {
+            self.verify(path.clone(), &bus)?;
+        }
 
         while self.view.is_some() {
             terminal.draw(|frame| {
                 frame.render_widget(&mut self, frame.area());
             })?;
 
-            match Event::read(&mut events).await {
-                #[allow(
-                    clippy::collapsible_match,
-                    reason = "https://github.com/rust-lang/rust-clippy/issues/17033"
-                )]
-                Some(Event::Yes) => {
-                    if self.confirm {
-                        // handle yes action
-                        self.confirm = false;
+            match bus.next().await {
+                Some(Message::Input(key)) => {
+                    self.status = None;
+                    if self.prompt.is_some() {
+                        self.handle_prompt(key, &bus)?;
+                    } else if let Some(event) = Event::from_key(key) {
+                        self.handle(event, &bus).await?;
                     }
                 }
-                Some(Event::No) => {
-                    /*
-                    if self.confirm {
-                        // handle no action
-                        self.confirm = false;
+                Some(Message::Tick) => {
+                    if let Some(View::Activity { .. }) = self.view {
+                        self.refresh_activity().await;
                     }
-                    */
-                    // TODO: dummy code, open the prompt
-                    self.confirm = true;
                 }
-                Some(Event::ScrollUp) => {
-                    self.scroll().select_previous();
+                Some(Message::Reloaded(reloaded)) => {
+                    self.reloading = false;
+                    self.apply_reload(reloaded?).await?;
                 }
-                Some(Event::ScrollDown) => {
-                    self.scroll().select_next();
-                }
-                Some(Event::ScrollFirst) => {
-                    self.scroll().select_first();
+                Some(Message::Verified(verified, lookup)) => {
+                    let verification =
+                        lookup.map(|lookup| Box::new(Verification::new(&self.config, *lookup)));
+                    // Ignore results of a file that's no longer shown
+                    if let Some(View::Verify { path, result }) = &mut self.view
+                        && *path == verified
+                    {
+                        *result = Some(verification);
+                    }
                 }
-                Some(Event::ScrollLast) => {
-                    self.scroll().select_last();
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Show the verification view of `path` and check it in the background
+    fn verify(&mut self, path: PathBuf, bus: &Bus) -> Result<()> {
+        let http = http::client_for_config(&self.config)?;
+        let rebuilders = self
+            .config
+            .trusted_rebuilders
+            .iter()
+            .map(|r| r.url.clone())
+            .collect::<Vec<_>>();
+        let tx = bus.sender();
+        self.view = Some(View::Verify {
+            path: path.clone(),
+            result: None,
+        });
+        tokio::spawn(async move {
+            let lookup = Lookup::fetch(&http, rebuilders, &path).await.map(Box::new);
+            let _ = tx.send(Message::Verified(path, lookup));
+        });
+        Ok(())
+    }
+
+    /// Switch to the next saved profile
+    async fn cycle_profile(&mut self, reverse: bool) -> Result<()> {
+        let Some(name) = self.config.next_profile(reverse).map(String::from) else {
+            return Ok(());
+        };
+        if let Err(err) = self.config.use_profile(&name).await {
+            self.status = Some(format!("{err:#}"));
+            return Ok(());
+        }
+        self.config.save().await?;
+        self.rebuilders = self.config.resolve_rebuilder_view();
+        Ok(())
+    }
+
+    /// The url of the selected rebuilder or decision
+    fn selected_url(&self) -> Option<String> {
+        match &self.view {
+            Some(View::Rebuilders { scroll }) => scroll
+                .selected()
+                .and_then(|idx| self.rebuilders.get(idx))
+                .map(|rebuilder| rebuilder.item.url.to_string()),
+            Some(View::Activity { scroll, .. }) => scroll
+                .selected()
+                .and_then(|idx| self.activity.get(idx))
+                .map(|record| record.url.clone()),
+            _ => None,
+        }
+    }
+
+    /// The fingerprints of the selected rebuilder, as published in DNS, or the sha256 of the selected decision
+    fn selected_fingerprint(&self) -> Option<String> {
+        match &self.view {
+            Some(View::Rebuilders { scroll }) => {
+                let rebuilder = scroll.selected().and_then(|idx| self.rebuilders.get(idx))?;
+                let fingerprints =
+                    signing::pem_fingerprints(rebuilder.item.signing_keyring.as_bytes()).ok()?;
+                let fingerprints = fingerprints
+                    .iter()
+                    .map(|fingerprint| format!("sha256={fingerprint}"))
+                    .collect::<Vec<_>>();
+                (!fingerprints.is_empty()).then(|| fingerprints.join(" "))
+            }
+            Some(View::Activity { scroll, .. }) => scroll
+                .selected()
+                .and_then(|idx| self.activity.get(idx))
+                .and_then(|record| record.sha256.clone()),
+            _ => None,
+        }
+    }
+
+    async fn copy(&mut self, text: String) {
+        self.status = Some(match clipboard::copy(&text).await {
+            Ok(clipboard::Method::Tool) => {
+                tr!("clipboard-copied", text = text.escape_default())
+            }
+            // The terminal may ignore the request, there's no way to tell
+            Ok(clipboard::Method::Osc52) => {
+                tr!("clipboard-sent-osc52", text = text.escape_default())
+            }
+            Err(err) => tr!("clipboard-failed", err = format!("{err:#}")),
+        });
+    }
+
+    /// Text input for the path prompt, keys aren't mapped to events while it's open
+    fn handle_prompt(&mut self, key: KeyEvent, bus: &Bus) -> Result<()> {
+        let Some(prompt) = &mut self.prompt else {
+            return Ok(());
+        };
+        match key.code {
+            KeyCode::Enter => {
+                let path = PathBuf::from(prompt.trim());
+                if !path.as_os_str().is_empty() {
+                    self.prompt = None;
+                    self.verify(path, bus)?;
                 }
-                Some(Event::Reload) => {
-                    if let Some(View::Rebuilders { .. }) = self.view {
-                        let http = http::client();
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.prompt = None;
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Backspace => {
+                prompt.pop();
+            }
+            KeyCode::Char(c) => prompt.push(c),
+            _ => (),
+        }
+        Ok(())
+    }
 
-                        let list = rebuilder::fetch_rebuilderd_community(&http).await?;
-                        self.config.cached_rebuilderd_community = list;
-                        self.config.save().await?;
+    /// Reload the audit log if it changed, so new decisions show up while the view is open
+    async fn refresh_activity(&mut self) {
+        let path = audit::path();
+        let modified = fs::metadata(&path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_some() && modified == self.activity_modified {
+            return;
+        }
+        self.activity_modified = modified;
+        // The audit log may be unreadable for non-root users
+        self.activity = audit::tail(&path, ACTIVITY_ENTRIES)
+            .await
+            .unwrap_or_default();
+    }
 
-                        let mut tasks = JoinSet::new();
-                        for rebuilder in self
-                            .config
-                            .custom_rebuilders
-                            .iter()
-                            .chain(&self.config.cached_rebuilderd_community)
-                        {
-                            let http = http.clone();
-                            let url = rebuilder.url.clone();
-                            tasks.spawn(async move {
-                                let keyring = http.fetch_signing_keyring(&url).await;
-                                (url, keyring)
-                            });
-                        }
+    async fn apply_reload(&mut self, reloaded: Reloaded) -> Result<()> {
+        self.config.cached_rebuilderd_community = reloaded.community;
+        self.config.cached_rebuilderd_community_at = Some(Utc::now());
 
-                        while let Some((url, keyring)) = tasks.join_next().await.transpose()? {
-                            let keyring = match keyring {
-                                Ok(keyring) => keyring,
-                                Err(_err) => {
-                                    // Can't render errors in TUI apps like this
-                                    // warn!("Failed to fetch signing keyring for {}: {:#}", url, err);
-                                    continue;
-                                }
-                            };
-
-                            for rebuilder in iter::empty()
-                                .chain(&mut self.config.custom_rebuilders)
-                                .chain(&mut self.config.cached_rebuilderd_community)
-                                .chain(&mut self.config.trusted_rebuilders)
-                                .filter(|r| r.url == url)
-                            {
-                                rebuilder.signing_keyring = keyring.clone();
-                            }
-                        }
-                        self.config.save().await?;
+        let tofu = self.config.trust_on_first_use;
+        for (url, keyring) in reloaded.keyrings {
+            for rebuilder in iter::empty()
+                .chain(&mut self.config.custom_rebuilders)
+                .chain(&mut self.config.cached_rebuilderd_community)
+                .filter(|r| r.url == url)
+            {
+                rebuilder.replace_signing_keyring(keyring.clone());
+            }
+            for rebuilder in self
+                .config
+                .trusted_rebuilders
+                .iter_mut()
+                .filter(|r| r.url == url)
+            {
+                // Never replace a keyring trusted on first use silently
+                if tofu && let Some(change) = tofu::compare(&rebuilder.signing_keyring, &keyring)? {
+                    KeyChanges::record(&url, change.clone()).await;
+                    self.key_changes.rebuilders.insert(url.to_string(), change);
+                    continue;
+                }
+                rebuilder.replace_signing_keyring(keyring.clone());
+            }
+        }
+        self.config.save().await?;
 
-                        self.rebuilders = self.config.resolve_rebuilder_view();
+        self.rebuilders = self.config.resolve_rebuilder_view();
+        Ok(())
+    }
+
+    async fn handle(&mut self, event: Event, bus: &Bus) -> Result<()> {
+        match event {
+            #[allow(
+                clippy::collapsible_match,
+                reason = "https://github.com/rust-lang/rust-clippy/issues/17033"
+            )]
+            Event::Yes => {
+                if let Some(confirm) = self.confirm.take() {
+                    match confirm {
+                        Confirm::DisableEnforcement => {
+                            self.config.rules.set_required_threshold(0);
+                            self.config.save().await?;
+                        }
                     }
+                } else if let Some(url) = self.selected_url() {
+                    self.copy(url).await;
                 }
-                Some(Event::Toggle) => {
-                    if let Some(View::Rebuilders { scroll }) = self.view
-                        && let Some(idx) = scroll.selected()
-                        && let Some(rebuilder) = self.rebuilders.get_mut(idx)
-                    {
-                        if rebuilder.active {
-                            self.config
-                                .trusted_rebuilders
-                                .retain(|r| r.url != rebuilder.item.url);
-                        } else {
-                            self.config.trusted_rebuilders.push(rebuilder.item.clone());
-                        }
-                        self.config.save().await?;
-
-                        rebuilder.active = !rebuilder.active;
+            }
+            Event::CopyFingerprint => {
+                if let Some(fingerprint) = self.selected_fingerprint() {
+                    self.copy(fingerprint).await;
+                }
+            }
+            Event::No => {
+                self.confirm = None;
+            }
+            Event::ScrollUp => {
+                self.scroll().select_previous();
+            }
+            Event::ScrollDown => {
+                self.scroll().select_next();
+            }
+            Event::ScrollFirst => {
+                self.scroll().select_first();
+            }
+            Event::ScrollLast => {
+                self.scroll().select_last();
+            }
+            Event::Reload => {
+                if let Some(View::Rebuilders { .. }) = self.view
+                    && !self.reloading
+                {
+                    let http = http::client_for_config(&self.config)?;
+                    let custom = self.config.custom_rebuilders.clone();
+                    let tx = bus.sender();
+                    self.reloading = true;
+                    tokio::spawn(async move {
+                        let result = Reloaded::fetch(&http, custom).await;
+                        let _ = tx.send(Message::Reloaded(result));
+                    });
+                } else if let Some(View::Verify {
+                    path,
+                    result: Some(_),
+                }) = &self.view
+                {
+                    // Check again, e.g. after more rebuilders caught up
+                    self.verify(path.clone(), bus)?;
+                }
+            }
+            Event::Toggle => {
+                if let Some(View::Rebuilders { scroll }) = self.view
+                    && let Some(idx) = scroll.selected()
+                    && let Some(rebuilder) = self.rebuilders.get_mut(idx)
+                {
+                    if rebuilder.active {
+                        self.config
+                            .trusted_rebuilders
+                            .retain(|r| r.url != rebuilder.item.url);
+                    } else {
+                        self.config.trusted_rebuilders.push(rebuilder.item.clone());
                     }
+                    self.config.save().await?;
+
+                    rebuilder.active = !rebuilder.active;
                 }
-                Some(Event::Enter) => {
-                    if let Some(View::Home) = self.view {
-                        match self.home_scroll.selected() {
-                            Some(0) => (),
-                            Some(1) => {
-                                self.view = Some(View::rebuilders());
-                                self.rebuilders = self.config.resolve_rebuilder_view();
-                            }
-                            Some(2) => {
-                                self.view = Some(View::blindly_trust());
-                            }
-                            Some(3) => self.view = None,
-                            _ => {}
+            }
+            Event::Enter => {
+                if let Some(View::Activity { details, .. }) = &mut self.view {
+                    *details = !*details && !self.activity.is_empty();
+                } else if let Some(View::Home) = self.view {
+                    match self.home_scroll.selected() {
+                        Some(0 | 1) => (),
+                        Some(2) => {
+                            self.view = Some(View::rebuilders());
+                            self.rebuilders = self.config.resolve_rebuilder_view();
+                        }
+                        Some(3) => {
+                            self.view = Some(View::blindly_trust());
                         }
+                        Some(4) => {
+                            self.view = Some(View::activity());
+                            self.activity_modified = None;
+                            self.refresh_activity().await;
+                        }
+                        Some(5) => self.prompt = Some(String::new()),
+                        Some(6) => self.view = None,
+                        _ => {}
                     }
                 }
-                Some(Event::Plus) => {
-                    if let Some(View::Home) = self.view
-                        && self.home_scroll.selected() == Some(0)
-                    {
-                        let threshold = &mut self.config.rules.required_threshold;
-                        *threshold = threshold.saturating_add(1);
-                        self.config.save().await?;
-                    }
+            }
+            Event::Plus => {
+                if let Some(View::Home) = self.view
+                    && self.home_scroll.selected() == Some(0)
+                {
+                    let rules = &mut self.config.rules;
+                    let threshold = if rules.is_disabled() {
+                        1
+                    } else {
+                        rules.required_threshold.saturating_add(1)
+                    };
+                    rules.set_required_threshold(threshold);
+                    self.config.save().await?;
+                } else if let Some(View::Home) = self.view
+                    && self.home_scroll.selected() == Some(1)
+                {
+                    self.cycle_profile(false).await?;
                 }
-                Some(Event::Minus) => {
-                    if let Some(View::Home) = self.view
-                        && self.home_scroll.selected() == Some(0)
-                    {
-                        let threshold = &mut self.config.rules.required_threshold;
-                        *threshold = threshold.saturating_sub(1);
+            }
+            Event::Minus => {
+                if let Some(View::Home) = self.view
+                    && self.home_scroll.selected() == Some(0)
+                {
+                    let rules = &mut self.config.rules;
+                    if rules.is_disabled() {
+                        // Already as low as it goes
+                    } else if rules.required_threshold <= 1 {
+                        // Going below 1 disables enforcement, this has to be confirmed
+                        self.confirm = Some(Confirm::DisableEnforcement);
+                    } else {
+                        rules.set_required_threshold(rules.required_threshold - 1);
                         self.config.save().await?;
                     }
+                } else if let Some(View::Home) = self.view
+                    && self.home_scroll.selected() == Some(1)
+                {
+                    self.cycle_profile(true).await?;
                 }
-                Some(Event::Esc) => {
+            }
+            Event::Esc => {
+                if let Some(View::Activity { details, .. }) = &mut self.view
+                    && *details
+                {
+                    *details = false;
+                } else {
                     self.view = Some(View::home());
                 }
-                Some(Event::Quit) => {
-                    self.view = if let Some(View::Home) = self.view {
-                        None
-                    } else {
-                        Some(View::home())
-                    }
+            }
+            Event::Quit => {
+                self.view = if let Some(View::Home) = self.view {
+                    None
+                } else {
+                    Some(View::home())
                 }
-                None => {}
             }
         }
-
         Ok(())
     }
 }
@@ -1,6 +1,14 @@
+use crate::config::Config;
+use crate::delta;
+use crate::errors::*;
+use crate::http;
+use crate::lockfile;
+use crate::pkcs11;
+use clap::builder::RangedU64ValueParser;
 use clap::{ArgAction, CommandFactory, Parser};
 use clap_complete::Shell;
 use std::io::stdout;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use url::Url;
 
@@ -12,14 +20,38 @@ pub struct Args {
     pub verbose: u8,
     #[clap(subcommand)]
     pub subcommand: Option<SubCommand>,
+    /// Open the terminal UI with this package file verified right away
+    #[arg(long, value_name = "PATH")]
+    pub verify: Option<PathBuf>,
 }
 
 #[derive(Debug, Parser)]
 pub enum SubCommand {
     #[clap(subcommand)]
-    Transport(Transport),
+    Transport(Box<Transport>),
     #[clap(subcommand)]
-    Plumbing(Plumbing),
+    Plumbing(Box<Plumbing>),
+    Cron(Cron),
+}
+
+/// Periodically re-verify recently downloaded packages as rebuilders catch up
+#[derive(Debug, Parser)]
+pub struct Cron {
+    /// Run a single pass and exit, e.g. when started from a systemd timer
+    #[arg(long)]
+    pub once: bool,
+    /// Seconds to wait between passes
+    #[arg(long, default_value = "3600")]
+    pub interval: u64,
+    /// Only check packages downloaded within this many days
+    #[arg(long, default_value = "14")]
+    pub max_age_days: u32,
+    /// Flag packages that are still not reproduced after this many days
+    #[arg(long, default_value = "3")]
+    pub grace_days: u32,
+    /// Directories to scan for downloaded packages
+    #[arg(long = "dir", default_value = "/var/cache/apt/archives")]
+    pub dirs: Vec<PathBuf>,
 }
 
 /// Integrations for package managers
@@ -32,14 +64,85 @@ pub enum Transport {
         output: PathBuf,
         /// The package to download
         url: Url,
+        /// Also check the package with pacman's keyring, so the signed package is the reproduced one
+        #[arg(long)]
+        verify_signature: bool,
+        #[command(flatten)]
+        options: TransportOptions,
+    },
+    /// Pacman PreTransaction hook, verifies the downloaded packages in the cache
+    AlpmHook {
+        /// Directory pacman downloads packages into, can be used multiple times
+        #[arg(long = "cache-dir", default_value = crate::transport::alpm::PACMAN_CACHE_DIR)]
+        cache_dirs: Vec<PathBuf>,
         #[command(flatten)]
         options: TransportOptions,
     },
     /// Integrations for APT's transport methods
-    Apt,
+    Apt {
+        #[command(flatten)]
+        http: HttpOptions,
+    },
+    /// DPkg::Post-Invoke hook, checks packages that were installed without the transport
+    AptPostInvoke {
+        /// The log dpkg writes its actions to
+        #[arg(long, default_value = crate::sideload::DPKG_LOG)]
+        dpkg_log: PathBuf,
+        /// Directory apt downloads packages into
+        #[arg(long, default_value = crate::sideload::APT_ARCHIVES)]
+        archives: PathBuf,
+        #[command(flatten)]
+        http: HttpOptions,
+    },
+    /// The privilege separated worker process of the apt transport
+    #[command(hide = true)]
+    AptWorker,
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Default, Parser)]
+pub struct HttpOptions {
+    /// Record all rebuilder HTTP exchanges into this directory
+    #[arg(long, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Replay rebuilder HTTP exchanges from this directory instead of using the network
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+    /// Log the headers of all rebuilder requests and responses, credentials are redacted
+    #[arg(long)]
+    pub trace_http: bool,
+    /// Also append the rebuilder response bodies to this file
+    #[arg(long, requires = "trace_http")]
+    pub trace_http_bodies: Option<PathBuf>,
+}
+
+impl HttpOptions {
+    pub fn client(&self, config: &Config) -> Result<http::Client> {
+        let client = http::client_for_config(config)?;
+        let client = if let Some(dir) = &self.record {
+            client.record(dir.clone())
+        } else if let Some(dir) = &self.replay {
+            client.replay(dir.clone())
+        } else {
+            client
+        };
+        let client = if self.trace_http {
+            client.trace(self.trace_http_bodies.clone())
+        } else {
+            client
+        };
+        Ok(client)
+    }
+}
+
+/// Options for commands that modify the config
+#[derive(Debug, Default, Clone, Copy, Parser)]
+pub struct CheckMode {
+    /// Only report whether the config would change, exit with 2 if it would, 0 otherwise
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Debug, Default, Parser)]
 pub struct TransportOptions {
     /*
     /// Example: socks5://127.0.0.1:9050
@@ -58,10 +161,29 @@ pub struct TransportOptions {
     /// Blindly trust these packages, even if nobody could reproduce the binary
     #[arg(long)]
     pub blindly_trust: Vec<String>,
+    #[command(flatten)]
+    pub http: HttpOptions,
+}
+
+impl TransportOptions {
+    /// Override the rules of the config with the command line options
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(required_confirms) = self.required_confirms {
+            config.rules.required_threshold = required_confirms;
+        }
+        config
+            .rules
+            .blindly_trust
+            .extend(self.blindly_trust.iter().cloned());
+    }
 }
 
 /// Low-level commands and utilities
 #[derive(Debug, Parser)]
+#[allow(
+    clippy::large_enum_variant,
+    reason = "parsed once from the command line"
+)]
 pub enum Plumbing {
     /// Fetch a curated list of well-known rebuilders
     FetchRebuilderdCommunity,
@@ -72,51 +194,480 @@ pub enum Plumbing {
         /// Set a human-friendly name for the rebuilder (defaults to the URL domain)
         #[arg(long = "name")]
         name: Option<String>,
+        #[command(flatten)]
+        check: CheckMode,
     },
     /// Remove a rebuilder from the trusted set
     RemoveRebuilder {
         /// The rebuilder URL
         url: Url,
+        #[command(flatten)]
+        check: CheckMode,
+    },
+    /// Trust rebuilders from the rebuilderd-community list and require all of them to agree
+    TrustCommunity {
+        /// Only select rebuilders for this distribution
+        #[arg(long)]
+        distribution: String,
+        /// The number of rebuilders to select, this is also set as the required threshold
+        #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+        min: usize,
+        /// Prefer rebuilders in distinct countries
+        #[arg(long)]
+        distinct_countries: bool,
+        /// Refresh the rebuilderd-community list instead of using the cached copy
+        #[arg(long)]
+        refresh: bool,
+        #[command(flatten)]
+        check: CheckMode,
+    },
+    /// Import rebuilders from a TOML file (`[[rebuilder]]` entries, like the rebuilderd-community list)
+    ImportRebuilders {
+        /// Also add the imported rebuilders to the trusted set
+        #[arg(long)]
+        trust: bool,
+        /// Parse the file as json instead of TOML (the default for `.json` files)
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        check: CheckMode,
+        /// The file to import
+        file: PathBuf,
+    },
+    /// Print rebuilders in the format read by `import-rebuilders`
+    ExportRebuilders {
+        /// Export all known rebuilders, not just trusted ones
+        #[arg(short = 'a', long = "all")]
+        all: bool,
+        /// Export as json instead of TOML
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show historical statistics about the configured rebuilders
+    RebuilderStats {
+        /// Print the statistics as json
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove the pinned keyring of a trusted rebuilder, so it's trusted again on next use
+    ForgetKey {
+        /// The rebuilder URL
+        url: Url,
+        #[command(flatten)]
+        check: CheckMode,
+    },
+    /// Mark the signing key of a trusted rebuilder as verified out-of-band
+    ///
+    /// Compare the fingerprint shown by `list-rebuilders` with the operator of the rebuilder first,
+    /// e.g. in person or over the phone, and pass the fingerprint they confirmed.
+    MarkVerified {
+        /// The rebuilder URL
+        url: Url,
+        /// The fingerprint confirmed by the operator, grouped hex or `sha256=...`
+        fingerprint: String,
+        #[command(flatten)]
+        check: CheckMode,
+    },
+    /// Remove the out-of-band verification mark of a trusted rebuilder
+    UnmarkVerified {
+        /// The rebuilder URL
+        url: Url,
+        #[command(flatten)]
+        check: CheckMode,
+    },
+    /// Save the current rules and trusted rebuilders as named profile, and make it the active one
+    SaveProfile {
+        /// The profile name, e.g. `paranoid` or `eu-only`
+        name: String,
+        #[command(flatten)]
+        check: CheckMode,
+    },
+    /// Switch to the rules and trusted rebuilders of a profile
+    ///
+    /// Changes made since the last switch are kept in the profile that was active until now.
+    UseProfile {
+        /// The profile name
+        name: String,
+        #[command(flatten)]
+        check: CheckMode,
+    },
+    /// List the saved profiles
+    ListProfiles,
+    /// Delete a saved profile, the current rules and trusted rebuilders stay as they are
+    RemoveProfile {
+        /// The profile name
+        name: String,
+        #[command(flatten)]
+        check: CheckMode,
+    },
+    /// Compare a rebuilder's signing keyring with the fingerprints it publishes in DNS
+    CheckDnsKeys {
+        /// The rebuilder URL
+        url: Url,
     },
     /// List configured rebuilders
     ListRebuilders {
         /// Show all known rebuilders, not just active/trusted ones
         #[arg(short = 'a', long = "all")]
         all: bool,
+        /// Only show rebuilders for this distribution (can be used multiple times)
+        #[arg(long = "distribution")]
+        distributions: Vec<String>,
+        /// Only show rebuilders in this country (can be used multiple times)
+        #[arg(long = "country")]
+        countries: Vec<String>,
+        /// Only show rebuilders with a signing keyring
+        #[arg(long)]
+        with_keys_only: bool,
+        /// Print each rebuilder as json, one per line
+        #[arg(long)]
+        json: bool,
     },
     /// Add a package to blindly-trust set
     AddBlindlyTrust {
-        /// Package name
+        /// Package name, glob pattern (`linux-image-*`) or regex (`regex:linux-image-.*`)
         pkg: String,
+        /// Match the source package instead of the binary package name
+        #[arg(long)]
+        source: bool,
+        #[command(flatten)]
+        check: CheckMode,
     },
     /// Remove a package from blindly-trust set
     RemoveBlindlyTrust {
         /// Package name
         pkg: String,
+        /// Remove from the source package set instead
+        #[arg(long)]
+        source: bool,
+        #[command(flatten)]
+        check: CheckMode,
     },
     /// List packages in blindly-trust set
     ListBlindlyTrust,
     /// Authenticate a package through rebuilder attestations
     Verify {
-        #[arg(short = 'S', long = "signing-key")]
+        /// Trust this signing key, needs --threshold
+        #[arg(short = 'S', long = "signing-key", requires = "threshold")]
         signing_keys: Vec<PathBuf>,
         #[arg(short = 'A', long = "attestation")]
         attestations: Vec<PathBuf>,
         #[arg(short = 'R', long = "rebuilder")]
         rebuilders: Vec<Url>,
-        #[arg(short = 't', long = "threshold")]
-        threshold: usize,
-        /// The file to authenticate
+        /// Number of required confirms
+        #[arg(short = 't', long = "threshold", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+        threshold: Option<usize>,
+        /// Load attestations from an evidence bundle, they're only counted for keys given with --signing-key
+        #[arg(long, requires_all = ["threshold", "signing_keys"])]
+        evidence: Option<PathBuf>,
+        /// Do not contact any rebuilders, only use local data
+        #[arg(long, conflicts_with = "rebuilders")]
+        offline: bool,
+        /// After the package was reproduced, write the counted attestations and their keys into this directory
+        #[arg(long)]
+        export_evidence: Option<PathBuf>,
+        /// Write a signed SLSA verification summary attestation (VSA) to this file, also if verification fails
+        #[arg(long, requires = "vsa_key")]
+        vsa: Option<PathBuf>,
+        /// The private key to sign the VSA with (see `keygen`), a file or a `pkcs11:` URI
+        #[arg(long, requires = "vsa")]
+        vsa_key: Option<String>,
+        /// Reconstruct the file from this delta before verifying it
+        #[arg(long, requires = "delta_base")]
+        delta: Option<PathBuf>,
+        /// The previous version of the artifact the delta applies to (`/` for debdelta uses the installed package)
+        #[arg(long)]
+        delta_base: Option<PathBuf>,
+        /// The format of the delta
+        #[arg(long, value_enum, default_value = "xdelta3")]
+        delta_format: delta::Format,
+        /// Read the artifact from stdin and keep it in memory, instead of reading a file
+        #[arg(long, conflicts_with_all = ["file", "delta", "export_evidence"])]
+        stdin: bool,
+        /// With a URL to verify, move the download to this path after successful verification
+        #[arg(long)]
+        keep: Option<PathBuf>,
+        /// Print the decision, its reasons and the votes of all signing keys as json
+        #[arg(long)]
+        json: bool,
+        /// Use the rules and trusted rebuilders of this config file (default without --threshold)
+        #[arg(long, conflicts_with_all = ["threshold", "evidence", "signing_keys"])]
+        config: Option<PathBuf>,
+        /// Only use the signing keys and threshold given on the command line, also ignores the
+        /// privacy and network settings of the system config
+        #[arg(long, conflicts_with = "config")]
+        no_config: bool,
+        /// The package name to search rebuilders for, instead of inspecting the artifact
+        #[arg(long, requires_all = ["version", "architecture"])]
+        name: Option<String>,
+        /// The package version to search rebuilders for
+        #[arg(long, requires_all = ["name", "architecture"])]
+        version: Option<String>,
+        /// The package architecture to search rebuilders for
+        #[arg(long = "arch", requires_all = ["name", "version"])]
+        architecture: Option<String>,
+        #[command(flatten)]
+        http: HttpOptions,
+        /// The file or http(s) URL to authenticate (with --delta, the reconstructed file is written here)
+        #[arg(required_unless_present = "stdin")]
+        file: Option<PathBuf>,
+    },
+    /// Generate a local Ed25519 key for countersigning verification results
+    Keygen {
+        /// Where to write the private key
+        #[arg(required_unless_present = "pkcs11")]
+        path: Option<PathBuf>,
+        /// Generate the key on a PKCS#11 token instead, like `pkcs11:token=mytoken;object=repro-threshold`
+        #[arg(long, conflicts_with = "path")]
+        pkcs11: Option<String>,
+        /// The type of the key on the PKCS#11 token (default: ed25519)
+        #[arg(long, value_enum)]
+        key_type: Option<pkcs11::KeyType>,
+    },
+    /// Check the reproduction status of all binary packages built from a source package
+    VerifySource {
+        /// The source package name
+        name: String,
+        /// The source package version
+        version: String,
+        /// Use these rebuilders instead of the configured ones
+        #[arg(short = 'R', long = "rebuilder")]
+        rebuilders: Vec<Url>,
+    },
+    /// Check a package by its sha256 only, e.g. from a lockfile or Packages index
+    ///
+    /// The artifact itself is not needed, the decision is based on rebuilder attestations alone.
+    VerifyHash {
+        /// The binary package name
+        #[arg(long)]
+        name: String,
+        /// The package version
+        #[arg(long)]
+        version: String,
+        /// The package architecture
+        #[arg(long = "arch")]
+        architecture: String,
+        /// The source package, defaults to the binary package name
+        #[arg(long)]
+        source: Option<String>,
+        /// The expected sha256 of the package, hex encoded
+        #[arg(long)]
+        sha256: String,
+        /// Use these rebuilders instead of the configured ones
+        #[arg(short = 'R', long = "rebuilder")]
+        rebuilders: Vec<Url>,
+        /// Print the decision as json
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that every package pinned in a lockfile is reproduced, e.g. to gate image builds
+    ///
+    /// Accepts an apt `Packages` index, lines of `name version [arch] sha256`, or a CycloneDX
+    /// SBOM. The packages are not downloaded, only the attestations of the rebuilders are used.
+    VerifyLockfile {
+        /// The format of the lockfile, detected from its content by default
+        #[arg(long, value_enum)]
+        format: Option<lockfile::Format>,
+        /// The architecture of entries that don't specify one
+        #[arg(long = "arch")]
+        architecture: Option<String>,
+        /// Use these rebuilders instead of the configured ones
+        #[arg(short = 'R', long = "rebuilder")]
+        rebuilders: Vec<Url>,
+        /// Print the decision of each package as json, one per line
+        #[arg(long)]
+        json: bool,
+        /// The lockfile to check
+        file: PathBuf,
+    },
+    /// Report how many packages baked into a container image are reproduced
+    ///
+    /// The image is pulled from its registry and the dpkg or pacman database is read from its
+    /// layers. Hashes are taken from apt's package lists if the image still contains them,
+    /// otherwise the build confirmed by the most rebuilders is used.
+    VerifyContainer {
+        /// The platform to select from a multi-platform image, like `linux/arm64`
+        #[arg(long)]
+        platform: Option<String>,
+        /// Use these rebuilders instead of the configured ones
+        #[arg(short = 'R', long = "rebuilder")]
+        rebuilders: Vec<Url>,
+        /// Print the decision of each package as json, one per line
+        #[arg(long)]
+        json: bool,
+        /// The image reference, like `debian:bookworm` or `ghcr.io/foo/bar@sha256:...`
+        image: String,
+    },
+    /// Verify the artifacts in the current directory against an in-toto layout
+    ///
+    /// Like `in-toto-verify`, inspections of the layout are executed in the current directory.
+    VerifyLayout {
+        /// The signed in-toto layout
+        #[arg(long)]
+        layout: PathBuf,
+        /// The public keys the layout needs to be signed with
+        #[arg(short = 'k', long = "layout-key", required = true)]
+        keys: Vec<PathBuf>,
+        /// Directory with the link metadata of the steps (`<step>.<keyid>.link`)
+        #[arg(long)]
+        links: PathBuf,
+    },
+    /// Verify the running repro-threshold binary through the attestations of its package
+    SelfVerify {
+        /// Use these rebuilders instead of the configured ones
+        #[arg(short = 'R', long = "rebuilder")]
+        rebuilders: Vec<Url>,
+        /// The .deb of the installed package (defaults to the copy in apt's cache)
+        #[arg(long)]
+        deb: Option<PathBuf>,
+    },
+    /// Summarize the policy, rebuilder reachability and package decisions of this machine
+    Status {
+        /// Print the summary as json
+        #[arg(long)]
+        json: bool,
+    },
+    /// List packages that were admitted without reaching the threshold
+    ///
+    /// Exits with an error if any of them passed their deadline.
+    Pending,
+    /// Compare two attestations side by side
+    DiffAttestations {
+        /// Only show the differences
+        #[arg(short = 'q', long)]
+        quiet: bool,
+        /// The first attestation (a file path or rebuilder attestation url)
+        a: String,
+        /// The second attestation (a file path or rebuilder attestation url)
+        b: String,
+    },
+    /// Download the artifacts rebuilt for a package and show how they differ with diffoscope
+    Investigate {
+        /// Use these rebuilders instead of the configured ones
+        #[arg(short = 'R', long = "rebuilder")]
+        rebuilders: Vec<Url>,
+        /// Where to store rebuilt artifacts (defaults to a temporary directory)
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+        /// The local .deb file
+        file: PathBuf,
+    },
+    /// Generate a Markdown report about a package that can be attached to a bug report
+    Report {
+        /// Use these rebuilders instead of the configured ones
+        #[arg(short = 'R', long = "rebuilder")]
+        rebuilders: Vec<Url>,
+        /// The .deb file to report about
+        file: PathBuf,
+    },
+    /// Collect sanitized config, version info, logs and rebuilder status into a tarball for support requests
+    DebugBundle {
+        /// Where to write the tarball (defaults to `repro-threshold-debug-<timestamp>.tar.xz`)
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+        /// Also include a report about this package (a .deb file)
+        #[arg(long)]
+        deb: Option<PathBuf>,
+        /// Include the last lines of these log files
+        #[arg(long = "log", default_value = "/var/log/apt/term.log")]
+        logs: Vec<PathBuf>,
+        /// Number of lines to include from each log file
+        #[arg(long, default_value = "500")]
+        log_lines: usize,
+    },
+    /// Countersign an evidence bundle with a local key
+    SignResult {
+        /// The private key to sign with, a file or a `pkcs11:` URI
+        #[arg(short = 'k', long = "key")]
+        key: String,
+        /// Where to write the countersignature (defaults to a file inside the bundle)
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+        /// Use the rules and trusted rebuilders of this config file instead of the system config
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// The evidence bundle directory
+        bundle: PathBuf,
+        /// The artifact the evidence bundle is about
+        artifact: PathBuf,
+    },
+    /// Check a countersignature against the expected public key
+    VerifyResult {
+        /// The public key of the host that created the countersignature
+        #[arg(short = 'k', long = "key")]
+        public_key: PathBuf,
+        /// The countersignature file
+        file: PathBuf,
+    },
+    /// Check the signature of a verification summary attestation and print its statement
+    VerifyVsa {
+        /// The public key of the host that created the VSA
+        #[arg(short = 'k', long = "key")]
+        public_key: PathBuf,
+        /// The VSA file (a DSSE envelope)
         file: PathBuf,
     },
+    /// Serve a directory of attestations over the rebuilderd API
+    ServeAttestations {
+        /// The address to listen on
+        #[arg(short = 'B', long, default_value = "127.0.0.1:8484")]
+        bind: SocketAddr,
+        /// The public key the attestations are signed with
+        #[arg(short = 'S', long = "signing-key")]
+        signing_key: PathBuf,
+        /// Directory with `<name>_<version>_<architecture>.in-toto.link` files
+        dir: PathBuf,
+    },
+    /// Answer package verification queries of Kubernetes admission webhooks over HTTPS
+    ServeAdmission {
+        /// The address to listen on
+        #[arg(short = 'B', long, default_value = "0.0.0.0:8443")]
+        bind: SocketAddr,
+        /// File with the bearer token clients need to send
+        #[arg(long)]
+        token_file: PathBuf,
+        /// The TLS certificate chain (PEM)
+        #[arg(long)]
+        tls_cert: PathBuf,
+        /// The TLS private key (PEM)
+        #[arg(long)]
+        tls_key: PathBuf,
+        /// Stop waiting for rebuilders after this many seconds and decide with the responses received
+        #[arg(long, default_value = "8")]
+        timeout: u64,
+    },
     /// Parse metadata from a .deb file
     InspectDeb {
+        /// List the files in the data.tar of the package, with their mode and size
+        #[arg(long)]
+        list_contents: bool,
         /// The .deb file to inspect
         file: PathBuf,
     },
+    /// Inspect the audit log of the transport decisions
+    #[clap(subcommand)]
+    Log(Log),
     Completions(Completions),
 }
 
+#[derive(Debug, Parser)]
+pub enum Log {
+    /// Check the hash chain and signatures of the audit log
+    Verify {
+        /// The public key of the audit log (defaults to the key of the configured `[audit]` signing key)
+        #[arg(short = 'k', long = "key")]
+        public_key: Option<PathBuf>,
+        /// The value of the TPM counter, the log has to end with this entry (defaults to the
+        /// configured `[audit]` counter for the log of this host)
+        #[arg(long)]
+        head: Option<u64>,
+        /// The log file (defaults to the audit log of this host, including the rotated part)
+        file: Option<PathBuf>,
+    },
+    /// Print the public key of the configured audit log signing key, to verify the log elsewhere
+    PublicKey,
+}
+
 /// Generate shell completions
 #[derive(Debug, Parser)]
 pub struct Completions {
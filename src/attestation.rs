@@ -1,19 +1,41 @@
 use crate::errors::*;
 use crate::http;
 use crate::inspect::deb::Deb;
+use crate::state::Misses;
+use crate::stats;
+use crate::telemetry;
+use futures::{Stream, StreamExt, future, stream};
 use in_toto::{
     crypto::{HashAlgorithm, KeyId, PublicKey},
     models::{Metablock, MetadataWrapper},
 };
 use sha2::{Digest, Sha256};
 use std::collections::{BTreeMap, BTreeSet};
+use std::io::Seek;
 use std::path::Path;
+use std::pin::pin;
 use std::slice;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncReadExt};
-use tokio::{fs, task::JoinSet};
+use tokio::{
+    fs,
+    task::{self, JoinSet},
+    time,
+};
 use url::Url;
 
+/// Parse a hex encoded sha256, e.g. given on the command line
+pub fn parse_sha256(hex: &str) -> Result<Vec<u8>> {
+    let sha256 = data_encoding::HEXLOWER_PERMISSIVE
+        .decode(hex.as_bytes())
+        .with_context(|| format!("Invalid sha256: {hex:?}"))?;
+    if sha256.len() != 32 {
+        bail!("Invalid sha256: {hex:?}");
+    }
+    Ok(sha256)
+}
+
 pub async fn sha256_file<R: AsyncRead + Unpin>(mut reader: R) -> Result<Vec<u8>> {
     let mut hasher = Sha256::new();
     let mut buffer = [0u8; 8192];
@@ -29,14 +51,148 @@ pub async fn sha256_file<R: AsyncRead + Unpin>(mut reader: R) -> Result<Vec<u8>>
     Ok(hasher.finalize().to_vec())
 }
 
+/// Read buffer size for hashing local files
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Hash a local file on a blocking thread, this is a lot faster than going through tokio's file io
+pub async fn sha256_path(path: &Path) -> Result<Vec<u8>> {
+    let (_, sha256) = open_hashed(path).await?;
+    Ok(sha256)
+}
+
+/// Open a local file and hash it, the returned handle is rewound to the start
+///
+/// Inspect the file through this handle instead of opening the path again, the path may point to
+/// a different file by then.
+pub async fn open_hashed(path: &Path) -> Result<(fs::File, Vec<u8>)> {
+    let mut file = std::fs::File::open(path)?;
+    let (file, sha256) = task::spawn_blocking(move || {
+        let sha256 = sha256_file_blocking(&file)?;
+        file.rewind()?;
+        Ok::<_, Error>((file, sha256))
+    })
+    .await??;
+    Ok((fs::File::from_std(file), sha256))
+}
+
+#[cfg(not(feature = "mmap"))]
+fn sha256_file_blocking(mut file: &std::fs::File) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(hasher.finalize().to_vec())
+}
+
+#[cfg(feature = "mmap")]
+fn sha256_file_blocking(file: &std::fs::File) -> Result<Vec<u8>> {
+    // SAFETY: if the file is truncated while mapped the process crashes with SIGBUS,
+    // this is why mmap is opt-in
+    let mmap = unsafe { memmap2::Mmap::map(file)? };
+    let mut hasher = Sha256::new();
+    for chunk in mmap.chunks(HASH_BUFFER_SIZE) {
+        hasher.update(chunk);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Attestations are small json documents, anything bigger than this is rejected before parsing
+pub const MAX_ATTESTATION_SIZE: usize = 1024 * 1024;
+/// Maximum nesting of json arrays and objects
+const MAX_JSON_DEPTH: usize = 32;
+/// Maximum number of signatures, each of them may need to be verified
+const MAX_SIGNATURES: usize = 16;
+/// Maximum number of products and materials of a link
+const MAX_ARTIFACTS: usize = 1024;
+
+/// The deepest nesting of arrays and objects, strings are skipped
+fn json_depth(bytes: &[u8]) -> usize {
+    let (mut depth, mut max) = (0usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for b in bytes {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => (),
+            }
+        } else {
+            match b {
+                b'"' => in_string = true,
+                b'[' | b'{' => {
+                    depth += 1;
+                    max = max.max(depth);
+                }
+                b']' | b'}' => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+        }
+    }
+    max
+}
+
 pub struct Attestation {
     metablock: Metablock,
+    bytes: Vec<u8>,
 }
 
 impl Attestation {
     pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() > MAX_ATTESTATION_SIZE {
+            bail!(
+                "Attestation is too big ({} bytes, limit is {MAX_ATTESTATION_SIZE})",
+                bytes.len()
+            );
+        }
+        let depth = json_depth(bytes);
+        if depth > MAX_JSON_DEPTH {
+            bail!("Attestation json is nested too deep ({depth}, limit is {MAX_JSON_DEPTH})");
+        }
+
         let metablock: Metablock = serde_json::from_slice(bytes)?;
-        Ok(Attestation { metablock })
+
+        let signatures = metablock.signatures.len();
+        if signatures > MAX_SIGNATURES {
+            bail!("Attestation has too many signatures ({signatures}, limit is {MAX_SIGNATURES})");
+        }
+        if let MetadataWrapper::Link(link) = &metablock.metadata {
+            let artifacts = link.products.len() + link.materials.len();
+            if artifacts > MAX_ARTIFACTS {
+                bail!(
+                    "Attestation has too many products and materials ({artifacts}, limit is {MAX_ARTIFACTS})"
+                );
+            }
+        }
+
+        Ok(Attestation {
+            metablock,
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    /// The attestation exactly as it was received
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The raw json of the signed section, for display purposes
+    pub fn signed_json(&self) -> Result<serde_json::Value> {
+        let mut value = serde_json::from_slice::<serde_json::Value>(&self.bytes)?;
+        let signed = value
+            .get_mut("signed")
+            .context("Attestation is missing `signed` section")?
+            .take();
+        Ok(signed)
     }
 
     pub async fn parse_file(path: &Path) -> Result<Self> {
@@ -60,10 +216,15 @@ impl Attestation {
         };
 
         // check signature (to avoid a warning, remove all other signatures)
+        //
+        // Only the first signature of the key is considered, so duplicates can't make a crafted
+        // attestation trigger several verifications per key
         let mut metablock = self.metablock.clone();
-        metablock
+        let mut signatures = metablock
             .signatures
-            .retain(|sig| sig.key_id() == public_key.key_id());
+            .into_iter()
+            .filter(|sig| sig.key_id() == public_key.key_id());
+        metablock.signatures = signatures.next().into_iter().collect();
         metablock
             .verify(1, slice::from_ref(public_key))
             .context("Failed to verify attestation signature")?;
@@ -81,15 +242,45 @@ impl Attestation {
         bail!("SHA256 hash does not match any product hash in attestation");
     }
 
-    pub fn list_key_ids(&self) -> Vec<KeyId> {
+    /// The sha256 hashes of all products, signatures are not checked
+    pub fn product_sha256s(&self) -> Vec<Vec<u8>> {
+        let MetadataWrapper::Link(link) = &self.metablock.metadata else {
+            return vec![];
+        };
+        link.products
+            .values()
+            .filter_map(|hashes| hashes.get(&HashAlgorithm::Sha256))
+            .map(|hash| hash.value().to_vec())
+            .collect()
+    }
+
+    /// The path of the product with this sha256, signatures are not checked
+    pub fn product_path(&self, sha256: &[u8]) -> Option<String> {
+        let MetadataWrapper::Link(link) = &self.metablock.metadata else {
+            return None;
+        };
+        link.products.iter().find_map(|(path, hashes)| {
+            let hash = hashes.get(&HashAlgorithm::Sha256)?;
+            (hash.value() == sha256).then(|| path.value().to_string())
+        })
+    }
+
+    /// The keys that signed this attestation, each listed once even if it signed multiple times
+    pub fn list_key_ids(&self) -> BTreeSet<KeyId> {
         self.metablock
             .signatures
             .iter()
+            .take(MAX_SIGNATURES)
             .map(|sig| sig.key_id().to_owned())
             .collect()
     }
 }
 
+pub struct Confirm<'a> {
+    pub signing_key: &'a PublicKey,
+    pub attestation: Arc<(String, Attestation)>,
+}
+
 #[derive(Default)]
 pub struct Tree {
     map: BTreeMap<KeyId, Vec<Arc<(String, Attestation)>>>,
@@ -111,6 +302,29 @@ impl Tree {
         }
     }
 
+    /// All sha256 hashes any attestation in this tree claims to have reproduced
+    pub fn candidate_sha256s(&self) -> BTreeSet<Vec<u8>> {
+        self.map
+            .values()
+            .flatten()
+            .flat_map(|item| item.1.product_sha256s())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// All attestations in this tree, each listed once
+    pub fn iter(&self) -> impl Iterator<Item = &(String, Attestation)> {
+        let mut seen = BTreeSet::new();
+        self.map
+            .values()
+            .flatten()
+            .map(|item| item.as_ref())
+            .filter(move |(label, _)| seen.insert(label.clone()))
+    }
+
     pub fn get(&self, key_id: &KeyId) -> Option<&[Arc<(String, Attestation)>]> {
         self.map.get(key_id).map(|v| v.as_slice())
     }
@@ -120,7 +334,37 @@ impl Tree {
         sha256: &[u8],
         signing_keys: I,
     ) -> BTreeSet<KeyId> {
-        let mut confirms = BTreeSet::new();
+        let mut span = telemetry::span("verify_signatures");
+        let confirms = self.confirm(sha256, signing_keys);
+        span.attr("confirms", confirms.len());
+        confirms.into_keys().collect()
+    }
+
+    /// Keys that signed attestations in this tree, but none of them for this sha256
+    pub fn disagreeing<'a, I: IntoIterator<Item = &'a PublicKey>>(
+        &self,
+        sha256: &[u8],
+        signing_keys: I,
+    ) -> BTreeSet<KeyId> {
+        let signing_keys = signing_keys
+            .into_iter()
+            .filter(|key| self.map.contains_key(key.key_id()))
+            .collect::<Vec<_>>();
+        let confirms = self.verify(sha256, signing_keys.iter().copied());
+        signing_keys
+            .into_iter()
+            .map(|key| key.key_id().to_owned())
+            .filter(|key_id| !confirms.contains(key_id))
+            .collect()
+    }
+
+    /// Like `verify`, but also return the attestation that was counted for each key
+    pub fn confirm<'a, I: IntoIterator<Item = &'a PublicKey>>(
+        &self,
+        sha256: &[u8],
+        signing_keys: I,
+    ) -> BTreeMap<KeyId, Confirm<'a>> {
+        let mut confirms = BTreeMap::new();
 
         for signing_key in signing_keys {
             let key_id = signing_key.key_id();
@@ -128,14 +372,20 @@ impl Tree {
                 continue;
             };
 
-            for attestation in attestations {
-                let (attestation_path, attestation) = attestation.as_ref();
+            for item in attestations {
+                let (attestation_path, attestation) = item.as_ref();
 
                 if attestation.verify_sha256(sha256, signing_key).is_ok() {
                     debug!(
                         "Successfully verified attestation {attestation_path:?} with signing key {key_id:?}"
                     );
-                    confirms.insert(key_id.to_owned());
+                    confirms.insert(
+                        key_id.to_owned(),
+                        Confirm {
+                            signing_key,
+                            attestation: Arc::clone(item),
+                        },
+                    );
                     // We only count one vote per key, so skip the other attestations and continue with the next key
                     break;
                 } else {
@@ -150,30 +400,209 @@ impl Tree {
     }
 }
 
+/// The attestations found on rebuilders, and which rebuilders couldn't be queried
+#[derive(Default)]
+pub struct Remote {
+    pub attestations: Tree,
+    pub unreachable: Vec<Url>,
+    /// Rebuilders that didn't answer before the deadline
+    pub pending: Vec<Url>,
+    /// Number of rebuilders that answered, with or without attestations
+    pub answered: usize,
+}
+
+impl Remote {
+    /// None of the rebuilders could be reached, likely offline or behind a captive portal
+    pub fn is_offline(&self) -> bool {
+        self.answered == 0 && !self.unreachable.is_empty()
+    }
+}
+
 pub async fn fetch_remote<I: IntoIterator<Item = Url>>(
     http: &http::Client,
     rebuilders: I,
     inspect: Deb,
-) -> Tree {
+) -> Remote {
+    fetch_remote_until(http, rebuilders, inspect, None, |_| false).await
+}
+
+/// The answer of a single rebuilder, and how long it took
+pub type Answer = (Url, Duration, Result<Tree>);
+
+/// Start querying all rebuilders, honoring the privacy settings of the client
+pub fn spawn_queries<I: IntoIterator<Item = Url>>(
+    http: &http::Client,
+    rebuilders: I,
+    inspect: Deb,
+) -> JoinSet<Answer> {
     let mut tasks = JoinSet::new();
 
+    let mut rebuilders = rebuilders.into_iter().collect::<Vec<_>>();
+    let privacy = http.privacy();
+    if privacy.shuffle_rebuilders {
+        http::shuffle(&mut rebuilders);
+    }
+
     let inspect = Arc::new(inspect);
+    let context = telemetry::current();
     for url in rebuilders {
         let http = http.clone();
         let inspect = inspect.clone();
-        tasks.spawn(async move { http.fetch_attestations_for_pkg(&url, &inspect).await });
+        let delay = Duration::from_millis(http::random_below(privacy.max_query_delay_ms));
+        tasks.spawn(telemetry::scope(context, async move {
+            let ttl = http.network().negative_cache_ttl();
+            if !ttl.is_zero() && Misses::contains(&url, &inspect, ttl).await {
+                debug!("Rebuilder recently had no build of this package, skipping: {url}");
+                return (url, Duration::ZERO, Ok(Tree::default()));
+            }
+            if !delay.is_zero() {
+                trace!("Delaying query to rebuilder {url} by {delay:?}");
+                time::sleep(delay).await;
+            }
+            let mut span = telemetry::span("rebuilder_query");
+            span.attr("rebuilder", url.as_str());
+            let start = Instant::now();
+            let result = http.fetch_attestations_for_pkg(&url, &inspect).await;
+            match &result {
+                Ok(tree) => span.attr("attestations", tree.iter().count()),
+                Err(err) => span.error(err),
+            }
+            if !ttl.is_zero() && result.as_ref().is_ok_and(Tree::is_empty) {
+                Misses::record(&url, &inspect, ttl).await;
+            }
+            (url, start.elapsed(), result)
+        }));
+    }
+    tasks
+}
+
+/// Like `fetch_remote`, but stop waiting for rebuilders at the deadline, or as soon as `done`
+/// is satisfied with the attestations received so far
+pub async fn fetch_remote_until<I, F>(
+    http: &http::Client,
+    rebuilders: I,
+    inspect: Deb,
+    deadline: Option<Instant>,
+    done: F,
+) -> Remote
+where
+    I: IntoIterator<Item = Url>,
+    F: FnMut(&Tree) -> bool,
+{
+    let rebuilders = rebuilders.into_iter().collect::<Vec<_>>();
+    let span = telemetry::span("fetch_remote");
+    let mut tasks = span
+        .instrument(async { spawn_queries(http, rebuilders.clone(), inspect) })
+        .await;
+    // Dropping the stream aborts the remaining queries
+    let answers = stream::poll_fn(move |cx| tasks.poll_join_next(cx)).filter_map(|res| {
+        future::ready(
+            res.inspect_err(|err| warn!("Rebuilder task panicked: {err:#}"))
+                .ok(),
+        )
+    });
+    collect_remote(answers, rebuilders, deadline, done).await
+}
+
+/// Collect rebuilder answers until all rebuilders answered, the deadline is reached or `done`
+/// is satisfied with the attestations received so far
+pub async fn collect_remote<S, F>(
+    answers: S,
+    rebuilders: Vec<Url>,
+    deadline: Option<Instant>,
+    mut done: F,
+) -> Remote
+where
+    S: Stream<Item = Answer>,
+    F: FnMut(&Tree) -> bool,
+{
+    let mut answers = pin!(answers);
+    let mut pending = rebuilders.into_iter().collect::<BTreeSet<_>>();
+
+    let mut remote = Remote::default();
+    let mut outcomes = Vec::new();
+    loop {
+        let res = match deadline {
+            Some(deadline) => match time::timeout_at(deadline.into(), answers.next()).await {
+                Ok(res) => res,
+                Err(_) => {
+                    debug!(
+                        "Deadline reached, {} rebuilders didn't answer",
+                        pending.len()
+                    );
+                    break;
+                }
+            },
+            None => answers.next().await,
+        };
+        let Some((url, latency, result)) = res else {
+            break;
+        };
+        pending.remove(&url);
+        match result {
+            Ok(response) => {
+                remote.answered += 1;
+                let outcome = if response.is_empty() {
+                    stats::Outcome::NotFound
+                } else {
+                    stats::Outcome::Found
+                };
+                outcomes.push((url, outcome, latency));
+                remote.attestations.merge(response);
+                if !pending.is_empty() && done(&remote.attestations) {
+                    debug!(
+                        "Enough attestations received, cancelling {} remaining queries",
+                        pending.len()
+                    );
+                    // The remaining rebuilders were not needed, they are not late
+                    pending.clear();
+                    break;
+                }
+            }
+            Err(err) => {
+                warn!("Failed to fetch remote attestations: {err:#}");
+                remote.unreachable.push(url.clone());
+                outcomes.push((url, stats::Outcome::Error, latency));
+            }
+        }
     }
+    stats::Stats::record_queries(outcomes).await;
 
-    let mut attestations = Tree::default();
+    remote.pending = pending.into_iter().collect();
+    remote
+}
+
+pub async fn fetch_remote_source<I: IntoIterator<Item = Url>>(
+    http: &http::Client,
+    rebuilders: I,
+    name: &str,
+    version: &str,
+) -> BTreeMap<Deb, Tree> {
+    let mut tasks = JoinSet::new();
+
+    for url in rebuilders {
+        let http = http.clone();
+        let (name, version) = (name.to_string(), version.to_string());
+        tasks.spawn(async move {
+            http.fetch_attestations_for_source(&url, &name, &version)
+                .await
+        });
+    }
+
+    let mut artifacts = BTreeMap::<_, Tree>::new();
     while let Some(res) = tasks.join_next().await {
         match res {
-            Ok(Ok(response)) => attestations.merge(response),
+            Ok(Ok(response)) => {
+                for (artifact, tree) in response {
+                    artifacts.entry(artifact).or_default().merge(tree);
+                }
+            }
             Ok(Err(err)) => warn!("Failed to fetch remote attestations: {err:#}"),
             Err(err) => warn!("Rebuilder task panicked: {err:#}"),
         }
     }
 
-    attestations
+    artifacts
 }
 
 pub async fn load_all_attestations<I: IntoIterator<Item = P>, P: AsRef<Path>>(paths: I) -> Tree {
@@ -196,8 +625,22 @@ pub async fn load_all_attestations<I: IntoIterator<Item = P>, P: AsRef<Path>>(pa
 mod tests {
     use super::*;
     use crate::signing;
+    use crate::test_server;
+    use proptest::prelude::*;
     use tokio::fs::File;
 
+    #[test]
+    fn test_parse_sha256() {
+        let hex = data_encoding::HEXLOWER.encode(&test_server::deb_sha256());
+        assert_eq!(parse_sha256(&hex).unwrap(), test_server::deb_sha256());
+        assert_eq!(
+            parse_sha256(&hex.to_uppercase()).unwrap(),
+            test_server::deb_sha256()
+        );
+        assert!(parse_sha256(&hex[..62]).is_err());
+        assert!(parse_sha256("not hex").is_err());
+    }
+
     #[tokio::test]
     async fn test_hash_file() {
         let file = File::open("test_data/filesystem-2025.10.12-1-any.pkg.tar.zst")
@@ -210,6 +653,19 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_hash_path() {
+        let hashed = sha256_path(Path::new(
+            "test_data/filesystem-2025.10.12-1-any.pkg.tar.zst",
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            data_encoding::HEXLOWER.encode(&hashed),
+            "6b6c3fee7432204840d3b6afc9bc1a68c28f591a47fb220071715c40cca956df"
+        );
+    }
+
     #[tokio::test]
     async fn test_verify_attestation_success() {
         let pem_data = include_bytes!("../test_data/reproducible-archlinux.pub");
@@ -265,4 +721,148 @@ mod tests {
         let result = attestation.verify(file, &key).await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_fetch_remote_until_done() {
+        let rebuilder = test_server::spawn_rebuilder().await;
+        // Accepts connections, but never responds
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let slow = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+
+        let remote = fetch_remote_until(
+            &http::client(),
+            [rebuilder, slow],
+            test_server::deb(),
+            None,
+            |attestations| !attestations.is_empty(),
+        )
+        .await;
+        assert!(!remote.attestations.is_empty());
+        assert!(remote.pending.is_empty());
+        assert!(remote.unreachable.is_empty());
+        assert_eq!(remote.answered, 1);
+        assert!(!remote.is_offline());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_remote_offline() {
+        let closed = "http://127.0.0.1:1/".parse::<Url>().unwrap();
+        let remote = fetch_remote(&http::client(), [closed], test_server::deb()).await;
+        assert_eq!(remote.unreachable.len(), 1);
+        assert_eq!(remote.answered, 0);
+        assert!(remote.is_offline());
+    }
+
+    #[test]
+    fn test_json_depth() {
+        assert_eq!(json_depth(b"{}"), 1);
+        assert_eq!(json_depth(br#"{"a": [1, {"b": "[[[["}]}"#), 3);
+        assert_eq!(json_depth(br#"["\"[", []]"#), 2);
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let nested = format!("{}{}", "[".repeat(1000), "]".repeat(1000));
+        let err = Attestation::parse(nested.as_bytes()).err().unwrap();
+        assert!(format!("{err:#}").contains("nested too deep"));
+
+        let big = vec![b' '; MAX_ATTESTATION_SIZE + 1];
+        let err = Attestation::parse(&big).err().unwrap();
+        assert!(format!("{err:#}").contains("too big"));
+
+        let attestation = include_bytes!("../test_data/filesystem-2025.10.12-1-any.in-toto.link");
+        let mut value = serde_json::from_slice::<serde_json::Value>(attestation).unwrap();
+        let signature = value["signatures"][0].clone();
+        value["signatures"] = serde_json::Value::Array(vec![signature; MAX_SIGNATURES + 1]);
+        let err = Attestation::parse(&serde_json::to_vec(&value).unwrap())
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("too many signatures"));
+    }
+
+    #[test]
+    fn test_duplicate_signatures() {
+        let key =
+            signing::pem_to_pubkeys(include_bytes!("../test_data/reproducible-archlinux.pub"))
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+        let attestation = include_bytes!("../test_data/filesystem-2025.10.12-1-any.in-toto.link");
+        let mut value = serde_json::from_slice::<serde_json::Value>(attestation).unwrap();
+        let signatures = value["signatures"].as_array().unwrap().clone();
+        let signatures = signatures.iter().cycle().take(MAX_SIGNATURES).cloned();
+        value["signatures"] = serde_json::Value::Array(signatures.collect());
+        let attestation = Attestation::parse(&serde_json::to_vec(&value).unwrap()).unwrap();
+        assert_eq!(attestation.list_key_ids().len(), 2);
+
+        let mut tree = Tree::default();
+        tree.insert("duplicated".to_string(), attestation);
+        assert_eq!(tree.get(key.key_id()).unwrap().len(), 1);
+        assert_eq!(tree.iter().count(), 1);
+
+        // The duplicates still count as a single confirm
+        let sha256 = data_encoding::HEXLOWER
+            .decode(b"6b6c3fee7432204840d3b6afc9bc1a68c28f591a47fb220071715c40cca956df")
+            .unwrap();
+        assert_eq!(tree.verify(&sha256, [&key]).len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_duplicate_signature() {
+        let key =
+            signing::pem_to_pubkeys(include_bytes!("../test_data/reproducible-archlinux.pub"))
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+        let attestation = include_bytes!("../test_data/filesystem-2025.10.12-1-any.in-toto.link");
+        let invalid =
+            include_bytes!("../test_data/filesystem-2025.10.12-1-any.INVALID.in-toto.link");
+        let valid = serde_json::from_slice::<serde_json::Value>(attestation).unwrap();
+        let mut crafted = serde_json::from_slice::<serde_json::Value>(invalid).unwrap();
+        // Bad signatures first, followed by valid ones for the same keys
+        let signatures = [&crafted, &valid]
+            .iter()
+            .flat_map(|value| value["signatures"].as_array().unwrap().clone())
+            .collect();
+        crafted = valid.clone();
+        crafted["signatures"] = serde_json::Value::Array(signatures);
+
+        let attestation = Attestation::parse(&serde_json::to_vec(&crafted).unwrap()).unwrap();
+        let sha256 = attestation.product_sha256s().remove(0);
+        assert!(attestation.verify_sha256(&sha256, &key).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_parse_arbitrary(bytes in proptest::collection::vec(any::<u8>(), 0..4096)) {
+            let _ = Attestation::parse(&bytes);
+        }
+
+        #[test]
+        fn proptest_parse_mutated(idx in any::<prop::sample::Index>(), byte in any::<u8>()) {
+            let mut bytes = include_bytes!("../test_data/filesystem-2025.10.12-1-any.in-toto.link").to_vec();
+            let idx = idx.index(bytes.len());
+            bytes[idx] = byte;
+            let _ = Attestation::parse(&bytes);
+        }
+
+        #[test]
+        fn proptest_verify_sha256(sha256 in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let key = signing::pem_to_pubkeys(include_bytes!("../test_data/reproducible-archlinux.pub"))
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+            let attestation = include_bytes!("../test_data/filesystem-2025.10.12-1-any.in-toto.link");
+            let attestation = Attestation::parse(attestation).unwrap();
+
+            let expected = attestation.product_sha256s();
+            let verified = attestation.verify_sha256(&sha256, &key).is_ok();
+            prop_assert_eq!(verified, expected.contains(&sha256));
+        }
+    }
 }
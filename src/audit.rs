@@ -0,0 +1,640 @@
+//! A log of the decisions of the transports, shown in the activity view of the TUI
+//!
+//! Each decision is appended as one JSON line to `audit.jsonl` in the state directory. Once the
+//! log grows past `MAX_SIZE` it's rotated to `audit.jsonl.1`, so it doesn't grow forever.
+//!
+//! With a signing key configured, each entry carries the hash of the line before it and a
+//! signature, so entries can't be modified or removed without `plumbing log verify` noticing.
+//! The key is best kept on a PKCS#11 token like the TPM, so it can't be copied off the host.
+//! Processes that can't use the key, like the apt method running as `_apt`, queue their entries
+//! in `audit.spool.jsonl` until a process running as root signs them.
+//!
+//! A monotonic counter in the TPM numbers the signed entries. It can't be reset, so cutting off
+//! the most recent entries or replacing the log is noticed as well.
+use crate::errors::*;
+use crate::inspect::deb::Deb;
+use crate::lock::Lock;
+use crate::sbom;
+use crate::signing::{self, Signer};
+use crate::state;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+const FILENAME: &str = "audit.jsonl";
+const MAX_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Audit {
+    /// Sign each entry and chain it to the previous one, with a private key file or a key on a
+    /// PKCS#11 token (`pkcs11:` URI)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+    /// NV index of a TPM counter that numbers the signed entries, like `0x1500016`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counter: Option<String>,
+}
+
+/// A monotonic counter in the NV memory of the TPM, it can only ever be incremented
+struct Counter<'a>(&'a str);
+
+impl Counter<'_> {
+    async fn tpm2(&self, bin: &str, args: &[&str]) -> Result<Vec<u8>> {
+        let output = Command::new(bin)
+            .args(args)
+            .arg(self.0)
+            .output()
+            .await
+            .with_context(|| format!("Failed to execute {bin:?}"))?;
+        if !output.status.success() {
+            bail!(
+                "{bin} exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.stdout)
+    }
+
+    async fn read(&self) -> Result<u64> {
+        let value = self
+            .tpm2("tpm2_nvread", &["-C", "o", "-s", "8"])
+            .await
+            .with_context(|| format!("Failed to read TPM counter {:?}", self.0))?;
+        let value = <[u8; 8]>::try_from(value.as_slice())
+            .map_err(|_| anyhow!("Unexpected value of TPM counter {:?}: {value:?}", self.0))?;
+        Ok(u64::from_be_bytes(value))
+    }
+
+    async fn increment(&self) -> Result<u64> {
+        self.tpm2("tpm2_nvincrement", &["-C", "o"])
+            .await
+            .with_context(|| format!("Failed to increment TPM counter {:?}", self.0))?;
+        self.read().await
+    }
+}
+
+impl Audit {
+    /// The current value of the counter, the number of the most recent signed entry
+    pub async fn head(&self) -> Result<Option<u64>> {
+        match &self.counter {
+            Some(index) => Counter(index).read().await.map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Reproduced,
+    BlindlyTrusted,
+    GracePeriod,
+    Rejected,
+}
+
+impl From<sbom::Status> for Outcome {
+    fn from(status: sbom::Status) -> Self {
+        match status {
+            sbom::Status::Reproduced => Outcome::Reproduced,
+            sbom::Status::BlindlyTrusted => Outcome::BlindlyTrusted,
+            sbom::Status::GracePeriod => Outcome::GracePeriod,
+        }
+    }
+}
+
+/// One decision of a transport
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub at: DateTime<Utc>,
+    /// The download url, or the path of the package file
+    pub url: String,
+    pub outcome: Outcome,
+    /// Unknown if the package was rejected before it was parsed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<Deb>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// The hosts of the rebuilders that confirmed the package
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rebuilders: Vec<String>,
+    /// Why the package was rejected, like `ThresholdNotMet`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// The value of the TPM counter for this entry, set in signed logs with a counter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// The sha256 of the line before this one, set in signed logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    /// Signature over the entry without this field, set in signed logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl Record {
+    pub fn new(url: &str, outcome: Outcome) -> Self {
+        Record {
+            at: Utc::now(),
+            url: url.to_string(),
+            outcome,
+            package: None,
+            sha256: None,
+            rebuilders: vec![],
+            reason: None,
+            message: None,
+            seq: None,
+            prev: None,
+            signature: None,
+        }
+    }
+
+    pub fn admitted(component: &sbom::Component) -> Self {
+        Record {
+            package: Some(component.package.clone()),
+            sha256: Some(component.sha256.clone()),
+            rebuilders: component.rebuilders.clone(),
+            ..Record::new(component.url.as_str(), component.status.into())
+        }
+    }
+
+    pub fn rejected(url: &str, reason: Option<&str>, message: String) -> Self {
+        Record {
+            reason: reason.map(String::from),
+            message: Some(message),
+            ..Record::new(url, Outcome::Rejected)
+        }
+    }
+
+    /// The bytes covered by the signature
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        let record = Record {
+            signature: None,
+            ..self.clone()
+        };
+        Ok(serde_json::to_vec(&record)?)
+    }
+
+    /// Number the record, chain it to the line before it and sign it
+    async fn sign(&self, seq: Option<u64>, prev: Option<&str>, key: &Signer) -> Result<Self> {
+        let mut record = Record {
+            seq,
+            prev: prev.map(hash_line),
+            ..self.clone()
+        };
+        let signature = key.sign(&record.signed_bytes()?).await?;
+        record.signature = Some(data_encoding::BASE64.encode(&signature));
+        Ok(record)
+    }
+
+    /// The package if it's known, the url otherwise
+    pub fn subject(&self) -> String {
+        match &self.package {
+            Some(package) => format!(
+                "{} {} ({})",
+                package.name, package.version, package.architecture
+            ),
+            None => self.url.clone(),
+        }
+    }
+}
+
+pub fn path() -> PathBuf {
+    state::state_dir().join(FILENAME)
+}
+
+/// Where the log is moved once it's too big
+pub fn rotated(path: &Path) -> PathBuf {
+    path.with_extension("jsonl.1")
+}
+
+/// Where entries wait until they can be signed
+fn spool(path: &Path) -> PathBuf {
+    path.with_extension("spool.jsonl")
+}
+
+fn hash_line(line: &str) -> String {
+    data_encoding::HEXLOWER.encode(&Sha256::digest(line.as_bytes()))
+}
+
+async fn read_optional(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path).await {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(Error::from(err).context(format!("Failed to read audit log: {path:?}"))),
+    }
+}
+
+/// The most recent line, from the rotated log if the log was just rotated
+async fn last_line(path: &Path) -> Result<Option<String>> {
+    for path in [path.to_path_buf(), rotated(path)] {
+        if let Some(line) = read_optional(&path)
+            .await?
+            .and_then(|text| text.lines().next_back().map(String::from))
+        {
+            return Ok(Some(line));
+        }
+    }
+    Ok(None)
+}
+
+/// Append a line, without following a symlink placed in the state directory
+async fn append_line(path: &Path, record: &Record) -> Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open audit log: {path:?}"))?;
+    // A single write, so concurrent transports don't interleave their lines
+    file.write_all(&line).await?;
+    Ok(())
+}
+
+async fn rotate(path: &Path) -> Result<()> {
+    if fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.len() >= MAX_SIZE)
+    {
+        fs::rename(path, rotated(path))
+            .await
+            .with_context(|| format!("Failed to rotate audit log: {path:?}"))?;
+    }
+    Ok(())
+}
+
+async fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    Ok(())
+}
+
+async fn write(path: &Path, record: &Record) -> Result<()> {
+    create_parent(path).await?;
+    rotate(path).await?;
+    append_line(path, record).await
+}
+
+/// Keep a record until it can be signed
+async fn queue(path: &Path, record: &Record) -> Result<()> {
+    create_parent(path).await?;
+    let _lock = Lock::acquire(FILENAME).await?;
+    append_line(&spool(path), record).await
+}
+
+/// Sign the queued records and `record`, in the order they were created
+async fn write_signed(
+    path: &Path,
+    key: &Signer,
+    counter: Option<&str>,
+    record: Option<&Record>,
+) -> Result<()> {
+    create_parent(path).await?;
+    // Signed entries depend on the line before them, so writers must not race
+    let _lock = Lock::acquire(FILENAME).await?;
+
+    let spool = spool(path);
+    let mut records = read_optional(&spool)
+        .await?
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            serde_json::from_str::<Record>(line)
+                .inspect_err(|err| warn!("Dropping invalid queued audit log entry: {err:#}"))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+    let queued = !records.is_empty();
+    records.extend(record.cloned());
+
+    for record in records {
+        rotate(path).await?;
+        let prev = last_line(path).await?;
+        let seq = match counter {
+            Some(index) => Some(Counter(index).increment().await?),
+            None => None,
+        };
+        let record = record.sign(seq, prev.as_deref(), key).await?;
+        append_line(path, &record).await?;
+    }
+    if queued {
+        fs::remove_file(&spool)
+            .await
+            .with_context(|| format!("Failed to remove signed entries from {spool:?}"))?;
+    }
+    Ok(())
+}
+
+/// Record a decision, failures are logged but not fatal
+///
+/// If the signing key is configured but can't be used, the entry is queued for `seal`.
+pub async fn append(audit: &Audit, record: &Record) {
+    let path = path();
+    let result = match &audit.signing_key {
+        Some(key) => match Signer::load(key).await {
+            Ok(key) => write_signed(&path, &key, audit.counter.as_deref(), Some(record)).await,
+            Err(err) => {
+                debug!("Can't use the audit log signing key, queueing entry: {err:#}");
+                queue(&path, record).await
+            }
+        },
+        None => write(&path, record).await,
+    };
+    if let Err(err) = result {
+        warn!("Failed to write audit log: {err:#}");
+    }
+}
+
+/// Sign the entries queued by processes without access to the signing key
+pub async fn seal(audit: &Audit) -> Result<()> {
+    let Some(key) = &audit.signing_key else {
+        return Ok(());
+    };
+    let key = Signer::load(key)
+        .await
+        .context("Failed to load audit log signing key")?;
+    write_signed(&path(), &key, audit.counter.as_deref(), None).await
+}
+
+/// The log of this host including its rotated part, oldest entry first
+///
+/// Fails if the rotated part was removed while the log still chains to it.
+pub async fn read(path: &Path) -> Result<String> {
+    let rotated = rotated(path);
+    let old = read_optional(&rotated).await?;
+    let text = read_optional(path).await?.unwrap_or_default();
+    if old.is_none()
+        && let Some(line) = text.lines().next()
+        && serde_json::from_str::<Record>(line).is_ok_and(|record| record.prev.is_some())
+    {
+        bail!("The rotated audit log {rotated:?} is missing, but the log continues it");
+    }
+    Ok(old.unwrap_or_default() + &text)
+}
+
+/// How many entries of a log were checked
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Entries written before signing was enabled
+    pub unsigned: usize,
+    pub signed: usize,
+}
+
+/// Check the hash chain and the signatures of a log, oldest entry first
+///
+/// The first entry may point to a line that was rotated away, so it's not checked against its
+/// predecessor. A log without any signed entry is refused, it may have been replaced. With
+/// `head`, the value of the TPM counter, the numbered entries have to be complete up to it.
+pub fn verify_chain(text: &str, public_key_pem: &str, head: Option<u64>) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let mut prev = None;
+    let mut last_seq = None;
+    for (idx, line) in text.lines().enumerate() {
+        let number = idx + 1;
+        let record = serde_json::from_str::<Record>(line)
+            .with_context(|| format!("Invalid audit log entry in line {number}"))?;
+        let Some(signature) = &record.signature else {
+            if summary.signed > 0 {
+                bail!("Line {number} is not signed, but follows signed entries");
+            }
+            summary.unsigned += 1;
+            prev = Some(line);
+            continue;
+        };
+        if let Some(prev) = prev
+            && record.prev.as_deref() != Some(hash_line(prev).as_str())
+        {
+            bail!(
+                "Line {number} does not chain to the line before it, entries were modified or removed"
+            );
+        }
+        let signature = data_encoding::BASE64
+            .decode(signature.as_bytes())
+            .with_context(|| format!("Invalid signature encoding in line {number}"))?;
+        signing::verify_local_signature(public_key_pem, &record.signed_bytes()?, &signature)
+            .with_context(|| format!("Invalid signature in line {number}"))?;
+        match (last_seq, record.seq) {
+            (Some(last), Some(seq)) if seq != last + 1 => {
+                bail!("Line {number} is entry {seq}, but follows entry {last}");
+            }
+            (Some(_), None) => bail!("Line {number} is not numbered, but follows numbered entries"),
+            _ => (),
+        }
+        last_seq = record.seq;
+        summary.signed += 1;
+        prev = Some(line);
+    }
+    if summary.signed == 0 {
+        bail!("The audit log has no signed entries");
+    }
+    if let Some(head) = head
+        && last_seq != Some(head)
+    {
+        bail!(
+            "The audit log ends with entry {}, but the TPM counter is at {head}, recent entries were removed",
+            last_seq.unwrap_or_default()
+        );
+    }
+    Ok(summary)
+}
+
+/// The last `count` records, most recent first
+fn parse_tail(text: &str, count: usize) -> Vec<Record> {
+    text.lines()
+        .rev()
+        .filter_map(|line| {
+            serde_json::from_str(line)
+                .inspect_err(|err| debug!("Ignoring invalid audit log entry: {err:#}"))
+                .ok()
+        })
+        .take(count)
+        .collect()
+}
+
+/// The last `count` records, most recent first
+pub async fn tail(path: &Path, count: usize) -> Result<Vec<Record>> {
+    match fs::read_to_string(path).await {
+        Ok(text) => Ok(parse_tail(&text, count)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(Error::from(err).context(format!("Failed to read audit log: {path:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::LocalKey;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn test_append_tail() {
+        let path = state::state_dir().join("test-audit.jsonl");
+        let _ = fs::remove_file(&path).await;
+        assert_eq!(tail(&path, 10).await.unwrap(), vec![]);
+
+        let component = sbom::Component {
+            package: test_server::deb(),
+            url: "https://deb.debian.org/debian/pool/test.deb"
+                .parse()
+                .unwrap(),
+            sha256: "00".repeat(32),
+            status: sbom::Status::Reproduced,
+            rebuilders: vec!["rebuilder.example.com".to_string()],
+        };
+        let admitted = Record::admitted(&component);
+        let rejected = Record::rejected(
+            "https://deb.debian.org/debian/pool/other.deb",
+            Some("ThresholdNotMet"),
+            "Threshold not met: only 0/1 required signatures".to_string(),
+        );
+        write(&path, &admitted).await.unwrap();
+        write(&path, &rejected).await.unwrap();
+
+        let records = tail(&path, 10).await.unwrap();
+        assert_eq!(records, [rejected.clone(), admitted.clone()]);
+        assert_eq!(tail(&path, 1).await.unwrap(), [records[0].clone()]);
+        assert_eq!(records[0].subject(), rejected.url);
+        assert_eq!(records[1].outcome, Outcome::Reproduced);
+        fs::remove_file(&path).await.unwrap();
+    }
+
+    const PRIVATE_KEY: &[u8] = &[
+        0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04,
+        0x20, 0x9d, 0x61, 0xb1, 0x9d, 0xef, 0xfd, 0x5a, 0x60, 0xba, 0x84, 0x4a, 0xf4, 0x92, 0xec,
+        0x2c, 0xc4, 0x44, 0x49, 0xc5, 0x69, 0x7b, 0x32, 0x69, 0x19, 0x70, 0x3b, 0xac, 0x03, 0x1c,
+        0xae, 0x7f, 0x60,
+    ];
+
+    #[tokio::test]
+    async fn test_verify_chain() {
+        let path = state::state_dir().join("test-audit-signed.jsonl");
+        let _ = fs::remove_file(&path).await;
+        let key = Signer::Local(LocalKey::from_pkcs8(PRIVATE_KEY).unwrap());
+        let public_key = key.public_key_pem();
+
+        let record = |url: &str| Record::new(url, Outcome::Reproduced);
+        write(&path, &record("/tmp/unsigned.deb")).await.unwrap();
+        assert!(verify_chain(&read(&path).await.unwrap(), &public_key, None).is_err());
+
+        // Queued entries are signed before the new one
+        queue(&path, &record("/tmp/a.deb")).await.unwrap();
+        for url in ["/tmp/b.deb", "/tmp/c.deb"] {
+            write_signed(&path, &key, None, Some(&record(url)))
+                .await
+                .unwrap();
+        }
+        assert!(!fs::try_exists(spool(&path)).await.unwrap());
+        let text = read(&path).await.unwrap();
+        fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(
+            verify_chain(&text, &public_key, None).unwrap(),
+            Summary {
+                unsigned: 1,
+                signed: 3
+            }
+        );
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines[1].contains("/tmp/a.deb"));
+        // The tail of a rotated log is still checked
+        assert!(verify_chain(&lines[2..].join("\n"), &public_key, None).is_ok());
+
+        let modified = text.replace("/tmp/b.deb", "/tmp/evil.deb");
+        let err = verify_chain(&modified, &public_key, None).unwrap_err();
+        assert!(format!("{err:#}").contains("Invalid signature in line 3"));
+
+        let removed = [lines[0], lines[1], lines[3]].join("\n");
+        let err = verify_chain(&removed, &public_key, None).unwrap_err();
+        assert!(format!("{err:#}").contains("Line 3 does not chain"));
+
+        let unsigned = format!("{text}{}\n", lines[0]);
+        let err = verify_chain(&unsigned, &public_key, None).unwrap_err();
+        assert!(format!("{err:#}").contains("Line 5 is not signed"));
+
+        // A log replaced with unsigned entries doesn't pass
+        let err = verify_chain(lines[0], &public_key, None).unwrap_err();
+        assert!(format!("{err:#}").contains("no signed entries"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_counter() {
+        let key = Signer::Local(LocalKey::from_pkcs8(PRIVATE_KEY).unwrap());
+        let public_key = key.public_key_pem();
+
+        let mut lines = Vec::<String>::new();
+        for seq in 1..=3 {
+            let record = Record::new("/tmp/a.deb", Outcome::Reproduced)
+                .sign(Some(seq), lines.last().map(String::as_str), &key)
+                .await
+                .unwrap();
+            lines.push(serde_json::to_string(&record).unwrap());
+        }
+        let text = lines.join("\n");
+        assert!(verify_chain(&text, &public_key, Some(3)).is_ok());
+
+        // The most recent entry was cut off
+        let err = verify_chain(&lines[..2].join("\n"), &public_key, Some(3)).unwrap_err();
+        assert!(format!("{err:#}").contains("recent entries were removed"));
+        // The counter was incremented without writing an entry
+        assert!(verify_chain(&text, &public_key, Some(4)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_rotated_removed() {
+        let path = state::state_dir().join("test-audit-rotated.jsonl");
+        let key = Signer::Local(LocalKey::from_pkcs8(PRIVATE_KEY).unwrap());
+        let record = Record::new("/tmp/a.deb", Outcome::Reproduced);
+        write_signed(&path, &key, None, Some(&record))
+            .await
+            .unwrap();
+        fs::rename(&path, rotated(&path)).await.unwrap();
+        write_signed(&path, &key, None, Some(&record))
+            .await
+            .unwrap();
+        assert_eq!(read(&path).await.unwrap().lines().count(), 2);
+
+        fs::remove_file(rotated(&path)).await.unwrap();
+        let err = read(&path).await.unwrap_err();
+        fs::remove_file(&path).await.unwrap();
+        assert!(format!("{err:#}").contains("is missing"));
+    }
+
+    #[test]
+    fn test_config() {
+        let audit = toml::from_str::<Audit>(
+            r#"
+signing_key = "pkcs11:token=audit;object=audit?module-path=/usr/lib/x86_64-linux-gnu/libtpm2_pkcs11.so.1"
+counter = "0x1500016"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            audit,
+            Audit {
+                signing_key: Some(
+                    "pkcs11:token=audit;object=audit?module-path=/usr/lib/x86_64-linux-gnu/libtpm2_pkcs11.so.1"
+                        .to_string()
+                ),
+                counter: Some("0x1500016".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_tail_skips_invalid() {
+        let text = "not json\n{\"at\":\"2026-01-01T00:00:00Z\",\"url\":\"/var/cache/pacman/pkg/foo-1-1-x86_64.pkg.tar.zst\",\"outcome\":\"blindly-trusted\"}\n{truncated";
+        let records = parse_tail(text, 10);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].outcome, Outcome::BlindlyTrusted);
+    }
+}
@@ -0,0 +1,107 @@
+//! Copy text to the system clipboard, for cross-checking fingerprints with rebuilder operators
+//!
+//! The clipboard tools of the desktop session are used if they're available, otherwise the text
+//! is sent to the terminal as OSC 52 escape sequence, which also works over ssh.
+use crate::errors::*;
+use std::env;
+use std::io::{self, Write};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// How the text ended up in the clipboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// A clipboard tool of the desktop session
+    Tool,
+    /// An escape sequence, if the terminal supports it
+    Osc52,
+}
+
+/// Clipboard tools to try, depending on the display server of the session
+fn tools(wayland: bool, x11: bool) -> Vec<(&'static str, &'static [&'static str])> {
+    let mut tools = Vec::new();
+    if wayland {
+        tools.push(("wl-copy", &[][..]));
+    }
+    if x11 {
+        tools.push(("xclip", &["-selection", "clipboard"][..]));
+        tools.push(("xsel", &["--clipboard", "--input"][..]));
+    }
+    tools
+}
+
+/// The escape sequence that asks the terminal to set its clipboard
+pub fn osc52(text: &str) -> String {
+    format!(
+        "\x1b]52;c;{}\x07",
+        data_encoding::BASE64.encode(text.as_bytes())
+    )
+}
+
+async fn run_tool(bin: &str, args: &[&str], text: &str) -> Result<()> {
+    let mut child = Command::new(bin)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to execute {bin:?}"))?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .with_context(|| format!("Failed to open stdin of {bin:?}"))?;
+    stdin.write_all(text.as_bytes()).await?;
+    drop(stdin);
+
+    let status = child.wait().await?;
+    if !status.success() {
+        bail!("{bin} exited with {status}");
+    }
+    Ok(())
+}
+
+pub async fn copy(text: &str) -> Result<Method> {
+    let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+    let x11 = env::var_os("DISPLAY").is_some();
+    for (bin, args) in tools(wayland, x11) {
+        match run_tool(bin, args, text).await {
+            Ok(()) => {
+                debug!("Copied to clipboard with {bin}");
+                return Ok(Method::Tool);
+            }
+            Err(err) => debug!("Failed to copy to clipboard with {bin}: {err:#}"),
+        }
+    }
+
+    let mut stdout = io::stdout();
+    stdout.write_all(osc52(text).as_bytes())?;
+    stdout.flush()?;
+    Ok(Method::Osc52)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52() {
+        assert_eq!(
+            osc52("https://rebuilder.example.com/"),
+            "\x1b]52;c;aHR0cHM6Ly9yZWJ1aWxkZXIuZXhhbXBsZS5jb20v\x07"
+        );
+    }
+
+    #[test]
+    fn test_tools() {
+        assert!(tools(false, false).is_empty());
+        let names = |wayland, x11| {
+            tools(wayland, x11)
+                .into_iter()
+                .map(|(bin, _)| bin)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(true, false), ["wl-copy"]);
+        assert_eq!(names(true, true), ["wl-copy", "xclip", "xsel"]);
+    }
+}
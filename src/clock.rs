@@ -0,0 +1,112 @@
+//! Sanity checks of the system clock for time based policy decisions
+//!
+//! Key expiry, grace period deadlines and cache freshness depend on the wall clock. A clock that
+//! was never set (a dead RTC battery, an embedded board without network time) would silently
+//! expire or revive keys, so implausible clocks are flagged and these decisions are refused.
+//! Waiting for rebuilders uses the monotonic clock, which isn't affected by clock changes.
+use chrono::{DateTime, TimeDelta, Utc};
+use std::fmt;
+use std::time::Instant;
+
+/// 2026-01-01, a clock before this was never set
+const PLAUSIBLE_SINCE: i64 = 1_767_225_600;
+/// 2100-01-01
+const PLAUSIBLE_UNTIL: i64 = 4_102_444_800;
+
+/// The system clock can't be right
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    NotSet(DateTime<Utc>),
+    FarFuture(DateTime<Utc>),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NotSet(now) => write!(f, "system clock reads {now}, it was never set"),
+            Problem::FarFuture(now) => {
+                write!(f, "system clock reads {now}, too far in the future")
+            }
+        }
+    }
+}
+
+pub fn check(now: DateTime<Utc>) -> Result<DateTime<Utc>, Problem> {
+    match now.timestamp() {
+        ts if ts < PLAUSIBLE_SINCE => Err(Problem::NotSet(now)),
+        ts if ts >= PLAUSIBLE_UNTIL => Err(Problem::FarFuture(now)),
+        _ => Ok(now),
+    }
+}
+
+/// The current time, if the system clock is plausible
+pub fn now() -> Result<DateTime<Utc>, Problem> {
+    check(Utc::now())
+}
+
+/// The wall clock and the monotonic clock, read at the same time
+#[derive(Debug, Clone, Copy)]
+pub struct Reading {
+    wall: DateTime<Utc>,
+    monotonic: Instant,
+}
+
+impl Reading {
+    pub fn now() -> Self {
+        Reading {
+            wall: Utc::now(),
+            monotonic: Instant::now(),
+        }
+    }
+
+    /// How far the wall clock was changed between the readings, if it's more than `tolerance`
+    pub fn jump(&self, later: &Reading, tolerance: TimeDelta) -> Option<TimeDelta> {
+        let monotonic = later.monotonic.saturating_duration_since(self.monotonic);
+        let monotonic = TimeDelta::from_std(monotonic).unwrap_or(TimeDelta::MAX);
+        let jump = (later.wall - self.wall) - monotonic;
+        (jump.abs() > tolerance).then_some(jump)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_check() {
+        let epoch = DateTime::UNIX_EPOCH;
+        assert_eq!(check(epoch), Err(Problem::NotSet(epoch)));
+        let now = DateTime::from_timestamp(1_790_000_000, 0).unwrap();
+        assert_eq!(check(now), Ok(now));
+        let future = DateTime::from_timestamp(PLAUSIBLE_UNTIL, 0).unwrap();
+        assert_eq!(check(future), Err(Problem::FarFuture(future)));
+        assert!(super::now().is_ok());
+    }
+
+    #[test]
+    fn test_jump() {
+        let start = Reading::now();
+        let tolerance = TimeDelta::minutes(5);
+        let later = Reading {
+            wall: start.wall + TimeDelta::minutes(10),
+            monotonic: start.monotonic + Duration::from_secs(600),
+        };
+        assert_eq!(start.jump(&later, tolerance), None);
+
+        let later = Reading {
+            wall: start.wall + TimeDelta::days(1),
+            monotonic: start.monotonic + Duration::from_secs(600),
+        };
+        assert_eq!(
+            start.jump(&later, tolerance),
+            Some(TimeDelta::days(1) - TimeDelta::minutes(10))
+        );
+
+        let later = Reading {
+            wall: start.wall - TimeDelta::hours(1),
+            monotonic: start.monotonic + Duration::from_secs(60),
+        };
+        assert_eq!(start.jump(&later, tolerance), Some(-TimeDelta::minutes(61)));
+    }
+}
@@ -1,22 +1,562 @@
 use crate::{
+    audit::Audit,
+    credentials::Credential,
     errors::*,
-    rebuilder::{Rebuilder, Selectable},
+    inspect::deb::Deb,
+    layout::Layout,
+    lock::Lock,
+    notify::Notify,
+    psl,
+    rebuilder::{self, ApiTemplate, Rebuilder, Selectable},
+    sbom::Sbom,
+    telemetry::Telemetry,
 };
+use chrono::{DateTime, TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs::Permissions;
+use std::io::Write;
+use std::net::IpAddr;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use tokio::{fs, io};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{fs, io, task};
+use url::Url;
 
 const PATH: &str = "/etc/repro-threshold.conf";
+/// Name of the lock that serializes writers of the config file
+const LOCK: &str = "config";
+const DEFAULT_REQUIRED_THRESHOLD: usize = 1;
+const DEFAULT_NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_CACHE_QUOTA_MIB: u64 = 512;
+const DEFAULT_MIN_FREE_MIB: u64 = 64;
+const DEFAULT_SANDBOX_USER: &str = "nobody";
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Rules {
-    /// Number of rebuilder attestations required until we believe them
+    /// Set to `disabled` to admit all packages without checking attestations
+    #[serde(default, skip_serializing_if = "Enforcement::is_enforce")]
+    pub enforcement: Enforcement,
+    /// Number of rebuilder attestations required until we believe them, at least 1 unless enforcement is disabled
     #[serde(default)]
     pub required_threshold: usize,
     /// Blindly allow these packages, even if nobody could reproduce the binary
+    ///
+    /// Entries are either exact package names, glob patterns (`linux-image-*`) or regular
+    /// expressions prefixed with `regex:`
     #[serde(default)]
     pub blindly_trust: BTreeSet<String>,
+    /// Blindly allow all binary packages built from these source packages (same syntax as `blindly_trust`)
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub blindly_trust_source: BTreeSet<String>,
+    /// Admit packages below the threshold, as long as attestations show up within this many days (0 disables this)
+    ///
+    /// Packages admitted this way are tracked and re-checked by `repro-threshold cron`.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub grace_period_days: u32,
+    /// Stop waiting for rebuilders after this long (e.g. `30s`) and decide with the responses received
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_verification_time: Option<Timespan>,
+    /// How far the system clock may be off before key expiry and grace period deadlines apply (default: `5m`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_clock_skew: Option<Timespan>,
+    /// What to do if the threshold wasn't reached before `max_verification_time`
+    #[serde(default)]
+    pub partial_results: PartialResults,
+    /// What to do if none of the trusted rebuilders can be reached, e.g. offline or behind a captive portal
+    #[serde(default, skip_serializing_if = "Offline::is_reject")]
+    pub offline: Offline,
+    /// Admit installer components (.udeb) without reproduction, instead of verifying them like .debs
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_udeb: bool,
+    /// Overrides for specific architectures, `all` also covers `any`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub architecture: BTreeMap<String, ArchRules>,
+    /// Verify packages against this in-toto layout instead of counting signatures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<Layout>,
+    /// Set to `registrable-domain` so all rebuilders of `example.org` count as one vote, not one per host
+    #[serde(default, skip_serializing_if = "VoteGrouping::is_host")]
+    pub group_votes_by: VoteGrouping,
+    /// Only count votes of rebuilders whose signing key was verified out-of-band (`plumbing mark-verified`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_out_of_band_verified: bool,
+    /// At least this many confirming votes have to come from rebuilders verified out-of-band
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub min_out_of_band_verified: u32,
+    /// The public suffix list used to find registrable domains (default: the distribution's copy)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_suffix_list: Option<PathBuf>,
+    /// Loaded from `public_suffix_list` when the config is loaded
+    #[serde(skip)]
+    pub public_suffixes: Option<Arc<psl::List>>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ArchRules {
+    /// Number of rebuilder attestations required for packages of this architecture
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_threshold: Option<usize>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Whether the rules are applied at all
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Enforcement {
+    /// Refuse packages that don't satisfy the rules
+    #[default]
+    Enforce,
+    /// Admit all packages, like a threshold of 0 did in older versions
+    Disabled,
+}
+
+impl Enforcement {
+    fn is_enforce(&self) -> bool {
+        *self == Enforcement::Enforce
+    }
+}
+
+/// Which rebuilders share a vote
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VoteGrouping {
+    /// Rebuilders on the same host
+    #[default]
+    Host,
+    /// Rebuilders under the same registrable domain, like `amd64.example.org` and `arm64.example.org`
+    RegistrableDomain,
+}
+
+impl VoteGrouping {
+    fn is_host(&self) -> bool {
+        *self == VoteGrouping::Host
+    }
+}
+
+/// How to decide if some rebuilders didn't answer in time, and the others didn't reach the threshold
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PartialResults {
+    /// Reject the package, like any other package below the threshold
+    #[default]
+    Reject,
+    /// Admit the package and track it like packages admitted under the grace period
+    GracePeriod,
+}
+
+/// How to decide if none of the trusted rebuilders could be reached at all
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Offline {
+    /// Reject the package, like any other package below the threshold
+    #[default]
+    Reject,
+    /// Refuse the package, and record it so `repro-threshold cron` settles it once the rebuilders are back
+    Defer,
+    /// Admit the package with a warning, `repro-threshold cron` has to verify it later
+    WarnOnly,
+}
+
+impl Offline {
+    fn is_reject(&self) -> bool {
+        *self == Offline::Reject
+    }
+}
+
+/// A duration like `30s`, `5m`, `1h` or `500ms`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timespan(pub Duration);
+
+impl FromStr for Timespan {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let split = s
+            .find(|c: char| !c.is_ascii_digit())
+            .with_context(|| format!("Missing unit in duration: {s:?}"))?;
+        let (num, unit) = s.split_at(split);
+        let num = num
+            .parse::<u64>()
+            .with_context(|| format!("Invalid duration: {s:?}"))?;
+        let duration = match unit {
+            "ms" => Duration::from_millis(num),
+            "s" => Duration::from_secs(num),
+            "m" => Duration::from_secs(num * 60),
+            "h" => Duration::from_secs(num * 60 * 60),
+            _ => bail!("Unknown unit in duration (expected ms, s, m or h): {s:?}"),
+        };
+        Ok(Timespan(duration))
+    }
+}
+
+impl fmt::Display for Timespan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.0.as_millis();
+        if !millis.is_multiple_of(1000) {
+            write!(f, "{millis}ms")
+        } else {
+            write!(f, "{}s", millis / 1000)
+        }
+    }
+}
+
+impl Serialize for Timespan {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timespan {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Architecture independent packages are called `all` in Debian and `any` in Arch Linux
+const ARCH_INDEPENDENT: &str = "all";
+
+pub fn normalize_arch(arch: &str) -> &str {
+    match arch {
+        "any" => ARCH_INDEPENDENT,
+        arch => arch,
+    }
+}
+
+fn any_matches(patterns: &BTreeSet<String>, name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match PackagePattern::parse(pattern) {
+            Ok(pattern) => pattern.matches(name),
+            Err(err) => {
+                warn!("Ignoring invalid blindly-trust pattern {pattern:?}: {err:#}");
+                false
+            }
+        })
+}
+
+impl Rules {
+    pub fn clock_skew(&self) -> TimeDelta {
+        let skew = self
+            .max_clock_skew
+            .map(|skew| skew.0)
+            .unwrap_or(DEFAULT_MAX_CLOCK_SKEW);
+        TimeDelta::from_std(skew).unwrap_or(TimeDelta::MAX)
+    }
+
+    pub fn is_blindly_trusted(&self, pkg: &str) -> bool {
+        any_matches(&self.blindly_trust, pkg)
+    }
+
+    pub fn is_source_blindly_trusted(&self, source: &str) -> bool {
+        any_matches(&self.blindly_trust_source, source)
+    }
+
+    /// The threshold configured for this specific architecture, if any
+    pub fn architecture_threshold(&self, arch: &str) -> Option<usize> {
+        self.architecture
+            .get(normalize_arch(arch))
+            .and_then(|rules| rules.required_threshold)
+    }
+
+    pub fn required_threshold_for(&self, arch: &str) -> usize {
+        self.architecture_threshold(arch)
+            .unwrap_or(self.required_threshold)
+    }
+
+    /// Check both the binary package name and the source package it was built from
+    pub fn blindly_trusts(&self, deb: &Deb) -> bool {
+        self.is_blindly_trusted(&deb.name) || self.is_source_blindly_trusted(&deb.source)
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.enforcement == Enforcement::Disabled
+    }
+
+    /// Set the threshold, 0 disables enforcement and anything above enables it
+    pub fn set_required_threshold(&mut self, threshold: usize) {
+        self.required_threshold = threshold;
+        self.enforcement = if threshold == 0 {
+            Enforcement::Disabled
+        } else {
+            Enforcement::Enforce
+        };
+    }
+
+    /// Fill in defaults that depend on which settings are present in the config file
+    ///
+    /// Only a literal `required_threshold = 0` is treated as the old way to disable enforcement,
+    /// a missing threshold defaults to 1.
+    fn migrate(&mut self, explicit_enforcement: bool, explicit_threshold: bool) {
+        if !explicit_threshold && self.required_threshold == 0 {
+            self.required_threshold = DEFAULT_REQUIRED_THRESHOLD;
+        }
+        if !explicit_enforcement && self.required_threshold == 0 {
+            warn!(
+                "required_threshold = 0 disables verification, treating it as enforcement = \"disabled\" (raise the threshold or set this explicitly)"
+            );
+            self.enforcement = Enforcement::Disabled;
+        }
+    }
+
+    async fn load_public_suffixes(&mut self) -> Result<()> {
+        if self.group_votes_by == VoteGrouping::RegistrableDomain {
+            let path = self
+                .public_suffix_list
+                .as_deref()
+                .unwrap_or(Path::new(psl::PUBLIC_SUFFIX_LIST));
+            let list = psl::List::load(path).await.with_context(|| {
+                format!(
+                    "Grouping votes by registrable domain needs the public suffix list: {path:?}"
+                )
+            })?;
+            self.public_suffixes = Some(Arc::new(list));
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.enforcement == Enforcement::Enforce {
+            if self.required_threshold == 0 {
+                bail!(
+                    "required_threshold = 0 would admit every package, raise it or set enforcement = \"disabled\""
+                );
+            }
+            if let Some(arch) = self
+                .architecture
+                .iter()
+                .find_map(|(arch, rules)| (rules.required_threshold == Some(0)).then_some(arch))
+            {
+                bail!(
+                    "required_threshold = 0 for architecture {arch:?} would admit every package, raise it or set enforcement = \"disabled\""
+                );
+            }
+        }
+        for pattern in self.blindly_trust.iter().chain(&self.blindly_trust_source) {
+            PackagePattern::parse(pattern)
+                .with_context(|| format!("Invalid blindly-trust pattern: {pattern:?}"))?;
+        }
+        Ok(())
+    }
+}
+
+const REGEX_PREFIX: &str = "regex:";
+
+#[derive(Debug)]
+pub enum PackagePattern<'a> {
+    Exact(&'a str),
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+impl<'a> PackagePattern<'a> {
+    pub fn parse(pattern: &'a str) -> Result<Self> {
+        if let Some(regex) = pattern.strip_prefix(REGEX_PREFIX) {
+            // Always match the full package name
+            let regex = regex::Regex::new(&format!("^(?:{regex})$"))?;
+            Ok(PackagePattern::Regex(regex))
+        } else if pattern.contains(['*', '?', '[']) {
+            Ok(PackagePattern::Glob(glob::Pattern::new(pattern)?))
+        } else {
+            Ok(PackagePattern::Exact(pattern))
+        }
+    }
+
+    pub fn matches(&self, pkg: &str) -> bool {
+        match self {
+            PackagePattern::Exact(name) => *name == pkg,
+            PackagePattern::Glob(glob) => glob.matches(pkg),
+            PackagePattern::Regex(regex) => regex.is_match(pkg),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Privacy {
+    /// Send this User-Agent instead of the default one, an empty string sends none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Query rebuilders in random order
+    #[serde(default)]
+    pub shuffle_rebuilders: bool,
+    /// Wait a random delay of up to this many milliseconds before querying each rebuilder
+    #[serde(default)]
+    pub max_query_delay_ms: u64,
+    /// Add a random-length padding header to rebuilder requests, so their length doesn't reveal the package name
+    #[serde(default)]
+    pub pad_requests: bool,
+    /// Send rebuilder queries through this relay (an HTTP proxy), so rebuilders can't link them to this host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay: Option<Url>,
+    /// Rebuilders that should always be queried directly, even if a relay is configured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relay_exclude: Vec<Url>,
+}
+
+/// Which IP versions to connect with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+impl IpVersion {
+    pub fn matches(&self, ip: &IpAddr) -> bool {
+        match self {
+            IpVersion::V4 => ip.is_ipv4(),
+            IpVersion::V6 => ip.is_ipv6(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Network {
+    /// Only connect over IPv4 or IPv6, by default both are tried (Happy Eyeballs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_version: Option<IpVersion>,
+    /// Static host to IP mappings, used instead of DNS (e.g. for split-horizon DNS)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, Vec<IpAddr>>,
+    /// Give up on querying rebuilders for a package after this many seconds (default: 300)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification_timeout_secs: Option<u64>,
+    /// Limit package downloads to this many KiB per second, like apt's `Acquire::http::Dl-Limit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_limit_kib: Option<u64>,
+    /// Query rebuilders below these urls over HTTP/3 (QUIC), needs a build with the `http3` feature
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub http3: Vec<Url>,
+    /// Don't ask a rebuilder about a package again for this long after it had no build (default: `5m`, `0s` disables this)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub negative_cache_ttl: Option<Timespan>,
+    /// Which redirects of mirrors and rebuilders are followed
+    #[serde(default)]
+    pub redirects: Redirects,
+}
+
+impl Network {
+    pub fn negative_cache_ttl(&self) -> Duration {
+        self.negative_cache_ttl
+            .map(|ttl| ttl.0)
+            .unwrap_or(DEFAULT_NEGATIVE_CACHE_TTL)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Redirects {
+    /// Follow at most this many redirects per request (default: 10, 0 disables redirects)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_hops: Option<usize>,
+    /// Let rebuilders redirect API requests to other hosts, by default only the same host is allowed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rebuilder_cross_host: bool,
+    /// Only follow mirror redirects to these hosts (`*.example.com` matches subdomains), any host if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirror_hosts: Vec<String>,
+}
+
+impl Redirects {
+    pub fn max_hops(&self) -> usize {
+        self.max_hops.unwrap_or(DEFAULT_MAX_REDIRECTS)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Sandbox {
+    /// Download and parse packages in a separate, unprivileged worker process
+    #[serde(default)]
+    pub privilege_separation: bool,
+    /// Limit the address space of the worker process to this many MiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_limit_mib: Option<u64>,
+    /// The user the worker process runs as, if the transport is started as root (default: `nobody`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl Sandbox {
+    pub fn user(&self) -> &str {
+        self.user.as_deref().unwrap_or(DEFAULT_SANDBOX_USER)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Storage {
+    /// Evict the least recently used cache entries once the cache is larger than this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_quota_mib: Option<u64>,
+    /// Refuse downloads that would leave less free space than this on the destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_free_mib: Option<u64>,
+}
+
+impl Storage {
+    pub fn cache_quota(&self) -> u64 {
+        self.cache_quota_mib.unwrap_or(DEFAULT_CACHE_QUOTA_MIB) * 1024 * 1024
+    }
+
+    pub fn min_free(&self) -> u64 {
+        self.min_free_mib.unwrap_or(DEFAULT_MIN_FREE_MIB) * 1024 * 1024
+    }
+}
+
+/// The colors used by the TUI
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeName {
+    #[default]
+    Default,
+    /// No colors at all, this is also used if `NO_COLOR` is set
+    NoColor,
+    /// Bright, bold colors and no dimmed text
+    HighContrast,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Ui {
+    #[serde(default)]
+    pub theme: ThemeName,
+    /// Only use ASCII symbols, for terminals and fonts without unicode glyphs
+    #[serde(default)]
+    pub ascii: bool,
+}
+
+/// Where a trusted rebuilder came from, and if the source still agrees with our copy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upstream {
+    /// The rebuilderd-community list has not been fetched yet
+    Unknown,
+    Custom,
+    /// Compiled into the binary, the rebuilderd-community list has not been fetched yet
+    Bundled,
+    Listed,
+    /// The rebuilder was removed from the rebuilderd-community list
+    Unlisted,
+    /// The rebuilderd-community list knows a different signing keyring
+    KeyringChanged,
+}
+
+impl Upstream {
+    /// A notice to show next to rebuilders
+    pub fn notice(&self) -> Option<&'static str> {
+        match self {
+            Upstream::Bundled => Some("bundled"),
+            Upstream::Unlisted => Some("no longer listed upstream"),
+            Upstream::KeyringChanged => Some("signing keyring changed upstream"),
+            Upstream::Unknown | Upstream::Custom | Upstream::Listed => None,
+        }
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -24,6 +564,43 @@ pub struct Config {
     /// Rules for attestation policy
     #[serde(default)]
     pub rules: Rules,
+    /// Reduce the metadata rebuilders learn about this host
+    #[serde(default)]
+    pub privacy: Privacy,
+    /// How to reach mirrors and rebuilders
+    #[serde(default)]
+    pub network: Network,
+    /// How the transports isolate network access and parsing
+    #[serde(default)]
+    pub sandbox: Sandbox,
+    /// How much disk space downloads and the cache may use
+    #[serde(default)]
+    pub storage: Storage,
+    /// How the TUI looks
+    #[serde(default)]
+    pub ui: Ui,
+    /// Where to report policy violations
+    #[serde(default)]
+    pub notify: Notify,
+    /// Where to write supply-chain evidence of admitted packages
+    #[serde(default)]
+    pub sbom: Sbom,
+    /// Where to send tracing spans
+    #[serde(default)]
+    pub telemetry: Telemetry,
+    /// How the decisions of the transports are recorded
+    #[serde(default)]
+    pub audit: Audit,
+    /// Credentials for rebuilders that require authentication
+    #[serde(default, rename = "credential", skip_serializing_if = "Vec::is_empty")]
+    pub credentials: Vec<Credential>,
+    /// Url templates for rebuilders with a nonstandard API
+    #[serde(
+        default,
+        rename = "rebuilder_api",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub rebuilder_apis: Vec<ApiTemplate>,
     /// Rebuilders selected as trusted by the user
     #[serde(
         default,
@@ -38,9 +615,67 @@ pub struct Config {
         skip_serializing_if = "Vec::is_empty"
     )]
     pub custom_rebuilders: Vec<Rebuilder>,
+    /// When the rebuilderd-community list was last refreshed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_rebuilderd_community_at: Option<DateTime<Utc>>,
     /// Cached list of rebuilders from rebuilderd-community
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub cached_rebuilderd_community: Vec<Rebuilder>,
+    /// Pin the keyring of trusted rebuilders on first contact, and refuse to verify anything if it changes
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub trust_on_first_use: bool,
+    /// Don't offer the rebuilders compiled into the binary before the rebuilderd-community list was fetched
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disable_bundled_rebuilders: bool,
+    /// The profile the rules and trusted rebuilders above belong to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    /// Saved sets of rules and trusted rebuilders to switch between, e.g. `paranoid` or `eu-only`
+    #[serde(
+        default,
+        rename = "profile",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named set of rules and trusted rebuilders
+///
+/// Only the urls of the rebuilders are stored. Their signing keys are only kept by the trusted
+/// rebuilders of the config, so a rotated or forgotten key doesn't come back by switching.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub rules: Rules,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_rebuilders: Vec<Url>,
+}
+
+/// A config loaded for a change, locked until this is dropped
+pub struct Writable {
+    config: Config,
+    path: PathBuf,
+    _lock: Lock,
+}
+
+impl Writable {
+    pub async fn save(&self) -> Result<()> {
+        self.config.write(&self.path).await
+    }
+}
+
+impl Deref for Writable {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &self.config
+    }
+}
+
+impl DerefMut for Writable {
+    fn deref_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
 }
 
 impl Config {
@@ -52,7 +687,7 @@ impl Config {
         std::env::var_os("REPRO_THRESHOLD_CONFIG").map(PathBuf::from)
     }
 
-    fn path() -> PathBuf {
+    pub fn path() -> PathBuf {
         Self::path_override().unwrap_or_else(|| PathBuf::from(PATH))
     }
 
@@ -81,16 +716,42 @@ impl Config {
 
     // XXX: these are provisory, replace with more robust implementation later
     async fn load_file(path: &Path) -> Result<Self> {
-        let config = match fs::read_to_string(&path).await {
-            Ok(content) => toml::from_str(&content)
-                .with_context(|| format!("Failed to parse config file: {path:?}"))?,
-            Err(err) if err.kind() == io::ErrorKind::NotFound => Config::new(),
-            Err(err) => {
-                return Err(
-                    Error::from(err).context(format!("Failed to read config file: {path:?}"))
-                );
-            }
-        };
+        let (mut config, explicit_enforcement, explicit_threshold) =
+            match fs::read_to_string(&path).await {
+                Ok(content) => {
+                    let table = toml::from_str::<toml::Table>(&content)
+                        .with_context(|| format!("Failed to parse config file: {path:?}"))?;
+                    let rules = table.get("rules");
+                    let explicit_enforcement =
+                        rules.and_then(|rules| rules.get("enforcement")).is_some();
+                    let explicit_threshold = rules
+                        .and_then(|rules| rules.get("required_threshold"))
+                        .is_some();
+                    let config = toml::Value::Table(table)
+                        .try_into::<Config>()
+                        .with_context(|| format!("Failed to parse config file: {path:?}"))?;
+                    (config, explicit_enforcement, explicit_threshold)
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (Config::new(), false, false),
+                Err(err) => {
+                    return Err(
+                        Error::from(err).context(format!("Failed to read config file: {path:?}"))
+                    );
+                }
+            };
+        config
+            .rules
+            .migrate(explicit_enforcement, explicit_threshold);
+        config
+            .rules
+            .validate()
+            .with_context(|| format!("Invalid config file: {path:?}"))?;
+        config.rules.load_public_suffixes().await?;
+        if config.rules.is_disabled() {
+            warn!(
+                "Enforcement is disabled, all packages are admitted without checking attestations"
+            );
+        }
         Ok(config)
     }
 
@@ -99,28 +760,189 @@ impl Config {
         Self::load_file(&path).await
     }
 
+    /// Load an explicitly selected config file, unlike the system config it has to exist
+    pub async fn load_from(path: &Path) -> Result<Self> {
+        fs::metadata(path)
+            .await
+            .with_context(|| format!("Failed to read config file: {path:?}"))?;
+        Self::load_file(path).await
+    }
+
     pub async fn load_writable() -> Result<Self> {
         let path = Self::path_writable().await?;
         Self::load_file(&path).await
     }
 
-    // XXX: these are provisory, replace with more robust implementation later
-    pub async fn save(&self) -> Result<()> {
+    /// Load the config for a change, other writers wait until the returned guard is dropped
+    pub async fn lock_writable() -> Result<Writable> {
         let path = Self::path_writable().await?;
+        let lock = Lock::acquire(LOCK).await?;
+        let config = Self::load_file(&path).await?;
+        Ok(Writable {
+            config,
+            path,
+            _lock: lock,
+        })
+    }
+
+    async fn write(&self, path: &Path) -> Result<()> {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)
                 .await
                 .with_context(|| format!("Failed to create config directory: {parent:?}"))?;
         }
 
+        // The rename replaces the file, keep the permissions it had
+        let permissions = match fs::metadata(path).await {
+            Ok(metadata) => metadata.permissions(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Permissions::from_mode(0o644),
+            Err(err) => {
+                return Err(
+                    Error::from(err).context(format!("Failed to read config file: {path:?}"))
+                );
+            }
+        };
+
+        // Readers must never see a partially written file
         let contents = toml::to_string_pretty(self)?;
-        fs::write(&path, contents)
-            .await
-            .with_context(|| format!("Failed to write config file: {path:?}"))?;
+        let path = path.to_path_buf();
+        task::spawn_blocking(move || {
+            let dir = path.parent().unwrap_or(Path::new("."));
+            let mut tmp = tempfile::NamedTempFile::new_in(dir)
+                .with_context(|| format!("Failed to create temporary file in {dir:?}"))?;
+            tmp.as_file()
+                .set_permissions(permissions)
+                .with_context(|| {
+                    format!("Failed to set permissions of config file: {:?}", tmp.path())
+                })?;
+            tmp.write_all(contents.as_bytes())
+                .with_context(|| format!("Failed to write config file: {:?}", tmp.path()))?;
+            tmp.persist(&path)
+                .with_context(|| format!("Failed to write config file: {path:?}"))?;
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Write the config, concurrent writers are serialized with a lock
+    ///
+    /// Changes made by others since the config was loaded are overwritten, use
+    /// `lock_writable` to hold the lock from loading until saving.
+    pub async fn save(&self) -> Result<()> {
+        let path = Self::path_writable().await?;
+        let _lock = Lock::acquire(LOCK).await?;
+        self.write(&path).await
+    }
+
+    fn snapshot(&self) -> Profile {
+        Profile {
+            rules: self.rules.clone(),
+            trusted_rebuilders: self
+                .trusted_rebuilders
+                .iter()
+                .map(|r| r.url.clone())
+                .collect(),
+        }
+    }
+
+    /// The rebuilders given on the command line, or the trusted rebuilders if there are none
+    pub fn rebuilder_urls_or(&self, rebuilders: Vec<Url>) -> Vec<Url> {
+        if rebuilders.is_empty() {
+            self.trusted_rebuilders
+                .iter()
+                .map(|r| r.url.clone())
+                .collect()
+        } else {
+            rebuilders
+        }
+    }
 
+    /// A rebuilder that's known without being trusted, e.g. from the rebuilderd-community list
+    fn is_listed(&self, url: &Url) -> bool {
+        self.custom_rebuilders
+            .iter()
+            .chain(&self.cached_rebuilderd_community)
+            .chain(self.bundled_rebuilders())
+            .any(|r| r.url == *url)
+    }
+
+    /// Store the current rules and trusted rebuilders as profile, and make it the active one
+    pub fn save_profile(&mut self, name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("Profile name must not be empty");
+        }
+        self.profiles.insert(name.to_string(), self.snapshot());
+        self.active_profile = Some(name.to_string());
         Ok(())
     }
 
+    /// Switch to the rules and trusted rebuilders of a profile
+    ///
+    /// Changes made since the last switch are kept in the profile that was active until now.
+    pub async fn use_profile(&mut self, name: &str) -> Result<()> {
+        if self.active_profile.as_deref() == Some(name) {
+            return self.save_profile(name);
+        }
+        let mut profile = self
+            .profiles
+            .get(name)
+            .with_context(|| format!("Profile does not exist: {name:?}"))?
+            .clone();
+        profile
+            .rules
+            .validate()
+            .with_context(|| format!("Invalid profile: {name:?}"))?;
+        profile.rules.load_public_suffixes().await?;
+        // Rebuilders that stay trusted keep their keys, others are taken from the lists
+        let trusted = profile
+            .trusted_rebuilders
+            .iter()
+            .map(|url| {
+                self.rebuilder_by_url(url.as_str())
+                    .map(|r| r.item.clone())
+                    .with_context(|| format!("Unknown rebuilder in profile {name:?}: {url}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(active) = self.active_profile.take() {
+            let snapshot = self.snapshot();
+            self.profiles.insert(active, snapshot);
+        }
+        // Keep rebuilders that aren't listed anywhere else, so they can be trusted again
+        let unlisted = self
+            .trusted_rebuilders
+            .iter()
+            .filter(|r| !trusted.iter().any(|t| t.url == r.url) && !self.is_listed(&r.url))
+            .map(|r| Rebuilder {
+                signing_keyring: String::new(),
+                valid_until: None,
+                verified_fingerprint: None,
+                ..r.clone()
+            })
+            .collect::<Vec<_>>();
+        self.custom_rebuilders.extend(unlisted);
+        self.rules = profile.rules;
+        self.trusted_rebuilders = trusted;
+        self.active_profile = Some(name.to_string());
+        Ok(())
+    }
+
+    /// The profile before or after the active one in alphabetical order, for cycling through them
+    pub fn next_profile(&self, reverse: bool) -> Option<&str> {
+        let names = self.profiles.keys().map(String::as_str).collect::<Vec<_>>();
+        let active = self
+            .active_profile
+            .as_deref()
+            .and_then(|active| names.iter().position(|name| *name == active));
+        let idx = match (active, reverse) {
+            (None, false) => 0,
+            (None, true) => names.len().checked_sub(1)?,
+            (Some(idx), false) => (idx + 1) % names.len(),
+            (Some(idx), true) => (idx + names.len() - 1) % names.len(),
+        };
+        names.get(idx).copied()
+    }
+
     fn rebuilders_by_precedence(&self) -> Vec<Selectable<&Rebuilder>> {
         let mut rebuilders = Vec::new();
         rebuilders.extend(self.trusted_rebuilders.iter().map(|r| Selectable {
@@ -135,15 +957,61 @@ impl Config {
             active: false,
             item: r,
         }));
+        rebuilders.extend(self.bundled_rebuilders().iter().map(|r| Selectable {
+            active: false,
+            item: r,
+        }));
         rebuilders
     }
 
+    /// The rebuilders compiled into the binary, if they are still needed
+    pub fn bundled_rebuilders(&self) -> &'static [Rebuilder] {
+        if self.disable_bundled_rebuilders || !self.cached_rebuilderd_community.is_empty() {
+            &[]
+        } else {
+            rebuilder::bundled()
+        }
+    }
+
     pub fn rebuilder_by_url(&self, url: &str) -> Option<Selectable<&Rebuilder>> {
         self.rebuilders_by_precedence()
             .into_iter()
             .find(|r| r.item.url.as_str() == url)
     }
 
+    /// Compare a trusted rebuilder with the cached rebuilderd-community list
+    pub fn upstream_status(&self, rebuilder: &Rebuilder) -> Upstream {
+        if self
+            .custom_rebuilders
+            .iter()
+            .any(|r| r.url == rebuilder.url)
+        {
+            return Upstream::Custom;
+        }
+        if self.cached_rebuilderd_community.is_empty() {
+            let bundled = self.bundled_rebuilders();
+            if bundled.iter().any(|r| r.url == rebuilder.url) {
+                return Upstream::Bundled;
+            }
+            return Upstream::Unknown;
+        }
+        match self
+            .cached_rebuilderd_community
+            .iter()
+            .find(|r| r.url == rebuilder.url)
+        {
+            None => Upstream::Unlisted,
+            Some(upstream)
+                if !upstream.signing_keyring.is_empty()
+                    && !rebuilder.signing_keyring.is_empty()
+                    && upstream.signing_keyring != rebuilder.signing_keyring =>
+            {
+                Upstream::KeyringChanged
+            }
+            Some(_) => Upstream::Listed,
+        }
+    }
+
     pub fn resolve_rebuilder_view(&self) -> Vec<Selectable<Rebuilder>> {
         let mut deduplicate = HashSet::new();
         let mut rebuilders = Vec::new();
@@ -157,3 +1025,347 @@ impl Config {
         rebuilders
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    fn rules(patterns: &[&str]) -> Rules {
+        Rules {
+            required_threshold: 1,
+            blindly_trust: patterns.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_blindly_trust_exact() {
+        let rules = rules(&["linux-image-amd64"]);
+        assert!(rules.is_blindly_trusted("linux-image-amd64"));
+        assert!(!rules.is_blindly_trusted("linux-image-amd64-dbg"));
+        assert!(!rules.is_blindly_trusted("linux-image"));
+    }
+
+    #[test]
+    fn test_blindly_trust_glob() {
+        let rules = rules(&["linux-image-*", "firmware-?"]);
+        assert!(rules.is_blindly_trusted("linux-image-6.12.48+deb13-amd64"));
+        assert!(rules.is_blindly_trusted("firmware-a"));
+        assert!(!rules.is_blindly_trusted("firmware-ab"));
+        assert!(!rules.is_blindly_trusted("linux-headers-amd64"));
+    }
+
+    #[test]
+    fn test_blindly_trust_regex() {
+        let rules = rules(&["regex:linux-(image|headers)-[0-9.]+"]);
+        assert!(rules.is_blindly_trusted("linux-image-6.12"));
+        assert!(rules.is_blindly_trusted("linux-headers-6.12"));
+        // the regex needs to match the full package name
+        assert!(!rules.is_blindly_trusted("linux-image-6.12-amd64"));
+        assert!(!rules.is_blindly_trusted("xlinux-image-6.12"));
+    }
+
+    #[test]
+    fn test_blindly_trust_source() {
+        let rules = Rules {
+            blindly_trust_source: ["rust-*".to_string()].into(),
+            ..Default::default()
+        };
+        let deb = Deb {
+            name: "librust-as-slice-dev".to_string(),
+            version: "0.2.1-1+b2".to_string(),
+            architecture: "amd64".to_string(),
+            source: "rust-as-slice".to_string(),
+            ..Default::default()
+        };
+        assert!(rules.blindly_trusts(&deb));
+        assert!(!rules.is_blindly_trusted(&deb.name));
+
+        let deb = Deb {
+            source: "as-slice".to_string(),
+            ..deb
+        };
+        assert!(!rules.blindly_trusts(&deb));
+    }
+
+    #[test]
+    fn test_required_threshold_for_arch() {
+        let rules = Rules {
+            required_threshold: 2,
+            architecture: BTreeMap::from([
+                (
+                    "amd64".to_string(),
+                    ArchRules {
+                        required_threshold: Some(3),
+                    },
+                ),
+                (
+                    "all".to_string(),
+                    ArchRules {
+                        required_threshold: Some(1),
+                    },
+                ),
+                ("arm64".to_string(), ArchRules::default()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(rules.required_threshold_for("amd64"), 3);
+        assert_eq!(rules.required_threshold_for("all"), 1);
+        assert_eq!(rules.required_threshold_for("any"), 1);
+        assert_eq!(rules.required_threshold_for("arm64"), 2);
+        assert_eq!(rules.required_threshold_for("riscv64"), 2);
+    }
+
+    #[test]
+    fn test_blindly_trust_validate() {
+        assert!(rules(&["linux-image-*"]).validate().is_ok());
+        assert!(rules(&["linux-image-[*"]).validate().is_err());
+        assert!(rules(&["regex:linux-(image"]).validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_keeps_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!(
+            "repro-threshold-test-write-mode-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("repro-threshold.conf");
+        fs::write(&path, "").await.unwrap();
+        fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+            .await
+            .unwrap();
+
+        Config::default().write(&path).await.unwrap();
+        let mode = fs::metadata(&path).await.unwrap().permissions().mode();
+        fs::remove_dir_all(&dir).await.unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_enforcement() {
+        let dir = std::env::temp_dir().join(format!(
+            "repro-threshold-test-enforcement-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("repro-threshold.conf");
+
+        // Older configs used a threshold of 0 to disable verification
+        fs::write(&path, "[rules]\nrequired_threshold = 0\n")
+            .await
+            .unwrap();
+        let config = Config::load_file(&path).await.unwrap();
+        assert_eq!(config.rules.enforcement, Enforcement::Disabled);
+        assert!(
+            toml::to_string(&config)
+                .unwrap()
+                .contains("enforcement = \"disabled\"")
+        );
+
+        fs::write(
+            &path,
+            "[rules]\nenforcement = \"enforce\"\nrequired_threshold = 0\n",
+        )
+        .await
+        .unwrap();
+        assert!(Config::load_file(&path).await.is_err());
+
+        fs::write(
+            &path,
+            "[rules]\nrequired_threshold = 2\n[rules.architecture.all]\nrequired_threshold = 0\n",
+        )
+        .await
+        .unwrap();
+        assert!(Config::load_file(&path).await.is_err());
+
+        fs::write(&path, "[rules]\nrequired_threshold = 2\n")
+            .await
+            .unwrap();
+        let mut config = Config::load_file(&path).await.unwrap();
+        assert_eq!(config.rules.enforcement, Enforcement::Enforce);
+        assert!(!toml::to_string(&config).unwrap().contains("enforcement"));
+
+        config.rules.set_required_threshold(0);
+        assert!(config.rules.is_disabled());
+        config.rules.set_required_threshold(1);
+        assert!(!config.rules.is_disabled());
+
+        // A config without a threshold, or no config at all, doesn't disable verification
+        fs::write(
+            &path,
+            "[rules]
+blindly_trust = []
+",
+        )
+        .await
+        .unwrap();
+        let config = Config::load_file(&path).await.unwrap();
+        assert_eq!(config.rules.enforcement, Enforcement::Enforce);
+        assert_eq!(config.rules.required_threshold, 1);
+        fs::remove_file(&path).await.unwrap();
+        let config = Config::load_file(&path).await.unwrap();
+        assert_eq!(config.rules.enforcement, Enforcement::Enforce);
+        assert_eq!(config.rules.required_threshold, 1);
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let rebuilder = test_server::rebuilder(
+            "https://rebuilder.example.com/".parse().unwrap(),
+            String::new(),
+        );
+        let config = Config {
+            trusted_rebuilders: vec![rebuilder.clone()],
+            cached_rebuilderd_community_at: Some(DateTime::UNIX_EPOCH),
+            cached_rebuilderd_community: vec![rebuilder],
+            ..Default::default()
+        };
+
+        let toml = toml::to_string_pretty(&config).unwrap();
+        let parsed = toml::from_str::<Config>(&toml).unwrap();
+        assert_eq!(parsed.trusted_rebuilders, config.trusted_rebuilders);
+        assert_eq!(
+            parsed.cached_rebuilderd_community_at,
+            Some(DateTime::UNIX_EPOCH)
+        );
+    }
+
+    #[test]
+    fn test_timespan() {
+        let parse = |s: &str| s.parse::<Timespan>().map(|t| t.0);
+        assert_eq!(parse("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse("500ms").unwrap(), Duration::from_millis(500));
+        assert!(parse("30").is_err());
+        assert!(parse("s").is_err());
+        assert!(parse("3d").is_err());
+
+        assert_eq!(Timespan(Duration::from_secs(300)).to_string(), "300s");
+        assert_eq!(Timespan(Duration::from_millis(1500)).to_string(), "1500ms");
+    }
+
+    #[test]
+    fn test_upstream_status() {
+        let rebuilder = |host: &str, keyring: &str| {
+            test_server::rebuilder(
+                format!("https://{host}/").parse().unwrap(),
+                keyring.to_string(),
+            )
+        };
+        let mut config = Config {
+            trusted_rebuilders: vec![rebuilder("a.example.com", "key")],
+            ..Default::default()
+        };
+        let trusted = config.trusted_rebuilders[0].clone();
+        assert_eq!(config.upstream_status(&trusted), Upstream::Unknown);
+
+        config.cached_rebuilderd_community = vec![rebuilder("a.example.com", "")];
+        assert_eq!(config.upstream_status(&trusted), Upstream::Listed);
+
+        config.cached_rebuilderd_community = vec![rebuilder("a.example.com", "other")];
+        assert_eq!(config.upstream_status(&trusted), Upstream::KeyringChanged);
+
+        config.cached_rebuilderd_community = vec![rebuilder("b.example.com", "")];
+        assert_eq!(config.upstream_status(&trusted), Upstream::Unlisted);
+
+        config.custom_rebuilders = vec![rebuilder("a.example.com", "")];
+        assert_eq!(config.upstream_status(&trusted), Upstream::Custom);
+    }
+
+    #[test]
+    fn test_bundled_rebuilders() {
+        let mut config = Config::default();
+        let bundled = rebuilder::bundled()[0].clone();
+        assert_eq!(config.upstream_status(&bundled), Upstream::Bundled);
+        let view = config.resolve_rebuilder_view();
+        assert!(view.iter().any(|r| r.item.url == bundled.url && !r.active));
+
+        config.disable_bundled_rebuilders = true;
+        assert_eq!(config.upstream_status(&bundled), Upstream::Unknown);
+        assert!(config.resolve_rebuilder_view().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_profiles() {
+        let mut config = Config {
+            trusted_rebuilders: vec![rebuilder::bundled()[0].clone()],
+            ..Default::default()
+        };
+        config.rules.required_threshold = 1;
+        assert_eq!(config.next_profile(false), None);
+        config.save_profile("fast").unwrap();
+        assert!(config.save_profile(" ").is_err());
+
+        config.rules.required_threshold = 3;
+        config.trusted_rebuilders = rebuilder::bundled().to_vec();
+        config.save_profile("paranoid").unwrap();
+        assert_eq!(config.active_profile.as_deref(), Some("paranoid"));
+        assert_eq!(config.next_profile(false), Some("fast"));
+        assert_eq!(config.next_profile(true), Some("fast"));
+
+        config.use_profile("fast").await.unwrap();
+        assert_eq!(config.rules.required_threshold, 1);
+        assert_eq!(config.trusted_rebuilders.len(), 1);
+        assert_eq!(config.next_profile(false), Some("paranoid"));
+
+        // Changes are kept in the active profile when switching away
+        config.rules.required_threshold = 2;
+        config.use_profile("paranoid").await.unwrap();
+        assert_eq!(config.rules.required_threshold, 3);
+        assert_eq!(config.profiles["fast"].rules.required_threshold, 2);
+        assert!(config.use_profile("missing").await.is_err());
+        assert_eq!(config.active_profile.as_deref(), Some("paranoid"));
+
+        let toml = toml::to_string_pretty(&config).unwrap();
+        let parsed = toml::from_str::<Config>(&toml).unwrap();
+        assert_eq!(parsed.active_profile.as_deref(), Some("paranoid"));
+        assert_eq!(
+            parsed.profiles["fast"].trusted_rebuilders,
+            config.profiles["fast"].trusted_rebuilders
+        );
+    }
+
+    #[tokio::test]
+    async fn test_profiles_keep_no_keys() {
+        let bundled = rebuilder::bundled()[0].clone();
+        let unlisted = test_server::rebuilder(
+            "https://unlisted.example.com/".parse().unwrap(),
+            String::new(),
+        );
+        let mut config = Config {
+            trusted_rebuilders: vec![Rebuilder {
+                signing_keyring: "pinned".to_string(),
+                ..bundled
+            }],
+            ..Default::default()
+        };
+        config.rules.required_threshold = 1;
+        config.save_profile("bundled").unwrap();
+        config.trusted_rebuilders.push(Rebuilder {
+            signing_keyring: "unlisted".to_string(),
+            ..unlisted.clone()
+        });
+        config.save_profile("both").unwrap();
+
+        // Rebuilders that are only trusted are kept, without their key
+        config.use_profile("bundled").await.unwrap();
+        assert_eq!(config.trusted_rebuilders.len(), 1);
+        assert_eq!(config.custom_rebuilders.len(), 1);
+        assert_eq!(config.custom_rebuilders[0].url, unlisted.url);
+        assert!(config.custom_rebuilders[0].signing_keyring.is_empty());
+
+        // The key was forgotten, switching doesn't bring it back
+        config.trusted_rebuilders[0].signing_keyring.clear();
+        config.use_profile("both").await.unwrap();
+        assert_eq!(config.trusted_rebuilders.len(), 2);
+        assert!(config.trusted_rebuilders[0].signing_keyring.is_empty());
+        assert_eq!(config.trusted_rebuilders[1].url, unlisted.url);
+        assert!(config.trusted_rebuilders[1].signing_keyring.is_empty());
+    }
+}
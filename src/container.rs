@@ -0,0 +1,724 @@
+//! Pull the package databases baked into a container image from an OCI registry
+//!
+//! Only the files of the package managers are extracted from the layers, the image is never
+//! unpacked to disk.
+use crate::errors::*;
+use crate::http;
+use crate::inspect::deb::{self, Deb};
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use futures::StreamExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use url::{Host, Url};
+
+const DOCKER_HUB: &str = "registry-1.docker.io";
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+/// Limit for manifests, image configs and auth tokens
+const MAX_METADATA_SIZE: usize = 4 * 1024 * 1024;
+/// Limit for compressed layers
+const MAX_BLOB_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// An image reference like `debian:bookworm` or `ghcr.io/foo/bar@sha256:...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub registry: String,
+    pub repository: String,
+    /// A tag or a digest
+    pub reference: String,
+}
+
+impl FromStr for Reference {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, reference) = if let Some((name, digest)) = s.split_once('@') {
+            (name, digest.to_string())
+        } else {
+            match s.rsplit_once(':') {
+                Some((name, tag)) if !tag.contains('/') => (name, tag.to_string()),
+                _ => (s, "latest".to_string()),
+            }
+        };
+
+        // Like docker, the first component is a registry if it looks like a hostname
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, path))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host.to_string(), path.to_string())
+            }
+            Some(_) => (DOCKER_HUB.to_string(), name.to_string()),
+            None => (DOCKER_HUB.to_string(), format!("library/{name}")),
+        };
+        if repository.is_empty() || reference.is_empty() {
+            bail!("Invalid image reference: {s:?}");
+        }
+
+        Ok(Reference {
+            registry,
+            repository,
+            reference,
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.reference.contains(':') {
+            '@'
+        } else {
+            ':'
+        };
+        write!(
+            f,
+            "{}/{}{separator}{}",
+            self.registry, self.repository, self.reference
+        )
+    }
+}
+
+impl Reference {
+    /// Like docker, registries on the local machine don't need TLS
+    fn is_local(&self) -> bool {
+        let Ok(url) = Url::parse(&format!("http://{}/", self.registry)) else {
+            return false;
+        };
+        match url.host() {
+            Some(Host::Domain(host)) => host == "localhost",
+            Some(Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(Host::Ipv6(ip)) => ip.is_loopback(),
+            None => false,
+        }
+    }
+
+    fn url(&self, kind: &str, reference: &str) -> Result<Url> {
+        let scheme = if self.is_local() { "http" } else { "https" };
+        let url = format!(
+            "{scheme}://{}/v2/{}/{kind}/{reference}",
+            self.registry, self.repository
+        );
+        url.parse()
+            .with_context(|| format!("Invalid registry url: {url:?}"))
+    }
+}
+
+/// The platform of the image to select from a multi-platform index, like `linux/amd64`
+pub fn default_platform() -> String {
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "powerpc64" => "ppc64le",
+        arch => arch,
+    };
+    format!("linux/{arch}")
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(default)]
+    media_type: String,
+    digest: String,
+    platform: Option<Platform>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Platform {
+    os: String,
+    architecture: String,
+    variant: Option<String>,
+}
+
+impl Platform {
+    fn matches(&self, platform: &str) -> bool {
+        let mut parts = platform.split('/');
+        parts.next() == Some(&self.os)
+            && parts.next() == Some(&self.architecture)
+            && parts
+                .next()
+                .is_none_or(|variant| self.variant.as_deref() == Some(variant))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    /// Set for an image index (manifest list)
+    manifests: Option<Vec<Descriptor>>,
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageConfig {
+    os: String,
+    architecture: String,
+}
+
+/// Parse the parameters of a `WWW-Authenticate: Bearer realm="...",service="..."` challenge
+fn parse_challenge(header: &str) -> Option<BTreeMap<String, String>> {
+    let params = header.strip_prefix("Bearer ")?;
+    let mut map = BTreeMap::new();
+    let mut rest = params.trim();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let (value, remaining) = if let Some(value) = value.strip_prefix('"') {
+            let (value, remaining) = value.split_once('"')?;
+            (value, remaining)
+        } else {
+            value.split_once(',').map_or((value, ""), |(v, r)| (v, r))
+        };
+        map.insert(key.trim().to_string(), value.to_string());
+        rest = remaining.trim_start_matches([',', ' ']);
+    }
+    Some(map)
+}
+
+struct Registry<'a> {
+    http: &'a http::Client,
+    reference: &'a Reference,
+    /// Anonymous pull token, requested on the first `401 Unauthorized`
+    token: Option<String>,
+}
+
+impl Registry<'_> {
+    async fn authenticate(&mut self, challenge: &str) -> Result<()> {
+        let params = parse_challenge(challenge)
+            .with_context(|| format!("Unsupported registry auth challenge: {challenge:?}"))?;
+        let realm = params
+            .get("realm")
+            .context("Registry auth challenge has no realm")?;
+        let mut url = Url::parse(realm).context("Invalid registry auth realm")?;
+        {
+            let mut query = url.query_pairs_mut();
+            if let Some(service) = params.get("service") {
+                query.append_pair("service", service);
+            }
+            let scope = format!("repository:{}:pull", self.reference.repository);
+            query.append_pair("scope", params.get("scope").unwrap_or(&scope));
+        }
+
+        #[derive(Deserialize)]
+        struct Token {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        debug!("Requesting registry token from {url}");
+        let response = self.http.get(url).send().await?.error_for_status()?;
+        let body = read_limited(response).await?;
+        let token = serde_json::from_slice::<Token>(&body).context("Invalid registry token")?;
+        self.token = Some(
+            token
+                .token
+                .or(token.access_token)
+                .context("Registry didn't issue a token")?,
+        );
+        Ok(())
+    }
+
+    async fn get(&mut self, url: Url, accept: &str) -> Result<reqwest::Response> {
+        loop {
+            let mut request = self.http.get(url.clone()).header("Accept", accept);
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            let response = request
+                .send()
+                .await
+                .with_context(|| format!("Failed to request {url}"))?;
+
+            if response.status() == reqwest::StatusCode::UNAUTHORIZED && self.token.is_none() {
+                let challenge = response
+                    .headers()
+                    .get("www-authenticate")
+                    .and_then(|value| value.to_str().ok())
+                    .context("Registry requires auth, but sent no challenge")?
+                    .to_string();
+                self.authenticate(&challenge).await?;
+                continue;
+            }
+            return response
+                .error_for_status()
+                .with_context(|| format!("Failed to request {url}"));
+        }
+    }
+
+    async fn manifest(&mut self, reference: &str) -> Result<Manifest> {
+        let url = self.reference.url("manifests", reference)?;
+        let response = self.get(url, MANIFEST_TYPES).await?;
+        let body = read_limited(response).await?;
+        serde_json::from_slice(&body).context("Failed to parse image manifest")
+    }
+
+    /// Download a blob into a file and verify its digest
+    async fn blob(&mut self, digest: &str, file: &mut File) -> Result<()> {
+        let expected = digest
+            .strip_prefix("sha256:")
+            .with_context(|| format!("Unsupported digest algorithm: {digest:?}"))?;
+
+        let url = self.reference.url("blobs", digest)?;
+        let mut response = self.get(url, "*/*").await?;
+        let mut sha256 = Sha256::new();
+        let mut size = 0;
+        while let Some(chunk) = response.chunk().await? {
+            size += chunk.len() as u64;
+            if size > MAX_BLOB_SIZE {
+                bail!("Blob {digest} exceeds {MAX_BLOB_SIZE} bytes");
+            }
+            sha256.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        let actual = data_encoding::HEXLOWER.encode(&sha256.finalize());
+        if actual != expected {
+            bail!("Digest mismatch for blob {digest}: got sha256:{actual}");
+        }
+        Ok(())
+    }
+}
+
+async fn read_limited(mut response: reqwest::Response) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_METADATA_SIZE {
+            bail!("Registry response exceeds {MAX_METADATA_SIZE} bytes");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Files that list the installed packages, or the hashes of available packages
+fn is_package_db(path: &str) -> bool {
+    path == "var/lib/dpkg/status"
+        // distroless images have one file per package
+        || path.starts_with("var/lib/dpkg/status.d/")
+        || (path.starts_with("var/lib/apt/lists/") && path.ends_with("_Packages"))
+        || (path.starts_with("var/lib/pacman/local/") && path.ends_with("/desc"))
+}
+
+/// Apply a layer to the collected files, including the whiteouts deleting files of lower layers
+async fn apply_layer<R: AsyncRead + Unpin>(
+    reader: R,
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<()> {
+    let mut tar = tokio_tar::Archive::new(reader);
+    let mut entries = tar.entries().context("Failed to read entries from layer")?;
+
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry.context("Failed to read entry from layer")?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let path = path.trim_start_matches("./").trim_start_matches('/');
+
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name == ".wh..wh..opq" {
+            let prefix = format!("{dir}/");
+            files.retain(|path, _| !path.starts_with(&prefix));
+            continue;
+        }
+        if let Some(name) = name.strip_prefix(".wh.") {
+            let deleted = if dir.is_empty() {
+                name.to_string()
+            } else {
+                format!("{dir}/{name}")
+            };
+            let prefix = format!("{deleted}/");
+            files.retain(|path, _| *path != deleted && !path.starts_with(&prefix));
+            continue;
+        }
+
+        if !is_package_db(path) || !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = path.to_string();
+        let mut content = Vec::new();
+        entry
+            .read_to_end(&mut content)
+            .await
+            .with_context(|| format!("Failed to read {path:?} from layer"))?;
+        trace!("Found package database in layer: {path:?}");
+        files.insert(path, content);
+    }
+    Ok(())
+}
+
+/// Decompress a layer with gzip or zstd, based on its media type
+async fn read_layer<R: AsyncRead + Unpin>(
+    media_type: &str,
+    reader: R,
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<()> {
+    let reader = BufReader::new(reader);
+    if media_type.ends_with("gzip") {
+        let mut decoder = GzipDecoder::new(reader);
+        decoder.multiple_members(true);
+        apply_layer(decoder, files).await
+    } else if media_type.ends_with("zstd") {
+        let mut decoder = ZstdDecoder::new(reader);
+        decoder.multiple_members(true);
+        apply_layer(decoder, files).await
+    } else if media_type.ends_with(".tar") {
+        apply_layer(reader, files).await
+    } else {
+        bail!("Unsupported layer media type: {media_type:?}");
+    }
+}
+
+/// A package installed in the image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    pub package: Deb,
+    /// Only known if the image still contains the package index it was installed from
+    pub sha256: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct Image {
+    /// The platform of the image config, like `linux/amd64`
+    pub platform: String,
+    pub packages: Vec<Package>,
+}
+
+fn field<'a>(paragraph: &'a deb822_fast::Paragraph, key: &str) -> Result<&'a str> {
+    paragraph
+        .get(key)
+        .ok_or_else(|| anyhow!("No {key:?} field in paragraph"))
+}
+
+/// Installed packages of a dpkg status file
+fn parse_dpkg_status(content: &str, packages: &mut Vec<Package>) -> Result<()> {
+    let deb822 = deb822_fast::Deb822::from_reader(content.as_bytes())
+        .map_err(|err| anyhow!("Failed to parse dpkg status: {err:#}"))?;
+    for paragraph in deb822.iter() {
+        // Files in status.d of distroless images don't have a status
+        if paragraph
+            .get("Status")
+            .is_some_and(|status| !status.ends_with(" installed"))
+        {
+            continue;
+        }
+        packages.push(Package {
+            package: deb::from_paragraph(paragraph)?,
+            sha256: None,
+        });
+    }
+    Ok(())
+}
+
+/// The sha256 of each package in an apt `Packages` index
+fn parse_apt_list(
+    content: &str,
+    hashes: &mut BTreeMap<(String, String, String), Vec<u8>>,
+) -> Result<()> {
+    let deb822 = deb822_fast::Deb822::from_reader(content.as_bytes())
+        .map_err(|err| anyhow!("Failed to parse package index: {err:#}"))?;
+    for paragraph in deb822.iter() {
+        let Some(sha256) = paragraph.get("SHA256") else {
+            continue;
+        };
+        let key = (
+            field(paragraph, "Package")?.to_string(),
+            field(paragraph, "Version")?.to_string(),
+            field(paragraph, "Architecture")?.to_string(),
+        );
+        hashes.insert(key, crate::attestation::parse_sha256(sha256)?);
+    }
+    Ok(())
+}
+
+/// A package of the pacman local database (`%NAME%`, `%VERSION%`, ... sections)
+fn parse_pacman_desc(content: &str) -> Result<Package> {
+    let mut sections = BTreeMap::new();
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        if let Some(key) = line.strip_prefix('%').and_then(|l| l.strip_suffix('%'))
+            && let Some(value) = lines.next()
+        {
+            sections.insert(key, value);
+        }
+    }
+    let get = |key| {
+        sections
+            .get(key)
+            .map(|value| value.to_string())
+            .with_context(|| format!("No %{key}% in pacman package description"))
+    };
+    let name = get("NAME")?;
+    Ok(Package {
+        package: Deb {
+            source: get("BASE").unwrap_or_else(|_| name.clone()),
+            name,
+            version: get("VERSION")?,
+            architecture: get("ARCH")?,
+            ..Default::default()
+        },
+        sha256: None,
+    })
+}
+
+/// List the installed packages of the collected package databases
+fn installed_packages(files: &BTreeMap<String, Vec<u8>>) -> Result<Vec<Package>> {
+    let mut packages = Vec::new();
+    let mut hashes = BTreeMap::new();
+    for (path, content) in files {
+        let content = String::from_utf8_lossy(content);
+        if path.starts_with("var/lib/dpkg/") {
+            parse_dpkg_status(&content, &mut packages)
+                .with_context(|| format!("Failed to parse {path:?}"))?;
+        } else if path.starts_with("var/lib/apt/lists/") {
+            parse_apt_list(&content, &mut hashes)
+                .with_context(|| format!("Failed to parse {path:?}"))?;
+        } else {
+            packages.push(
+                parse_pacman_desc(&content).with_context(|| format!("Failed to parse {path:?}"))?,
+            );
+        }
+    }
+
+    for package in &mut packages {
+        let deb = &package.package;
+        let key = (
+            deb.name.clone(),
+            deb.version.clone(),
+            deb.architecture.clone(),
+        );
+        package.sha256 = hashes.get(&key).cloned();
+    }
+    packages.sort_by(|a, b| a.package.cmp(&b.package));
+    packages.dedup();
+    Ok(packages)
+}
+
+/// Pull the image config and the package databases from the layers of an image
+pub async fn pull(http: &http::Client, reference: &Reference, platform: &str) -> Result<Image> {
+    let mut registry = Registry {
+        http,
+        reference,
+        token: None,
+    };
+
+    info!("Fetching manifest of {reference}");
+    let mut manifest = registry.manifest(&reference.reference).await?;
+    if let Some(manifests) = &manifest.manifests {
+        let descriptor = manifests
+            .iter()
+            .find(|m| m.platform.as_ref().is_some_and(|p| p.matches(platform)))
+            .with_context(|| format!("Image {reference} has no manifest for {platform}"))?;
+        debug!("Selected manifest {} for {platform}", descriptor.digest);
+        manifest = registry.manifest(&descriptor.digest).await?;
+    }
+
+    let config = manifest
+        .config
+        .as_ref()
+        .context("Image manifest has no config")?;
+    // Blobs are downloaded into an unnamed temporary file, the digest is verified before reading
+    let mut buf = Vec::new();
+    let mut file = blob_file()?;
+    registry.blob(&config.digest, &mut file).await?;
+    file.rewind().await?;
+    file.take(MAX_METADATA_SIZE as u64)
+        .read_to_end(&mut buf)
+        .await?;
+    let config =
+        serde_json::from_slice::<ImageConfig>(&buf).context("Failed to parse image config")?;
+
+    let mut files = BTreeMap::new();
+    for (idx, layer) in manifest.layers.iter().enumerate() {
+        info!(
+            "Reading layer {}/{}: {}",
+            idx + 1,
+            manifest.layers.len(),
+            layer.digest
+        );
+        let mut file = blob_file()?;
+        registry.blob(&layer.digest, &mut file).await?;
+        file.rewind().await?;
+        read_layer(&layer.media_type, file, &mut files)
+            .await
+            .with_context(|| format!("Failed to read layer {}", layer.digest))?;
+    }
+
+    Ok(Image {
+        platform: format!("{}/{}", config.os, config.architecture),
+        packages: installed_packages(&files)?,
+    })
+}
+
+fn blob_file() -> Result<File> {
+    let file = tempfile::tempfile().context("Failed to create temporary file for blob")?;
+    Ok(File::from_std(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[test]
+    fn test_parse_reference() {
+        let reference = "debian:bookworm".parse::<Reference>().unwrap();
+        assert_eq!(
+            reference,
+            Reference {
+                registry: DOCKER_HUB.to_string(),
+                repository: "library/debian".to_string(),
+                reference: "bookworm".to_string(),
+            }
+        );
+
+        let reference = "localhost:5000/foo/bar".parse::<Reference>().unwrap();
+        assert_eq!(reference.registry, "localhost:5000");
+        assert_eq!(reference.repository, "foo/bar");
+        assert_eq!(reference.reference, "latest");
+        assert_eq!(reference.to_string(), "localhost:5000/foo/bar:latest");
+        assert!(reference.is_local());
+        for registry in ["127.0.0.1:5000", "[::1]:5000", "localhost"] {
+            let reference = format!("{registry}/foo/bar").parse::<Reference>().unwrap();
+            assert!(reference.is_local(), "{registry}");
+        }
+        for registry in ["localhost.example.com", "127.example.com:5000", "ghcr.io"] {
+            let reference = format!("{registry}/foo/bar").parse::<Reference>().unwrap();
+            assert!(!reference.is_local(), "{registry}");
+        }
+
+        let digest = format!("sha256:{}", "00".repeat(32));
+        let reference = format!("ghcr.io/foo/bar@{digest}")
+            .parse::<Reference>()
+            .unwrap();
+        assert_eq!(reference.registry, "ghcr.io");
+        assert_eq!(reference.reference, digest);
+
+        let reference = "archlinux/archlinux:base".parse::<Reference>().unwrap();
+        assert_eq!(reference.repository, "archlinux/archlinux");
+    }
+
+    #[test]
+    fn test_parse_challenge() {
+        let params = parse_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/debian:pull,push""#,
+        )
+        .unwrap();
+        assert_eq!(params["realm"], "https://auth.docker.io/token");
+        assert_eq!(params["service"], "registry.docker.io");
+        assert_eq!(params["scope"], "repository:library/debian:pull,push");
+        assert_eq!(parse_challenge("Basic realm=\"x\""), None);
+    }
+
+    fn desc(name: &str, version: &str) -> String {
+        format!("%NAME%\n{name}\n\n%VERSION%\n{version}\n\n%BASE%\n{name}\n\n%ARCH%\nx86_64\n")
+    }
+
+    async fn layer(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = tokio_tar::Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, path, *content).await.unwrap();
+        }
+        tar.into_inner().await.unwrap()
+    }
+
+    fn digest(blob: &[u8]) -> String {
+        format!(
+            "sha256:{}",
+            data_encoding::HEXLOWER.encode(&Sha256::digest(blob))
+        )
+    }
+
+    #[tokio::test]
+    async fn test_pull() {
+        let base = layer(&[
+            (
+                "var/lib/pacman/local/zlib-1.3.1-2/desc",
+                desc("zlib", "1.3.1-2").as_bytes(),
+            ),
+            (
+                "var/lib/pacman/local/curl-8.11.1-1/desc",
+                desc("curl", "8.11.1-1").as_bytes(),
+            ),
+            ("usr/bin/curl", b"\x7fELF"),
+        ])
+        .await;
+        // The upgrade removes the old curl and installs a new one
+        let upgrade = layer(&[
+            ("var/lib/pacman/local/.wh.curl-8.11.1-1", b""),
+            (
+                "var/lib/pacman/local/curl-8.11.1-3/desc",
+                desc("curl", "8.11.1-3").as_bytes(),
+            ),
+        ])
+        .await;
+        let mut encoder = async_compression::tokio::write::GzipEncoder::new(Vec::new());
+        encoder.write_all(&upgrade).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        let upgrade = encoder.into_inner();
+        let config = serde_json::to_vec(&serde_json::json!({
+            "os": "linux",
+            "architecture": "amd64",
+        }))
+        .unwrap();
+
+        let tar = "application/vnd.oci.image.layer.v1.tar";
+        let manifest = serde_json::json!({
+            "config": { "mediaType": "application/vnd.oci.image.config.v1+json", "digest": digest(&config) },
+            "layers": [
+                { "mediaType": tar, "digest": digest(&base) },
+                { "mediaType": format!("{tar}+gzip"), "digest": digest(&upgrade) },
+            ],
+        });
+        let index = serde_json::json!({
+            "manifests": [
+                { "digest": "sha256:arm", "platform": { "os": "linux", "architecture": "arm64" } },
+                { "digest": "sha256:amd", "platform": { "os": "linux", "architecture": "amd64" } },
+            ],
+        });
+
+        let mut routes = BTreeMap::from([
+            (
+                "/v2/test/manifests/latest".to_string(),
+                serde_json::to_vec(&index).unwrap(),
+            ),
+            (
+                "/v2/test/manifests/sha256:amd".to_string(),
+                serde_json::to_vec(&manifest).unwrap(),
+            ),
+        ]);
+        for blob in [config, base, upgrade] {
+            routes.insert(format!("/v2/test/blobs/{}", digest(&blob)), blob);
+        }
+        let url = test_server::spawn(routes).await;
+
+        let reference = format!("{}:{}/test", url.host_str().unwrap(), url.port().unwrap())
+            .parse::<Reference>()
+            .unwrap();
+        let image = pull(&http::client(), &reference, "linux/amd64")
+            .await
+            .unwrap();
+        assert_eq!(image.platform, "linux/amd64");
+        let packages = image
+            .packages
+            .iter()
+            .map(|p| (p.package.name.as_str(), p.package.version.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(packages, [("curl", "8.11.1-3"), ("zlib", "1.3.1-2")]);
+
+        assert!(
+            pull(&http::client(), &reference, "linux/riscv64")
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::attestation::{self, Remote};
+use crate::config::Config;
+use crate::errors::*;
+use crate::evidence::Bundle;
+use crate::policy::{self, Outcome};
+use crate::signing::{self, Signer};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs;
+
+pub const FILENAME: &str = "countersignature.json";
+
+/// The claim made by this host about an evidence bundle
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Statement {
+    pub artifact: String,
+    pub sha256: String,
+    pub threshold: usize,
+    pub confirms: usize,
+    pub verified_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Countersignature {
+    /// The json encoded statement, stored as string to keep the signed bytes intact
+    pub payload: String,
+    pub public_key: String,
+    pub sig: String,
+}
+
+impl Countersignature {
+    pub async fn sign(key: &Signer, statement: &Statement) -> Result<Self> {
+        let payload = serde_json::to_string(statement)?;
+        let sig = key.sign(payload.as_bytes()).await?;
+        Ok(Countersignature {
+            payload,
+            public_key: key.public_key_pem(),
+            sig: data_encoding::HEXLOWER.encode(&sig),
+        })
+    }
+
+    pub async fn load(path: &Path) -> Result<Self> {
+        let buf = fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read countersignature: {path:?}"))?;
+        let countersig = serde_json::from_slice(&buf)
+            .with_context(|| format!("Failed to parse countersignature: {path:?}"))?;
+        Ok(countersig)
+    }
+
+    /// Verify the signature and ensure it was made by the given public key
+    pub fn verify_with(&self, public_key_pem: &str) -> Result<Statement> {
+        let expected = pem::parse(public_key_pem).context("Failed to parse pem file")?;
+        let actual = pem::parse(&self.public_key).context("Failed to parse pem file")?;
+        if expected.contents() != actual.contents() {
+            bail!("Countersignature was created by a different key");
+        }
+        self.verify()
+    }
+
+    pub fn verify(&self) -> Result<Statement> {
+        let sig = data_encoding::HEXLOWER_PERMISSIVE
+            .decode(self.sig.as_bytes())
+            .context("Failed to decode signature")?;
+        signing::verify_local_signature(&self.public_key, self.payload.as_bytes(), &sig)?;
+        let statement = serde_json::from_str(&self.payload)?;
+        Ok(statement)
+    }
+}
+
+/// Re-verify the attestations of an evidence bundle and create a statement for it
+///
+/// Only the keys of the trusted rebuilders and the threshold of the config are used, the keys
+/// and threshold that came with the bundle are not trusted.
+pub async fn attest_bundle(config: &Config, dir: &Path, artifact: &Path) -> Result<Statement> {
+    let bundle = Bundle::load(dir)
+        .await
+        .with_context(|| format!("Failed to load evidence bundle: {dir:?}"))?;
+
+    let sha256 = attestation::sha256_path(artifact)
+        .await
+        .with_context(|| format!("Failed to calculate hash for file: {artifact:?}"))?;
+    bundle.ensure_artifact(&sha256)?;
+
+    let remote = Remote {
+        attestations: bundle.attestations,
+        ..Default::default()
+    };
+    let decision = policy::Engine::new(config).decide(None, &sha256, &remote);
+    if decision.outcome != Outcome::Reproduced {
+        bail!(
+            "Evidence bundle does not satisfy the configured policy: {}",
+            decision.message()
+        );
+    }
+
+    Ok(Statement {
+        artifact: bundle.summary.artifact,
+        sha256: data_encoding::HEXLOWER.encode(&sha256),
+        threshold: decision.threshold,
+        confirms: decision.confirms(),
+        verified_at: Utc::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::LocalKey;
+
+    const PRIVATE_KEY: &[u8] = &[
+        0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04,
+        0x20, 0x9d, 0x61, 0xb1, 0x9d, 0xef, 0xfd, 0x5a, 0x60, 0xba, 0x84, 0x4a, 0xf4, 0x92, 0xec,
+        0x2c, 0xc4, 0x44, 0x49, 0xc5, 0x69, 0x7b, 0x32, 0x69, 0x19, 0x70, 0x3b, 0xac, 0x03, 0x1c,
+        0xae, 0x7f, 0x60,
+    ];
+
+    fn statement() -> Statement {
+        Statement {
+            artifact: "filesystem-2025.10.12-1-any.pkg.tar.zst".to_string(),
+            sha256: "6b6c3fee7432204840d3b6afc9bc1a68c28f591a47fb220071715c40cca956df".to_string(),
+            threshold: 2,
+            confirms: 3,
+            verified_at: "2025-10-20T12:00:00Z".parse().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_countersign_roundtrip() {
+        let key = Signer::Local(LocalKey::from_pkcs8(PRIVATE_KEY).unwrap());
+        let countersig = Countersignature::sign(&key, &statement()).await.unwrap();
+        assert_eq!(countersig.verify().unwrap(), statement());
+
+        let public_key = key.public_key_pem();
+        assert_eq!(countersig.verify_with(&public_key).unwrap(), statement());
+    }
+
+    #[tokio::test]
+    async fn test_countersign_tampered() {
+        let key = Signer::Local(LocalKey::from_pkcs8(PRIVATE_KEY).unwrap());
+        let mut countersig = Countersignature::sign(&key, &statement()).await.unwrap();
+        countersig.payload = countersig
+            .payload
+            .replace("\"threshold\":2", "\"threshold\":0");
+        assert!(countersig.verify().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_attest_bundle_uses_config() {
+        let dir = std::env::temp_dir().join(format!(
+            "repro-threshold-test-countersign-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).await.unwrap();
+        let artifact = dir.join("artifact");
+        fs::write(&artifact, b"hello").await.unwrap();
+        let sha256 = attestation::sha256_path(&artifact).await.unwrap();
+
+        // A bundle that claims nothing needs to be confirmed
+        let summary = serde_json::json!({
+            "artifact": "artifact",
+            "sha256": data_encoding::HEXLOWER.encode(&sha256),
+            "threshold": 0,
+            "confirms": [],
+        });
+        fs::write(dir.join("summary.json"), summary.to_string())
+            .await
+            .unwrap();
+
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        let err = attest_bundle(&config, &dir, &artifact).await.unwrap_err();
+        assert!(err.to_string().contains("configured policy"), "{err:#}");
+
+        fs::write(&artifact, b"tampered").await.unwrap();
+        let err = attest_bundle(&config, &dir, &artifact).await.unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{err:#}");
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use crate::args::Cron;
+use crate::attestation;
+use crate::audit;
+use crate::clock;
+use crate::config::Config;
+use crate::disk;
+use crate::errors::*;
+use crate::http;
+use crate::inspect::{self, deb::Deb};
+use crate::notify::{self, Event};
+use crate::policy;
+use crate::sideload;
+use crate::state::{Pending, Reason, Store};
+use crate::tofu;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+use tokio::time;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Verified,
+    BlindlyTrusted,
+    Pending { confirms: usize, threshold: usize },
+}
+
+pub struct Package {
+    pub age: Duration,
+    pub inspect: Deb,
+    pub sha256: Vec<u8>,
+}
+
+impl Package {
+    pub async fn load(path: &Path, age: Duration) -> Result<Self> {
+        let (file, sha256) = attestation::open_hashed(path)
+            .await
+            .with_context(|| format!("Failed to open and hash file {path:?}"))?;
+        let inspect = inspect::cache::inspect(file, &sha256)
+            .await
+            .with_context(|| format!("Failed to inspect metadata: {path:?}"))?;
+        Ok(Package {
+            age,
+            inspect,
+            sha256,
+        })
+    }
+}
+
+/// List all .deb files in `dirs` that were modified within `max_age`
+pub async fn recent_packages(
+    dirs: &[PathBuf],
+    max_age: Duration,
+) -> Result<Vec<(PathBuf, Duration)>> {
+    let now = SystemTime::now();
+    let mut packages = Vec::new();
+    for dir in dirs {
+        let mut entries = fs::read_dir(dir)
+            .await
+            .with_context(|| format!("Failed to read directory: {dir:?}"))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("deb") {
+                continue;
+            }
+            let metadata = entry.metadata().await?;
+            let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+            if metadata.is_file() && age <= max_age {
+                packages.push((path, age));
+            }
+        }
+    }
+    packages.sort();
+    Ok(packages)
+}
+
+pub async fn check(http: &http::Client, config: &Config, pkg: &Package) -> Status {
+    let engine = policy::Engine::new(config);
+    if engine.blindly_trusts(&pkg.inspect) {
+        return Status::BlindlyTrusted;
+    }
+
+    let rebuilders = config.trusted_rebuilders.iter().map(|r| r.url.clone());
+    let remote = attestation::fetch_remote(http, rebuilders, pkg.inspect.clone()).await;
+
+    let decision = engine.decide(Some(&pkg.inspect), &pkg.sha256, &remote);
+    if decision.verified() {
+        Status::Verified
+    } else {
+        Status::Pending {
+            confirms: decision.confirms(),
+            threshold: decision.threshold,
+        }
+    }
+}
+
+/// Packages that have been handled in an earlier pass and don't need to be checked again
+#[derive(Default)]
+struct Done {
+    verified: BTreeSet<PathBuf>,
+    flagged: BTreeSet<PathBuf>,
+}
+
+async fn pass(http: &http::Client, config: &Config, args: &Cron, done: &mut Done) -> Result<()> {
+    let grace = DAY * args.grace_days;
+    for (path, age) in recent_packages(&args.dirs, DAY * args.max_age_days).await? {
+        if done.verified.contains(&path) {
+            continue;
+        }
+
+        let pkg = match Package::load(&path, age).await {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                warn!("Skipping package {path:?}: {err:#}");
+                continue;
+            }
+        };
+        let name = format!(
+            "{} {} ({})",
+            pkg.inspect.name, pkg.inspect.version, pkg.inspect.architecture
+        );
+
+        match check(http, config, &pkg).await {
+            Status::Verified => {
+                info!("Verified {name}");
+                done.verified.insert(path);
+            }
+            Status::BlindlyTrusted => debug!("Blindly trusted: {name}"),
+            Status::Pending {
+                confirms,
+                threshold,
+            } if pkg.age >= grace => {
+                warn!("Never reproduced {name}: only {confirms}/{threshold} required signatures");
+                if done.flagged.insert(path) {
+                    let event = Event::Rejected {
+                        package: pkg.inspect,
+                        sha256: data_encoding::HEXLOWER.encode(&pkg.sha256),
+                        confirms,
+                        threshold,
+                    };
+                    notify::dispatch(http, &config.notify, &event).await;
+                }
+            }
+            Status::Pending {
+                confirms,
+                threshold,
+            } => {
+                info!(
+                    "Still waiting for rebuilders on {name}: {confirms}/{threshold} required signatures"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Re-check packages admitted under the grace period and escalate the ones past their deadline
+async fn pass_pending(http: &http::Client, config: &Config) -> Result<()> {
+    let pending = Pending::load().await?;
+    // Deadlines only pass once the clock is past them by more than the allowed skew
+    let now = match clock::now() {
+        Ok(now) => Some(now - config.rules.clock_skew()),
+        Err(problem) => {
+            warn!("Not escalating overdue packages, {problem}");
+            None
+        }
+    };
+
+    // Collect the changes first, the file is only locked while they are applied
+    let mut verified = Vec::new();
+    let mut escalated = Vec::new();
+    for (sha256, entry) in pending.packages {
+        let name = format!(
+            "{} {} ({})",
+            entry.package.name, entry.package.version, entry.package.architecture
+        );
+        let pkg = Package {
+            age: Duration::ZERO,
+            inspect: entry.package.clone(),
+            sha256: data_encoding::HEXLOWER.decode(sha256.as_bytes())?,
+        };
+
+        // Blindly trusted packages have no deadline and are only tracked for visibility
+        if entry.reason != Reason::BlindlyTrusted
+            && check(http, config, &pkg).await == Status::Verified
+        {
+            if entry.reason == Reason::Deferred {
+                info!("Deferred package {name} is verified now and can be installed");
+            } else {
+                info!("Verified pending package {name}");
+            }
+            verified.push(sha256);
+            continue;
+        }
+
+        if now.is_some_and(|now| entry.is_overdue(now)) && !entry.escalated {
+            warn!("Pending package passed its deadline: {name}");
+            if let Some(deadline) = entry.deadline {
+                let event = Event::DeadlinePassed {
+                    package: entry.package.clone(),
+                    sha256: sha256.clone(),
+                    deadline,
+                };
+                notify::dispatch(http, &config.notify, &event).await;
+            }
+            escalated.push(sha256);
+        }
+    }
+
+    if !verified.is_empty() || !escalated.is_empty() {
+        Pending::update(|pending| {
+            for sha256 in &verified {
+                pending.packages.remove(sha256);
+            }
+            for sha256 in &escalated {
+                if let Some(entry) = pending.packages.get_mut(sha256) {
+                    entry.escalated = true;
+                }
+            }
+        })
+        .await;
+    }
+    Ok(())
+}
+
+pub async fn run(args: Cron) -> Result<()> {
+    let mut done = Done::default();
+    let mut last = clock::Reading::now();
+    loop {
+        // Reload the config each pass, so changes are picked up without a restart
+        let mut config = Config::load().await?;
+        let reading = clock::Reading::now();
+        if let Some(jump) = last.jump(&reading, config.rules.clock_skew()) {
+            warn!(
+                "System clock jumped by {}s since the last pass, deadlines may be off",
+                jump.num_seconds()
+            );
+        }
+        last = reading;
+        let http = http::client_for_config(&config)?;
+
+        // Nothing is settled with keyrings that may belong to somebody else
+        if let Err(err) = tofu::enforce(&http, &mut config).await {
+            error!("{err:#}");
+            if args.once {
+                return Err(err);
+            }
+            time::sleep(Duration::from_secs(args.interval)).await;
+            continue;
+        }
+        if let Err(err) = audit::seal(&config.audit).await {
+            warn!("Failed to sign queued audit log entries: {err:#}");
+        }
+        pass(&http, &config, &args, &mut done).await?;
+        pass_pending(&http, &config).await?;
+        let logs = sideload::Log::detect().await;
+        if let Err(err) =
+            sideload::scan(&http, &config, &logs, Path::new(sideload::APT_ARCHIVES)).await
+        {
+            warn!("Failed to check for side-loaded packages: {err:#}");
+        }
+        sideload::recheck(&http, &config).await?;
+        disk::prune_cache(&config).await;
+
+        if args.once {
+            break;
+        }
+        debug!("Sleeping for {}s until next pass", args.interval);
+        time::sleep(Duration::from_secs(args.interval)).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn test_recent_packages() {
+        let dirs = [PathBuf::from("test_data")];
+        let packages = recent_packages(&dirs, Duration::MAX).await.unwrap();
+        let paths = packages
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [Path::new(test_server::DEB_PATH)]);
+
+        let packages = recent_packages(&dirs, Duration::ZERO).await.unwrap();
+        assert!(packages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let rebuilder = test_server::spawn_rebuilder().await;
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        config.trusted_rebuilders = vec![test_server::rebuilder(
+            rebuilder,
+            test_server::DEB_KEYRING.to_string(),
+        )];
+        let http = http::client();
+
+        let pkg = Package::load(Path::new(test_server::DEB_PATH), Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(check(&http, &config, &pkg).await, Status::Verified);
+
+        config.rules.required_threshold = 2;
+        assert_eq!(
+            check(&http, &config, &pkg).await,
+            Status::Pending {
+                confirms: 1,
+                threshold: 2
+            }
+        );
+
+        config.rules.blindly_trust.insert("librust-*".to_string());
+        assert_eq!(check(&http, &config, &pkg).await, Status::BlindlyTrusted);
+    }
+}
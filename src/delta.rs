@@ -0,0 +1,159 @@
+use crate::errors::*;
+use clap::ValueEnum;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Delta formats that can be reconstructed into a full artifact
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Binary delta between two package files, as used by pacman (`xdelta3`)
+    Xdelta3,
+    /// Debian delta created by debdelta (`debpatch`)
+    Debdelta,
+}
+
+impl Format {
+    fn command(&self, base: &Path, delta: &Path, output: &Path) -> Command {
+        match self {
+            Format::Xdelta3 => {
+                let mut cmd = Command::new("xdelta3");
+                cmd.arg("-d")
+                    .arg("-f")
+                    .arg("-s")
+                    .arg(base)
+                    .arg(delta)
+                    .arg(output);
+                cmd
+            }
+            Format::Debdelta => {
+                // The base can be either the old .deb or `/` to use the installed package
+                let mut cmd = Command::new("debpatch");
+                cmd.arg(delta).arg(base).arg(output);
+                cmd
+            }
+        }
+    }
+}
+
+/// Rebuild the full artifact from a delta, attestations always refer to the full artifact
+pub async fn reconstruct(format: Format, base: &Path, delta: &Path, output: &Path) -> Result<()> {
+    let mut cmd = format.command(base, delta, output);
+    debug!("Reconstructing artifact from delta: {cmd:?}");
+
+    let status = cmd
+        .status()
+        .await
+        .with_context(|| format!("Failed to execute delta tool for {format:?}"))?;
+    if !status.success() {
+        bail!("Failed to reconstruct artifact from delta {delta:?}: {status}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::fs;
+
+    /// If the delta tool can be executed, the tests using it are skipped otherwise
+    async fn installed(program: &str) -> bool {
+        let found = Command::new(program).arg("--help").output().await.is_ok();
+        if !found {
+            eprintln!("Skipping test, {program} is not installed");
+        }
+        found
+    }
+
+    /// A directory with the `base` file, and the contents of a newer version of it
+    fn files() -> (tempfile::TempDir, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let base = (0..64 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut new = base.clone();
+        new[1000..1100].fill(0xff);
+        new.extend_from_slice(b"appended to the new version");
+        fs::write(dir.path().join("base"), &base).unwrap();
+        (dir, new)
+    }
+
+    #[tokio::test]
+    async fn test_xdelta3_roundtrip() {
+        if !installed("xdelta3").await {
+            return;
+        }
+        let (dir, new) = files();
+        let base = dir.path().join("base");
+        let delta = dir.path().join("delta");
+        let output = dir.path().join("output");
+        fs::write(dir.path().join("new"), &new).unwrap();
+        let status = Command::new("xdelta3")
+            .arg("-e")
+            .arg("-s")
+            .arg(&base)
+            .arg(dir.path().join("new"))
+            .arg(&delta)
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        reconstruct(Format::Xdelta3, &base, &delta, &output)
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), new);
+    }
+
+    #[tokio::test]
+    async fn test_xdelta3_corrupt() {
+        if !installed("xdelta3").await {
+            return;
+        }
+        let (dir, _) = files();
+        let base = dir.path().join("base");
+        let delta = dir.path().join("delta");
+        fs::write(&delta, b"this is not a vcdiff delta").unwrap();
+        let err = reconstruct(Format::Xdelta3, &base, &delta, &dir.path().join("output"))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").starts_with("Failed to reconstruct artifact from delta"));
+    }
+
+    #[test]
+    fn test_debdelta_command() {
+        let cmd = Format::Debdelta.command(
+            Path::new("/"),
+            Path::new("foo.debdelta"),
+            Path::new("foo.deb"),
+        );
+        let cmd = cmd.as_std();
+        assert_eq!(cmd.get_program(), "debpatch");
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            [
+                OsStr::new("foo.debdelta"),
+                OsStr::new("/"),
+                OsStr::new("foo.deb")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_debdelta_corrupt() {
+        if !installed("debpatch").await {
+            return;
+        }
+        let (dir, _) = files();
+        let delta = dir.path().join("delta");
+        fs::write(&delta, b"this is not a debdelta").unwrap();
+        let err = reconstruct(
+            Format::Debdelta,
+            &dir.path().join("base"),
+            &delta,
+            &dir.path().join("output"),
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{err:#}").starts_with("Failed to reconstruct artifact from delta"));
+    }
+}
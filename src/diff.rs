@@ -0,0 +1,139 @@
+use crate::attestation::Attestation;
+use crate::errors::*;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// The sections of an in-toto link that are compared
+pub const SECTIONS: &[&str] = &[
+    "name",
+    "command",
+    "materials",
+    "products",
+    "byproducts",
+    "environment",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Same(String, Value),
+    Removed(String, Value),
+    Added(String, Value),
+    Changed(String, Value, Value),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Same(key, value) => write!(f, "  {key:?}: {value}"),
+            Change::Removed(key, value) => write!(f, "- {key:?}: {value}"),
+            Change::Added(key, value) => write!(f, "+ {key:?}: {value}"),
+            Change::Changed(key, old, new) => {
+                write!(f, "- {key:?}: {old}\n+ {key:?}: {new}")
+            }
+        }
+    }
+}
+
+impl Change {
+    pub fn is_same(&self) -> bool {
+        matches!(self, Change::Same(..))
+    }
+}
+
+/// Compare two values, objects are compared key by key
+pub fn diff_value(a: Option<&Value>, b: Option<&Value>) -> Vec<Change> {
+    match (a, b) {
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            let keys = a.keys().chain(b.keys()).collect::<BTreeSet<_>>();
+            keys.into_iter()
+                .filter_map(|key| match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) if a == b => Some(Change::Same(key.clone(), a.clone())),
+                    (Some(a), Some(b)) => Some(Change::Changed(key.clone(), a.clone(), b.clone())),
+                    (Some(a), None) => Some(Change::Removed(key.clone(), a.clone())),
+                    (None, Some(b)) => Some(Change::Added(key.clone(), b.clone())),
+                    (None, None) => None,
+                })
+                .collect()
+        }
+        (Some(a), Some(b)) if a == b => vec![Change::Same(String::new(), a.clone())],
+        (Some(a), Some(b)) => vec![Change::Changed(String::new(), a.clone(), b.clone())],
+        (Some(a), None) => vec![Change::Removed(String::new(), a.clone())],
+        (None, Some(b)) => vec![Change::Added(String::new(), b.clone())],
+        (None, None) => vec![],
+    }
+}
+
+/// Compare the signed sections of two attestations
+pub fn diff(a: &Attestation, b: &Attestation) -> Result<Vec<(&'static str, Vec<Change>)>> {
+    let (a, b) = (a.signed_json()?, b.signed_json()?);
+
+    let sections = SECTIONS
+        .iter()
+        .map(|section| (*section, diff_value(a.get(section), b.get(section))))
+        .collect();
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_objects() {
+        let a = json!({
+            "foo.deb": {"sha256": "aaaa"},
+            "bar.deb": {"sha256": "bbbb"},
+            "old.deb": {"sha256": "cccc"},
+        });
+        let b = json!({
+            "foo.deb": {"sha256": "aaaa"},
+            "bar.deb": {"sha256": "dddd"},
+            "new.deb": {"sha256": "eeee"},
+        });
+        assert_eq!(
+            diff_value(Some(&a), Some(&b)),
+            vec![
+                Change::Changed(
+                    "bar.deb".to_string(),
+                    json!({"sha256": "bbbb"}),
+                    json!({"sha256": "dddd"})
+                ),
+                Change::Same("foo.deb".to_string(), json!({"sha256": "aaaa"})),
+                Change::Added("new.deb".to_string(), json!({"sha256": "eeee"})),
+                Change::Removed("old.deb".to_string(), json!({"sha256": "cccc"})),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_scalars() {
+        assert_eq!(
+            diff_value(Some(&json!("a")), Some(&json!("a"))),
+            vec![Change::Same(String::new(), json!("a"))]
+        );
+        assert_eq!(
+            diff_value(Some(&json!(["a"])), None),
+            vec![Change::Removed(String::new(), json!(["a"]))]
+        );
+        assert_eq!(diff_value(None, None), vec![]);
+    }
+
+    #[test]
+    fn test_diff_attestations() {
+        let a = Attestation::parse(include_bytes!(
+            "../test_data/filesystem-2025.10.12-1-any.in-toto.link"
+        ))
+        .unwrap();
+        let b = Attestation::parse(include_bytes!(
+            "../test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.in-toto.link"
+        ))
+        .unwrap();
+
+        let sections = diff(&a, &b).unwrap();
+        let (_, products) = sections.iter().find(|(s, _)| *s == "products").unwrap();
+        assert!(products.iter().all(|change| !change.is_same()));
+        assert_eq!(products.len(), 2);
+    }
+}
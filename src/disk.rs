@@ -0,0 +1,233 @@
+//! Free space checks before downloads, and the size limit of the cache directory
+//!
+//! Downloads are refused up front if they wouldn't fit, instead of failing halfway with a
+//! truncated file. The cache is pruned by evicting the least recently used files, entries are
+//! touched whenever they're read.
+use crate::config::Config;
+use crate::errors::*;
+use crate::state;
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs;
+
+const MIB: u64 = 1024 * 1024;
+
+/// A download doesn't fit on the destination filesystem
+#[derive(Debug, PartialEq)]
+pub struct NoSpace {
+    /// MiB needed for the download, including the configured reserve
+    pub needed: u64,
+    /// MiB available on the filesystem
+    pub available: u64,
+}
+
+impl fmt::Display for NoSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Not enough disk space: {} MiB needed, {} MiB available",
+            self.needed, self.available
+        )
+    }
+}
+
+impl std::error::Error for NoSpace {}
+
+/// Bytes available to unprivileged users on the filesystem of `path`
+pub fn available(path: &Path) -> Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: the path is nul terminated and statvfs initializes the struct on success
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to query free disk space of {path:?}"));
+        }
+        stat.assume_init()
+    };
+    #[allow(
+        clippy::unnecessary_cast,
+        reason = "the field types differ between platforms"
+    )]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+fn check(needed: u64, available: u64, min_free: u64) -> Result<(), NoSpace> {
+    if needed.saturating_add(min_free) > available {
+        return Err(NoSpace {
+            needed: needed.saturating_add(min_free).div_ceil(MIB),
+            available: available / MIB,
+        });
+    }
+    Ok(())
+}
+
+/// Make sure `needed` more bytes fit next to `path`, keeping `min_free` bytes available
+///
+/// If the free space can't be determined the download is attempted anyway.
+pub fn check_space(path: &Path, needed: u64, min_free: u64) -> Result<(), NoSpace> {
+    match available(path) {
+        Ok(available) => check(needed, available, min_free),
+        Err(err) => {
+            warn!("{err:#}");
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    used: SystemTime,
+}
+
+async fn entries(dir: &Path) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = match fs::read_dir(&dir).await {
+            Ok(read_dir) => read_dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read directory: {dir:?}"));
+            }
+        };
+        while let Some(entry) = read_dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else if metadata.is_file() {
+                let modified = metadata.modified()?;
+                let used = metadata
+                    .accessed()
+                    .map_or(modified, |accessed| accessed.max(modified));
+                entries.push(Entry {
+                    path: entry.path(),
+                    size: metadata.len(),
+                    used,
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Pick the least recently used entries until the rest fits into the quota
+fn evict(mut entries: Vec<Entry>, quota: u64) -> Vec<Entry> {
+    let mut total = entries.iter().map(|entry| entry.size).sum::<u64>();
+    entries.sort_by_key(|entry| entry.used);
+    entries
+        .into_iter()
+        .take_while(|entry| {
+            let evict = total > quota;
+            total -= entry.size;
+            evict
+        })
+        .collect()
+}
+
+/// Delete the least recently used files in `dir` until it's no larger than `quota` bytes
+pub async fn enforce_quota(dir: &Path, quota: u64) -> Result<u64> {
+    let mut freed = 0;
+    for entry in evict(entries(dir).await?, quota) {
+        match fs::remove_file(&entry.path).await {
+            Ok(()) => freed += entry.size,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => warn!("Failed to evict cache entry {:?}: {err:#}", entry.path),
+        }
+    }
+    Ok(freed)
+}
+
+/// Keep the cache within `[storage] cache_quota_mib`, failures are logged but not fatal
+pub async fn prune_cache(config: &Config) {
+    let dir = state::cache_dir();
+    match enforce_quota(&dir, config.storage.cache_quota()).await {
+        Ok(0) => (),
+        Ok(freed) => debug!("Evicted {freed} bytes from cache {dir:?}"),
+        Err(err) => warn!("Failed to prune cache {dir:?}: {err:#}"),
+    }
+}
+
+/// Mark a file as recently used, so it's evicted last
+pub async fn touch(path: &Path) {
+    let result = async {
+        let file = fs::File::options().write(true).open(path).await?;
+        file.into_std().await.set_modified(SystemTime::now())
+    }
+    .await;
+    if let Err(err) = result {
+        debug!("Failed to update modification time of {path:?}: {err:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check(10 * MIB, 100 * MIB, 64 * MIB), Ok(()));
+        assert_eq!(
+            check(40 * MIB, 100 * MIB, 64 * MIB),
+            Err(NoSpace {
+                needed: 104,
+                available: 100,
+            })
+        );
+        assert_eq!(check(u64::MAX, 0, 0).map_err(|err| err.available), Err(0));
+    }
+
+    #[test]
+    fn test_evict() {
+        let entry = |name: &str, size, secs| Entry {
+            path: PathBuf::from(name),
+            size,
+            used: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        };
+        let entries = || {
+            vec![
+                entry("new", 10, 3),
+                entry("old", 10, 1),
+                entry("middle", 10, 2),
+            ]
+        };
+        assert_eq!(evict(entries(), 30), vec![]);
+        assert_eq!(evict(entries(), 25), vec![entry("old", 10, 1)]);
+        assert_eq!(
+            evict(entries(), 10),
+            vec![entry("old", 10, 1), entry("middle", 10, 2)]
+        );
+        assert_eq!(evict(entries(), 0).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_enforce_quota() {
+        let dir =
+            std::env::temp_dir().join(format!("repro-threshold-test-quota-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).await.unwrap();
+        let old = dir.join("sub/old");
+        let new = dir.join("new");
+        fs::write(&old, [0; 100]).await.unwrap();
+        fs::write(&new, [0; 100]).await.unwrap();
+        let file = std::fs::File::options().write(true).open(&old).unwrap();
+        let past = SystemTime::now() - Duration::from_secs(3600);
+        file.set_times(
+            std::fs::FileTimes::new()
+                .set_accessed(past)
+                .set_modified(past),
+        )
+        .unwrap();
+
+        assert_eq!(enforce_quota(&dir, 200).await.unwrap(), 0);
+        assert_eq!(enforce_quota(&dir, 150).await.unwrap(), 100);
+        assert!(!old.exists());
+        assert!(new.exists());
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
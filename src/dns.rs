@@ -0,0 +1,247 @@
+//! Minimal DNS client to look up TXT records through the system resolver
+//!
+//! The resolver has to validate DNSSEC, we only look at the AD (authenticated data) flag of its
+//! response, like OpenSSH's `VerifyHostKeyDNS`. This is only trustworthy with a local resolver.
+use crate::errors::*;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::{fs, time};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// The UDP payload size we announce with EDNS, responses are truncated beyond this
+const MAX_UDP_SIZE: u16 = 4096;
+
+const TYPE_TXT: u16 = 16;
+const TYPE_OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_AUTHENTICATED: u16 = 0x0020;
+const RCODE_NXDOMAIN: u16 = 3;
+/// The DNSSEC OK bit in the TTL field of the OPT record
+const EDNS_DNSSEC_OK: u32 = 0x8000;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Txt {
+    pub records: Vec<String>,
+    /// The resolver validated the answer with DNSSEC
+    pub authenticated: bool,
+}
+
+/// The first nameserver in resolv.conf
+async fn nameserver() -> SocketAddr {
+    let ip = fs::read_to_string(RESOLV_CONF)
+        .await
+        .ok()
+        .and_then(|conf| {
+            conf.lines()
+                .filter_map(|line| line.trim().strip_prefix("nameserver"))
+                .find_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        })
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    SocketAddr::new(ip, 53)
+}
+
+fn encode_query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(512);
+    buf.extend(id.to_be_bytes());
+    buf.extend((FLAG_RECURSION_DESIRED | FLAG_AUTHENTICATED).to_be_bytes());
+    // One question, one additional record (OPT)
+    buf.extend([0, 1, 0, 0, 0, 0, 0, 1]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("Invalid domain name: {name:?}");
+        }
+        buf.push(label.len() as u8);
+        buf.extend(label.as_bytes());
+    }
+    buf.push(0);
+    buf.extend(TYPE_TXT.to_be_bytes());
+    buf.extend(CLASS_IN.to_be_bytes());
+
+    buf.push(0);
+    buf.extend(TYPE_OPT.to_be_bytes());
+    buf.extend(MAX_UDP_SIZE.to_be_bytes());
+    buf.extend(EDNS_DNSSEC_OK.to_be_bytes());
+    buf.extend([0, 0]);
+    Ok(buf)
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + n)
+            .context("Truncated DNS response")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Skip a (possibly compressed) domain name
+    fn skip_name(&mut self) -> Result<()> {
+        loop {
+            let len = self.take(1)?[0];
+            match len {
+                0 => return Ok(()),
+                len if len & 0xc0 == 0xc0 => {
+                    self.take(1)?;
+                    return Ok(());
+                }
+                len => {
+                    self.take(len as usize)?;
+                }
+            }
+        }
+    }
+}
+
+fn decode_response(id: u16, buf: &[u8]) -> Result<Txt> {
+    let mut reader = Reader { buf, pos: 0 };
+    if reader.u16()? != id {
+        bail!("DNS response id doesn't match query");
+    }
+    let flags = reader.u16()?;
+    if flags & FLAG_RESPONSE == 0 {
+        bail!("DNS response is not a response");
+    }
+    if flags & FLAG_TRUNCATED != 0 {
+        bail!("DNS response was truncated");
+    }
+    let authenticated = flags & FLAG_AUTHENTICATED != 0;
+    match flags & 0xf {
+        0 => (),
+        RCODE_NXDOMAIN => {
+            return Ok(Txt {
+                records: Vec::new(),
+                authenticated,
+            });
+        }
+        rcode => bail!("DNS query failed with rcode {rcode}"),
+    }
+
+    let questions = reader.u16()?;
+    let answers = reader.u16()?;
+    reader.take(4)?;
+    for _ in 0..questions {
+        reader.skip_name()?;
+        reader.take(4)?;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        reader.skip_name()?;
+        let rtype = reader.u16()?;
+        let _class = reader.u16()?;
+        reader.take(4)?;
+        let len = reader.u16()? as usize;
+        let data = reader.take(len)?;
+        if rtype != TYPE_TXT {
+            continue;
+        }
+
+        // A TXT record is a list of length-prefixed strings that are concatenated
+        let mut data = Reader { buf: data, pos: 0 };
+        let mut record = Vec::new();
+        while data.pos < len {
+            let len = data.take(1)?[0] as usize;
+            record.extend(data.take(len)?);
+        }
+        records.push(String::from_utf8_lossy(&record).into_owned());
+    }
+
+    Ok(Txt {
+        records,
+        authenticated,
+    })
+}
+
+/// Look up the TXT records of a domain, a domain that doesn't exist has no records
+pub async fn lookup_txt(name: &str) -> Result<Txt> {
+    let mut id = [0; 2];
+    SystemRandom::new()
+        .fill(&mut id)
+        .map_err(|_| anyhow!("Failed to generate DNS query id"))?;
+    let id = u16::from_be_bytes(id);
+    let query = encode_query(id, name)?;
+
+    let server = nameserver().await;
+    let bind = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => "[::]:0".parse()?,
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    debug!("Looking up TXT records for {name:?} with {server}");
+
+    let response = async {
+        socket.send(&query).await?;
+        let mut buf = vec![0; MAX_UDP_SIZE as usize];
+        loop {
+            let n = socket.recv(&mut buf).await?;
+            // Ignore unrelated packets, a spoofed response also needs to guess the id
+            if buf[..n].starts_with(&id.to_be_bytes()) {
+                return decode_response(id, &buf[..n]);
+            }
+        }
+    };
+    time::timeout(TIMEOUT, response)
+        .await
+        .map_err(|_| anyhow!("DNS query to {server} timed out after {TIMEOUT:?}"))?
+        .with_context(|| format!("Failed to look up TXT records for {name:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_response() {
+        let query = encode_query(0x1234, "_repro-threshold.example.com").unwrap();
+        // Reuse the question section of the query
+        let question = &query[12..query.len() - 11];
+
+        let mut response = vec![0x12, 0x34, 0x81, 0xa0, 0, 1, 0, 1, 0, 0, 0, 0];
+        response.extend(question);
+        // Compressed name pointing at the question, TXT IN, TTL 300
+        response.extend([0xc0, 12, 0, 16, 0, 1, 0, 0, 1, 44]);
+        response.extend([0, 10, 4]);
+        response.extend(b"v=rt");
+        response.extend([4]);
+        response.extend(b" a=b");
+
+        let txt = decode_response(0x1234, &response).unwrap();
+        assert_eq!(
+            txt,
+            Txt {
+                records: vec!["v=rt a=b".to_string()],
+                authenticated: true,
+            }
+        );
+
+        assert!(decode_response(0x4321, &response).is_err());
+        assert!(decode_response(0x1234, &response[..response.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn test_nxdomain() {
+        let response = [0x12, 0x34, 0x81, 0x83, 0, 0, 0, 0, 0, 0, 0, 0];
+        let txt = decode_response(0x1234, &response).unwrap();
+        assert_eq!(txt, Txt::default());
+    }
+}
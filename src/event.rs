@@ -1,5 +1,15 @@
-use crossterm::event::{EventStream, KeyCode, KeyModifiers};
+use crate::app::{Lookup, Reloaded};
+use crate::errors::*;
+use crossterm::event::{EventStream, KeyCode, KeyEvent, KeyModifiers};
 use futures::StreamExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time;
+
+/// How often the TUI is redrawn, even if nothing happened
+const TICK_RATE: Duration = Duration::from_millis(250);
 
 pub enum Event {
     Yes,
@@ -10,6 +20,8 @@ pub enum Event {
     ScrollLast,
     Reload,
     Toggle,
+    /// Copy the key fingerprint of the selection, `Yes` copies its url
+    CopyFingerprint,
     Plus,
     Minus,
     Enter,
@@ -18,10 +30,10 @@ pub enum Event {
 }
 
 impl Event {
-    pub async fn read(stream: &mut EventStream) -> Option<Self> {
-        let event = stream.next().await?.ok()?.as_key_press_event()?;
+    pub fn from_key(event: KeyEvent) -> Option<Self> {
         match event.code {
             KeyCode::Char('y') => Some(Event::Yes),
+            KeyCode::Char('Y') => Some(Event::CopyFingerprint),
             KeyCode::Char('n') => Some(Event::No),
             KeyCode::Char('k') | KeyCode::Up => Some(Event::ScrollUp),
             KeyCode::Char('j') | KeyCode::Down => Some(Event::ScrollDown),
@@ -43,3 +55,77 @@ impl Event {
         }
     }
 }
+
+/// Everything the TUI reacts to
+pub enum Message {
+    /// A key press, mapped to an `Event` unless a text prompt is open
+    Input(KeyEvent),
+    /// Periodic tick, also sent for terminal events that only need a redraw (e.g. resize)
+    Tick,
+    /// A background reload of the rebuilder list has finished
+    Reloaded(Result<Reloaded>),
+    /// A verification started from the TUI has finished
+    Verified(PathBuf, Result<Box<Lookup>>),
+}
+
+/// Merges terminal input, ticks and messages from background tasks into one stream
+pub struct Bus {
+    tx: mpsc::UnboundedSender<Message>,
+    rx: mpsc::UnboundedReceiver<Message>,
+    // Aborted when the bus is dropped
+    _tasks: JoinSet<()>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut tasks = JoinSet::new();
+
+        let input = tx.clone();
+        tasks.spawn(async move {
+            let mut stream = EventStream::new();
+            while let Some(event) = stream.next().await {
+                let msg = match event {
+                    Ok(event) => match event.as_key_press_event() {
+                        Some(key) => Message::Input(key),
+                        None => Message::Tick,
+                    },
+                    Err(err) => {
+                        debug!("Failed to read terminal event: {err:#}");
+                        continue;
+                    }
+                };
+                if input.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let ticks = tx.clone();
+        tasks.spawn(async move {
+            let mut interval = time::interval(TICK_RATE);
+            interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                if ticks.send(Message::Tick).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Bus {
+            tx,
+            rx,
+            _tasks: tasks,
+        }
+    }
+
+    /// A handle for background tasks to report back to the TUI
+    pub fn sender(&self) -> mpsc::UnboundedSender<Message> {
+        self.tx.clone()
+    }
+
+    pub async fn next(&mut self) -> Option<Message> {
+        self.rx.recv().await
+    }
+}
@@ -0,0 +1,245 @@
+use crate::attestation::{self, Confirm};
+use crate::errors::*;
+use crate::signing;
+use in_toto::crypto::KeyId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::fs;
+
+const SUMMARY: &str = "summary.json";
+const ATTESTATIONS_DIR: &str = "attestations";
+const KEYS_DIR: &str = "keys";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Summary {
+    /// File name of the verified artifact
+    pub artifact: String,
+    /// Hex-encoded sha256 of the verified artifact
+    pub sha256: String,
+    /// Number of confirms that were required
+    pub threshold: usize,
+    /// Attestations that have been counted towards the threshold
+    pub confirms: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub key_id: KeyId,
+    /// Path of the public key, relative to the bundle
+    pub signing_key: String,
+    /// Path of the attestation, relative to the bundle
+    pub attestation: String,
+    /// Where the attestation was originally loaded from
+    pub source: String,
+}
+
+impl Summary {
+    pub async fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(SUMMARY);
+        let buf = fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read evidence summary: {path:?}"))?;
+        let summary = serde_json::from_slice(&buf)
+            .with_context(|| format!("Failed to parse evidence summary: {path:?}"))?;
+        Ok(summary)
+    }
+}
+
+/// The attestations of an evidence bundle
+///
+/// The signing keys and threshold in the bundle are only informational, anybody can write a
+/// bundle. Attestations have to be verified with keys that are trusted already.
+pub struct Bundle {
+    pub summary: Summary,
+    pub attestations: attestation::Tree,
+}
+
+impl Bundle {
+    pub async fn load(dir: &Path) -> Result<Self> {
+        let summary = Summary::load(dir).await?;
+
+        let attestations = attestation::load_all_attestations(
+            summary.confirms.iter().map(|e| dir.join(&e.attestation)),
+        )
+        .await;
+
+        Ok(Bundle {
+            summary,
+            attestations,
+        })
+    }
+
+    /// Ensure the bundle is about the artifact with this sha256
+    pub fn ensure_artifact(&self, sha256: &[u8]) -> Result<()> {
+        let hex = data_encoding::HEXLOWER.encode(sha256);
+        if self.summary.sha256 != hex {
+            bail!(
+                "File does not match evidence bundle: expected sha256 {:?}, got {:?}",
+                self.summary.sha256,
+                hex
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Write a bundle of the attestations that were counted for a decision, and their keys
+pub async fn export(
+    dir: &Path,
+    artifact: &Path,
+    sha256: &[u8],
+    threshold: usize,
+    confirms: &BTreeMap<KeyId, Confirm<'_>>,
+) -> Result<()> {
+    for subdir in [ATTESTATIONS_DIR, KEYS_DIR] {
+        let path = dir.join(subdir);
+        fs::create_dir_all(&path)
+            .await
+            .with_context(|| format!("Failed to create directory: {path:?}"))?;
+    }
+
+    let mut entries = Vec::new();
+    for (idx, (key_id, confirm)) in confirms.iter().enumerate() {
+        let (source, attestation) = confirm.attestation.as_ref();
+
+        let attestation_path = format!("{ATTESTATIONS_DIR}/{idx}.link");
+        let path = dir.join(&attestation_path);
+        fs::write(&path, attestation.as_bytes())
+            .await
+            .with_context(|| format!("Failed to write attestation: {path:?}"))?;
+
+        let key_path = format!("{KEYS_DIR}/{idx}.pub");
+        let path = dir.join(&key_path);
+        let pem = signing::pubkey_to_pem(confirm.signing_key)?;
+        fs::write(&path, pem)
+            .await
+            .with_context(|| format!("Failed to write signing key: {path:?}"))?;
+
+        entries.push(Entry {
+            key_id: key_id.to_owned(),
+            signing_key: key_path,
+            attestation: attestation_path,
+            source: source.to_string(),
+        });
+    }
+
+    let summary = Summary {
+        artifact: artifact
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        sha256: data_encoding::HEXLOWER.encode(sha256),
+        threshold,
+        confirms: entries,
+    };
+    let path = dir.join(SUMMARY);
+    let json = serde_json::to_string_pretty(&summary)?;
+    fs::write(&path, json)
+        .await
+        .with_context(|| format!("Failed to write evidence summary: {path:?}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use in_toto::crypto::PublicKey;
+    use std::path::PathBuf;
+
+    const SHA256: &str = "6b6c3fee7432204840d3b6afc9bc1a68c28f591a47fb220071715c40cca956df";
+
+    fn key(pem: &[u8]) -> PublicKey {
+        signing::pem_to_pubkeys(pem)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn write_bundle(name: &str, attestation: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "repro-threshold-test-evidence-{name}-{}",
+            std::process::id()
+        ));
+        for subdir in [ATTESTATIONS_DIR, KEYS_DIR] {
+            fs::create_dir_all(dir.join(subdir)).await.unwrap();
+        }
+        let pem = include_bytes!("../test_data/reproducible-archlinux.pub");
+        fs::write(dir.join("attestations/0.link"), attestation)
+            .await
+            .unwrap();
+        fs::write(dir.join("keys/0.pub"), pem).await.unwrap();
+
+        let summary = Summary {
+            artifact: "filesystem-2025.10.12-1-any.pkg.tar.zst".to_string(),
+            sha256: SHA256.to_string(),
+            threshold: 1,
+            confirms: vec![Entry {
+                key_id: key(pem).key_id().to_owned(),
+                signing_key: "keys/0.pub".to_string(),
+                attestation: "attestations/0.link".to_string(),
+                source: "test".to_string(),
+            }],
+        };
+        fs::write(dir.join(SUMMARY), serde_json::to_vec(&summary).unwrap())
+            .await
+            .unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_bundle_trusted_key() {
+        let dir = write_bundle(
+            "trusted",
+            include_bytes!("../test_data/filesystem-2025.10.12-1-any.in-toto.link"),
+        )
+        .await;
+        let bundle = Bundle::load(&dir).await.unwrap();
+        let sha256 = attestation::parse_sha256(SHA256).unwrap();
+        bundle.ensure_artifact(&sha256).unwrap();
+        assert!(bundle.ensure_artifact(&[0; 32]).is_err());
+
+        let trusted = [key(include_bytes!(
+            "../test_data/reproducible-archlinux.pub"
+        ))];
+        assert_eq!(bundle.attestations.verify(&sha256, &trusted).len(), 1);
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bundle_forged_key() {
+        let dir = write_bundle(
+            "forged",
+            include_bytes!("../test_data/filesystem-2025.10.12-1-any.in-toto.link"),
+        )
+        .await;
+        let bundle = Bundle::load(&dir).await.unwrap();
+        let sha256 = attestation::parse_sha256(SHA256).unwrap();
+
+        // The key that came with the bundle is not trusted
+        let trusted = [key(include_bytes!(
+            "../test_data/reproduce-debian-net-amd64.pub"
+        ))];
+        assert!(bundle.attestations.verify(&sha256, &trusted).is_empty());
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bundle_tampered_attestation() {
+        let dir = write_bundle(
+            "tampered",
+            include_bytes!("../test_data/filesystem-2025.10.12-1-any.INVALID.in-toto.link"),
+        )
+        .await;
+        let bundle = Bundle::load(&dir).await.unwrap();
+        let sha256 = attestation::parse_sha256(SHA256).unwrap();
+
+        let trusted = [key(include_bytes!(
+            "../test_data/reproducible-archlinux.pub"
+        ))];
+        assert!(bundle.attestations.verify(&sha256, &trusted).is_empty());
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -1,8 +1,27 @@
 use crate::attestation::{self, Attestation};
+use crate::config::{Config, IpVersion, Network, Privacy, Redirects};
+use crate::credentials::{Auth, Credential, Redacted};
 use crate::errors::*;
 use crate::inspect::deb::Deb;
-use serde::Deserialize;
+use crate::rebuilder::ApiTemplate;
+use crate::serve;
+use crate::signing;
+use bytes::Bytes;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::OnceCell;
 use url::Url;
 
 const USER_AGENT: &str = concat!(
@@ -17,54 +36,680 @@ const USER_AGENT: &str = concat!(
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 const READ_TIMEOUT: Duration = Duration::from_secs(60);
 
+const MAX_PADDING: u64 = 256;
+/// Headers that are never logged by `--trace-http`
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+/// Limit for rebuilder API responses, like search results and signing keys
+const MAX_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+/// Where rebuilders without the rebuilderd API publish their keyring, relative to the host
+const WELL_KNOWN_KEYRING: &str = "/.well-known/repro-threshold/keys.pem";
+/// Stop following paginated search results after this many pages
+const MAX_SEARCH_PAGES: usize = 10;
+/// Only fetch the most recent builds of a package from each rebuilder
+const MAX_ARTIFACTS_PER_PACKAGE: usize = 5;
+/// Stop fetching the attestations of a source package after this many, over all its binary packages
+const MAX_ARTIFACTS_PER_SOURCE: usize = 100;
+
 pub fn client() -> Client {
-    let client = reqwest::Client::builder()
-        .user_agent(USER_AGENT)
+    client_with_settings(&Privacy::default(), &Network::default())
+        .expect("Failed to setup HTTP client")
+}
+
+/// Only resolve hostnames to addresses of one IP version
+struct FamilyResolver(IpVersion);
+
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let version = self.0;
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| version.matches(&addr.ip()))
+                .collect::<Vec<_>>();
+            if addrs.is_empty() {
+                let msg = format!("No {version:?} address found for {:?}", name.as_str());
+                return Err(io::Error::new(io::ErrorKind::NotFound, msg).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Which kind of server a client talks to, they have different redirect rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Endpoint {
+    Mirror,
+    Rebuilder,
+}
+
+/// `*.example.com` matches all subdomains of example.com, anything else only the exact host
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+/// If `url` is `prefix` or a path below it, on the same scheme, host and port
+///
+/// The urls are compared parsed, so `https://example.com` doesn't match `https://example.com.evil/`
+/// and `https://example.com/a` doesn't match `https://example.com/ab`.
+pub fn is_below(prefix: &Url, url: &Url) -> bool {
+    if url.scheme() != prefix.scheme()
+        || url.host() != prefix.host()
+        || url.port_or_known_default() != prefix.port_or_known_default()
+    {
+        return false;
+    }
+    let path = prefix.path().trim_end_matches('/');
+    url.path()
+        .strip_prefix(path)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Check a redirect to `next`, `previous` starts with the url that was originally requested
+fn check_redirect(
+    redirects: &Redirects,
+    endpoint: Endpoint,
+    previous: &[Url],
+    next: &Url,
+) -> Result<()> {
+    let max_hops = redirects.max_hops();
+    if previous.len() > max_hops {
+        bail!("Too many redirects (limit is {max_hops})");
+    }
+    if let Some(last) = previous.last()
+        && last.scheme() == "https"
+        && next.scheme() != "https"
+    {
+        bail!("Refusing redirect from https to {}", next.scheme());
+    }
+
+    let origin = previous.first().and_then(|url| url.host_str());
+    let host = next.host_str().unwrap_or_default();
+    if origin == Some(host) {
+        return Ok(());
+    }
+    match endpoint {
+        Endpoint::Rebuilder if !redirects.rebuilder_cross_host => {
+            bail!("Refusing redirect of rebuilder request to different host: {host:?}")
+        }
+        Endpoint::Mirror
+            if !redirects.mirror_hosts.is_empty()
+                && !redirects
+                    .mirror_hosts
+                    .iter()
+                    .any(|pattern| host_matches(pattern, host)) =>
+        {
+            bail!("Refusing redirect of mirror request to host that is not allowed: {host:?}")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn redirect_policy(network: &Network, endpoint: Endpoint) -> reqwest::redirect::Policy {
+    let redirects = network.redirects.clone();
+    reqwest::redirect::Policy::custom(move |attempt| {
+        match check_redirect(&redirects, endpoint, attempt.previous(), attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(err) => attempt.error(format!("{err:#}")),
+        }
+    })
+}
+
+/// Log where a download actually came from, if the mirror redirected it
+pub fn log_redirect(requested: &Url, response: &reqwest::Response) {
+    if response.url() != requested {
+        info!(
+            "Followed redirect: {} -> {}",
+            Redacted(requested),
+            Redacted(response.url())
+        );
+    }
+}
+
+/// Rebuilder clients, mirror downloads override the redirect policy
+fn builder(privacy: &Privacy, network: &Network) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder()
         .connect_timeout(CONNECT_TIMEOUT)
         .read_timeout(READ_TIMEOUT)
+        .redirect(redirect_policy(network, Endpoint::Rebuilder));
+    if let Some(version) = network.ip_version {
+        builder = builder.dns_resolver(FamilyResolver(version));
+    }
+    for (host, addrs) in &network.hosts {
+        // The port of the url is used if the port is 0
+        let addrs = addrs
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, 0))
+            .collect::<Vec<_>>();
+        builder = builder.resolve_to_addrs(host, &addrs);
+    }
+    match privacy.user_agent.as_deref() {
+        None => builder.user_agent(USER_AGENT),
+        Some("") => builder,
+        Some(user_agent) => builder.user_agent(user_agent),
+    }
+}
+
+/// A client that only speaks HTTP/3, for rebuilders listed in `network.http3`
+#[cfg(feature = "http3")]
+fn http3_client(privacy: &Privacy, network: &Network) -> Result<Option<reqwest::Client>> {
+    if network.http3.is_empty() {
+        return Ok(None);
+    }
+    let client = builder(privacy, network)
+        .http3_prior_knowledge()
         .build()
-        .expect("Failed to setup HTTP client");
-    Client { client }
+        .context("Failed to setup HTTP/3 client")?;
+    Ok(Some(client))
+}
+
+#[cfg(not(feature = "http3"))]
+fn http3_client(_privacy: &Privacy, network: &Network) -> Result<Option<reqwest::Client>> {
+    static UNSUPPORTED: std::sync::Once = std::sync::Once::new();
+    if !network.http3.is_empty() {
+        UNSUPPORTED.call_once(|| {
+            warn!("HTTP/3 is configured for rebuilders, but this build doesn't support it (`http3` feature)");
+        });
+    }
+    Ok(None)
+}
+
+/// A client with the privacy settings and rebuilder credentials of the config
+pub fn client_for_config(config: &Config) -> Result<Client> {
+    let client = client_with_settings(&config.privacy, &config.network)?;
+    let client = client.api_templates(config.rebuilder_apis.clone());
+    client.credentials(config.credentials.clone())
+}
+
+pub fn client_with_settings(privacy: &Privacy, network: &Network) -> Result<Client> {
+    // Mirror downloads are hashed and written to disk as-is, some servers
+    // wrongly send `Content-Encoding: gzip` for .gz files
+    let client = builder(privacy, network)
+        .redirect(redirect_policy(network, Endpoint::Mirror))
+        .no_brotli()
+        .no_gzip()
+        .no_zstd()
+        .build()
+        .context("Failed to setup HTTP client")?;
+    // Rebuilder API responses are negotiated with compression
+    let api = builder(privacy, network)
+        .build()
+        .context("Failed to setup HTTP client")?;
+    let http3 = http3_client(privacy, network)?;
+
+    let relay = if let Some(relay) = &privacy.relay {
+        let proxy = reqwest::Proxy::all(relay.clone())
+            .with_context(|| format!("Invalid relay url: {relay}"))?;
+        let client = builder(privacy, network)
+            .proxy(proxy)
+            .build()
+            .context("Failed to setup HTTP client for relay")?;
+        Some(client)
+    } else {
+        None
+    };
+
+    Ok(Client {
+        client,
+        api,
+        http3,
+        relay,
+        mode: Mode::Live,
+        privacy: privacy.clone(),
+        network: network.clone(),
+        credentials: Arc::new(vec![]),
+        identities: Arc::new(BTreeMap::new()),
+        secrets: Arc::new(BTreeMap::new()),
+        trace: None,
+        templates: Arc::new(vec![]),
+    })
+}
+
+/// Random number in `0..max`, returns 0 if `max` is 0
+pub fn random_below(max: u64) -> u64 {
+    if max == 0 {
+        return 0;
+    }
+    let mut buf = [0u8; 8];
+    SystemRandom::new()
+        .fill(&mut buf)
+        .expect("Failed to read random bytes");
+    u64::from_le_bytes(buf) % max
+}
+
+pub fn shuffle<T>(list: &mut [T]) {
+    for i in (1..list.len()).rev() {
+        let j = random_below(i as u64 + 1) as usize;
+        list.swap(i, j);
+    }
+}
+
+/// Split a `unix://` or `file://` rebuilder url into the local path and the rebuilderd API request target
+///
+/// `unix:///run/rebuilderd.sock/api/v1/meta/public-keys` is split into
+/// `/run/rebuilderd.sock` and `/api/v1/meta/public-keys`.
+fn local_target(url: &Url) -> Result<(PathBuf, String)> {
+    let Some((local, api)) = url.path().split_once("/api/v1/") else {
+        bail!("Url is not a rebuilderd API request: {}", Redacted(url));
+    };
+
+    let mut base = url.clone();
+    base.set_path(local);
+    base.set_query(None);
+    let path = base
+        .to_file_path()
+        .map_err(|_| anyhow!("Url has no valid local path: {}", Redacted(url)))?;
+
+    let mut target = format!("/api/v1/{api}");
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    Ok((path, target))
+}
+
+#[derive(Debug, Clone)]
+enum Mode {
+    Live,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+/// A rebuilder HTTP exchange, as stored on disk by record mode
+#[derive(Debug, Serialize, Deserialize)]
+struct Exchange {
+    url: Url,
+    status: u16,
+    body: String,
+}
+
+impl Exchange {
+    fn path(dir: &Path, url: &Url) -> PathBuf {
+        let hash = Sha256::digest(url.as_str().as_bytes());
+        dir.join(format!("{}.json", data_encoding::HEXLOWER.encode(&hash)))
+    }
+
+    async fn load(dir: &Path, url: &Url) -> Result<Self> {
+        let path = Self::path(dir, url);
+        let buf = fs::read(&path).await.with_context(|| {
+            format!("No recorded response for url: {} ({path:?})", Redacted(url))
+        })?;
+        let exchange = serde_json::from_slice(&buf)
+            .with_context(|| format!("Failed to parse recorded response: {path:?}"))?;
+        Ok(exchange)
+    }
+
+    async fn save(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create directory: {dir:?}"))?;
+        let path = Self::path(dir, &self.url);
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(&path, json)
+            .await
+            .with_context(|| format!("Failed to write recorded response: {path:?}"))?;
+        Ok(())
+    }
+}
+
+/// The value of a header for the trace log, secrets are redacted
+fn trace_value<'a>(
+    name: &reqwest::header::HeaderName,
+    value: &'a reqwest::header::HeaderValue,
+) -> Cow<'a, str> {
+    if value.is_sensitive() || SECRET_HEADERS.contains(&name.as_str()) {
+        "REDACTED".into()
+    } else {
+        String::from_utf8_lossy(value.as_bytes())
+    }
+}
+
+/// Log all headers of a request or response, secrets are redacted
+fn trace_headers(prefix: char, headers: &reqwest::header::HeaderMap) {
+    for (name, value) in headers {
+        info!("{prefix} {name}: {}", trace_value(name, value));
+    }
+}
+
+/// Append a response body to the trace file
+async fn trace_body(path: &Path, url: &Url, status: u16, body: &[u8]) -> Result<()> {
+    let mut entry = format!("### GET {} -> {status}\n", Redacted(url)).into_bytes();
+    entry.extend_from_slice(body);
+    entry.extend_from_slice(b"\n\n");
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open trace file: {path:?}"))?;
+    file.write_all(&entry)
+        .await
+        .with_context(|| format!("Failed to write trace file: {path:?}"))?;
+    Ok(())
 }
 
 #[derive(Clone)]
 pub struct Client {
+    /// Used for mirror downloads, without transparent decompression
     client: reqwest::Client,
+    /// Used for rebuilder queries
+    api: reqwest::Client,
+    /// Used for rebuilders that opted into HTTP/3
+    http3: Option<reqwest::Client>,
+    relay: Option<reqwest::Client>,
+    mode: Mode,
+    privacy: Privacy,
+    network: Network,
+    credentials: Arc<Vec<Credential>>,
+    /// Clients with a mutual TLS identity, keyed by credential url
+    identities: Arc<BTreeMap<Url, reqwest::Client>>,
+    /// The secrets of each credential, resolved on first use and shared by all clones
+    secrets: Arc<BTreeMap<Url, OnceCell<Auth>>>,
+    /// Log rebuilder requests and responses, the bodies are appended to the file if set
+    trace: Option<Option<PathBuf>>,
+    templates: Arc<Vec<ApiTemplate>>,
 }
 
 impl Client {
+    /// Write all rebuilder responses into a directory
+    pub fn record(mut self, dir: PathBuf) -> Self {
+        self.mode = Mode::Record(dir);
+        self
+    }
+
+    /// Answer rebuilder requests from a directory created by `record`, without using the network
+    pub fn replay(mut self, dir: PathBuf) -> Self {
+        self.mode = Mode::Replay(dir);
+        self
+    }
+
+    /// Log the headers of all rebuilder requests and responses, and optionally write the bodies
+    /// into a file
+    pub fn trace(mut self, bodies: Option<PathBuf>) -> Self {
+        self.trace = Some(bodies);
+        self
+    }
+
+    /// Query rebuilders with a nonstandard API through these url templates
+    pub fn api_templates(mut self, templates: Vec<ApiTemplate>) -> Self {
+        self.templates = Arc::new(templates);
+        self
+    }
+
+    /// Authenticate rebuilder requests with these credentials
+    pub fn credentials(mut self, credentials: Vec<Credential>) -> Result<Self> {
+        let mut identities = BTreeMap::new();
+        for credential in &credentials {
+            let Some(identity) = credential.identity()? else {
+                continue;
+            };
+            let mut builder = builder(&self.privacy, &self.network).identity(identity);
+            if let Some(relay) = &self.privacy.relay
+                && !self.is_relay_excluded(&credential.url)
+            {
+                let proxy = reqwest::Proxy::all(relay.clone())
+                    .with_context(|| format!("Invalid relay url: {relay}"))?;
+                builder = builder.proxy(proxy);
+            }
+            let client = builder
+                .build()
+                .with_context(|| format!("Failed to setup HTTP client for {}", credential.url))?;
+            identities.insert(credential.url.clone(), client);
+        }
+        self.secrets = Arc::new(
+            credentials
+                .iter()
+                .map(|credential| (credential.url.clone(), OnceCell::new()))
+                .collect(),
+        );
+        self.credentials = Arc::new(credentials);
+        self.identities = Arc::new(identities);
+        Ok(self)
+    }
+
     pub fn get<U: reqwest::IntoUrl>(&self, url: U) -> reqwest::RequestBuilder {
         self.client.get(url)
     }
 
+    pub fn post<U: reqwest::IntoUrl>(&self, url: U) -> reqwest::RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn privacy(&self) -> &Privacy {
+        &self.privacy
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    fn is_relay_excluded(&self, url: &Url) -> bool {
+        self.privacy.relay_exclude.iter().any(|r| is_below(r, url))
+    }
+
+    /// If queries to this rebuilder are sent through the relay
+    pub fn is_relayed(&self, url: &Url) -> bool {
+        self.relay.is_some() && !self.is_relay_excluded(url)
+    }
+
+    fn is_http3(&self, url: &Url) -> bool {
+        self.network.http3.iter().any(|r| is_below(r, url))
+    }
+
+    /// Pick the client for a rebuilder query, this is the relay unless the rebuilder opted out
+    ///
+    /// Rebuilders with a client certificate use a dedicated client, which honors the relay setting too.
+    /// HTTP/3 can't be sent through the relay, it's only used if the rebuilder is excluded from it.
+    fn rebuilder_client(&self, url: &Url) -> &reqwest::Client {
+        if let Some(credential) = Credential::find(&self.credentials, url)
+            && let Some(client) = self.identities.get(&credential.url)
+        {
+            return client;
+        }
+        match &self.relay {
+            Some(relay) if !self.is_relay_excluded(url) => {
+                trace!("Sending request through relay: {}", Redacted(url));
+                relay
+            }
+            _ => match &self.http3 {
+                Some(http3) if self.is_http3(url) => http3,
+                _ => &self.api,
+            },
+        }
+    }
+
+    /// Fetch a rebuilder API response, honoring record/replay mode
+    ///
+    /// Responses bigger than `limit` are rejected, without reading all of them into memory.
+    async fn fetch(&self, url: &Url, limit: usize) -> Result<Bytes> {
+        let (status, body) = self.fetch_response(url, limit).await?;
+        if !(200..300).contains(&status) {
+            bail!(
+                "Failed to fetch url: {}: HTTP status {status}",
+                Redacted(url)
+            );
+        }
+        Ok(body)
+    }
+
+    /// Like `fetch`, but return the HTTP status instead of failing on errors
+    async fn fetch_response(&self, url: &Url, limit: usize) -> Result<(u16, Bytes)> {
+        let (status, body) = if let Mode::Replay(dir) = &self.mode {
+            debug!("Replaying recorded response for url: {}", Redacted(url));
+            let exchange = Exchange::load(dir, url).await?;
+            let body = data_encoding::BASE64
+                .decode(exchange.body.as_bytes())
+                .with_context(|| {
+                    format!(
+                        "Failed to decode recorded response for url: {}",
+                        Redacted(url)
+                    )
+                })?;
+            (exchange.status, Bytes::from(body))
+        } else if url.scheme() == "file" {
+            // A local attestation dump, in the layout used by `serve-attestations`
+            let (dir, target) = local_target(url)?;
+            let index = serve::Index::open(&dir).await?;
+            let (status, body) = serve::respond(&index, &target).await?;
+            (status, Bytes::from(body))
+        } else {
+            let (client, request_url) = if url.scheme() == "unix" {
+                let (socket, target) = local_target(url)?;
+                let client = builder(&self.privacy, &self.network)
+                    .unix_socket(socket.as_path())
+                    .build()
+                    .with_context(|| format!("Failed to setup HTTP client for {socket:?}"))?;
+                // The host is only used for the Host header
+                let request_url = format!("http://localhost{target}").parse::<Url>()?;
+                (client, request_url)
+            } else {
+                (self.rebuilder_client(url).clone(), url.clone())
+            };
+
+            let mut request = client.get(request_url.clone());
+            if let Some(credential) = Credential::find(&self.credentials, url)
+                && let Some(secrets) = self.secrets.get(&credential.url)
+            {
+                let auth = secrets
+                    .get_or_try_init(|| credential.resolve())
+                    .await
+                    .with_context(|| {
+                        format!("Failed to load credentials for {}", credential.url)
+                    })?;
+                request = auth.apply(request);
+            }
+            if self.privacy.pad_requests {
+                let padding = "0".repeat(random_below(MAX_PADDING) as usize + 1);
+                request = request.header("X-Padding", padding);
+            }
+            let request = request
+                .build()
+                .with_context(|| format!("Failed to build request: {}", Redacted(url)))?;
+            if self.trace.is_some() {
+                info!("> {} {}", request.method(), Redacted(url));
+                trace_headers('>', request.headers());
+            }
+            let mut response = client
+                .execute(request)
+                .await
+                .with_context(|| format!("Failed to fetch url: {}", Redacted(url)))?;
+            let status = response.status().as_u16();
+            if response.url() != &request_url {
+                debug!(
+                    "Rebuilder redirected {} -> {}",
+                    Redacted(url),
+                    Redacted(response.url())
+                );
+            }
+            if self.trace.is_some() {
+                info!("< {:?} {}", response.version(), response.status());
+                trace_headers('<', response.headers());
+            }
+            let mut body = Vec::new();
+            while let Some(chunk) = response
+                .chunk()
+                .await
+                .with_context(|| format!("Failed to fetch url: {}", Redacted(url)))?
+            {
+                if body.len() + chunk.len() > limit {
+                    bail!("Response from url exceeds {limit} bytes: {}", Redacted(url));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            let body = Bytes::from(body);
+
+            if let Some(Some(path)) = &self.trace
+                && let Err(err) = trace_body(path, url, status, &body).await
+            {
+                warn!("{err:#}");
+            }
+
+            if let Mode::Record(dir) = &self.mode {
+                let exchange = Exchange {
+                    url: url.clone(),
+                    status,
+                    body: data_encoding::BASE64.encode(&body),
+                };
+                exchange.save(dir).await?;
+            }
+
+            (status, body)
+        };
+
+        if body.len() > limit {
+            bail!("Response from url exceeds {limit} bytes: {}", Redacted(url));
+        }
+
+        Ok((status, body))
+    }
+
     pub async fn fetch_signing_keyring(&self, url: &Url) -> Result<String> {
         let (mut url, base_url) = (url.clone(), url);
 
         url.path_segments_mut()
-            .map_err(|_| anyhow!("Failed to get path from url: {base_url}"))?
+            .map_err(|_| anyhow!("Failed to get path from url: {}", Redacted(base_url)))?
             .pop_if_empty()
             .push("api")
             .push("v1")
             .push("meta")
             .push("public-keys");
 
-        debug!("Running search query on rebuilder: {url}");
-        let response = self
-            .get(url.clone())
-            .send()
-            .await
-            .with_context(|| format!("Failed to fetch url: {url}"))?
-            .error_for_status()
-            .with_context(|| format!("Failed to fetch url: {url}"))?
-            .json::<PublicKeys>()
-            .await
-            .with_context(|| format!("Failed to fetch url: {url}"))?;
+        debug!("Running search query on rebuilder: {}", Redacted(&url));
+        let (status, body) = self.fetch_response(&url, MAX_RESPONSE_SIZE).await?;
+        if status == 404 && matches!(base_url.scheme(), "http" | "https") {
+            debug!("Rebuilder has no public-keys endpoint, trying well-known location");
+            return self.fetch_well_known_keyring(base_url).await;
+        }
+        if !(200..300).contains(&status) {
+            bail!(
+                "Failed to fetch url: {}: HTTP status {status}",
+                Redacted(&url)
+            );
+        }
+        let response = serde_json::from_slice::<PublicKeys>(&body)
+            .with_context(|| format!("Failed to parse response from url: {}", Redacted(&url)))?;
 
         response
             .current
             .into_iter()
             .next()
-            .with_context(|| format!("No public keys found at url: {url}"))
+            .with_context(|| format!("No public keys found at url: {}", Redacted(&url)))
+    }
+
+    /// Fetch the keyring from a static location, for providers that don't run rebuilderd
+    async fn fetch_well_known_keyring(&self, url: &Url) -> Result<String> {
+        let url = url.join(WELL_KNOWN_KEYRING)?;
+        debug!("Fetching signing keyring from: {}", Redacted(&url));
+        let body = self.fetch(&url, MAX_RESPONSE_SIZE).await?;
+        let keyring = String::from_utf8(body.to_vec())
+            .with_context(|| format!("Keyring is not valid utf-8: {}", Redacted(&url)))?;
+        let keys = signing::pem_fingerprints(keyring.as_bytes())
+            .with_context(|| format!("Failed to parse keyring from url: {}", Redacted(&url)))?;
+        if keys.is_empty() {
+            bail!("No public keys found at url: {}", Redacted(&url));
+        }
+        Ok(keyring)
+    }
+
+    pub async fn fetch_attestation(&self, url: &Url) -> Result<Attestation> {
+        let response = self.fetch(url, attestation::MAX_ATTESTATION_SIZE).await?;
+        Attestation::parse(&response).with_context(|| {
+            format!(
+                "Failed to parse attestation from rebuilder: {}",
+                Redacted(url)
+            )
+        })
     }
 
     pub async fn fetch_attestations_for_pkg(
@@ -72,36 +717,220 @@ impl Client {
         url: &Url,
         inspect: &Deb,
     ) -> Result<attestation::Tree> {
+        let query = [
+            ("name", inspect.name.as_str()),
+            ("version", inspect.version.as_str()),
+            ("architecture", inspect.architecture.as_str()),
+        ];
+
+        let mut attestations = attestation::Tree::default();
+        let found = self
+            .search_attestations(url, &query, MAX_ARTIFACTS_PER_PACKAGE)
+            .await?;
+        for (_record, url, attestation) in found {
+            attestations.insert(url.to_string(), attestation);
+        }
+
+        Ok(attestations)
+    }
+
+    /// Fetch the attestations of all binary packages built from a source package
+    pub async fn fetch_attestations_for_source(
+        &self,
+        url: &Url,
+        source: &str,
+        version: &str,
+    ) -> Result<BTreeMap<Deb, attestation::Tree>> {
+        let query = [("source", source), ("source_version", version)];
+
+        let mut artifacts = BTreeMap::<_, attestation::Tree>::new();
+        let found = self
+            .search_attestations(url, &query, MAX_ARTIFACTS_PER_SOURCE)
+            .await?;
+        for (record, url, attestation) in found {
+            let (Some(name), Some(version), Some(architecture)) =
+                (record.name, record.version, record.architecture)
+            else {
+                continue;
+            };
+            let artifact = Deb {
+                name,
+                version,
+                architecture,
+                source: source.to_string(),
+                ..Default::default()
+            };
+            artifacts
+                .entry(artifact)
+                .or_default()
+                .insert(url.to_string(), attestation);
+        }
+
+        Ok(artifacts)
+    }
+
+    /// Download the artifacts a rebuilder has built for a package, reproduced or not
+    pub async fn fetch_rebuilt_artifacts(
+        &self,
+        url: &Url,
+        inspect: &Deb,
+    ) -> Result<Vec<(Url, Bytes)>> {
+        let query = [
+            ("name", inspect.name.as_str()),
+            ("version", inspect.version.as_str()),
+            ("architecture", inspect.architecture.as_str()),
+        ];
+
+        let found = self.search_artifacts(url, &query, "download").await?;
+        let mut artifacts = Vec::new();
+        for (_record, url) in found.into_iter().take(MAX_ARTIFACTS_PER_PACKAGE) {
+            debug!(
+                "Downloading rebuilt artifact from rebuilder: {}",
+                Redacted(&url)
+            );
+            // Rebuilt artifacts are as big as the package they are compared to
+            let artifact = self.fetch(&url, usize::MAX).await?;
+            artifacts.push((url, artifact));
+        }
+
+        Ok(artifacts)
+    }
+
+    /// Download the attestations of the most recent builds found by a search query
+    ///
+    /// At most `MAX_ARTIFACTS_PER_PACKAGE` builds of each package are fetched, and `limit` in total.
+    async fn search_attestations(
+        &self,
+        url: &Url,
+        query: &[(&str, &str)],
+        limit: usize,
+    ) -> Result<Vec<(SearchRecord, Url, Attestation)>> {
+        let mut found = self.search_artifacts(url, query, "attestation").await?;
+        found.retain(|(record, url)| match record.mismatch(query) {
+            Some(reason) => {
+                debug!("Skipping search result ({reason}): {}", Redacted(url));
+                false
+            }
+            None => true,
+        });
+        let mut builds = BTreeMap::<_, usize>::new();
+        found.retain(|(record, _)| {
+            let package = (
+                record.name.clone(),
+                record.version.clone(),
+                record.architecture.clone(),
+            );
+            let count = builds.entry(package).or_default();
+            *count += 1;
+            *count <= MAX_ARTIFACTS_PER_PACKAGE
+        });
+        if found.len() > limit {
+            debug!(
+                "Rebuilder has {} builds, only fetching the {limit} most recent: {}",
+                found.len(),
+                Redacted(url)
+            );
+        }
+        let mut attestations = Vec::new();
+        for (record, url) in found.into_iter().take(limit) {
+            debug!("Downloading attestation from rebuilder: {}", Redacted(&url));
+            let attestation = self.fetch_attestation(&url).await?;
+            attestations.push((record, url, attestation));
+        }
+        Ok(attestations)
+    }
+
+    /// Run a search query and return the url of `endpoint` for each artifact found, the most
+    /// recent builds first
+    async fn search_artifacts(
+        &self,
+        url: &Url,
+        query: &[(&str, &str)],
+        endpoint: &str,
+    ) -> Result<Vec<(SearchRecord, Url)>> {
         let (mut url, base_url) = (url.clone(), url);
 
-        url.path_segments_mut()
-            .map_err(|_| anyhow!("Failed to get path from url: {base_url}"))?
-            .pop_if_empty()
-            .push("api")
-            .push("v1")
-            .push("packages")
-            .push("binary");
-        url.query_pairs_mut()
-            .append_pair("name", &inspect.name)
-            .append_pair("version", &inspect.version)
-            .append_pair("architecture", &inspect.architecture);
-
-        debug!("Running search query on rebuilder: {url}");
-        let search = self
-            .get(url.clone())
-            .send()
-            .await
-            .with_context(|| format!("Failed to fetch url: {url}"))?
-            .error_for_status()
-            .with_context(|| format!("Failed to fetch url: {url}"))?
-            .json::<Search>()
-            .await
-            .with_context(|| format!("Failed to fetch url: {url}"))?;
-        trace!("Rebuilder search response: {search:#?}");
+        let template = ApiTemplate::find(&self.templates, base_url);
+        let mut vars = query.iter().copied().collect::<BTreeMap<_, _>>();
+        if let Some(arch) = vars.get("architecture").copied() {
+            vars.insert("arch", arch);
+        }
 
-        let mut attestations = attestation::Tree::default();
+        if let Some(search) = template.and_then(|t| t.search.as_deref()) {
+            url = ApiTemplate::render(search, base_url, &vars)?;
+        } else {
+            url.path_segments_mut()
+                .map_err(|_| anyhow!("Failed to get path from url: {}", Redacted(base_url)))?
+                .pop_if_empty()
+                .push("api")
+                .push("v1")
+                .push("packages")
+                .push("binary");
+            url.query_pairs_mut().extend_pairs(query);
+        }
+
+        let mut records = Vec::<SearchRecord>::new();
+        let mut seen = BTreeSet::new();
+        let mut page = Some(url);
+        for _ in 0..MAX_SEARCH_PAGES {
+            let Some(url) = page.take() else {
+                break;
+            };
+            debug!("Running search query on rebuilder: {}", Redacted(&url));
+            let body = self.fetch(&url, MAX_RESPONSE_SIZE).await?;
+            let search = serde_json::from_slice::<Search>(&body).with_context(|| {
+                format!("Failed to parse response from url: {}", Redacted(&url))
+            })?;
+            trace!("Rebuilder search response: {search:#?}");
+
+            page = search.next_page(&url, records.len() + search.records.len())?;
+            for record in search.records {
+                // Pages can overlap if builds were added while paging
+                let key = (record.build_id, record.artifact_id);
+                if key.0.is_some() && !seen.insert(key) {
+                    continue;
+                }
+                records.push(record);
+            }
+        }
+        if let Some(page) = page {
+            warn!(
+                "Rebuilder search has more than {MAX_SEARCH_PAGES} pages, ignoring the rest: {}",
+                Redacted(&page)
+            );
+        }
+        // Build ids are assigned in order, this puts the most recent builds first
+        records.sort_by_key(|record| Reverse(record.build_id));
+
+        let mut artifacts = Vec::new();
+
+        let attestation = template
+            .and_then(|t| t.attestation.as_deref())
+            .filter(|_| endpoint == "attestation");
+        for record in records {
+            if let Some(attestation) = attestation {
+                let build_id = record.build_id.map(|id| id.to_string());
+                let artifact_id = record.artifact_id.map(|id| id.to_string());
+                let mut vars = vars.clone();
+                for (key, value) in [
+                    ("build_id", &build_id),
+                    ("artifact_id", &artifact_id),
+                    ("hash", &record.sha256),
+                    ("name", &record.name),
+                    ("version", &record.version),
+                    ("arch", &record.architecture),
+                ] {
+                    if let Some(value) = value {
+                        vars.insert(key, value);
+                    }
+                }
+                match ApiTemplate::render(attestation, base_url, &vars) {
+                    Ok(url) => artifacts.push((record, url)),
+                    Err(err) => debug!("Skipping search result: {err:#}"),
+                }
+                continue;
+            }
 
-        for record in search.records {
             let Some(build_id) = record.build_id else {
                 continue;
             };
@@ -111,7 +940,7 @@ impl Client {
 
             let mut url = base_url.clone();
             url.path_segments_mut()
-                .map_err(|_| anyhow!("Failed to get path from url: {base_url}"))?
+                .map_err(|_| anyhow!("Failed to get path from url: {}", Redacted(base_url)))?
                 .pop_if_empty()
                 .push("api")
                 .push("v1")
@@ -119,41 +948,561 @@ impl Client {
                 .push(build_id.to_string().as_str())
                 .push("artifacts")
                 .push(artifact_id.to_string().as_str())
-                .push("attestation");
-
-            debug!("Downloading attestation from rebuilder: {url}");
-            let response = self
-                .get(url.clone())
-                .send()
-                .await
-                .with_context(|| format!("Failed to fetch url: {url}"))?
-                .error_for_status()
-                .with_context(|| format!("Failed to fetch url: {url}"))?
-                .bytes()
-                .await
-                .with_context(|| format!("Failed to fetch url: {url}"))?;
-
-            let attestation = Attestation::parse(&response)
-                .with_context(|| format!("Failed to parse attestation from rebuilder: {url}"))?;
-            attestations.insert(url.to_string(), attestation);
+                .push(endpoint);
+            artifacts.push((record, url));
         }
 
-        Ok(attestations)
+        Ok(artifacts)
     }
 }
 
 #[derive(Debug, Deserialize)]
 struct Search {
     records: Vec<SearchRecord>,
+    /// Link to the next page, for responses paginated with links
+    #[serde(default)]
+    next: Option<String>,
+    /// Number of records on all pages, for responses paginated with offsets
+    #[serde(default)]
+    total: Option<usize>,
+}
+
+impl Search {
+    /// The url of the next page, `fetched` is the number of records received so far
+    fn next_page(&self, url: &Url, fetched: usize) -> Result<Option<Url>> {
+        if let Some(next) = &self.next {
+            let next = url
+                .join(next)
+                .with_context(|| format!("Invalid url for next page: {next:?}"))?;
+            if (next.scheme(), next.host(), next.port_or_known_default())
+                != (url.scheme(), url.host(), url.port_or_known_default())
+            {
+                bail!(
+                    "Rebuilder linked next page on different host: {}",
+                    Redacted(&next)
+                );
+            }
+            return Ok(Some(next));
+        }
+
+        match self.total {
+            Some(total) if fetched < total && !self.records.is_empty() => {
+                let query = url
+                    .query_pairs()
+                    .filter(|(key, _)| key != "offset")
+                    .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                    .collect::<Vec<_>>();
+                let mut next = url.clone();
+                next.query_pairs_mut()
+                    .clear()
+                    .extend_pairs(query)
+                    .append_pair("offset", &fetched.to_string());
+                Ok(Some(next))
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct SearchRecord {
     build_id: Option<u64>,
     artifact_id: Option<u64>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    architecture: Option<String>,
+    /// Only used by url templates
+    #[serde(default)]
+    sha256: Option<String>,
+    /// The build result, e.g. `GOOD`, `BAD` or `UNKWN`
+    #[serde(default)]
+    status: Option<String>,
+}
+
+impl SearchRecord {
+    /// Why the attestation of this record can't be used for the query, if it can't
+    fn mismatch(&self, query: &[(&str, &str)]) -> Option<String> {
+        if let Some(status) = &self.status
+            && status != "GOOD"
+        {
+            return Some(format!("build status is {status:?}"));
+        }
+        for (key, field) in [
+            ("name", &self.name),
+            ("version", &self.version),
+            ("architecture", &self.architecture),
+        ] {
+            let expected = query.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+            if let (Some(expected), Some(value)) = (expected, field)
+                && value != expected
+            {
+                return Some(format!("{key} is {value:?}, expected {expected:?}"));
+            }
+        }
+        None
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct PublicKeys {
     current: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use std::env;
+
+    fn urls(urls: &[&str]) -> Vec<Url> {
+        urls.iter().map(|url| url.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_is_below() {
+        let prefix = "https://rebuilder.example.org/".parse().unwrap();
+        let below = |url: &str| is_below(&prefix, &url.parse().unwrap());
+        assert!(below("https://rebuilder.example.org/api/v1/builds"));
+        assert!(below("https://rebuilder.example.org:443/"));
+        assert!(!below("https://rebuilder.example.org.evil/api/v1/builds"));
+        assert!(!below("https://rebuilder.example.org:8443/"));
+        assert!(!below("http://rebuilder.example.org/"));
+
+        let prefix = "https://example.org/debian".parse().unwrap();
+        let below = |url: &str| is_below(&prefix, &url.parse().unwrap());
+        assert!(below("https://example.org/debian"));
+        assert!(below("https://example.org/debian/api"));
+        assert!(!below("https://example.org/debian-security/api"));
+
+        let prefix = "unix:///run/rebuilderd.sock".parse().unwrap();
+        assert!(is_below(&prefix, &prefix));
+    }
+
+    #[test]
+    fn test_redirect_hops() {
+        let redirects = Redirects {
+            max_hops: Some(2),
+            ..Default::default()
+        };
+        let next = "https://a.example.com/c".parse().unwrap();
+        let previous = urls(&["https://a.example.com/a", "https://a.example.com/b"]);
+        assert!(check_redirect(&redirects, Endpoint::Rebuilder, &previous, &next).is_ok());
+        let previous = urls(&[
+            "https://a.example.com/a",
+            "https://a.example.com/b",
+            "https://a.example.com/c",
+        ]);
+        assert!(check_redirect(&redirects, Endpoint::Rebuilder, &previous, &next).is_err());
+
+        let redirects = Redirects {
+            max_hops: Some(0),
+            ..Default::default()
+        };
+        let previous = urls(&["https://a.example.com/a"]);
+        assert!(check_redirect(&redirects, Endpoint::Mirror, &previous, &next).is_err());
+    }
+
+    #[test]
+    fn test_redirect_downgrade() {
+        let redirects = Redirects::default();
+        let previous = urls(&["https://a.example.com/a"]);
+        let next = "http://a.example.com/b".parse().unwrap();
+        assert!(check_redirect(&redirects, Endpoint::Mirror, &previous, &next).is_err());
+        let previous = urls(&["http://a.example.com/a"]);
+        let next = "https://a.example.com/b".parse().unwrap();
+        assert!(check_redirect(&redirects, Endpoint::Mirror, &previous, &next).is_ok());
+    }
+
+    #[test]
+    fn test_redirect_hosts() {
+        let mut redirects = Redirects::default();
+        let previous = urls(&["https://a.example.com/a"]);
+        let other = "https://b.example.com/b".parse().unwrap();
+        assert!(check_redirect(&redirects, Endpoint::Rebuilder, &previous, &other).is_err());
+        assert!(check_redirect(&redirects, Endpoint::Mirror, &previous, &other).is_ok());
+
+        redirects.rebuilder_cross_host = true;
+        redirects.mirror_hosts = vec!["*.cdn.example.net".to_string()];
+        assert!(check_redirect(&redirects, Endpoint::Rebuilder, &previous, &other).is_ok());
+        assert!(check_redirect(&redirects, Endpoint::Mirror, &previous, &other).is_err());
+        let cdn = "https://eu.cdn.example.net/b".parse().unwrap();
+        assert!(check_redirect(&redirects, Endpoint::Mirror, &previous, &cdn).is_ok());
+        let same = "https://a.example.com/b".parse().unwrap();
+        assert!(check_redirect(&redirects, Endpoint::Mirror, &previous, &same).is_ok());
+
+        assert!(host_matches("*.example.com", "a.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "aexample.com"));
+        assert!(host_matches("example.com", "example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_static_hosts() {
+        let mut url = test_server::spawn_rebuilder().await;
+        url.set_host(Some("rebuilder.invalid")).unwrap();
+
+        let network = Network {
+            ip_version: Some(IpVersion::V4),
+            hosts: BTreeMap::from([(
+                "rebuilder.invalid".to_string(),
+                vec!["127.0.0.1".parse().unwrap()],
+            )]),
+            ..Default::default()
+        };
+        let http = client_with_settings(&Privacy::default(), &network).unwrap();
+        let keyring = http.fetch_signing_keyring(&url).await.unwrap();
+        assert_eq!(keyring, test_server::DEB_KEYRING);
+    }
+
+    #[tokio::test]
+    async fn test_well_known_keyring() {
+        let url = test_server::spawn(BTreeMap::from([(
+            "/.well-known/repro-threshold/keys.pem".to_string(),
+            test_server::DEB_KEYRING.as_bytes().to_vec(),
+        )]))
+        .await;
+        let keyring = client()
+            .fetch_signing_keyring(&url.join("rebuilds/").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(keyring, test_server::DEB_KEYRING);
+
+        let url = test_server::spawn(BTreeMap::new()).await;
+        assert!(client().fetch_signing_keyring(&url).await.is_err());
+    }
+
+    #[test]
+    fn test_trace_value() {
+        use reqwest::header::{self, HeaderName, HeaderValue};
+
+        let value = HeaderValue::from_static("Bearer hunter2");
+        assert_eq!(trace_value(&header::AUTHORIZATION, &value), "REDACTED");
+        let name = HeaderName::from_static("x-api-key");
+        assert_eq!(trace_value(&name, &value), "REDACTED");
+        let mut sensitive = value.clone();
+        sensitive.set_sensitive(true);
+        assert_eq!(trace_value(&header::ACCEPT, &sensitive), "REDACTED");
+        assert_eq!(trace_value(&header::ACCEPT, &value), "Bearer hunter2");
+    }
+
+    #[tokio::test]
+    async fn test_trace_bodies() {
+        let url = test_server::spawn_rebuilder().await;
+        let path = env::temp_dir().join(format!(
+            "repro-threshold-test-trace-{}.log",
+            std::process::id()
+        ));
+        let http = client().trace(Some(path.clone()));
+        let keyring = http.fetch_signing_keyring(&url).await.unwrap();
+
+        let trace = fs::read_to_string(&path).await.unwrap();
+        fs::remove_file(&path).await.unwrap();
+        assert!(trace.starts_with(&format!("### GET {url}api/v1/meta/public-keys -> 200\n")));
+        assert!(trace.contains(&serde_json::to_string(&keyring).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_api_template() {
+        let search = serde_json::json!({
+            "records": [{ "sha256": "00".repeat(32), "name": "librust-as-slice-dev" }]
+        });
+        let url = test_server::spawn(BTreeMap::from([(
+            "/search/librust-as-slice-dev/0.2.1-1%2Bb2/amd64".to_string(),
+            serde_json::to_vec(&search).unwrap(),
+        )]))
+        .await;
+
+        let http = client().api_templates(vec![ApiTemplate {
+            url: url.clone(),
+            search: Some("{base}/search/{name}/{version}/{arch}".to_string()),
+            attestation: Some("{base}/attestations/{hash}".to_string()),
+        }]);
+        let deb = test_server::deb();
+        let query = [
+            ("name", deb.name.as_str()),
+            ("version", deb.version.as_str()),
+            ("architecture", deb.architecture.as_str()),
+        ];
+        let artifacts = http
+            .search_artifacts(&url, &query, "attestation")
+            .await
+            .unwrap();
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(
+            artifacts[0].1,
+            url.join(&format!("attestations/{}", "00".repeat(32)))
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_pagination() {
+        let first = serde_json::json!({
+            "records": [{ "build_id": 1, "artifact_id": 1 }, { "build_id": 3, "artifact_id": 3 }],
+            "next": "/api/v1/packages/binary/2",
+        });
+        let second = serde_json::json!({
+            "records": [{ "build_id": 3, "artifact_id": 3 }, { "build_id": 2, "artifact_id": 2 }],
+        });
+        let url = test_server::spawn(BTreeMap::from([
+            (
+                "/api/v1/packages/binary".to_string(),
+                serde_json::to_vec(&first).unwrap(),
+            ),
+            (
+                "/api/v1/packages/binary/2".to_string(),
+                serde_json::to_vec(&second).unwrap(),
+            ),
+        ]))
+        .await;
+
+        let artifacts = client()
+            .search_artifacts(&url, &[("name", "foo")], "attestation")
+            .await
+            .unwrap();
+        let build_ids = artifacts
+            .iter()
+            .map(|(record, _)| record.build_id)
+            .collect::<Vec<_>>();
+        assert_eq!(build_ids, [Some(3), Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn test_search_record_mismatch() {
+        let query = [("name", "foo"), ("version", "1.0-1")];
+        let record = |value| serde_json::from_value::<SearchRecord>(value).unwrap();
+
+        let good = record(serde_json::json!({ "build_id": 1, "artifact_id": 1, "status": "GOOD" }));
+        assert_eq!(good.mismatch(&query), None);
+        let unknown = record(serde_json::json!({ "build_id": 1, "artifact_id": 1 }));
+        assert_eq!(unknown.mismatch(&query), None);
+
+        let bad = record(serde_json::json!({ "build_id": 1, "artifact_id": 1, "status": "BAD" }));
+        assert!(bad.mismatch(&query).is_some());
+        let old =
+            record(serde_json::json!({ "build_id": 1, "artifact_id": 1, "version": "0.9-1" }));
+        assert!(old.mismatch(&query).is_some());
+    }
+
+    #[test]
+    fn test_search_next_page() {
+        let url = "https://rebuilder.example.com/api/v1/packages/binary?name=foo&offset=2"
+            .parse::<Url>()
+            .unwrap();
+        let search = serde_json::from_value::<Search>(serde_json::json!({
+            "records": [{ "build_id": 1, "artifact_id": 1 }],
+            "total": 5,
+        }))
+        .unwrap();
+        assert_eq!(
+            search.next_page(&url, 3).unwrap().unwrap().as_str(),
+            "https://rebuilder.example.com/api/v1/packages/binary?name=foo&offset=3"
+        );
+        assert_eq!(search.next_page(&url, 5).unwrap(), None);
+
+        let search = serde_json::from_value::<Search>(serde_json::json!({
+            "records": [],
+            "next": "https://evil.example.com/api/v1/packages/binary",
+        }))
+        .unwrap();
+        assert!(search.next_page(&url, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_family_resolver() {
+        let addrs = FamilyResolver(IpVersion::V4)
+            .resolve("localhost".parse().unwrap())
+            .await
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|addr| addr.is_ipv4()));
+    }
+
+    #[test]
+    fn test_local_target() {
+        let url = "unix:///run/rebuilderd.sock/api/v1/packages/binary?name=foo"
+            .parse()
+            .unwrap();
+        let (path, target) = local_target(&url).unwrap();
+        assert_eq!(path, Path::new("/run/rebuilderd.sock"));
+        assert_eq!(target, "/api/v1/packages/binary?name=foo");
+
+        let url = "file:///srv/attestations/".parse().unwrap();
+        assert!(local_target(&url).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_rebuilder() {
+        let dir = env::current_dir().unwrap().join("test_data");
+        let url = Url::from_directory_path(&dir).unwrap();
+        let deb = test_server::deb();
+        let query = [
+            ("name", deb.name.as_str()),
+            ("version", deb.version.as_str()),
+            ("architecture", deb.architecture.as_str()),
+        ];
+
+        let http = client();
+        let artifacts = http
+            .search_artifacts(&url, &query, "attestation")
+            .await
+            .unwrap();
+        assert_eq!(artifacts.len(), 1);
+
+        let body = http
+            .fetch(&artifacts[0].1, attestation::MAX_ATTESTATION_SIZE)
+            .await
+            .unwrap();
+        let expected = fs::read(dir.join("librust-as-slice-dev_0.2.1-1+b2_amd64.in-toto.link"))
+            .await
+            .unwrap();
+        assert_eq!(body, expected);
+
+        // There's no signing-key.pub in test_data
+        assert!(http.fetch_signing_keyring(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unix_rebuilder() {
+        let path = env::temp_dir().join(format!(
+            "repro-threshold-test-unix-{}.sock",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path).await;
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (stream, _addr) = listener.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let (_method, target) = serve::read_request(tokio::io::BufReader::new(reader))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(target, "/api/v1/meta/public-keys");
+            let body = serde_json::json!({ "current": [test_server::DEB_KEYRING] });
+            let body = serde_json::to_vec(&body).unwrap();
+            serve::write_response(writer, "200 OK", "application/json", &body)
+                .await
+                .unwrap();
+        });
+
+        let url = format!("unix://{}", path.display()).parse().unwrap();
+        let keyring = client().fetch_signing_keyring(&url).await;
+        let _ = fs::remove_file(&path).await;
+        assert_eq!(keyring.unwrap(), test_server::DEB_KEYRING);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_limit() {
+        let url =
+            test_server::spawn(BTreeMap::from([("/big".to_string(), vec![b'x'; 1024])])).await;
+        let url = url.join("big").unwrap();
+
+        let http = client();
+        assert_eq!(http.fetch(&url, 1024).await.unwrap().len(), 1024);
+        let err = http.fetch(&url, 1023).await.unwrap_err();
+        assert!(format!("{err:#}").contains("exceeds 1023 bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_credentials_resolved_once() {
+        let url = test_server::spawn(BTreeMap::from([("/a".to_string(), b"a".to_vec())])).await;
+        let dir = tempfile::tempdir().unwrap();
+        let token = dir.path().join("token");
+        std::fs::write(&token, "hunter2\n").unwrap();
+        let credential = Credential {
+            url: url.clone(),
+            token: Some(crate::credentials::Secret::File(token.clone())),
+            username: None,
+            password: None,
+            client_cert: None,
+            client_key: None,
+        };
+        let http = client().credentials(vec![credential]).unwrap();
+        let url = url.join("a").unwrap();
+        http.fetch(&url, 1024).await.unwrap();
+
+        // The secret isn't read again, also not by clones of the client
+        std::fs::remove_file(&token).unwrap();
+        http.clone().fetch(&url, 1024).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record_replay() {
+        let dir = env::temp_dir().join(format!(
+            "repro-threshold-test-record-replay-{}",
+            std::process::id()
+        ));
+        let url = test_server::spawn_rebuilder().await;
+
+        let recorded = client()
+            .record(dir.clone())
+            .fetch_signing_keyring(&url)
+            .await
+            .unwrap();
+        assert_eq!(recorded, test_server::DEB_KEYRING);
+
+        let replay = client().replay(dir.clone());
+        let replayed = replay.fetch_signing_keyring(&url).await;
+        let missing = replay
+            .fetch_signing_keyring(&"http://127.0.0.1:1/".parse().unwrap())
+            .await;
+        let _ = fs::remove_dir_all(&dir).await;
+
+        assert_eq!(replayed.unwrap(), recorded);
+        assert!(missing.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_attestations_for_source() {
+        let search = serde_json::json!({
+            "records": [
+                {
+                    "build_id": 1,
+                    "artifact_id": 1,
+                    "name": "librust-as-slice-dev",
+                    "version": "0.2.1-1+b2",
+                    "architecture": "amd64",
+                },
+                { "build_id": 2, "artifact_id": 2 },
+            ]
+        });
+        let url = test_server::spawn(BTreeMap::from([
+            (
+                "/api/v1/packages/binary".to_string(),
+                serde_json::to_vec(&search).unwrap(),
+            ),
+            (
+                "/api/v1/builds/1/artifacts/1/attestation".to_string(),
+                include_bytes!("../test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.in-toto.link")
+                    .to_vec(),
+            ),
+            (
+                "/api/v1/builds/2/artifacts/2/attestation".to_string(),
+                include_bytes!("../test_data/filesystem-2025.10.12-1-any.in-toto.link").to_vec(),
+            ),
+        ]))
+        .await;
+
+        let artifacts = client()
+            .fetch_attestations_for_source(&url, "rust-as-slice", "0.2.1-1")
+            .await
+            .unwrap();
+        assert_eq!(artifacts.keys().collect::<Vec<_>>(), [&test_server::deb()]);
+        assert_eq!(
+            artifacts[&test_server::deb()].candidate_sha256s(),
+            [test_server::deb_sha256()].into()
+        );
+    }
+
+    #[test]
+    fn test_shuffle_keeps_items() {
+        let mut list = (0..32).collect::<Vec<_>>();
+        shuffle(&mut list);
+        list.sort();
+        assert_eq!(list, (0..32).collect::<Vec<_>>());
+    }
+}
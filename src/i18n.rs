@@ -0,0 +1,169 @@
+//! Translations of user-facing messages
+//!
+//! Messages are Fluent resources compiled into the binary and looked up by their id, English is
+//! the fallback for messages that aren't translated. The language is selected with the usual
+//! `LC_ALL`, `LC_MESSAGES` and `LANG` variables.
+
+use crate::errors::*;
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::env;
+use std::sync::{LazyLock, OnceLock};
+use unic_langid::LanguageIdentifier;
+
+type Bundle = FluentBundle<FluentResource>;
+
+const ENGLISH: &str = include_str!("../locales/en.ftl");
+const CATALOGS: &[(&str, &str)] = &[("de", include_str!("../locales/de.ftl"))];
+
+static FALLBACK: LazyLock<Option<Bundle>> = LazyLock::new(|| bundle("en", ENGLISH));
+static CATALOG: OnceLock<Option<Bundle>> = OnceLock::new();
+
+/// The language code of a locale like `de_DE.UTF-8`, `C` and `POSIX` mean no translation
+fn language_of(locale: &str) -> Option<&str> {
+    let language = locale
+        .split(['_', '.', '@'])
+        .next()
+        .filter(|language| !language.is_empty())?;
+    match language {
+        "C" | "POSIX" => None,
+        language => Some(language),
+    }
+}
+
+fn language() -> Option<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())?;
+    language_of(&locale).map(String::from)
+}
+
+/// Select the catalog for the language of the environment
+///
+/// Until this is called, messages are shown in English.
+pub fn init() {
+    let catalog = language().and_then(|language| load(&language));
+    if CATALOG.set(catalog).is_err() {
+        warn!("Messages were already translated before the language was selected");
+    }
+}
+
+fn load(language: &str) -> Option<Bundle> {
+    let (_, source) = CATALOGS.iter().find(|(code, _)| *code == language)?;
+    bundle(language, source)
+}
+
+fn bundle(language: &str, source: &str) -> Option<Bundle> {
+    let langid = language
+        .parse::<LanguageIdentifier>()
+        .inspect_err(|err| warn!("Invalid language {language:?}: {err:#}"))
+        .ok()?;
+    let resource = FluentResource::try_new(source.to_string())
+        .map_err(|(_, errors)| warn!("Failed to load translations for {language:?}: {errors:?}"))
+        .ok()?;
+    let mut bundle = Bundle::new_concurrent(vec![langid]);
+    // Unicode isolation marks around values show up as garbage in terminals
+    bundle.set_use_isolating(false);
+    bundle
+        .add_resource(resource)
+        .map_err(|errors| warn!("Failed to load translations for {language:?}: {errors:?}"))
+        .ok()?;
+    Some(bundle)
+}
+
+fn format(bundle: &Bundle, id: &str, args: Option<&FluentArgs>) -> Option<String> {
+    let pattern = bundle.get_message(id)?.value()?;
+    let mut errors = Vec::new();
+    let msg = bundle.format_pattern(pattern, args, &mut errors);
+    if !errors.is_empty() {
+        debug!("Failed to format message {id:?}: {errors:?}");
+    }
+    Some(msg.into_owned())
+}
+
+/// Format the message with this id, falling back to English
+pub fn translate(id: &str, args: Option<&FluentArgs>) -> String {
+    let catalog = CATALOG.get().and_then(Option::as_ref);
+    catalog
+        .into_iter()
+        .chain(FALLBACK.as_ref())
+        .find_map(|bundle| format(bundle, id, args))
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Translate a message, e.g. `tr!("home-menu-quit")` or `tr!("detail-signatures", confirms = 1, threshold = 2)`
+macro_rules! tr {
+    ($id:literal) => {
+        $crate::i18n::translate($id, None)
+    };
+    ($id:literal, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = ::fluent_bundle::FluentArgs::new();
+        $(args.set(stringify!($name), $value.to_string());)+
+        $crate::i18n::translate($id, Some(&args))
+    }};
+}
+pub(crate) use tr;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    /// The messages of a catalog with the names of their placeholders
+    fn messages(source: &str) -> BTreeMap<&str, BTreeSet<&str>> {
+        source
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once(" = "))
+            .map(|(id, msg)| {
+                let placeholders = msg
+                    .split("{ $")
+                    .skip(1)
+                    .filter_map(|s| s.split_once(" }").map(|(name, _)| name))
+                    .collect();
+                (id, placeholders)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_language_of() {
+        assert_eq!(language_of("de_DE.UTF-8"), Some("de"));
+        assert_eq!(language_of("fr"), Some("fr"));
+        assert_eq!(language_of("sr_RS@latin"), Some("sr"));
+        assert_eq!(language_of("C.UTF-8"), None);
+        assert_eq!(language_of("POSIX"), None);
+        assert_eq!(language_of(""), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            tr!("fail-threshold-not-met", confirms = 1, threshold = 2),
+            "Threshold not met: only 1/2 required signatures"
+        );
+        assert_eq!(tr!("home-menu-quit"), "Quit");
+        assert_eq!(tr!("detail-outcome"), "Outcome: ");
+        // Values are never expanded themselves
+        assert_eq!(
+            tr!("detail-source", source = "{ $version }", version = "1.0-1"),
+            "Source: { $version } 1.0-1"
+        );
+        assert_eq!(tr!("missing-message"), "missing-message");
+    }
+
+    #[test]
+    fn test_catalogs() {
+        assert!(FALLBACK.is_some());
+        let english = messages(ENGLISH);
+        for (language, source) in CATALOGS {
+            assert!(load(language).is_some(), "{language}");
+            let catalog = messages(source);
+            assert!(!catalog.is_empty(), "{language}");
+            for (id, placeholders) in &catalog {
+                assert_eq!(english.get(id), Some(placeholders), "{language}: {id}");
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Parsed .deb metadata, keyed by the sha256 of the file
+//!
+//! The metadata only depends on the content of the file, so entries never expire. The file is
+//! hashed while it's parsed, an entry is only written if it matches the expected sha256. Bumping
+//! `VERSION` drops all entries written by an older parser. The size of the cache is limited by
+//! `[storage] cache_quota_mib`, reading an entry marks it as recently used.
+use crate::disk;
+use crate::errors::*;
+use crate::inspect::deb::{self, Deb};
+use crate::state;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::fs;
+use tokio::io::{self, AsyncRead, ReadBuf};
+
+const VERSION: u32 = 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    version: u32,
+    deb: Deb,
+}
+
+fn path(sha256: &[u8]) -> PathBuf {
+    let hex = data_encoding::HEXLOWER.encode(sha256);
+    state::cache_dir()
+        .join("inspect")
+        .join(format!("{hex}.json"))
+}
+
+/// Look up the metadata of a file that was inspected before
+pub async fn get(sha256: &[u8]) -> Option<Deb> {
+    let path = path(sha256);
+    let buf = fs::read(&path).await.ok()?;
+    match serde_json::from_slice::<Entry>(&buf) {
+        Ok(entry) if entry.version == VERSION => {
+            disk::touch(&path).await;
+            Some(entry.deb)
+        }
+        Ok(_) => None,
+        Err(err) => {
+            debug!("Ignoring invalid inspect cache entry {path:?}: {err:#}");
+            None
+        }
+    }
+}
+
+async fn write(path: &Path, deb: &Deb) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let entry = Entry {
+        version: VERSION,
+        deb: deb.clone(),
+    };
+    // Concurrent processes may write the same entry, each uses its own temporary file
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    fs::write(&tmp, serde_json::to_vec(&entry)?).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// Remember the metadata of a file, failures are logged but not fatal
+pub async fn insert(sha256: &[u8], deb: &Deb) {
+    let path = path(sha256);
+    if let Err(err) = write(&path, deb).await {
+        debug!("Failed to write inspect cache entry {path:?}: {err:#}");
+    }
+}
+
+/// Hash everything that's read through it
+struct Hashed<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Hashed<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.hasher.update(&buf.filled()[filled_before..]);
+        }
+        poll
+    }
+}
+
+/// Inspect a .deb with a known sha256, reusing the result of an earlier inspection
+///
+/// The reader is hashed while it's parsed, if it doesn't match the sha256 the inspection fails.
+pub async fn inspect<R: AsyncRead + Unpin>(reader: R, sha256: &[u8]) -> Result<Deb> {
+    if let Some(deb) = get(sha256).await {
+        debug!("Using cached .deb data: {deb:?}");
+        return Ok(deb);
+    }
+    let mut reader = Hashed {
+        inner: reader,
+        hasher: Sha256::new(),
+    };
+    let deb = deb::inspect(&mut reader).await?;
+    io::copy(&mut reader, &mut io::sink())
+        .await
+        .context("Failed to read the rest of the .deb")?;
+    if reader.hasher.finalize().as_slice() != sha256 {
+        bail!("The inspected .deb doesn't match its sha256, it was modified while reading");
+    }
+    insert(sha256, &deb).await;
+    Ok(deb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn test_cached_inspect() {
+        let sha256 = [0x42; 32];
+        assert_eq!(get(&sha256).await, None);
+
+        insert(&sha256, &test_server::deb()).await;
+        // Not a valid .deb, so this can only succeed from the cache
+        let deb = inspect(&b"not a .deb"[..], &sha256).await.unwrap();
+        assert_eq!(deb, test_server::deb());
+
+        let outdated = serde_json::json!({ "version": 0, "deb": test_server::deb() });
+        fs::write(path(&sha256), outdated.to_string())
+            .await
+            .unwrap();
+        assert_eq!(get(&sha256).await, None);
+        assert!(inspect(&b"not a .deb"[..], &sha256).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_inspect_hash_mismatch() {
+        let deb = fs::read("test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.deb")
+            .await
+            .unwrap();
+        let sha256 = Sha256::digest(&deb).to_vec();
+        let other = [0x23; 32];
+
+        // Not written to the cache under a hash of other content
+        assert!(inspect(deb.as_slice(), &other).await.is_err());
+        assert_eq!(get(&other).await, None);
+
+        let inspected = inspect(deb.as_slice(), &sha256).await.unwrap();
+        assert_eq!(get(&sha256).await, Some(inspected));
+    }
+}
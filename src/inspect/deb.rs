@@ -1,26 +1,101 @@
 use crate::errors::*;
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
 use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, BufReader};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Deb {
     pub name: String,
     pub version: String,
     pub architecture: String,
+    /// The source package this binary package was built from
+    pub source: String,
+    /// The version of the source package, if it differs from the binary version (e.g. binNMUs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintainer: Option<String>,
+    /// The `Installed-Size` in KiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_size: Option<u64>,
+    /// Other source packages included in this package, like `rustc (= 1.85.0+dfsg1-1)`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub built_using: Vec<String>,
+}
+
+impl Deb {
+    /// The version of the source package this binary package was built from
+    pub fn source_version(&self) -> &str {
+        self.source_version.as_deref().unwrap_or(&self.version)
+    }
+}
+
+/// Split a `Source` field like `rust-as-slice (0.2.1-1)` into name and version
+fn parse_source(field: &str) -> (&str, Option<&str>) {
+    let (name, version) = field.trim().split_once(' ').unwrap_or((field.trim(), ""));
+    let version = version
+        .trim()
+        .strip_prefix('(')
+        .and_then(|version| version.strip_suffix(')'))
+        .map(str::trim)
+        .filter(|version| !version.is_empty());
+    (name, version)
+}
+
+/// Split a relationship field like `Built-Using` into its entries
+fn parse_relations(field: &str) -> Vec<String> {
+    field
+        .split(',')
+        .map(|entry| entry.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|entry| !entry.is_empty())
+        .collect()
 }
 
 enum Compression {
     Xz,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression of an archive member like `data.tar.xz`, `None` for other members
+    fn of_member(name: &str, member: &str) -> Result<Option<Self>> {
+        let Some(extension) = name
+            .strip_prefix(member)
+            .and_then(|name| name.strip_prefix('.'))
+        else {
+            return Ok(None);
+        };
+        match extension {
+            "xz" => Ok(Some(Compression::Xz)),
+            "gz" => Ok(Some(Compression::Gzip)),
+            "zst" => Ok(Some(Compression::Zstd)),
+            extension => bail!("Found {member} with unsupported extension: {extension}"),
+        }
+    }
 }
 
 enum Decompressor<R: AsyncBufRead> {
     Xz(async_compression::tokio::bufread::XzDecoder<R>),
+    Gzip(async_compression::tokio::bufread::GzipDecoder<R>),
+    Zstd(async_compression::tokio::bufread::ZstdDecoder<R>),
 }
 
 impl<R: AsyncBufRead> Decompressor<R> {
     fn new(reader: R, compression: Compression) -> Self {
         match compression {
             Compression::Xz => Self::Xz(async_compression::tokio::bufread::XzDecoder::new(reader)),
+            Compression::Gzip => {
+                Self::Gzip(async_compression::tokio::bufread::GzipDecoder::new(reader))
+            }
+            Compression::Zstd => {
+                Self::Zstd(async_compression::tokio::bufread::ZstdDecoder::new(reader))
+            }
         }
     }
 }
@@ -33,11 +108,18 @@ impl<R: AsyncBufRead + Unpin> AsyncRead for Decompressor<R> {
     ) -> std::task::Poll<std::io::Result<()>> {
         match &mut *self {
             Decompressor::Xz(decoder) => std::pin::Pin::new(decoder).poll_read(cx, buf),
+            Decompressor::Gzip(decoder) => std::pin::Pin::new(decoder).poll_read(cx, buf),
+            Decompressor::Zstd(decoder) => std::pin::Pin::new(decoder).poll_read(cx, buf),
         }
     }
 }
 
-async fn extract_control_from_deb<R: AsyncRead + Unpin>(reader: R) -> Result<String> {
+/// Find a member of a .deb like `data.tar.xz` by its name without extension, and read it decompressed
+async fn read_member<R, T, F>(reader: R, member: &str, f: F) -> Result<T>
+where
+    R: AsyncRead + Unpin,
+    F: for<'a> AsyncFnOnce(Decompressor<BufReader<tokio_ar::Entry<'a, R>>>) -> Result<T>,
+{
     let mut archive = tokio_ar::Archive::new(reader);
 
     while let Some(entry) = archive.next_entry().await {
@@ -45,23 +127,22 @@ async fn extract_control_from_deb<R: AsyncRead + Unpin>(reader: R) -> Result<Str
         let Ok(name) = str::from_utf8(entry.header().identifier()) else {
             continue;
         };
-
-        // Determine compression
-        let compression = match name.strip_prefix("control.tar.") {
-            Some("xz") => Compression::Xz,
-            Some(extension) => bail!("Found control.tar with unsupported extension: {extension}"),
-            None => continue,
+        let Some(compression) = Compression::of_member(name, member)? else {
+            continue;
         };
 
-        // Setup decompression reader
         let reader = BufReader::new(entry);
-        let decompressor = Decompressor::new(reader, compression);
-
-        // Extract control file from control.tar.*
-        return find_control_file(decompressor).await;
+        return f(Decompressor::new(reader, compression)).await;
     }
 
-    bail!("No control.tar found in .deb")
+    bail!("No {member} found in .deb")
+}
+
+async fn extract_control_from_deb<R: AsyncRead + Unpin>(reader: R) -> Result<String> {
+    read_member(reader, "control.tar", async |tar| {
+        find_control_file(tar).await
+    })
+    .await
 }
 
 async fn find_control_file<R: AsyncRead + Unpin>(reader: R) -> Result<String> {
@@ -89,23 +170,123 @@ async fn find_control_file<R: AsyncRead + Unpin>(reader: R) -> Result<String> {
     bail!("No control file found in control.tar")
 }
 
-pub async fn inspect<R: AsyncRead + Unpin>(reader: R) -> Result<Deb> {
-    let content = extract_control_from_deb(reader).await?;
-    trace!("Control file content: {content:?}");
+/// Calculate the sha256 of a file shipped in the data.tar of a .deb, `None` if it's not included
+pub async fn sha256_data_file<R: AsyncRead + Unpin>(
+    reader: R,
+    path: &Path,
+) -> Result<Option<Vec<u8>>> {
+    read_member(reader, "data.tar", async |tar| {
+        sha256_tar_entry(tar, path).await
+    })
+    .await
+}
 
-    // now process the buffered data
-    let deb822 = deb822_fast::Deb822::from_reader(content.as_bytes())
-        .map_err(|err| anyhow!("Failed to parse deb822: {err:#}"))?;
-    let mut paragraphs = deb822.iter();
+async fn sha256_tar_entry<R: AsyncRead + Unpin>(reader: R, path: &Path) -> Result<Option<Vec<u8>>> {
+    // Paths in data.tar are relative, like `./usr/bin/foo`
+    let path = Path::new(".").join(path.strip_prefix("/").unwrap_or(path));
 
-    let paragraph = paragraphs
-        .next()
-        .ok_or_else(|| anyhow!("No paragraphs found in control file"))?;
+    let mut tar = tokio_tar::Archive::new(reader);
+    let mut entries = tar
+        .entries()
+        .context("Failed to read entries from data.tar")?;
 
-    if paragraphs.next().is_some() {
-        bail!("More than one paragraph found in control file");
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry.context("Failed to read entry from data.tar")?;
+        if *entry.path()? != path {
+            continue;
+        }
+
+        let mut sha256 = Sha256::new();
+        let mut buf = [0; 64 * 1024];
+        loop {
+            let n = entry
+                .read(&mut buf)
+                .await
+                .context("Failed to read file from data.tar")?;
+            if n == 0 {
+                break;
+            }
+            sha256.update(&buf[..n]);
+        }
+        return Ok(Some(sha256.finalize().to_vec()));
+    }
+
+    Ok(None)
+}
+
+/// A file, directory or link in the data.tar of a .deb
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFile {
+    /// The path as stored in the archive, like `./usr/bin/foo`
+    pub path: String,
+    pub mode: u32,
+    pub size: u64,
+    pub kind: tokio_tar::EntryType,
+    /// The target of symlinks and hardlinks
+    pub link: Option<String>,
+}
+
+impl fmt::Display for DataFile {
+    /// Similar to `tar -tv`, e.g. `-rw-r--r--       1234 ./usr/share/doc/foo/copyright`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            tokio_tar::EntryType::Directory => 'd',
+            tokio_tar::EntryType::Symlink => 'l',
+            tokio_tar::EntryType::Link => 'h',
+            tokio_tar::EntryType::Char => 'c',
+            tokio_tar::EntryType::Block => 'b',
+            tokio_tar::EntryType::Fifo => 'p',
+            _ => '-',
+        };
+        let perms = "rwxrwxrwx"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                if self.mode & (0o400 >> i) != 0 {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect::<String>();
+        write!(f, "{kind}{perms} {:>10} {}", self.size, self.path)?;
+        if let Some(link) = &self.link {
+            write!(f, " -> {link}")?;
+        }
+        Ok(())
+    }
+}
+
+/// List the contents of the data.tar of a .deb, in archive order
+pub async fn list_data_files<R: AsyncRead + Unpin>(reader: R) -> Result<Vec<DataFile>> {
+    read_member(reader, "data.tar", async |tar| list_tar_entries(tar).await).await
+}
+
+async fn list_tar_entries<R: AsyncRead + Unpin>(reader: R) -> Result<Vec<DataFile>> {
+    let mut tar = tokio_tar::Archive::new(reader);
+    let mut entries = tar
+        .entries()
+        .context("Failed to read entries from data.tar")?;
+
+    let mut files = Vec::new();
+    while let Some(entry) = entries.next().await {
+        let entry = entry.context("Failed to read entry from data.tar")?;
+        let header = entry.header();
+        files.push(DataFile {
+            path: entry.path()?.to_string_lossy().into_owned(),
+            mode: header.mode()? & 0o7777,
+            size: header.size()?,
+            kind: header.entry_type(),
+            link: entry
+                .link_name()?
+                .map(|link| link.to_string_lossy().into_owned()),
+        });
     }
+    Ok(files)
+}
 
+/// Read the package metadata of a control file, a `Packages` index or the dpkg status
+pub fn from_paragraph(paragraph: &deb822_fast::Paragraph) -> Result<Deb> {
     let name = paragraph
         .get("Package")
         .ok_or_else(|| anyhow!("No 'Package' field in paragraph"))?;
@@ -118,11 +299,58 @@ pub async fn inspect<R: AsyncRead + Unpin>(reader: R) -> Result<Deb> {
         .get("Architecture")
         .ok_or_else(|| anyhow!("No 'Architecture' field in paragraph"))?;
 
-    let data = Deb {
+    // The field is omitted if source and binary package have the same name,
+    // it may also contain a version in parenthesis: `rust-as-slice (0.2.1-1)`
+    let (source, source_version) = paragraph
+        .get("Source")
+        .map(parse_source)
+        .unwrap_or((name, None));
+
+    let installed_size = paragraph
+        .get("Installed-Size")
+        .map(|size| {
+            size.trim()
+                .parse()
+                .with_context(|| format!("Invalid 'Installed-Size' field: {size:?}"))
+        })
+        .transpose()?;
+
+    Ok(Deb {
         name: name.to_string(),
         version: version.to_string(),
         architecture: architecture.to_string(),
-    };
+        source: source.to_string(),
+        source_version: source_version
+            .filter(|source_version| *source_version != version)
+            .map(String::from),
+        section: paragraph.get("Section").map(String::from),
+        maintainer: paragraph.get("Maintainer").map(String::from),
+        installed_size,
+        built_using: paragraph
+            .get("Built-Using")
+            .map(parse_relations)
+            .unwrap_or_default(),
+    })
+}
+
+pub async fn inspect<R: AsyncRead + Unpin>(reader: R) -> Result<Deb> {
+    let content = extract_control_from_deb(reader).await?;
+    trace!("Control file content: {content:?}");
+
+    // now process the buffered data
+    let deb822 = deb822_fast::Deb822::from_reader(content.as_bytes())
+        .map_err(|err| anyhow!("Failed to parse deb822: {err:#}"))?;
+    let mut paragraphs = deb822.iter();
+
+    let paragraph = paragraphs
+        .next()
+        .ok_or_else(|| anyhow!("No paragraphs found in control file"))?;
+
+    if paragraphs.next().is_some() {
+        bail!("More than one paragraph found in control file");
+    }
+
+    let data = from_paragraph(paragraph)?;
     debug!("Parsed .deb data: {data:?}");
     Ok(data)
 }
@@ -130,6 +358,7 @@ pub async fn inspect<R: AsyncRead + Unpin>(reader: R) -> Result<Deb> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
     use tokio::fs::File;
 
     #[tokio::test]
@@ -145,7 +374,120 @@ mod tests {
                 name: "librust-as-slice-dev".to_string(),
                 version: "0.2.1-1+b2".to_string(),
                 architecture: "amd64".to_string(),
+                source: "rust-as-slice".to_string(),
+                source_version: Some("0.2.1-1".to_string()),
+                section: Some("rust".to_string()),
+                maintainer: Some(
+                    "Debian Rust Maintainers <pkg-rust-maintainers@alioth-lists.debian.net>"
+                        .to_string()
+                ),
+                installed_size: Some(40),
+                built_using: vec![],
             }
         );
+        assert_eq!(deb.source_version(), "0.2.1-1");
+    }
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!(
+            parse_source("rust-as-slice (0.2.1-1)"),
+            ("rust-as-slice", Some("0.2.1-1"))
+        );
+        assert_eq!(parse_source("rust-as-slice"), ("rust-as-slice", None));
+        assert_eq!(
+            parse_relations("rustc (= 1.85.0+dfsg1-1),\n rust-foo (= 1.0-1) "),
+            ["rustc (= 1.85.0+dfsg1-1)", "rust-foo (= 1.0-1)"]
+        );
+    }
+
+    #[test]
+    fn test_member_compression() {
+        let of = |name| Compression::of_member(name, "data.tar");
+        assert!(matches!(of("data.tar.xz"), Ok(Some(Compression::Xz))));
+        assert!(matches!(of("data.tar.gz"), Ok(Some(Compression::Gzip))));
+        assert!(matches!(of("data.tar.zst"), Ok(Some(Compression::Zstd))));
+        assert!(matches!(of("control.tar.xz"), Ok(None)));
+        assert!(matches!(of("data.tarball"), Ok(None)));
+        assert!(of("data.tar.bz2").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_data_files() {
+        let file = File::open("test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.deb")
+            .await
+            .unwrap();
+        let files = list_data_files(file).await.unwrap();
+        assert_eq!(files.len(), 24);
+        assert_eq!(files[0].to_string(), "drwxr-xr-x          0 ./");
+        assert_eq!(
+            files.last().unwrap().to_string(),
+            "-rw-r--r--       1764 ./usr/share/doc/librust-as-slice-dev/copyright"
+        );
+    }
+
+    #[test]
+    fn test_display_data_file() {
+        let file = DataFile {
+            path: "./usr/bin/foo".to_string(),
+            mode: 0o4755,
+            size: 0,
+            kind: tokio_tar::EntryType::Symlink,
+            link: Some("bar".to_string()),
+        };
+        assert_eq!(
+            file.to_string(),
+            "lrwxr-xr-x          0 ./usr/bin/foo -> bar"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sha256_data_file() {
+        let path = Path::new("/usr/share/cargo/registry/as-slice-0.2.1/Cargo.toml");
+        let file = File::open("test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.deb")
+            .await
+            .unwrap();
+        let sha256 = sha256_data_file(file, path).await.unwrap();
+        assert_eq!(
+            sha256.map(|sha256| data_encoding::HEXLOWER.encode(&sha256)),
+            Some("3c6be7429fc29c6f1090284598048e378868919ffc6d09380dc638e5fb4ed872".to_string())
+        );
+
+        let file = File::open("test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.deb")
+            .await
+            .unwrap();
+        let sha256 = sha256_data_file(file, Path::new("/usr/bin/repro-threshold"))
+            .await
+            .unwrap();
+        assert_eq!(sha256, None);
+    }
+
+    fn inspect_bytes(bytes: &[u8]) -> Result<Deb> {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(inspect(bytes))
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_inspect_arbitrary(bytes in proptest::collection::vec(any::<u8>(), 0..4096)) {
+            let _ = inspect_bytes(&bytes);
+        }
+
+        #[test]
+        fn proptest_inspect_mutated(idx in any::<prop::sample::Index>(), byte in any::<u8>()) {
+            let mut bytes = std::fs::read("test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.deb").unwrap();
+            let idx = idx.index(bytes.len());
+            bytes[idx] = byte;
+            let _ = inspect_bytes(&bytes);
+        }
+
+        #[test]
+        fn proptest_inspect_truncated(len in any::<prop::sample::Index>()) {
+            let bytes = std::fs::read("test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.deb").unwrap();
+            let len = len.index(bytes.len());
+            let _ = inspect_bytes(&bytes[..len]);
+        }
     }
 }
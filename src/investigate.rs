@@ -0,0 +1,131 @@
+use crate::errors::*;
+use crate::http;
+use crate::inspect::deb::Deb;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::process::Command;
+use url::Url;
+
+/// How a rebuilt artifact compares to the local file
+#[derive(Debug, PartialEq)]
+pub enum Finding {
+    Identical,
+    Differs(PathBuf),
+}
+
+/// Download the artifacts rebuilt by each rebuilder and compare them to the local file
+///
+/// Artifacts that differ are written to `dir` so they can be passed to diffoscope.
+pub async fn fetch_rebuilt(
+    http: &http::Client,
+    rebuilders: &[Url],
+    inspect: &Deb,
+    sha256: &[u8],
+    dir: &Path,
+) -> Result<Vec<(Url, Finding)>> {
+    fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create directory: {dir:?}"))?;
+
+    let mut findings = Vec::new();
+    for rebuilder in rebuilders {
+        let artifacts = match http.fetch_rebuilt_artifacts(rebuilder, inspect).await {
+            Ok(artifacts) => artifacts,
+            Err(err) => {
+                warn!("Failed to fetch rebuilt artifacts from {rebuilder}: {err:#}");
+                continue;
+            }
+        };
+
+        for (url, artifact) in artifacts {
+            if Sha256::digest(&artifact).as_slice() == sha256 {
+                findings.push((url, Finding::Identical));
+                continue;
+            }
+
+            // The package name comes from the file being investigated, don't use it in paths
+            let path = dir.join(format!("rebuilt-{}.deb", findings.len() + 1));
+            fs::write(&path, &artifact)
+                .await
+                .with_context(|| format!("Failed to write rebuilt artifact: {path:?}"))?;
+            findings.push((url, Finding::Differs(path)));
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Show the differences between two files with diffoscope
+pub async fn diffoscope(local: &Path, rebuilt: &Path) -> Result<()> {
+    let mut cmd = Command::new("diffoscope");
+    cmd.arg(local).arg(rebuilt);
+    debug!("Executing diffoscope: {cmd:?}");
+
+    let status = match cmd.status().await {
+        Ok(status) => status,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            bail!("diffoscope is not installed, the rebuilt artifact is available at {rebuilt:?}")
+        }
+        Err(err) => return Err(err).context("Failed to execute diffoscope"),
+    };
+
+    // diffoscope exits with 1 if differences were found
+    match status.code() {
+        Some(0 | 1) => Ok(()),
+        _ => bail!("diffoscope failed: {status}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use std::collections::BTreeMap;
+    use std::env;
+
+    #[tokio::test]
+    async fn test_fetch_rebuilt() {
+        let deb = std::fs::read(test_server::DEB_PATH).unwrap();
+        let search = serde_json::json!({ "records": [
+            { "build_id": 1, "artifact_id": 1 },
+            { "build_id": 2, "artifact_id": 1 },
+        ] });
+        let rebuilder = test_server::spawn(BTreeMap::from([
+            (
+                "/api/v1/packages/binary".to_string(),
+                serde_json::to_vec(&search).unwrap(),
+            ),
+            // The most recent build is checked first
+            ("/api/v1/builds/2/artifacts/1/download".to_string(), deb),
+            (
+                "/api/v1/builds/1/artifacts/1/download".to_string(),
+                b"not the same".to_vec(),
+            ),
+        ]))
+        .await;
+
+        let dir = env::temp_dir().join(format!(
+            "repro-threshold-test-investigate-{}",
+            std::process::id()
+        ));
+        let findings = fetch_rebuilt(
+            &http::client(),
+            &[rebuilder],
+            &test_server::deb(),
+            &test_server::deb_sha256(),
+            &dir,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].1, Finding::Identical);
+        let Finding::Differs(path) = &findings[1].1 else {
+            panic!("Expected the second artifact to differ");
+        };
+        assert_eq!(std::fs::read(path).unwrap(), b"not the same");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+//! Verification against an in-toto layout supplied by the distribution
+//!
+//! Instead of counting signatures, the attestations of the rebuilders are used as link metadata
+//! of the steps in the layout. The layout defines the steps, their authorized functionaries and
+//! thresholds, and inspections that are run on the artifact.
+use crate::attestation::Tree;
+use crate::errors::*;
+use crate::signing;
+use in_toto::models::Metablock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::fs;
+use tokio::process::Command;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    /// The signed root.layout
+    pub path: PathBuf,
+    /// Public keys, the layout needs to be signed by all of them
+    pub keys: Vec<PathBuf>,
+}
+
+/// The filename in-toto expects for a link, `<step>.<first 8 characters of the keyid>.link`
+fn link_filename(step: &str, key_id: &str) -> Result<String> {
+    if step.is_empty() || step.contains(['/', '\0']) || step.starts_with('.') {
+        bail!("Invalid step name in attestation: {step:?}");
+    }
+    let prefix = key_id.get(..8).unwrap_or(key_id);
+    Ok(format!("{step}.{prefix}.link"))
+}
+
+/// Write the attestations into a directory as link metadata, returns the number of links
+pub async fn write_links(dir: &Path, attestations: &Tree) -> Result<usize> {
+    fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create directory: {dir:?}"))?;
+
+    let mut written = 0;
+    for (label, attestation) in attestations.iter() {
+        let signed = attestation.signed_json()?;
+        let Some(step) = signed.get("name").and_then(|name| name.as_str()) else {
+            warn!("Attestation has no step name, skipping: {label}");
+            continue;
+        };
+        for key_id in attestation.list_key_ids() {
+            let key_id = serde_json::to_value(&key_id)?;
+            let key_id = key_id.as_str().context("Key id is not a string")?;
+            let path = dir.join(link_filename(step, key_id)?);
+            fs::write(&path, attestation.as_bytes())
+                .await
+                .with_context(|| format!("Failed to write link: {path:?}"))?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// Verify the links in a directory against the layout, inspections run in the current directory
+pub async fn verify(layout: &Layout, links: &Path) -> Result<()> {
+    let path = &layout.path;
+    let buf = fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read layout: {path:?}"))?;
+    let metablock = serde_json::from_slice::<Metablock>(&buf)
+        .with_context(|| format!("Failed to parse layout: {path:?}"))?;
+
+    let keys = signing::load_all_signing_keys(&layout.keys).await?;
+    if keys.is_empty() {
+        bail!("No public keys configured for layout: {path:?}");
+    }
+    let keys = keys
+        .into_iter()
+        .map(|key| (key.key_id().clone(), key))
+        .collect::<HashMap<_, _>>();
+
+    let links = links
+        .to_str()
+        .with_context(|| format!("Link directory is not valid utf-8: {links:?}"))?
+        .to_string();
+    tokio::task::spawn_blocking(move || {
+        in_toto::verifylib::in_toto_verify(&metablock, keys, &links, None)
+    })
+    .await?
+    .context("Layout verification failed")?;
+
+    Ok(())
+}
+
+/// Verify an artifact against the layout with the given attestations
+///
+/// Inspections expect the artifact in their working directory, this is done in a separate
+/// process (`plumbing verify-layout`) so the working directory of this one is left alone.
+///
+/// At least one attestation has to list the artifact as product, otherwise nothing binds the
+/// artifact to the links if the layout has no inspections.
+pub async fn verify_artifact(
+    layout: &Layout,
+    attestations: &Tree,
+    sha256: &[u8],
+    file: &Path,
+) -> Result<()> {
+    let dir = tempfile::Builder::new()
+        .prefix("repro-threshold-layout-")
+        .tempdir()
+        .context("Failed to create temporary directory")?;
+    let result = verify_in(dir.path(), layout, attestations, sha256, file).await;
+    let path = dir.path().to_path_buf();
+    if let Err(err) = dir.close() {
+        warn!("Failed to remove temporary directory {path:?}: {err:#}");
+    }
+    result
+}
+
+async fn verify_in(
+    dir: &Path,
+    layout: &Layout,
+    attestations: &Tree,
+    sha256: &[u8],
+    file: &Path,
+) -> Result<()> {
+    let links = dir.join("links");
+    if write_links(&links, attestations).await? == 0 {
+        bail!("No link metadata for layout verification");
+    }
+
+    // Use the name the rebuilders recorded for the artifact, the layout rules refer to it
+    let name = attestations
+        .iter()
+        .find_map(|(_, attestation)| attestation.product_path(sha256))
+        .context("No attestation lists the artifact as product")?;
+    if name.contains('/') || name.starts_with('.') {
+        bail!("Invalid artifact name in attestation: {name:?}");
+    }
+
+    let work = dir.join("work");
+    fs::create_dir_all(&work)
+        .await
+        .with_context(|| format!("Failed to create directory: {work:?}"))?;
+    let artifact = work.join(&name);
+    if fs::hard_link(file, &artifact).await.is_err() {
+        fs::copy(file, &artifact)
+            .await
+            .with_context(|| format!("Failed to copy artifact to {artifact:?}"))?;
+    }
+
+    let exe = env::current_exe().context("Failed to determine path of current executable")?;
+    let mut command = Command::new(exe);
+    command
+        .args(["plumbing", "verify-layout", "--layout"])
+        .arg(&layout.path)
+        .arg("--links")
+        .arg(&links)
+        .current_dir(&work)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    for key in &layout.keys {
+        command.arg("--layout-key").arg(key);
+    }
+    debug!("Running layout verification: {command:?}");
+
+    let output = command
+        .output()
+        .await
+        .context("Failed to execute layout verification")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or_default().trim();
+        bail!("Layout verification failed ({}): {reason}", output.status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_filename() {
+        assert_eq!(
+            link_filename("rebuild", "1f2a1c8f03c3b1be6bbc0a7e70a1bf4a").unwrap(),
+            "rebuild.1f2a1c8f.link"
+        );
+        assert!(link_filename("../rebuild", "1f2a1c8f").is_err());
+        assert!(link_filename("", "1f2a1c8f").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+mod admission;
+pub mod app;
+pub mod args;
+pub mod attestation;
+mod audit;
+mod clipboard;
+mod clock;
+pub mod config;
+mod container;
+mod countersign;
+mod credentials;
+pub mod cron;
+mod debug_bundle;
+mod delta;
+mod diff;
+mod disk;
+mod dns;
+pub mod errors;
+mod event;
+mod evidence;
+mod http;
+mod i18n;
+pub mod inspect;
+mod investigate;
+mod layout;
+mod lock;
+mod lockfile;
+mod notify;
+pub mod pkcs11;
+pub mod plumbing;
+mod policy;
+mod psl;
+pub mod rebuilder;
+mod report;
+mod sbom;
+mod self_verify;
+mod serve;
+mod sideload;
+pub mod signing;
+mod state;
+mod stats;
+mod status;
+mod telemetry;
+#[cfg(any(test, feature = "test-server"))]
+pub mod test_server;
+mod throttle;
+mod tofu;
+pub mod transport;
+mod ui;
+mod vsa;
+mod withhold;
+
+/// Use the directories and the language of the system, called once by the binary
+///
+/// Without this, like in tests, nothing outside of the temporary directory is touched and
+/// messages are shown in English.
+pub fn init() {
+    state::init();
+    i18n::init();
+}
@@ -0,0 +1,94 @@
+use crate::errors::*;
+use crate::state;
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use tokio::task;
+
+/// An exclusive lock, held until this is dropped
+///
+/// Locks are taken with `flock`, so they are released automatically if the process dies.
+/// The lock files are kept in the state directory, since the apt method, cron and the TUI all
+/// need to write there anyway. The lock file contains the pid of the current holder for
+/// debugging.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    pub async fn acquire(name: &str) -> Result<Self> {
+        let path = state::state_dir().join(format!("{name}.lock"));
+        task::spawn_blocking(move || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create state directory: {parent:?}"))?;
+            }
+            // Don't follow a symlink somebody with access to the state directory placed here
+            let result = File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(&path);
+            // A lock file created by another user can still be locked, just not written
+            let (mut file, writable) = match result {
+                Ok(file) => (file, true),
+                Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                    let file = File::options()
+                        .read(true)
+                        .custom_flags(libc::O_NOFOLLOW)
+                        .open(&path)
+                        .with_context(|| format!("Failed to open lock file: {path:?}"))?;
+                    (file, false)
+                }
+                Err(err) => {
+                    return Err(
+                        Error::from(err).context(format!("Failed to open lock file: {path:?}"))
+                    );
+                }
+            };
+
+            if file.try_lock().is_err() {
+                debug!("Waiting for lock: {path:?}");
+                file.lock()
+                    .with_context(|| format!("Failed to acquire lock: {path:?}"))?;
+            }
+            trace!("Acquired lock: {path:?}");
+
+            if writable {
+                file.set_len(0)?;
+                writeln!(file, "{}", std::process::id())?;
+            }
+            Ok(Lock { _file: file })
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::task::JoinSet;
+
+    #[tokio::test]
+    async fn test_lock_exclusive() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let mut tasks = JoinSet::new();
+        for _ in 0..8 {
+            let counter = Arc::clone(&counter);
+            tasks.spawn(async move {
+                let _lock = Lock::acquire("test-exclusive").await.unwrap();
+                // Nobody else may hold the lock at the same time
+                assert_eq!(counter.fetch_add(1, Ordering::SeqCst), 0);
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                counter.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        while let Some(res) = tasks.join_next().await {
+            res.unwrap();
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Parse the package lists that pin the contents of an image, so every artifact can be checked
+use crate::attestation;
+use crate::errors::*;
+use crate::inspect::deb::{self, Deb};
+use clap::ValueEnum;
+
+/// Package lists with a sha256 for each package
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// An apt `Packages` index, with the `SHA256` field of each paragraph
+    Packages,
+    /// Lines of `name version [arch] sha256`, like `pacman -Q` with the hashes appended
+    Pacman,
+    /// A CycloneDX json SBOM, with `pkg:deb` or `pkg:alpm` package urls
+    Cyclonedx,
+}
+
+impl Format {
+    /// Guess the format from the content of the file
+    pub fn detect(content: &str) -> Self {
+        let content = content.trim_start();
+        if content.starts_with('{') {
+            Format::Cyclonedx
+        } else if content.lines().any(|line| line.starts_with("Package:")) {
+            Format::Packages
+        } else {
+            Format::Pacman
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub package: Deb,
+    pub sha256: Vec<u8>,
+}
+
+/// Parse a package list, `arch` is used for entries that don't specify one
+pub fn parse(format: Format, content: &str, arch: Option<&str>) -> Result<Vec<Entry>> {
+    match format {
+        Format::Packages => parse_packages(content),
+        Format::Pacman => parse_pacman(content, arch),
+        Format::Cyclonedx => parse_cyclonedx(content),
+    }
+}
+
+fn parse_packages(content: &str) -> Result<Vec<Entry>> {
+    let deb822 = deb822_fast::Deb822::from_reader(content.as_bytes())
+        .map_err(|err| anyhow!("Failed to parse deb822: {err:#}"))?;
+
+    let mut entries = Vec::new();
+    for paragraph in deb822.iter() {
+        let package = deb::from_paragraph(paragraph)?;
+        let sha256 = paragraph
+            .get("SHA256")
+            .with_context(|| format!("No 'SHA256' field for package {:?}", package.name))?;
+        entries.push(Entry {
+            sha256: attestation::parse_sha256(sha256)?,
+            package,
+        });
+    }
+    Ok(entries)
+}
+
+fn parse_pacman(content: &str, arch: Option<&str>) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (name, version, architecture, sha256) = match fields[..] {
+            [name, version, architecture, sha256] => (name, version, architecture, sha256),
+            [name, version, sha256] => {
+                let architecture = arch.with_context(|| {
+                    format!("Line {} has no architecture, pass --arch", idx + 1)
+                })?;
+                (name, version, architecture, sha256)
+            }
+            _ => bail!(
+                "Line {} is not of the form `name version [arch] sha256`: {line:?}",
+                idx + 1
+            ),
+        };
+
+        entries.push(Entry {
+            package: Deb {
+                name: name.to_string(),
+                version: version.to_string(),
+                architecture: architecture.to_string(),
+                source: name.to_string(),
+                ..Default::default()
+            },
+            sha256: attestation::parse_sha256(sha256)
+                .with_context(|| format!("Invalid sha256 in line {}", idx + 1))?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Decode the `%XX` escapes of a package url
+fn percent_decode(s: &str) -> Result<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [
+                bytes.next().context("Truncated escape in package url")?,
+                bytes.next().context("Truncated escape in package url")?,
+            ];
+            let hex = std::str::from_utf8(&hex)?;
+            out.push(u8::from_str_radix(hex, 16).context("Invalid escape in package url")?);
+        } else {
+            out.push(b);
+        }
+    }
+    Ok(String::from_utf8(out)?)
+}
+
+/// Parse a package url like `pkg:deb/debian/curl@7.74.0-1?arch=amd64`
+fn parse_purl(purl: &str) -> Result<Option<Deb>> {
+    let Some(rest) = purl.strip_prefix("pkg:") else {
+        bail!("Invalid package url: {purl:?}");
+    };
+    let (kind, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    if kind != "deb" && kind != "alpm" {
+        return Ok(None);
+    }
+
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (path, qualifiers) = rest.split_once('?').unwrap_or((rest, ""));
+    let (path, version) = path
+        .rsplit_once('@')
+        .with_context(|| format!("Package url has no version: {purl:?}"))?;
+    let name = path.rsplit('/').next().unwrap_or(path);
+
+    let mut architecture = None;
+    let mut source = None;
+    for qualifier in qualifiers.split('&') {
+        match qualifier.split_once('=') {
+            Some(("arch", value)) => architecture = Some(percent_decode(value)?),
+            Some(("upstream", value)) => source = Some(percent_decode(value)?),
+            _ => (),
+        }
+    }
+    let architecture =
+        architecture.with_context(|| format!("Package url has no architecture: {purl:?}"))?;
+
+    let name = percent_decode(name)?;
+    Ok(Some(Deb {
+        source: source.unwrap_or_else(|| name.clone()),
+        name,
+        version: percent_decode(version)?,
+        architecture,
+        ..Default::default()
+    }))
+}
+
+fn parse_cyclonedx(content: &str) -> Result<Vec<Entry>> {
+    let sbom = serde_json::from_str::<serde_json::Value>(content)
+        .context("Failed to parse CycloneDX SBOM")?;
+    let components = sbom["components"]
+        .as_array()
+        .context("SBOM has no components")?;
+
+    let mut entries = Vec::new();
+    for component in components {
+        let Some(purl) = component["purl"].as_str() else {
+            continue;
+        };
+        let Some(package) = parse_purl(purl)? else {
+            debug!("Skipping component that is not a Debian/Arch package: {purl:?}");
+            continue;
+        };
+        let sha256 = component["hashes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|hash| hash["alg"] == "SHA-256")
+            .and_then(|hash| hash["content"].as_str())
+            .with_context(|| format!("Component has no SHA-256 hash: {purl:?}"))?;
+
+        entries.push(Entry {
+            package,
+            sha256: attestation::parse_sha256(sha256)?,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            Format::detect("\n{\"bomFormat\": \"CycloneDX\"}"),
+            Format::Cyclonedx
+        );
+        assert_eq!(
+            Format::detect("Package: curl\nVersion: 7.74.0-1\n"),
+            Format::Packages
+        );
+        assert_eq!(Format::detect("curl 8.11.1-3 x86_64 00"), Format::Pacman);
+    }
+
+    #[test]
+    fn test_parse_pacman() {
+        let sha256 = "ab".repeat(32);
+        let content = format!(
+            "# generated by the image build\ncurl 8.11.1-3 x86_64 {sha256}\n\nzlib 1:1.3.1-2 {sha256}\n"
+        );
+        assert!(parse(Format::Pacman, &content, None).is_err());
+
+        let entries = parse(Format::Pacman, &content, Some("any")).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    package: Deb {
+                        name: "curl".to_string(),
+                        version: "8.11.1-3".to_string(),
+                        architecture: "x86_64".to_string(),
+                        source: "curl".to_string(),
+                        ..Default::default()
+                    },
+                    sha256: vec![0xab; 32],
+                },
+                Entry {
+                    package: Deb {
+                        name: "zlib".to_string(),
+                        version: "1:1.3.1-2".to_string(),
+                        architecture: "any".to_string(),
+                        source: "zlib".to_string(),
+                        ..Default::default()
+                    },
+                    sha256: vec![0xab; 32],
+                },
+            ]
+        );
+
+        assert!(parse(Format::Pacman, "curl 8.11.1-3\n", Some("x86_64")).is_err());
+        assert!(parse(Format::Pacman, "curl 8.11.1-3 x86_64 abc\n", None).is_err());
+    }
+
+    #[test]
+    fn test_parse_cyclonedx() {
+        let sbom = serde_json::json!({
+            "bomFormat": "CycloneDX",
+            "components": [
+                {
+                    "name": "curl",
+                    "purl": "pkg:deb/debian/curl@1%3A7.74.0-1?arch=amd64&distro=debian-11",
+                    "hashes": [
+                        { "alg": "SHA-1", "content": "00".repeat(20) },
+                        { "alg": "SHA-256", "content": "00".repeat(32) },
+                    ],
+                },
+                {
+                    "name": "serde",
+                    "purl": "pkg:cargo/serde@1.0.0",
+                },
+                {
+                    "name": "rust-as-slice",
+                    "purl": "pkg:deb/debian/librust-as-slice-dev@0.2.1-1?arch=all&upstream=rust-as-slice",
+                    "hashes": [{ "alg": "SHA-256", "content": "11".repeat(32) }],
+                },
+                {
+                    "name": "zlib",
+                    "purl": "pkg:alpm/arch/zlib@1.3.1-2?arch=x86_64",
+                    "hashes": [{ "alg": "SHA-256", "content": "22".repeat(32) }],
+                },
+            ],
+        });
+        let entries = parse(Format::Cyclonedx, &sbom.to_string(), None).unwrap();
+        let packages = entries
+            .iter()
+            .map(|entry| {
+                let p = &entry.package;
+                (
+                    p.name.as_str(),
+                    p.version.as_str(),
+                    p.architecture.as_str(),
+                    p.source.as_str(),
+                    entry.sha256[0],
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            packages,
+            [
+                ("curl", "1:7.74.0-1", "amd64", "curl", 0x00),
+                (
+                    "librust-as-slice-dev",
+                    "0.2.1-1",
+                    "all",
+                    "rust-as-slice",
+                    0x11
+                ),
+                ("zlib", "1.3.1-2", "x86_64", "zlib", 0x22),
+            ]
+        );
+
+        let missing = serde_json::json!({
+            "components": [{ "purl": "pkg:deb/debian/curl@7.74.0-1?arch=amd64" }],
+        });
+        assert!(parse(Format::Cyclonedx, &missing.to_string(), None).is_err());
+    }
+}
@@ -1,24 +1,10 @@
-mod app;
-mod args;
-mod attestation;
-mod config;
-mod errors;
-mod event;
-mod http;
-mod inspect;
-mod plumbing;
-mod rebuilder;
-mod signing;
-mod transport;
-mod ui;
-mod withhold;
-
-use crate::app::App;
-use crate::args::{Args, SubCommand};
-use crate::config::Config;
-use crate::errors::*;
 use clap::Parser;
 use env_logger::Env;
+use repro_threshold::app::App;
+use repro_threshold::args::{self, Args, SubCommand};
+use repro_threshold::config::Config;
+use repro_threshold::errors::*;
+use repro_threshold::{cron, plumbing, transport};
 use std::env;
 
 fn is_apt_transport_multicall() -> bool {
@@ -46,18 +32,28 @@ async fn main() -> Result<()> {
         _ => "trace",
     };
     env_logger::init_from_env(Env::default().default_filter_or(log_level));
+    repro_threshold::init();
 
     match args.subcommand {
-        None if is_apt_transport_multicall() => transport::run(args::Transport::Apt).await,
+        None if is_apt_transport_multicall() => {
+            let http = Default::default();
+            transport::run(args::Transport::Apt { http }).await
+        }
         None => {
             let config = Config::load_writable().await?;
 
+            let mut app = App::new(config);
+            if let Some(path) = args.verify {
+                app = app.verify_file(path);
+            }
+
             let terminal = ratatui::init();
-            let result = App::new(config).run(terminal).await;
+            let result = app.run(terminal).await;
             ratatui::restore();
             result
         }
-        Some(SubCommand::Transport(transport)) => transport::run(transport).await,
-        Some(SubCommand::Plumbing(plumbing)) => plumbing::run(plumbing).await,
+        Some(SubCommand::Transport(transport)) => transport::run(*transport).await,
+        Some(SubCommand::Plumbing(plumbing)) => plumbing::run(*plumbing).await,
+        Some(SubCommand::Cron(args)) => cron::run(args).await,
     }
 }
@@ -0,0 +1,365 @@
+use crate::errors::*;
+use crate::http;
+use crate::inspect::deb::Deb;
+use crate::state::{EmailLimit, Store};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::task::JoinSet;
+use url::Url;
+
+const SENDMAIL: &str = "/usr/sbin/sendmail";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Notify {
+    /// Send policy violations to these webhooks
+    #[serde(default, rename = "webhook", skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+    /// Send policy violations by email
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<Email>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Email {
+    pub recipient: String,
+    /// The sendmail-compatible binary to pipe the email into
+    #[serde(default = "default_sendmail")]
+    pub sendmail: PathBuf,
+    /// Send at most one email within this many seconds, other events are counted and mentioned in the next email
+    #[serde(default)]
+    pub rate_limit_secs: u64,
+}
+
+fn default_sendmail() -> PathBuf {
+    PathBuf::from(SENDMAIL)
+}
+
+impl Email {
+    /// Check the rate limit, returns the number of suppressed events if an email may be sent
+    fn acquire(&self, limit: &mut EmailLimit, now: DateTime<Utc>) -> Option<usize> {
+        let interval = Duration::from_secs(self.rate_limit_secs);
+        // An email sent in the future is from a clock that was off, it doesn't hold back new ones
+        if let Some(last_sent) = limit.last_sent
+            && now
+                .signed_duration_since(last_sent)
+                .to_std()
+                .is_ok_and(|elapsed| elapsed < interval)
+        {
+            limit.suppressed += 1;
+            return None;
+        }
+        limit.last_sent = Some(now);
+        Some(std::mem::take(&mut limit.suppressed))
+    }
+
+    /// Check the rate limit shared by all processes, the email is sent if the state can't be updated
+    async fn try_acquire(&self) -> Option<usize> {
+        if self.rate_limit_secs == 0 {
+            return Some(0);
+        }
+        let mut acquired = Some(0);
+        if let Err(err) =
+            EmailLimit::try_update(|limit| acquired = self.acquire(limit, Utc::now())).await
+        {
+            warn!("Failed to apply email rate limit: {err:#}");
+        }
+        acquired
+    }
+
+    fn render(&self, event: &Event, suppressed: usize) -> Result<String> {
+        let message = event.message();
+        // Headers must not contain newlines
+        let subject = message.replace(['\r', '\n'], " ");
+        let recipient = self.recipient.replace(['\r', '\n'], "");
+        let mut mail =
+            format!("To: {recipient}\nSubject: [repro-threshold] {subject}\n\n{message}\n");
+        if suppressed > 0 {
+            mail.push_str(&format!(
+                "\n{suppressed} more events were suppressed by the rate limit\n"
+            ));
+        }
+        mail.push_str(&format!("\n{}\n", serde_json::to_string_pretty(event)?));
+        Ok(mail)
+    }
+
+    async fn send(&self, event: &Event) -> Result<()> {
+        let Some(suppressed) = self.try_acquire().await else {
+            debug!("Email notification suppressed by rate limit");
+            return Ok(());
+        };
+        let mail = self.render(event, suppressed)?;
+
+        let mut child = Command::new(&self.sendmail)
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to execute {:?}", self.sendmail))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .context("Failed to open sendmail stdin")?;
+        stdin.write_all(mail.as_bytes()).await?;
+        drop(stdin);
+
+        let status = child.wait().await?;
+        if !status.success() {
+            bail!("sendmail exited with {status}");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: Url,
+    #[serde(default)]
+    pub format: Format,
+}
+
+/// The request body sent to a webhook
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// The event as generic JSON object
+    #[default]
+    Json,
+    /// A Slack incoming webhook
+    Slack,
+    /// A Matrix webhook bridge (e.g. matrix-hookshot)
+    Matrix,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// The package did not reach the required threshold and was rejected
+    Rejected {
+        package: Deb,
+        sha256: String,
+        confirms: usize,
+        threshold: usize,
+    },
+    /// The package was allowed without checking attestations
+    BlindlyTrusted { package: Deb, sha256: String },
+    /// A package admitted under the grace period was still not reproduced by its deadline
+    DeadlinePassed {
+        package: Deb,
+        sha256: String,
+        deadline: DateTime<Utc>,
+    },
+    /// Trusted rebuilders signed attestations that don't match the package
+    Conflicting {
+        package: Deb,
+        sha256: String,
+        rebuilders: Vec<String>,
+    },
+    /// The package was installed without going through the transport and isn't reproduced
+    Bypassed {
+        package: Deb,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+    },
+}
+
+impl Event {
+    pub fn message(&self) -> String {
+        match self {
+            Event::Rejected {
+                package,
+                confirms,
+                threshold,
+                ..
+            } => format!(
+                "Rejected {} {} ({}): only {confirms}/{threshold} required signatures",
+                package.name, package.version, package.architecture
+            ),
+            Event::BlindlyTrusted { package, .. } => format!(
+                "Blindly trusted {} {} ({}) without reproducible builds attestations",
+                package.name, package.version, package.architecture
+            ),
+            Event::DeadlinePassed {
+                package, deadline, ..
+            } => format!(
+                "Deadline passed for {} {} ({}): still not reproduced since {deadline}",
+                package.name, package.version, package.architecture
+            ),
+            Event::Conflicting {
+                package,
+                rebuilders,
+                ..
+            } => format!(
+                "Conflicting attestations for {} {} ({}) from: {}",
+                package.name,
+                package.version,
+                package.architecture,
+                rebuilders.join(", ")
+            ),
+            Event::Bypassed { package, .. } => format!(
+                "Installed {} {} ({}) without going through the transport, it could not be verified",
+                package.name, package.version, package.architecture
+            ),
+        }
+    }
+
+    fn payload(&self, format: Format) -> Result<serde_json::Value> {
+        let payload = match format {
+            Format::Json => serde_json::to_value(self)?,
+            Format::Slack => serde_json::json!({ "text": self.message() }),
+            Format::Matrix => serde_json::json!({
+                "msgtype": "m.text",
+                "text": self.message(),
+            }),
+        };
+        Ok(payload)
+    }
+}
+
+async fn send(http: &http::Client, webhook: &Webhook, event: &Event) -> Result<()> {
+    let payload = event.payload(webhook.format)?;
+    http.post(webhook.url.clone())
+        .json(&payload)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Send an event to all configured webhooks and email, failures are logged but never fatal
+pub async fn dispatch(http: &http::Client, notify: &Notify, event: &Event) {
+    for webhook in &notify.webhooks {
+        debug!(
+            "Sending {:?} notification to {}",
+            webhook.format, webhook.url
+        );
+        if let Err(err) = send(http, webhook, event).await {
+            warn!("Failed to send notification to {}: {err:#}", webhook.url);
+        }
+    }
+    if let Some(email) = &notify.email {
+        debug!("Sending email notification to {:?}", email.recipient);
+        if let Err(err) = email.send(event).await {
+            warn!("Failed to send email notification: {err:#}");
+        }
+    }
+}
+
+/// Notifications sent in the background, so they don't hold up downloads
+#[derive(Default)]
+pub struct Background {
+    tasks: JoinSet<()>,
+}
+
+impl Background {
+    pub fn dispatch(&mut self, http: &http::Client, notify: &Notify, event: Event) {
+        let http = http.clone();
+        let notify = notify.clone();
+        self.tasks
+            .spawn(async move { dispatch(&http, &notify, &event).await });
+    }
+
+    /// Wait for the notifications that are still being sent
+    pub async fn finish(self) {
+        self.tasks.join_all().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use chrono::TimeDelta;
+
+    fn event() -> Event {
+        Event::Rejected {
+            package: test_server::deb(),
+            sha256: "00".to_string(),
+            confirms: 1,
+            threshold: 2,
+        }
+    }
+
+    #[test]
+    fn test_payload_json() {
+        let payload = event().payload(Format::Json).unwrap();
+        assert_eq!(payload["event"], "rejected");
+        assert_eq!(payload["package"]["name"], "librust-as-slice-dev");
+        assert_eq!(payload["confirms"], 1);
+    }
+
+    #[test]
+    fn test_payload_chat() {
+        let msg = "Rejected librust-as-slice-dev 0.2.1-1+b2 (amd64): only 1/2 required signatures";
+        assert_eq!(
+            event().payload(Format::Slack).unwrap(),
+            serde_json::json!({ "text": msg })
+        );
+        assert_eq!(
+            event().payload(Format::Matrix).unwrap(),
+            serde_json::json!({ "msgtype": "m.text", "text": msg })
+        );
+    }
+
+    #[test]
+    fn test_email_render() {
+        let email = Email {
+            recipient: "root@localhost".to_string(),
+            sendmail: default_sendmail(),
+            rate_limit_secs: 0,
+        };
+        let mail = email.render(&event(), 3).unwrap();
+        assert!(mail.starts_with("To: root@localhost\nSubject: [repro-threshold] Rejected librust-as-slice-dev 0.2.1-1+b2 (amd64): only 1/2 required signatures\n\n"));
+        assert!(mail.contains("3 more events were suppressed"));
+        assert!(mail.contains("\"event\": \"rejected\""));
+    }
+
+    #[test]
+    fn test_email_rate_limit() {
+        let email = Email {
+            recipient: "root@localhost".to_string(),
+            sendmail: default_sendmail(),
+            rate_limit_secs: 60,
+        };
+        let mut limit = EmailLimit::default();
+        let now = Utc::now();
+        assert_eq!(email.acquire(&mut limit, now), Some(0));
+        assert_eq!(
+            email.acquire(&mut limit, now + TimeDelta::seconds(10)),
+            None
+        );
+        assert_eq!(
+            email.acquire(&mut limit, now + TimeDelta::seconds(20)),
+            None
+        );
+        assert_eq!(limit.suppressed, 2);
+        assert_eq!(
+            email.acquire(&mut limit, now + TimeDelta::seconds(61)),
+            Some(2)
+        );
+        assert_eq!(limit.suppressed, 0);
+        // A clock that was off doesn't hold back emails forever
+        assert_eq!(email.acquire(&mut limit, now), Some(0));
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let notify = toml::from_str::<Notify>(
+            r#"
+[[webhook]]
+url = "https://hooks.slack.com/services/xxx"
+format = "slack"
+
+[[webhook]]
+url = "https://example.com/hook"
+"#,
+        )
+        .unwrap();
+        assert_eq!(notify.webhooks.len(), 2);
+        assert_eq!(notify.webhooks[0].format, Format::Slack);
+        assert_eq!(notify.webhooks[1].format, Format::Json);
+    }
+}
@@ -0,0 +1,386 @@
+//! Countersigning keys that live in a PKCS#11 token, like a smartcard, an HSM or the TPM
+//!
+//! Tokens are addressed with `pkcs11:` URIs (RFC 7512) and driven through `pkcs11-tool` of
+//! OpenSC, so the private key never leaves the token. The TPM is reachable through the
+//! `tpm2-pkcs11` module, it only supports P-256 keys.
+use crate::errors::*;
+use crate::signing;
+use clap::ValueEnum;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+pub const SCHEME: &str = "pkcs11:";
+const TOOL: &str = "pkcs11-tool";
+/// The PIN is handed to `pkcs11-tool` in this variable, the command line is visible to everyone
+const PIN_ENV: &str = "REPRO_THRESHOLD_PKCS11_PIN";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum KeyType {
+    /// Supported by most smartcards and HSMs
+    #[default]
+    Ed25519,
+    /// NIST P-256 with SHA-256, for tokens without Ed25519 like the TPM
+    P256,
+}
+
+impl KeyType {
+    fn keypairgen(&self) -> &'static str {
+        match self {
+            KeyType::Ed25519 => "EC:edwards25519",
+            KeyType::P256 => "EC:prime256v1",
+        }
+    }
+
+    fn mechanism(&self) -> &'static str {
+        match self {
+            KeyType::Ed25519 => "EDDSA",
+            KeyType::P256 => "ECDSA-SHA256",
+        }
+    }
+}
+
+/// Where to find the PIN of the token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pin {
+    Value(String),
+    Source(PathBuf),
+}
+
+/// The attributes of a `pkcs11:` URI that are passed to `pkcs11-tool`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Uri {
+    pub module: Option<PathBuf>,
+    pub slot: Option<String>,
+    pub token: Option<String>,
+    pub object: Option<String>,
+    /// The raw `CKA_ID` of the key
+    pub id: Option<Vec<u8>>,
+    pub pin: Option<Pin>,
+}
+
+fn percent_decode(value: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            out.push(b);
+            continue;
+        }
+        let hex = [
+            bytes.next().context("Truncated percent encoding")?,
+            bytes.next().context("Truncated percent encoding")?,
+        ];
+        let decoded = data_encoding::HEXLOWER_PERMISSIVE
+            .decode(&hex)
+            .with_context(|| format!("Invalid percent encoding in {value:?}"))?;
+        out.extend(decoded);
+    }
+    Ok(out)
+}
+
+fn decode_string(value: &str) -> Result<String> {
+    String::from_utf8(percent_decode(value)?).context("Attribute is not valid utf-8")
+}
+
+impl Uri {
+    pub fn parse(uri: &str) -> Result<Self> {
+        let rest = uri
+            .strip_prefix(SCHEME)
+            .with_context(|| format!("Not a {SCHEME} URI: {uri:?}"))?;
+        let mut parsed = Uri::default();
+        for attr in rest.split(['?', ';', '&']).filter(|attr| !attr.is_empty()) {
+            let (key, value) = attr
+                .split_once('=')
+                .with_context(|| format!("Invalid attribute in {SCHEME} URI: {attr:?}"))?;
+            match key {
+                "module-path" => parsed.module = Some(decode_string(value)?.into()),
+                "slot-id" => parsed.slot = Some(decode_string(value)?),
+                "token" => parsed.token = Some(decode_string(value)?),
+                "object" => parsed.object = Some(decode_string(value)?),
+                "id" => parsed.id = Some(percent_decode(value)?),
+                "pin-value" => parsed.pin = Some(Pin::Value(decode_string(value)?)),
+                "pin-source" => {
+                    let source = decode_string(value)?;
+                    let path = source.strip_prefix("file:").unwrap_or(&source);
+                    parsed.pin = Some(Pin::Source(path.into()));
+                }
+                "type" => {
+                    if value != "private" {
+                        bail!("Only private keys can be used for signing, not {value:?}");
+                    }
+                }
+                _ => debug!("Ignoring {SCHEME} URI attribute: {key:?}"),
+            }
+        }
+        if parsed.object.is_none() && parsed.id.is_none() {
+            bail!("{SCHEME} URI needs an `object` or `id` attribute to select the key");
+        }
+        Ok(parsed)
+    }
+
+    /// Arguments that select the token
+    fn token_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(module) = &self.module {
+            args.extend([
+                "--module".to_string(),
+                module.to_string_lossy().into_owned(),
+            ]);
+        }
+        if let Some(slot) = &self.slot {
+            args.extend(["--slot".to_string(), slot.clone()]);
+        }
+        if let Some(token) = &self.token {
+            args.extend(["--token-label".to_string(), token.clone()]);
+        }
+        args
+    }
+
+    /// Arguments that select the key on the token
+    fn object_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(id) = &self.id {
+            args.extend(["--id".to_string(), data_encoding::HEXLOWER.encode(id)]);
+        }
+        if let Some(object) = &self.object {
+            args.extend(["--label".to_string(), object.clone()]);
+        }
+        args
+    }
+
+    /// Arguments to log in and the PIN, `pkcs11-tool` asks for the PIN if none is configured
+    async fn login(&self) -> Result<Login> {
+        let mut args = vec!["--login".to_string()];
+        let pin = match &self.pin {
+            Some(Pin::Value(pin)) => Some(pin.clone()),
+            Some(Pin::Source(path)) => {
+                let pin = fs::read_to_string(path)
+                    .await
+                    .with_context(|| format!("Failed to read PIN: {path:?}"))?;
+                Some(pin.trim_end().to_string())
+            }
+            None => None,
+        };
+        if pin.is_some() {
+            args.extend(["--pin".to_string(), format!("env:{PIN_ENV}")]);
+        }
+        Ok(Login { args, pin })
+    }
+}
+
+struct Login {
+    args: Vec<String>,
+    pin: Option<String>,
+}
+
+async fn run(args: &[String], pin: Option<&str>, stdin: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut cmd = Command::new(TOOL);
+    if let Some(pin) = pin {
+        cmd.env(PIN_ENV, pin);
+    }
+    let mut child = cmd
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute {TOOL:?}"))?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input).await?;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "{TOOL} exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// A private key on a PKCS#11 token
+pub struct Key {
+    uri: Uri,
+    key_type: KeyType,
+    /// The DER encoded SubjectPublicKeyInfo
+    spki: Vec<u8>,
+}
+
+impl Key {
+    pub async fn load(uri: &str) -> Result<Self> {
+        let uri = Uri::parse(uri)?;
+        let mut args = uri.token_args();
+        args.extend(["--read-object", "--type", "pubkey"].map(String::from));
+        args.extend(uri.object_args());
+        let spki = run(&args, None, None)
+            .await
+            .context("Failed to read the public key from the PKCS#11 token")?;
+        let key_type = signing::spki_key_type(&spki)?;
+        Ok(Key {
+            uri,
+            key_type,
+            spki,
+        })
+    }
+
+    /// Create a new key pair on the token, the private key can't be exported
+    pub async fn generate(uri: &str, key_type: KeyType) -> Result<Self> {
+        let parsed = Uri::parse(uri)?;
+        let login = parsed.login().await?;
+        let mut args = parsed.token_args();
+        args.extend(login.args);
+        args.extend(["--keypairgen".to_string(), "--key-type".to_string()]);
+        args.push(key_type.keypairgen().to_string());
+        args.extend(parsed.object_args());
+        run(&args, login.pin.as_deref(), None)
+            .await
+            .context("Failed to generate a key pair on the PKCS#11 token")?;
+        Self::load(uri).await
+    }
+
+    pub async fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        let login = self.uri.login().await?;
+        let mut args = self.uri.token_args();
+        args.extend(login.args);
+        args.extend(["--sign".to_string(), "--mechanism".to_string()]);
+        args.push(self.key_type.mechanism().to_string());
+        args.extend(self.uri.object_args());
+        run(&args, login.pin.as_deref(), Some(msg))
+            .await
+            .context("Failed to sign with the PKCS#11 token")
+    }
+
+    pub fn public_key_pem(&self) -> String {
+        signing::spki_to_pem(&self.spki)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_uri() {
+        let uri = Uri::parse(
+            "pkcs11:token=My%20Token;object=repro-threshold;id=%01%a2?module-path=/usr/lib/softhsm/libsofthsm2.so&pin-source=file:/etc/repro-threshold/pin",
+        )
+        .unwrap();
+        assert_eq!(
+            uri,
+            Uri {
+                module: Some("/usr/lib/softhsm/libsofthsm2.so".into()),
+                slot: None,
+                token: Some("My Token".to_string()),
+                object: Some("repro-threshold".to_string()),
+                id: Some(vec![0x01, 0xa2]),
+                pin: Some(Pin::Source("/etc/repro-threshold/pin".into())),
+            }
+        );
+        assert_eq!(
+            uri.token_args(),
+            [
+                "--module",
+                "/usr/lib/softhsm/libsofthsm2.so",
+                "--token-label",
+                "My Token"
+            ]
+        );
+        assert_eq!(
+            uri.object_args(),
+            ["--id", "01a2", "--label", "repro-threshold"]
+        );
+    }
+
+    #[test]
+    fn test_parse_uri_invalid() {
+        assert!(Uri::parse("/etc/repro-threshold/key.pem").is_err());
+        assert!(Uri::parse("pkcs11:token=My%20Token").is_err());
+        assert!(Uri::parse("pkcs11:object=key;type=cert").is_err());
+        assert!(Uri::parse("pkcs11:object=key%2").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_login_args() {
+        let uri = Uri::parse("pkcs11:object=key;slot-id=0;pin-value=1234").unwrap();
+        assert_eq!(uri.token_args(), ["--slot", "0"]);
+        let login = uri.login().await.unwrap();
+        // The PIN must not show up on the command line
+        assert_eq!(
+            login.args,
+            ["--login", "--pin", "env:REPRO_THRESHOLD_PKCS11_PIN"]
+        );
+        assert_eq!(login.pin.as_deref(), Some("1234"));
+        let uri = Uri::parse("pkcs11:object=key").unwrap();
+        let login = uri.login().await.unwrap();
+        assert_eq!(login.args, ["--login"]);
+        assert_eq!(login.pin, None);
+    }
+
+    const SOFTHSM_MODULES: &[&str] = &[
+        "/usr/lib/softhsm/libsofthsm2.so",
+        "/usr/lib/x86_64-linux-gnu/softhsm/libsofthsm2.so",
+        "/usr/lib/aarch64-linux-gnu/softhsm/libsofthsm2.so",
+        "/usr/lib64/pkcs11/libsofthsm2.so",
+    ];
+
+    #[tokio::test]
+    async fn test_sign_softhsm() {
+        let Some(module) = SOFTHSM_MODULES
+            .iter()
+            .find(|path| std::path::Path::new(path).exists())
+        else {
+            eprintln!("Skipping test, softhsm is not installed");
+            return;
+        };
+        let dir = std::env::temp_dir().join(format!(
+            "repro-threshold-test-softhsm-{}",
+            std::process::id()
+        ));
+        let tokens = dir.join("tokens");
+        fs::create_dir_all(&tokens).await.unwrap();
+        let conf = dir.join("softhsm2.conf");
+        fs::write(
+            &conf,
+            format!("directories.tokendir = {}\n", tokens.display()),
+        )
+        .await
+        .unwrap();
+        // SAFETY: no other test reads this variable
+        unsafe { std::env::set_var("SOFTHSM2_CONF", &conf) };
+
+        let init = Command::new("softhsm2-util")
+            .args(["--init-token", "--free", "--label", "test"])
+            .args(["--pin", "1234", "--so-pin", "5678"])
+            .output()
+            .await;
+        if !init.is_ok_and(|output| output.status.success()) {
+            eprintln!("Skipping test, failed to initialize softhsm token");
+            fs::remove_dir_all(&dir).await.unwrap();
+            return;
+        }
+
+        let uri = format!("pkcs11:token=test;object=key?module-path={module}&pin-value=1234");
+        let result = async {
+            let key = Key::generate(&uri, KeyType::P256).await?;
+            let sig = key.sign(b"hello world").await?;
+            signing::verify_local_signature(&key.public_key_pem(), b"hello world", &sig)?;
+            assert!(
+                signing::verify_local_signature(&key.public_key_pem(), b"hello moon", &sig)
+                    .is_err()
+            );
+            Ok::<_, Error>(())
+        }
+        .await;
+        fs::remove_dir_all(&dir).await.unwrap();
+        result.unwrap();
+    }
+}
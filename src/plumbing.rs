@@ -1,25 +1,196 @@
-use crate::args::Plumbing;
-use crate::attestation;
-use crate::config::Config;
+use crate::admission;
+use crate::args::{CheckMode, Log, Plumbing};
+use crate::attestation::{self, Attestation};
+use crate::audit;
+use crate::config::{self, Config};
+use crate::container;
+use crate::countersign;
+use crate::credentials::Redacted;
+use crate::debug_bundle;
+use crate::delta;
+use crate::diff;
 use crate::errors::*;
+use crate::evidence;
 use crate::http;
-use crate::inspect;
+use crate::inspect::{self, deb::Deb};
+use crate::investigate;
+use crate::layout;
+use crate::lockfile;
+use crate::pkcs11;
+use crate::policy;
 use crate::rebuilder;
+use crate::report;
+use crate::self_verify;
+use crate::serve;
 use crate::signing;
-use tokio::fs::File;
-use tokio::io::AsyncSeekExt;
+use crate::state::{self, Store};
+use crate::stats::Stats;
+use crate::status;
+use crate::vsa;
+use crate::withhold;
+use chrono::Utc;
+use futures::{StreamExt, stream};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncReadExt, AsyncSeekExt};
+use url::Url;
+
+/// Load an attestation from a local file or a rebuilder url
+async fn load_attestation(http: &http::Client, source: &str) -> Result<Attestation> {
+    match source.parse::<Url>() {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => http.fetch_attestation(&url).await,
+        _ => {
+            let path = Path::new(source);
+            Attestation::parse_file(path)
+                .await
+                .with_context(|| format!("Failed to read attestation {path:?}"))
+        }
+    }
+}
+
+/// The artifact authenticated by `plumbing verify`
+enum Artifact {
+    File(File),
+    /// Read from stdin, this is never written to disk
+    Memory(Vec<u8>),
+    /// Downloaded from a URL, the last chunk is withheld until the artifact is verified
+    Download(Option<withhold::Writer<File>>),
+}
+
+/// Downloads larger than this are aborted, even the largest packages are far below
+const MAX_DOWNLOAD_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+impl Artifact {
+    async fn download(http: &http::Client, url: &Url, file: File) -> Result<Self> {
+        let mut writer = withhold::Writer::new(file);
+
+        info!("Downloading {}", Redacted(url));
+        let mut response = http
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to download {url}"))?;
+        if response
+            .content_length()
+            .is_some_and(|len| len > MAX_DOWNLOAD_SIZE)
+        {
+            bail!("Download is larger than {MAX_DOWNLOAD_SIZE} bytes: {url}");
+        }
+        let mut size = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("Failed to download {url}"))?
+        {
+            size += chunk.len() as u64;
+            if size > MAX_DOWNLOAD_SIZE {
+                bail!("Download is larger than {MAX_DOWNLOAD_SIZE} bytes: {url}");
+            }
+            writer.write_all(chunk).await?;
+        }
+        Ok(Artifact::Download(Some(writer)))
+    }
+
+    async fn from_stdin() -> Result<Self> {
+        let mut buffer = Vec::new();
+        io::stdin()
+            .read_to_end(&mut buffer)
+            .await
+            .context("Failed to read artifact from stdin")?;
+        Ok(Artifact::Memory(buffer))
+    }
+
+    /// Extract the .deb metadata, or reuse it from an earlier inspection of the same file
+    async fn inspect(&mut self, sha256: &[u8]) -> Result<Deb> {
+        match self {
+            Artifact::File(file) => {
+                file.rewind()
+                    .await
+                    .context("Failed to rewind file before inspection")?;
+                let inspect = inspect::cache::inspect(&mut *file, sha256).await?;
+                file.rewind()
+                    .await
+                    .context("Failed to rewind file after inspection")?;
+                Ok(inspect)
+            }
+            Artifact::Memory(buffer) => inspect::cache::inspect(buffer.as_slice(), sha256).await,
+            Artifact::Download(download) => {
+                let writer = download.take().context("Download is not available")?;
+                let mut reader = writer.into_reader().await?;
+                let inspect = inspect::cache::inspect(&mut reader, sha256).await;
+                *download = Some(reader.into_writer().await?);
+                inspect
+            }
+        }
+    }
+
+    async fn sha256(&mut self) -> Result<Vec<u8>> {
+        match self {
+            Artifact::File(file) => attestation::sha256_file(file).await,
+            Artifact::Memory(buffer) => attestation::sha256_file(buffer.as_slice()).await,
+            Artifact::Download(download) => {
+                let writer = download.as_ref().context("Download is not available")?;
+                Ok(writer.sha256())
+            }
+        }
+    }
+
+    /// Write the withheld data of a verified download
+    async fn finalize(&mut self) -> Result<()> {
+        if let Artifact::Download(Some(writer)) = self {
+            writer.finalize().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Exit code of `--check` if the config would be changed
+const CHECK_CHANGED: i32 = 2;
+/// How many packages of a lockfile or image are looked up at the same time
+const LOOKUP_CONCURRENCY: usize = 4;
+
+/// Only write the config if it was modified, so repeated runs are idempotent
+///
+/// Prints `changed` or `unchanged` for configuration management tools.
+async fn finish_change(config: &config::Writable, before: &str, mode: CheckMode) -> Result<()> {
+    let changed = toml::to_string(&**config)? != before;
+    println!("{}", if changed { "changed" } else { "unchanged" });
+
+    if mode.check {
+        if changed {
+            process::exit(CHECK_CHANGED);
+        }
+    } else if changed {
+        config.save().await?;
+    }
+    Ok(())
+}
+
+/// The signing key of the audit log, as configured in `[audit]`
+async fn audit_key(config: &Config) -> Result<signing::Signer> {
+    let Some(key) = &config.audit.signing_key else {
+        bail!("No audit log signing key configured, set `signing_key` in the `[audit]` section");
+    };
+    signing::Signer::load(key).await
+}
 
 pub async fn run(plumbing: Plumbing) -> Result<()> {
     match plumbing {
         Plumbing::FetchRebuilderdCommunity => {
             let http = http::client();
-            for rebuilder in rebuilder::fetch_rebuilderd_community(&http).await? {
+            let community = rebuilder::fetch_rebuilderd_community(&http).await?;
+            for rebuilder in community.into_rebuilders() {
                 let json = serde_json::to_string_pretty(&rebuilder)?;
                 println!("{}", json);
             }
         }
-        Plumbing::AddRebuilder { url, name } => {
-            let mut config = Config::load_writable().await?;
+        Plumbing::AddRebuilder { url, name, check } => {
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
 
             if let Some(rebuilder) = config.trusted_rebuilders.iter_mut().find(|r| r.url == url) {
                 // we track selected rebuilders as copy in case they get deleted from e.g. the rebuilderd-community list
@@ -45,94 +216,592 @@ pub async fn run(plumbing: Plumbing) -> Result<()> {
                     country: None,
                     contact: None,
                     signing_keyring: String::new(),
+                    valid_until: None,
+                    verified_fingerprint: None,
                 };
                 config.custom_rebuilders.push(rebuilder);
             }
 
-            config.save().await?;
+            finish_change(&config, &before, check).await?;
         }
-        Plumbing::RemoveRebuilder { url } => {
-            let mut config = Config::load_writable().await?;
+        Plumbing::RemoveRebuilder { url, check } => {
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
 
             config.trusted_rebuilders.retain(|r| r.url != url);
             config.custom_rebuilders.retain(|r| r.url != url);
 
-            config.save().await?;
+            finish_change(&config, &before, check).await?;
+        }
+        Plumbing::TrustCommunity {
+            distribution,
+            min,
+            distinct_countries,
+            refresh,
+            check,
+        } => {
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+            let http = http::client_for_config(&config)?;
+
+            if refresh || config.cached_rebuilderd_community.is_empty() {
+                match rebuilder::fetch_rebuilderd_community(&http).await {
+                    Ok(community) => {
+                        config.cached_rebuilderd_community = community.into_rebuilders();
+                        config.cached_rebuilderd_community_at = Some(Utc::now());
+                    }
+                    // Fall back to the bundled rebuilders on first run
+                    Err(err) if !refresh && !config.bundled_rebuilders().is_empty() => {
+                        warn!(
+                            "Failed to fetch rebuilderd-community list, using bundled rebuilders: {err:#}"
+                        );
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+
+            let candidates = if config.cached_rebuilderd_community.is_empty() {
+                config.bundled_rebuilders()
+            } else {
+                &config.cached_rebuilderd_community
+            };
+            let mut selected = Vec::new();
+            for rebuilder in rebuilder::rank(candidates, &distribution, distinct_countries) {
+                if selected.len() >= min {
+                    break;
+                }
+                // Pin the keyring that is currently in use
+                let mut rebuilder = rebuilder.clone();
+                if let Err(err) = rebuilder.refresh_signing_keyring(&http).await {
+                    warn!("Skipping rebuilder {:?}: {err:#}", rebuilder.url.as_str());
+                    continue;
+                }
+                info!(
+                    "Selected rebuilder {:?} ({})",
+                    rebuilder.name, rebuilder.url
+                );
+                selected.push(rebuilder);
+            }
+
+            if selected.len() < min {
+                bail!(
+                    "Only found {} usable rebuilders for {distribution:?}, but {min} are required",
+                    selected.len()
+                );
+            }
+
+            for rebuilder in selected {
+                if let Some(existing) = config
+                    .trusted_rebuilders
+                    .iter_mut()
+                    .find(|r| r.url == rebuilder.url)
+                {
+                    *existing = rebuilder;
+                } else {
+                    config.trusted_rebuilders.push(rebuilder);
+                }
+            }
+            config.rules.set_required_threshold(min);
+
+            finish_change(&config, &before, check).await?;
         }
-        Plumbing::ListRebuilders { all } => {
+        Plumbing::ImportRebuilders {
+            trust,
+            json,
+            check,
+            file,
+        } => {
+            let text = fs::read_to_string(&file)
+                .await
+                .with_context(|| format!("Failed to read file: {file:?}"))?;
+            let json = json || file.extension().is_some_and(|ext| ext == "json");
+            let list = rebuilder::List::parse(&text, json)
+                .with_context(|| format!("Failed to import rebuilders from {file:?}"))?;
+
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+
+            for rebuilder in list.rebuilders {
+                // Keep the copy of trusted rebuilders in sync
+                if let Some(existing) = config
+                    .trusted_rebuilders
+                    .iter_mut()
+                    .find(|r| r.url == rebuilder.url)
+                {
+                    *existing = rebuilder.clone();
+                } else if trust {
+                    config.trusted_rebuilders.push(rebuilder.clone());
+                }
+
+                if let Some(existing) = config
+                    .custom_rebuilders
+                    .iter_mut()
+                    .find(|r| r.url == rebuilder.url)
+                {
+                    *existing = rebuilder;
+                } else {
+                    config.custom_rebuilders.push(rebuilder);
+                }
+            }
+
+            finish_change(&config, &before, check).await?;
+        }
+        Plumbing::ExportRebuilders { all, json } => {
             let config = Config::load().await?;
-            for rebuilder in config.resolve_rebuilder_view() {
-                let status = if rebuilder.active {
-                    "[x]"
-                } else if all {
-                    "[ ]"
+            let rebuilders = config
+                .resolve_rebuilder_view()
+                .into_iter()
+                .filter(|r| all || r.active)
+                .map(|r| r.item)
+                .collect();
+            let list = rebuilder::List { rebuilders };
+            print!("{}", list.to_string(json)?);
+        }
+        Plumbing::RebuilderStats { json } => {
+            let stats = Stats::load().await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                for (url, stats) in &stats.rebuilders {
+                    let latency = stats
+                        .median_latency()
+                        .map(|latency| format!("{}ms", latency.as_millis()))
+                        .unwrap_or_else(|| "-".to_string());
+                    println!(
+                        "{url:?} - {} queries, {:.1}% found, {:.1}% errors, {} disagreements, median latency {latency}",
+                        stats.queries,
+                        stats.found_rate() * 100.0,
+                        stats.error_rate() * 100.0,
+                        stats.disagreements,
+                    );
+                }
+            }
+        }
+        Plumbing::MarkVerified {
+            url,
+            fingerprint,
+            check,
+        } => {
+            let fingerprint = signing::parse_fingerprint(&fingerprint)?;
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+
+            let rebuilder = config
+                .trusted_rebuilders
+                .iter_mut()
+                .find(|r| r.url == url)
+                .with_context(|| format!("Rebuilder is not trusted: {url}"))?;
+            let current = rebuilder
+                .fingerprint()
+                .with_context(|| format!("No signing key pinned for {url}"))?;
+            if current != fingerprint {
+                bail!(
+                    "Fingerprint does not match the signing key of {url}, it's {}",
+                    signing::format_fingerprint(&current)
+                );
+            }
+            rebuilder.verified_fingerprint = Some(fingerprint);
+
+            finish_change(&config, &before, check).await?;
+        }
+        Plumbing::UnmarkVerified { url, check } => {
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+
+            let rebuilder = config
+                .trusted_rebuilders
+                .iter_mut()
+                .find(|r| r.url == url)
+                .with_context(|| format!("Rebuilder is not trusted: {url}"))?;
+            rebuilder.verified_fingerprint = None;
+
+            finish_change(&config, &before, check).await?;
+        }
+        Plumbing::SaveProfile { name, check } => {
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+            config.save_profile(&name)?;
+            finish_change(&config, &before, check).await?;
+        }
+        Plumbing::UseProfile { name, check } => {
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+            config.use_profile(&name).await?;
+            finish_change(&config, &before, check).await?;
+        }
+        Plumbing::ListProfiles => {
+            let config = Config::load().await?;
+            for (name, profile) in &config.profiles {
+                let active = config.active_profile.as_deref() == Some(name.as_str());
+                // The active profile is stored when switching away, show the current state
+                let (rules, trusted) = if active {
+                    (&config.rules, config.trusted_rebuilders.len())
+                } else {
+                    (&profile.rules, profile.trusted_rebuilders.len())
+                };
+                let threshold = if rules.is_disabled() {
+                    "disabled".to_string()
                 } else {
+                    rules.required_threshold.to_string()
+                };
+                println!(
+                    "{} {name:?} - threshold {threshold}, {} trusted rebuilders",
+                    if active { "[x]" } else { "[ ]" },
+                    trusted
+                );
+            }
+        }
+        Plumbing::RemoveProfile { name, check } => {
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+            if config.profiles.remove(&name).is_none() {
+                bail!("Profile does not exist: {name:?}");
+            }
+            if config.active_profile.as_deref() == Some(name.as_str()) {
+                config.active_profile = None;
+            }
+            finish_change(&config, &before, check).await?;
+        }
+        Plumbing::ForgetKey { url, check } => {
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+
+            let mut found = false;
+            for rebuilder in config
+                .trusted_rebuilders
+                .iter_mut()
+                .filter(|r| r.url == url)
+            {
+                rebuilder.signing_keyring.clear();
+                found = true;
+            }
+            if !found {
+                bail!("Rebuilder is not trusted: {url}");
+            }
+            if !config.trust_on_first_use {
+                warn!(
+                    "Trust on first use is disabled, the keyring needs to be added again manually"
+                );
+            }
+
+            finish_change(&config, &before, check).await?;
+            if !check.check {
+                state::KeyChanges::update(|changes| {
+                    changes.rebuilders.remove(url.as_str());
+                })
+                .await;
+                state::Pins::try_update(|pins| {
+                    pins.keyrings.remove(url.as_str());
+                })
+                .await?;
+            }
+        }
+        Plumbing::CheckDnsKeys { url } => {
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+            let rebuilder = rebuilder::Rebuilder {
+                name: url.to_string(),
+                url: url.clone(),
+                distributions: vec![],
+                country: None,
+                contact: None,
+                signing_keyring: http.fetch_signing_keyring(&url).await?,
+                valid_until: None,
+                verified_fingerprint: None,
+            };
+
+            let name = rebuilder
+                .dns_record_name()
+                .with_context(|| format!("Failed to detect domain from url: {url:?}"))?;
+            let fingerprints = signing::pem_fingerprints(rebuilder.signing_keyring.as_bytes())?;
+            let record = fingerprints
+                .iter()
+                .fold("v=rt1".to_string(), |record, fp| record + " sha256=" + fp);
+            println!("Expected record: {name} TXT {record:?}");
+
+            match rebuilder.dns_binding(&http).await? {
+                rebuilder::DnsBinding::Missing => bail!("No fingerprints published in DNS"),
+                rebuilder::DnsBinding::Match { authenticated } => {
+                    println!("Keyring matches DNS (DNSSEC validated: {authenticated})");
+                }
+                rebuilder::DnsBinding::Mismatch {
+                    authenticated,
+                    published,
+                } => {
+                    for fingerprint in published {
+                        println!("Published: sha256={fingerprint}");
+                    }
+                    bail!("Keyring disagrees with DNS (DNSSEC validated: {authenticated})");
+                }
+            }
+        }
+        Plumbing::ListRebuilders {
+            all,
+            distributions,
+            countries,
+            with_keys_only,
+            json,
+        } => {
+            let config = Config::load().await?;
+            let filter = rebuilder::Filter {
+                distributions,
+                countries,
+                with_keys_only,
+            };
+            let now = Utc::now();
+            for rebuilder in config.resolve_rebuilder_view() {
+                if !(rebuilder.active || all) || !filter.matches(&rebuilder.item) {
                     continue;
+                }
+                let notice = rebuilder
+                    .item
+                    .expiry_notice(now)
+                    .or_else(|| config.upstream_status(&rebuilder.item).notice());
+                let fingerprint = rebuilder.item.fingerprint().ok();
+
+                if json {
+                    let line = serde_json::json!({
+                        "active": rebuilder.active,
+                        "name": rebuilder.item.name,
+                        "url": rebuilder.item.url,
+                        "distributions": rebuilder.item.distributions,
+                        "country": rebuilder.item.country,
+                        "contact": rebuilder.item.contact,
+                        "fingerprint": fingerprint,
+                        "verified_out_of_band": rebuilder.item.is_verified_out_of_band(),
+                        "valid_until": rebuilder.item.valid_until,
+                        "notice": notice,
+                    });
+                    println!("{line}");
+                    continue;
+                }
+
+                let status = if rebuilder.active { "[x]" } else { "[ ]" };
+                let notice = notice
+                    .map(|notice| format!(" ({notice})"))
+                    .unwrap_or_default();
+                let fingerprint = fingerprint
+                    .map(|fingerprint| format!(" - {}", signing::format_fingerprint(&fingerprint)))
+                    .unwrap_or_default();
+                let verified = if rebuilder.item.is_verified_out_of_band() {
+                    " (verified out-of-band)"
+                } else {
+                    ""
                 };
                 println!(
-                    "{} {:?} - {:?}",
+                    "{} {:?} - {:?}{fingerprint}{verified}{notice}",
                     status, rebuilder.item.name, rebuilder.item.url
                 );
             }
         }
-        Plumbing::AddBlindlyTrust { pkg } => {
-            let mut config = Config::load_writable().await?;
-            config.rules.blindly_trust.insert(pkg);
-            config.save().await?;
+        Plumbing::AddBlindlyTrust { pkg, source, check } => {
+            config::PackagePattern::parse(&pkg)
+                .with_context(|| format!("Invalid blindly-trust pattern: {pkg:?}"))?;
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+            if source {
+                config.rules.blindly_trust_source.insert(pkg);
+            } else {
+                config.rules.blindly_trust.insert(pkg);
+            }
+            finish_change(&config, &before, check).await?;
         }
-        Plumbing::RemoveBlindlyTrust { pkg } => {
-            let mut config = Config::load_writable().await?;
-            config.rules.blindly_trust.remove(&pkg);
-            config.save().await?;
+        Plumbing::RemoveBlindlyTrust { pkg, source, check } => {
+            let mut config = Config::lock_writable().await?;
+            let before = toml::to_string(&*config)?;
+            if source {
+                config.rules.blindly_trust_source.remove(&pkg);
+            } else {
+                config.rules.blindly_trust.remove(&pkg);
+            }
+            finish_change(&config, &before, check).await?;
         }
         Plumbing::ListBlindlyTrust => {
             let config = Config::load().await?;
             for pkg in &config.rules.blindly_trust {
                 println!("{pkg}");
             }
+            for pkg in &config.rules.blindly_trust_source {
+                println!("source:{pkg}");
+            }
         }
         Plumbing::Verify {
             signing_keys,
             attestations,
             rebuilders,
             threshold,
+            evidence,
+            offline,
+            export_evidence,
+            vsa,
+            vsa_key,
+            delta,
+            delta_base,
+            delta_format,
+            stdin,
+            keep,
+            json,
+            config,
+            no_config,
+            name,
+            version,
+            architecture,
+            http,
             file,
         } => {
-            let path = &file;
-            let mut file = File::open(path)
+            let package = match (name, version, architecture) {
+                (Some(name), Some(version), Some(architecture)) => Some(Deb {
+                    // Only used for source package queries, not known here
+                    source: name.clone(),
+                    name,
+                    version,
+                    architecture,
+                    ..Default::default()
+                }),
+                _ => None,
+            };
+
+            let path = if stdin {
+                PathBuf::from("-")
+            } else {
+                file.context("Missing file to verify")?
+            };
+            let url = path
+                .to_str()
+                .and_then(|path| path.parse::<Url>().ok())
+                .filter(|url| matches!(url.scheme(), "http" | "https"));
+            // Only the file name of a URL is used, for evidence bundles and VSAs
+            let path = match &url {
+                Some(url) => PathBuf::from(
+                    url.path_segments()
+                        .and_then(|mut segments| segments.next_back())
+                        .filter(|name| !name.is_empty())
+                        .unwrap_or("download"),
+                ),
+                None if keep.is_some() => bail!("--keep can only be used with a URL to verify"),
+                None => path,
+            };
+            if url.is_some() && delta.is_some() {
+                bail!("--delta can't be used with a URL to verify");
+            }
+
+            if let (Some(delta), Some(base)) = (&delta, &delta_base) {
+                info!("Reconstructing {path:?} from delta {delta:?}");
+                delta::reconstruct(delta_format, base, delta, &path).await?;
+            }
+
+            let bundle = if let Some(dir) = &evidence {
+                // The threshold of the bundle is chosen by whoever wrote it
+                if threshold.is_none() {
+                    bail!(
+                        "Verifying an evidence bundle needs an explicit --threshold of at least 1"
+                    );
+                }
+                let bundle = evidence::Bundle::load(dir)
+                    .await
+                    .with_context(|| format!("Failed to load evidence bundle: {dir:?}"))?;
+                Some(bundle)
+            } else {
+                None
+            };
+
+            // Without a threshold on the command line, rules and rebuilders come from a config file
+            let config_path = match config {
+                Some(path) => Some(path),
+                None if no_config || threshold.is_some() => None,
+                None => Some(Config::path()),
+            };
+            let policy_config = match &config_path {
+                Some(path) => Some(Config::load_from(path).await?),
+                None if threshold.is_none() => {
+                    bail!("Missing required threshold, use --threshold or --config")
+                }
+                None => None,
+            };
+            let policy_source = match &config_path {
+                Some(path) => format!("config file {path:?}"),
+                None => "command line".to_string(),
+            };
+            info!("Using policy from {policy_source}");
+
+            let rebuilders = match &policy_config {
+                Some(config) if !offline => config.rebuilder_urls_or(rebuilders),
+                _ => rebuilders,
+            };
+            let fetch = !offline && !rebuilders.is_empty();
+            // The privacy and network settings apply even if the rules come from the command line
+            let client = match &policy_config {
+                Some(config) => http.client(config)?,
+                None if no_config => http.client(&Default::default())?,
+                None => http.client(&Config::load().await?)?,
+            };
+
+            let path = &path;
+            let mut download = None;
+            let mut artifact = if stdin {
+                Artifact::from_stdin().await?
+            } else if let Some(url) = &url {
+                // Download next to the destination, so it can be moved there after verification.
+                // The temporary file is deleted when dropped, unless it was verified and kept.
+                let mut builder = tempfile::Builder::new();
+                builder.suffix(".part");
+                let tmp = match &keep {
+                    Some(keep) => {
+                        let dir = match keep.parent() {
+                            Some(dir) if !dir.as_os_str().is_empty() => dir,
+                            _ => Path::new("."),
+                        };
+                        builder
+                            .prefix(&format!(
+                                ".{}.",
+                                keep.file_name().unwrap_or_default().to_string_lossy()
+                            ))
+                            .tempfile_in(dir)
+                    }
+                    None => builder.prefix("repro-threshold-verify-").tempfile(),
+                }
+                .context("Failed to create temporary file for download")?;
+                let (file, tmp) = tmp.into_parts();
+                download = Some(tmp);
+                Artifact::download(&client, url, File::from_std(file)).await?
+            } else {
+                let file = File::open(path)
+                    .await
+                    .with_context(|| format!("Failed to open file {path:?}"))?;
+                Artifact::File(file)
+            };
+
+            let sha256 = artifact
+                .sha256()
                 .await
-                .with_context(|| format!("Failed to open file {path:?}"))?;
+                .with_context(|| format!("Failed to calculate hash for file: {path:?}"))?;
 
             // Extract .deb metadata (if needed)
-            let inspect = if !rebuilders.is_empty() {
+            let inspect = if !fetch && policy_config.is_none() {
+                None
+            } else if let Some(package) = package {
+                Some(package)
+            } else {
                 debug!("Inspecting package metadata: {path:?}");
 
                 // TODO: this is currently .deb only
-                let inspect = inspect::deb::inspect(&mut file)
+                let inspect = artifact
+                    .inspect(&sha256)
                     .await
                     .with_context(|| format!("Failed to inspect metadata: {path:?}"))?;
-                file.rewind()
-                    .await
-                    .with_context(|| format!("Failed to rewind file after inspection: {path:?}"))?;
 
                 Some(inspect)
-            } else {
-                None
             };
 
             // Load all files from the local filesystem and await rebuilder responses
-            let (sha256, mut attestations, remote_attestations, signing_keys) = tokio::try_join!(
-                async {
-                    attestation::sha256_file(file)
-                        .await
-                        .with_context(|| format!("Failed to calculate hash for file: {path:?}"))
-                },
+            let remote_package = inspect.clone().filter(|_| fetch);
+            let (mut attestations, remote_attestations, signing_keys) = tokio::try_join!(
                 async { Ok(attestation::load_all_attestations(&attestations).await) },
                 async {
-                    if let Some(inspect) = inspect {
-                        let http = http::client();
-                        let attestations =
-                            attestation::fetch_remote(&http, rebuilders, inspect).await;
+                    if let Some(package) = remote_package {
+                        let attestations = attestation::fetch_remote(&client, rebuilders, package)
+                            .await
+                            .attestations;
                         Ok(attestations)
                     } else {
                         Ok(Default::default())
@@ -144,30 +813,745 @@ pub async fn run(plumbing: Plumbing) -> Result<()> {
             // Merge local and remote attestations
             attestations.merge(remote_attestations);
 
-            // Process all attestations for verification
-            let confirms = attestations.verify(&sha256, &signing_keys);
-            if confirms.len() >= threshold {
-                info!(
-                    "Successfully verified attestations with {}/{} required signatures",
-                    confirms.len(),
-                    threshold
+            if let Some(bundle) = bundle {
+                bundle.ensure_artifact(&sha256)?;
+                attestations.merge(bundle.attestations);
+            }
+
+            let engine = match &policy_config {
+                Some(config) => policy::Engine::new(config),
+                None => policy::Engine::with_keys(&signing_keys, threshold.unwrap_or_default()),
+            };
+
+            let remote = attestation::Remote {
+                attestations,
+                ..Default::default()
+            };
+            let mut decision = engine.decide(inspect.as_ref(), &sha256, &remote);
+            decision.policy = Some(policy_source);
+            let confirms = engine.counted(&decision, &sha256, &remote.attestations);
+            let threshold = decision.threshold;
+            if json {
+                println!("{}", serde_json::to_string(&decision)?);
+            }
+
+            if let (Some(vsa_path), Some(vsa_key)) = (&vsa, &vsa_key) {
+                let key = signing::Signer::load(vsa_key).await?;
+                let artifact = path
+                    .file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+                    .into_owned();
+                let summary = vsa::Summary::new(
+                    artifact,
+                    &sha256,
+                    &decision,
+                    engine.signing_keys(),
+                    &confirms,
+                );
+                let envelope = vsa::Envelope::sign(&key, &summary.statement()?).await?;
+                let json = serde_json::to_string(&envelope)?;
+                fs::write(vsa_path, json + "\n")
+                    .await
+                    .with_context(|| format!("Failed to write VSA: {vsa_path:?}"))?;
+                info!("Wrote verification summary attestation to {vsa_path:?}");
+            }
+
+            if decision.verified() {
+                info!("Successfully verified attestations: {}", decision.message());
+
+                if let Some(dir) = &export_evidence {
+                    // A bundle without counted attestations can't be verified again
+                    if decision.outcome != policy::Outcome::Reproduced {
+                        bail!(
+                            "Refusing to export an evidence bundle, the package wasn't reproduced: {}",
+                            decision.message()
+                        );
+                    }
+                    evidence::export(dir, path, &sha256, threshold, &confirms)
+                        .await
+                        .with_context(|| format!("Failed to export evidence bundle: {dir:?}"))?;
+                    info!("Wrote evidence bundle to {dir:?}");
+                }
+
+                if let (Some(download), Some(keep)) = (download, &keep) {
+                    artifact.finalize().await?;
+                    download
+                        .persist(keep)
+                        .with_context(|| format!("Failed to move download to {keep:?}"))?;
+                    info!("Wrote verified download to {keep:?}");
+                }
+            } else {
+                bail!("Failed to verify attestations: {}", decision.message());
+            }
+        }
+        Plumbing::VerifySource {
+            name,
+            version,
+            rebuilders,
+        } => {
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+
+            let rebuilders = config.rebuilder_urls_or(rebuilders);
+            let artifacts =
+                attestation::fetch_remote_source(&http, rebuilders, &name, &version).await;
+            if artifacts.is_empty() {
+                bail!("No attestations found for source package {name:?} {version:?}");
+            }
+
+            let engine = policy::Engine::new(&config);
+            let mut failed = 0;
+            for (artifact, attestations) in artifacts {
+                // Find the hash with the most trusted confirms
+                let confirms = attestations
+                    .candidate_sha256s()
+                    .iter()
+                    .map(|sha256| engine.confirms(sha256, &attestations).len())
+                    .max()
+                    .unwrap_or(0);
+
+                let threshold = engine.threshold(Some(&artifact));
+                let status = if confirms >= threshold {
+                    "[x]"
+                } else {
+                    failed += 1;
+                    "[ ]"
+                };
+                println!(
+                    "{status} {} {} {} - {confirms}/{threshold} confirms",
+                    artifact.name, artifact.version, artifact.architecture
                 );
+            }
+
+            if failed > 0 {
+                bail!("{failed} binary packages did not reach the required threshold");
+            }
+        }
+        Plumbing::VerifyHash {
+            name,
+            version,
+            architecture,
+            source,
+            sha256,
+            rebuilders,
+            json,
+        } => {
+            let sha256 = attestation::parse_sha256(&sha256)?;
+            let package = Deb {
+                source: source.unwrap_or_else(|| name.clone()),
+                name,
+                version,
+                architecture,
+                ..Default::default()
+            };
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+
+            let rebuilders = config.rebuilder_urls_or(rebuilders);
+            let remote = attestation::fetch_remote(&http, rebuilders, package.clone()).await;
+            let decision = policy::Engine::new(&config).decide(Some(&package), &sha256, &remote);
+            if json {
+                println!("{}", serde_json::to_string(&decision)?);
+            }
+
+            let label = format!(
+                "{} {} {}",
+                package.name, package.version, package.architecture
+            );
+            if decision.verified() {
+                info!("{label}: {}", decision.message());
             } else {
+                bail!("{label}: {}", decision.message());
+            }
+        }
+        Plumbing::VerifyLockfile {
+            format,
+            architecture,
+            rebuilders,
+            json,
+            file,
+        } => {
+            let content = fs::read_to_string(&file)
+                .await
+                .with_context(|| format!("Failed to read lockfile: {file:?}"))?;
+            let format = format.unwrap_or_else(|| lockfile::Format::detect(&content));
+            debug!("Parsing {file:?} as {format:?}");
+            let entries = lockfile::parse(format, &content, architecture.as_deref())
+                .with_context(|| format!("Failed to parse lockfile: {file:?}"))?;
+            if entries.is_empty() {
+                bail!("No packages found in lockfile: {file:?}");
+            }
+
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+            let rebuilders = config.rebuilder_urls_or(rebuilders);
+
+            let engine = policy::Engine::new(&config);
+            let mut decisions = stream::iter(&entries)
+                .map(|entry| {
+                    let (http, rebuilders) = (&http, rebuilders.clone());
+                    async move {
+                        let remote =
+                            attestation::fetch_remote(http, rebuilders, entry.package.clone())
+                                .await;
+                        (entry, remote)
+                    }
+                })
+                .buffered(LOOKUP_CONCURRENCY);
+
+            let mut failed = 0;
+            while let Some((entry, remote)) = decisions.next().await {
+                let package = &entry.package;
+                let decision = engine.decide(Some(package), &entry.sha256, &remote);
+                if !decision.verified() {
+                    failed += 1;
+                }
+
+                if json {
+                    let line = serde_json::json!({
+                        "package": package,
+                        "sha256": data_encoding::HEXLOWER.encode(&entry.sha256),
+                        "decision": decision,
+                    });
+                    println!("{line}");
+                } else {
+                    let status = if decision.verified() { "[x]" } else { "[ ]" };
+                    println!(
+                        "{status} {} {} {} - {}",
+                        package.name,
+                        package.version,
+                        package.architecture,
+                        decision.message()
+                    );
+                }
+            }
+
+            if failed > 0 {
+                bail!(
+                    "{failed} of {} packages in the lockfile are not verified",
+                    entries.len()
+                );
+            }
+            info!(
+                "All {} packages in the lockfile are verified",
+                entries.len()
+            );
+        }
+        Plumbing::VerifyContainer {
+            platform,
+            rebuilders,
+            json,
+            image,
+        } => {
+            let reference = image.parse::<container::Reference>()?;
+            let platform = platform.unwrap_or_else(container::default_platform);
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+
+            let image = container::pull(&http, &reference, &platform).await?;
+            if image.packages.is_empty() {
+                bail!("No dpkg or pacman package database found in {reference}");
+            }
+            info!(
+                "Found {} installed packages in {reference} ({})",
+                image.packages.len(),
+                image.platform
+            );
+
+            let rebuilders = config.rebuilder_urls_or(rebuilders);
+
+            let engine = policy::Engine::new(&config);
+            let mut decisions = stream::iter(&image.packages)
+                .map(|package| {
+                    let (http, rebuilders) = (&http, rebuilders.clone());
+                    async move {
+                        let remote =
+                            attestation::fetch_remote(http, rebuilders, package.package.clone())
+                                .await;
+                        (package, remote)
+                    }
+                })
+                .buffered(LOOKUP_CONCURRENCY);
+
+            let (mut verified, mut unpinned) = (0, 0);
+            while let Some((package, remote)) = decisions.next().await {
+                // Without a pinned hash, use the build confirmed by the most rebuilders
+                let sha256 = package.sha256.clone().unwrap_or_else(|| {
+                    unpinned += 1;
+                    remote
+                        .attestations
+                        .candidate_sha256s()
+                        .into_iter()
+                        .max_by_key(|sha256| engine.confirms(sha256, &remote.attestations).len())
+                        .unwrap_or_default()
+                });
+                let deb = &package.package;
+                let decision = engine.decide(Some(deb), &sha256, &remote);
+                if decision.verified() {
+                    verified += 1;
+                }
+
+                if json {
+                    let line = serde_json::json!({
+                        "package": deb,
+                        "sha256": package.sha256.as_ref().map(|sha256| data_encoding::HEXLOWER.encode(sha256)),
+                        "decision": decision,
+                    });
+                    println!("{line}");
+                } else {
+                    let status = if decision.verified() { "[x]" } else { "[ ]" };
+                    let note = if package.sha256.is_none() {
+                        " (hash not pinned in image)"
+                    } else {
+                        ""
+                    };
+                    println!(
+                        "{status} {} {} {} - {}{note}",
+                        deb.name,
+                        deb.version,
+                        deb.architecture,
+                        decision.message()
+                    );
+                }
+            }
+
+            let total = image.packages.len();
+            info!("{verified}/{total} packages are reproduced, {unpinned} without a pinned hash");
+            if verified < total {
+                bail!(
+                    "{} of {total} packages in {reference} are not verified",
+                    total - verified
+                );
+            }
+        }
+        Plumbing::VerifyLayout {
+            layout,
+            keys,
+            links,
+        } => {
+            let layout = layout::Layout { path: layout, keys };
+            layout::verify(&layout, &links).await?;
+            info!("Layout verification successful");
+        }
+        Plumbing::SelfVerify { rebuilders, deb } => {
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+
+            let rebuilders = config.rebuilder_urls_or(rebuilders);
+
+            let exe =
+                env::current_exe().context("Failed to determine path of current executable")?;
+            let installed = self_verify::owning_package(&exe).await?;
+            let path = deb.unwrap_or_else(|| {
+                Path::new(self_verify::APT_ARCHIVES).join(self_verify::archive_filename(&installed))
+            });
+            if !fs::try_exists(&path).await? {
                 bail!(
-                    "Failed to verify attestations: only {}/{} required signatures",
-                    confirms.len(),
-                    threshold
+                    "Package file not found: {path:?}, run `apt-get download {}={}` and pass it with --deb",
+                    installed.name,
+                    installed.version
+                );
+            }
+
+            let (mut file, sha256) = attestation::open_hashed(&path)
+                .await
+                .with_context(|| format!("Failed to open and hash file {path:?}"))?;
+            let inspect = inspect::deb::inspect(&mut file)
+                .await
+                .with_context(|| format!("Failed to inspect metadata: {path:?}"))?;
+            if (&inspect.name, &inspect.version, &inspect.architecture)
+                != (&installed.name, &installed.version, &installed.architecture)
+            {
+                bail!(
+                    "Package file {path:?} is {} {} {}, but {} {} {} is installed",
+                    inspect.name,
+                    inspect.version,
+                    inspect.architecture,
+                    installed.name,
+                    installed.version,
+                    installed.architecture
+                );
+            }
+
+            // The running binary needs to be the one shipped in the package
+            file.rewind()
+                .await
+                .with_context(|| format!("Failed to rewind file {path:?}"))?;
+            let shipped = inspect::deb::sha256_data_file(file, &exe)
+                .await
+                .with_context(|| format!("Failed to read data.tar of {path:?}"))?
+                .with_context(|| format!("Package file {path:?} does not contain {exe:?}"))?;
+            let running = attestation::sha256_path(&exe)
+                .await
+                .with_context(|| format!("Failed to calculate hash for file: {exe:?}"))?;
+            let unmodified = shipped == running;
+
+            let remote = attestation::fetch_remote(&http, rebuilders, inspect.clone()).await;
+            let decision = policy::Engine::new(&config).decide(Some(&inspect), &sha256, &remote);
+            let (confirms, threshold) = (decision.confirms(), decision.threshold);
+            let reproduced = decision.verified();
+
+            let status = |ok| if ok { "[x]" } else { "[ ]" };
+            println!(
+                "{} {exe:?} is identical to the file in {path:?}",
+                status(unmodified)
+            );
+            println!(
+                "{} {} {} {} - {confirms}/{threshold} confirms",
+                status(reproduced),
+                inspect.name,
+                inspect.version,
+                inspect.architecture
+            );
+
+            if !unmodified {
+                bail!("The running binary does not match the installed package");
+            }
+            if !reproduced {
+                bail!("The installed package did not reach the required threshold");
+            }
+        }
+        Plumbing::Status { json } => {
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+            let status = status::collect(&http, &config).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                status.print();
+            }
+        }
+        Plumbing::Pending => {
+            let pending = state::Pending::load().await?;
+            let now = Utc::now();
+
+            for (sha256, entry) in &pending.packages {
+                let status = if entry.is_overdue(now) { "[!]" } else { "[ ]" };
+                let deadline = entry
+                    .deadline
+                    .map(|deadline| format!("deadline {deadline}"))
+                    .unwrap_or_else(|| "no deadline".to_string());
+                println!(
+                    "{status} {} {} {} - {:?}, {deadline}, sha256 {sha256}",
+                    entry.package.name,
+                    entry.package.version,
+                    entry.package.architecture,
+                    entry.reason,
                 );
             }
+
+            let overdue = pending.overdue(now).count();
+            if overdue > 0 {
+                bail!("{overdue} packages passed their deadline without getting reproduced");
+            }
+        }
+        Plumbing::DiffAttestations { quiet, a, b } => {
+            let http = http::client();
+            let (a, b) =
+                tokio::try_join!(load_attestation(&http, &a), load_attestation(&http, &b))?;
+
+            let mut identical = true;
+            for (section, changes) in diff::diff(&a, &b)? {
+                println!("{section}:");
+                for change in changes {
+                    if change.is_same() {
+                        if quiet {
+                            continue;
+                        }
+                    } else {
+                        identical = false;
+                    }
+                    println!("{change}");
+                }
+            }
+
+            if identical {
+                info!("Attestations are identical");
+            }
+        }
+        Plumbing::Investigate {
+            rebuilders,
+            output,
+            file,
+        } => {
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+
+            let rebuilders = config.rebuilder_urls_or(rebuilders);
+
+            let path = &file;
+            let (file, sha256) = attestation::open_hashed(path)
+                .await
+                .with_context(|| format!("Failed to open and hash file {path:?}"))?;
+            let inspect = inspect::cache::inspect(file, &sha256)
+                .await
+                .with_context(|| format!("Failed to inspect metadata: {path:?}"))?;
+
+            // The rebuilt artifacts are kept for further investigation
+            let dir = match output {
+                Some(dir) => dir,
+                None => tempfile::Builder::new()
+                    .prefix("repro-threshold-investigate-")
+                    .tempdir()
+                    .context("Failed to create temporary directory")?
+                    .keep(),
+            };
+            let findings =
+                investigate::fetch_rebuilt(&http, &rebuilders, &inspect, &sha256, &dir).await?;
+            if findings.is_empty() {
+                bail!("No rebuilt artifacts found for {path:?}");
+            }
+
+            for (url, finding) in findings {
+                match finding {
+                    investigate::Finding::Identical => {
+                        info!("Rebuilt artifact is identical to local file: {url}");
+                    }
+                    investigate::Finding::Differs(rebuilt) => {
+                        info!("Rebuilt artifact differs from local file: {url}");
+                        investigate::diffoscope(path, &rebuilt).await?;
+                    }
+                }
+            }
+        }
+        Plumbing::Report { rebuilders, file } => {
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+
+            let rebuilders = if rebuilders.is_empty() {
+                config.trusted_rebuilders.clone()
+            } else {
+                let mut list = Vec::new();
+                for url in rebuilders {
+                    let name = url.domain().unwrap_or(url.as_str()).to_string();
+                    let mut rebuilder = rebuilder::Rebuilder {
+                        name,
+                        url,
+                        distributions: vec![],
+                        country: None,
+                        contact: None,
+                        signing_keyring: String::new(),
+                        valid_until: None,
+                        verified_fingerprint: None,
+                    };
+                    if let Err(err) = rebuilder.refresh_signing_keyring(&http).await {
+                        warn!("Failed to fetch signing key for {}: {err:#}", rebuilder.url);
+                    }
+                    list.push(rebuilder);
+                }
+                list
+            };
+
+            let path = &file;
+            let (file, sha256) = attestation::open_hashed(path)
+                .await
+                .with_context(|| format!("Failed to open and hash file {path:?}"))?;
+            let inspect = inspect::cache::inspect(file, &sha256)
+                .await
+                .with_context(|| format!("Failed to inspect metadata: {path:?}"))?;
+
+            let mut results = Vec::new();
+            for rebuilder in rebuilders {
+                results.push(report::check(&http, rebuilder, &inspect, &sha256).await);
+            }
+            print!("{}", report::render(&inspect, &sha256, &results)?);
+        }
+        Plumbing::DebugBundle {
+            output,
+            deb,
+            logs,
+            log_lines,
+        } => {
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+
+            let bundle =
+                debug_bundle::collect(&http, &config, deb.as_deref(), &logs, log_lines).await;
+            let path = output.unwrap_or_else(|| {
+                PathBuf::from(format!(
+                    "repro-threshold-debug-{}.tar.xz",
+                    Utc::now().format("%Y%m%dT%H%M%SZ")
+                ))
+            });
+            bundle.write(&path).await?;
+
+            for name in bundle.names() {
+                info!("Collected {name}");
+            }
+            info!("Wrote debug bundle to {path:?}, please review it before sharing");
+        }
+        Plumbing::Keygen {
+            path,
+            pkcs11,
+            key_type,
+        } => {
+            let public_key = match (path, pkcs11) {
+                (_, Some(uri)) => {
+                    let key = pkcs11::Key::generate(&uri, key_type.unwrap_or_default()).await?;
+                    // The URI may contain the PIN, so it's not logged
+                    info!("Generated private key on the PKCS#11 token");
+                    key.public_key_pem()
+                }
+                (Some(path), None) => {
+                    if key_type.is_some() {
+                        bail!("--key-type needs --pkcs11, key files are always Ed25519");
+                    }
+                    let key = signing::LocalKey::generate(&path).await?;
+                    info!("Wrote private key to {path:?}");
+                    key.public_key_pem()
+                }
+                (None, None) => bail!("Either a path or --pkcs11 is required"),
+            };
+            print!("{public_key}");
+        }
+        Plumbing::SignResult {
+            key,
+            output,
+            config,
+            bundle,
+            artifact,
+        } => {
+            let config = match &config {
+                Some(path) => Config::load_from(path).await?,
+                None => Config::load().await?,
+            };
+            let key = signing::Signer::load(&key).await?;
+            let statement = countersign::attest_bundle(&config, &bundle, &artifact).await?;
+            let countersig = countersign::Countersignature::sign(&key, &statement).await?;
+
+            let path = output.unwrap_or_else(|| bundle.join(countersign::FILENAME));
+            let json = serde_json::to_string_pretty(&countersig)?;
+            fs::write(&path, json)
+                .await
+                .with_context(|| format!("Failed to write countersignature: {path:?}"))?;
+            info!("Wrote countersignature to {path:?}");
+        }
+        Plumbing::VerifyResult { public_key, file } => {
+            let public_key = fs::read_to_string(&public_key)
+                .await
+                .with_context(|| format!("Failed to read public key: {public_key:?}"))?;
+            let countersig = countersign::Countersignature::load(&file).await?;
+            let statement = countersig
+                .verify_with(&public_key)
+                .with_context(|| format!("Failed to verify countersignature: {file:?}"))?;
+            println!("{}", serde_json::to_string_pretty(&statement)?);
+        }
+        Plumbing::VerifyVsa { public_key, file } => {
+            let public_key = fs::read_to_string(&public_key)
+                .await
+                .with_context(|| format!("Failed to read public key: {public_key:?}"))?;
+            let buf = fs::read(&file)
+                .await
+                .with_context(|| format!("Failed to read VSA: {file:?}"))?;
+            let envelope = serde_json::from_slice::<vsa::Envelope>(&buf)
+                .with_context(|| format!("Failed to parse VSA: {file:?}"))?;
+            let statement = envelope
+                .verify(&public_key)
+                .with_context(|| format!("Failed to verify VSA: {file:?}"))?;
+            println!("{}", serde_json::to_string_pretty(&statement)?);
+        }
+        Plumbing::ServeAttestations {
+            bind,
+            signing_key,
+            dir,
+        } => {
+            let keyring = fs::read_to_string(&signing_key)
+                .await
+                .with_context(|| format!("Failed to read signing key: {signing_key:?}"))?;
+            signing::pem_to_pubkeys(keyring.as_bytes())
+                .with_context(|| format!("Failed to parse signing key: {signing_key:?}"))?
+                .next()
+                .with_context(|| format!("No public key found in file: {signing_key:?}"))??;
+
+            let index = serve::Index::scan(&dir, keyring).await?;
+            serve::run(bind, index).await?;
+        }
+        Plumbing::ServeAdmission {
+            bind,
+            token_file,
+            tls_cert,
+            tls_key,
+            timeout,
+        } => {
+            let config = Config::load().await?;
+            let http = http::client_for_config(&config)?;
+
+            let token = fs::read_to_string(&token_file)
+                .await
+                .with_context(|| format!("Failed to read token file: {token_file:?}"))?;
+            let token = token.trim().to_string();
+            if token.is_empty() {
+                bail!("Token file is empty: {token_file:?}");
+            }
+
+            let verifier = admission::Verifier::new(config, http, Duration::from_secs(timeout));
+            admission::run(bind, token, &tls_cert, &tls_key, verifier).await?;
         }
-        Plumbing::InspectDeb { file } => {
+        Plumbing::InspectDeb {
+            list_contents,
+            file,
+        } => {
             let path = &file;
             let file = File::open(path)
                 .await
                 .with_context(|| format!("Failed to open file {path:?}"))?;
 
-            let data = inspect::deb::inspect(file).await?;
-            println!("data={data:#?}");
+            if list_contents {
+                let files = inspect::deb::list_data_files(file)
+                    .await
+                    .with_context(|| format!("Failed to list contents of {path:?}"))?;
+                for file in files {
+                    println!("{file}");
+                }
+            } else {
+                let data = inspect::deb::inspect(file).await?;
+                println!("data={data:#?}");
+            }
+        }
+        Plumbing::Log(Log::Verify {
+            public_key,
+            head,
+            file,
+        }) => {
+            let config = Config::load().await?;
+            let public_key = match public_key {
+                Some(path) => fs::read_to_string(&path)
+                    .await
+                    .with_context(|| format!("Failed to read public key: {path:?}"))?,
+                None => audit_key(&config).await?.public_key_pem(),
+            };
+            let (text, head) = match file {
+                Some(file) => {
+                    let text = fs::read_to_string(&file)
+                        .await
+                        .with_context(|| format!("Failed to read audit log: {file:?}"))?;
+                    (text, head)
+                }
+                None => {
+                    // Sign what's queued first, so the log is complete up to the counter
+                    audit::seal(&config.audit).await?;
+                    let text = audit::read(&audit::path()).await?;
+                    let head = match head {
+                        Some(head) => Some(head),
+                        None => config.audit.head().await?,
+                    };
+                    (text, head)
+                }
+            };
+
+            let summary = audit::verify_chain(&text, &public_key, head)?;
+            if summary.unsigned > 0 {
+                warn!(
+                    "{} entries were written before signing was enabled and can't be verified",
+                    summary.unsigned
+                );
+            }
+            println!("Verified {} signed audit log entries", summary.signed);
+        }
+        Plumbing::Log(Log::PublicKey) => {
+            let config = Config::load().await?;
+            print!("{}", audit_key(&config).await?.public_key_pem());
         }
         Plumbing::Completions(completions) => {
             completions.generate();
@@ -176,3 +1560,242 @@ pub async fn run(plumbing: Plumbing) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    fn verify(rebuilders: Vec<url::Url>, threshold: usize) -> Plumbing {
+        Plumbing::Verify {
+            signing_keys: vec!["test_data/reproduce-debian-net-amd64.pub".into()],
+            attestations: vec![],
+            rebuilders,
+            threshold: Some(threshold),
+            evidence: None,
+            offline: false,
+            export_evidence: None,
+            vsa: None,
+            vsa_key: None,
+            delta: None,
+            delta_base: None,
+            delta_format: delta::Format::Xdelta3,
+            stdin: false,
+            keep: None,
+            json: false,
+            config: None,
+            no_config: true,
+            name: None,
+            version: None,
+            architecture: None,
+            http: Default::default(),
+            file: Some(test_server::DEB_PATH.into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_with_rebuilder() {
+        let rebuilder = test_server::spawn_rebuilder().await;
+        run(verify(vec![rebuilder], 1)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_with_rebuilder_below_threshold() {
+        let rebuilder = test_server::spawn_rebuilder().await;
+        let result = run(verify(vec![rebuilder], 2)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify_with_package_args() {
+        let rebuilder = test_server::spawn_rebuilder().await;
+        let deb = test_server::deb();
+        let mut verify = verify(vec![rebuilder], 1);
+        if let Plumbing::Verify {
+            name,
+            version,
+            architecture,
+            ..
+        } = &mut verify
+        {
+            *name = Some(deb.name);
+            *version = Some(deb.version);
+            *architecture = Some(deb.architecture);
+        }
+        run(verify).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_artifact_memory_sha256() {
+        let buffer = fs::read(test_server::DEB_PATH).await.unwrap();
+        let sha256 = Artifact::Memory(buffer).sha256().await.unwrap();
+        assert_eq!(sha256, test_server::deb_sha256());
+    }
+
+    #[tokio::test]
+    async fn test_verify_without_attestations() {
+        let empty = test_server::spawn(Default::default()).await;
+        let result = run(verify(vec![empty], 1)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify_privacy_from_system_config() {
+        let rebuilder = test_server::spawn_rebuilder().await;
+        // The relay only counts connections, requests sent through it fail
+        let relay = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = relay.local_addr().unwrap();
+        let relayed = tokio::spawn(async move { relay.accept().await.is_ok() });
+
+        let path = env::temp_dir().join(format!(
+            "repro-threshold-test-verify-privacy-{}.conf",
+            process::id()
+        ));
+        fs::write(&path, format!("[privacy]\nrelay = \"http://{addr}\"\n"))
+            .await
+            .unwrap();
+        // SAFETY: no other test reads this variable, they all pass no_config or a config path
+        unsafe { env::set_var("REPRO_THRESHOLD_CONFIG", &path) };
+
+        let deb = test_server::deb();
+        let mut verify = verify(vec![rebuilder], 1);
+        if let Plumbing::Verify {
+            signing_keys,
+            no_config,
+            name,
+            version,
+            architecture,
+            ..
+        } = &mut verify
+        {
+            signing_keys.clear();
+            *no_config = false;
+            *name = Some(deb.name);
+            *version = Some(deb.version);
+            *architecture = Some(deb.architecture);
+        }
+        let result = run(verify).await;
+        fs::remove_file(&path).await.unwrap();
+        assert!(result.is_err());
+        assert!(relayed.is_finished());
+        assert!(relayed.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify_with_config() {
+        let path = env::temp_dir().join(format!(
+            "repro-threshold-test-verify-config-{}.conf",
+            process::id()
+        ));
+        let with_config = || {
+            let deb = test_server::deb();
+            let mut verify = verify(vec![], 1);
+            if let Plumbing::Verify {
+                signing_keys,
+                threshold,
+                offline,
+                config,
+                name,
+                version,
+                architecture,
+                ..
+            } = &mut verify
+            {
+                signing_keys.clear();
+                *threshold = None;
+                *offline = true;
+                *config = Some(path.clone());
+                *name = Some(deb.name);
+                *version = Some(deb.version);
+                *architecture = Some(deb.architecture);
+            }
+            verify
+        };
+
+        // An explicitly selected config file has to exist
+        assert!(run(with_config()).await.is_err());
+
+        fs::write(&path, "[rules]\nrequired_threshold = 1\n")
+            .await
+            .unwrap();
+        let rejected = run(with_config()).await;
+        let blindly_trust = format!(
+            "[rules]\nrequired_threshold = 1\nblindly_trust = [{:?}]\n",
+            test_server::deb().name
+        );
+        fs::write(&path, blindly_trust).await.unwrap();
+        let blindly_trusted = run(with_config()).await;
+        fs::remove_file(&path).await.unwrap();
+        assert!(rejected.is_err());
+        blindly_trusted.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_url_keep() {
+        let mirror = test_server::spawn_mirror().await;
+        let url = mirror.join("pool/test.deb").unwrap();
+        let keep = env::temp_dir().join(format!(
+            "repro-threshold-test-verify-keep-{}.deb",
+            process::id()
+        ));
+        let rejecting = env::temp_dir().join(format!(
+            "repro-threshold-test-verify-keep-rejecting-{}.conf",
+            process::id()
+        ));
+        let trusting = env::temp_dir().join(format!(
+            "repro-threshold-test-verify-keep-trusting-{}.conf",
+            process::id()
+        ));
+        fs::write(&rejecting, "[rules]\nrequired_threshold = 1\n")
+            .await
+            .unwrap();
+        let blindly_trust = format!(
+            "[rules]\nrequired_threshold = 1\nblindly_trust = [{:?}]\n",
+            test_server::deb().name
+        );
+        fs::write(&trusting, blindly_trust).await.unwrap();
+
+        let from_url = |path: &Path| {
+            let deb = test_server::deb();
+            let mut verify = verify(vec![], 1);
+            if let Plumbing::Verify {
+                signing_keys,
+                threshold,
+                offline,
+                keep: keep_path,
+                config,
+                name,
+                version,
+                architecture,
+                file,
+                ..
+            } = &mut verify
+            {
+                signing_keys.clear();
+                *threshold = None;
+                *offline = true;
+                *keep_path = Some(keep.clone());
+                *config = Some(path.to_path_buf());
+                *name = Some(deb.name);
+                *version = Some(deb.version);
+                *architecture = Some(deb.architecture);
+                *file = Some(url.as_str().into());
+            }
+            verify
+        };
+
+        // Nothing is written if verification fails
+        let rejected = run(from_url(&rejecting)).await;
+        let kept = keep.exists();
+        let trusted = run(from_url(&trusting)).await;
+        fs::remove_file(&rejecting).await.unwrap();
+        fs::remove_file(&trusting).await.unwrap();
+        assert!(rejected.is_err());
+        assert!(!kept);
+
+        trusted.unwrap();
+        let buf = fs::read(&keep).await.unwrap();
+        fs::remove_file(&keep).await.unwrap();
+        assert_eq!(buf, fs::read(test_server::DEB_PATH).await.unwrap());
+    }
+}
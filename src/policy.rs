@@ -0,0 +1,781 @@
+//! The policy engine, deciding if an artifact is admitted based on the attestations found
+//!
+//! All transports and verification commands go through this, so they only have to fetch
+//! attestations and present the decision.
+use crate::attestation::{Confirm, Remote, Tree};
+use crate::config::{Config, Offline, PartialResults, Rules};
+use crate::inspect::deb::Deb;
+use crate::signing::DomainTree;
+use in_toto::crypto::{KeyId, PublicKey};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+enum Threshold<'a> {
+    /// The rules of the configuration, including per-architecture thresholds
+    Rules(&'a Rules),
+    /// A fixed threshold, e.g. given on the command line
+    Fixed(usize),
+}
+
+/// Why the outcome was chosen, the most specific reason comes first
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Reason {
+    ThresholdReached {
+        confirms: usize,
+        threshold: usize,
+    },
+    /// Trusted rebuilders reproduced a different binary
+    Conflicting {
+        rebuilders: Vec<String>,
+    },
+    RebuildersUnreachable {
+        count: usize,
+    },
+    /// Trusted rebuilders didn't answer before the deadline
+    DeadlineReached {
+        count: usize,
+    },
+    NoAttestations,
+    ThresholdNotMet {
+        confirms: usize,
+        threshold: usize,
+    },
+    /// Too few confirms came from rebuilders verified out-of-band
+    OutOfBandVerifiedNotMet {
+        verified: usize,
+        required: usize,
+    },
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::ThresholdReached {
+                confirms,
+                threshold,
+            } => write!(
+                f,
+                "Threshold reached with {confirms}/{threshold} required signatures"
+            ),
+            Reason::Conflicting { rebuilders } => write!(
+                f,
+                "Conflicting attestations: {} reproduced a different binary",
+                rebuilders.join(", ")
+            ),
+            Reason::RebuildersUnreachable { count } => write!(
+                f,
+                "Rebuilders unreachable: {count} trusted rebuilders could not be queried"
+            ),
+            Reason::DeadlineReached { count } => write!(
+                f,
+                "Deadline reached: {count} trusted rebuilders did not answer in time"
+            ),
+            Reason::NoAttestations => write!(
+                f,
+                "No attestations found: none of the trusted rebuilders reproduced this package yet"
+            ),
+            Reason::ThresholdNotMet {
+                confirms,
+                threshold,
+            } => write!(
+                f,
+                "Threshold not met: only {confirms}/{threshold} required signatures"
+            ),
+            Reason::OutOfBandVerifiedNotMet { verified, required } => write!(
+                f,
+                "Not enough verified rebuilders: only {verified}/{required} required signatures from rebuilders verified out-of-band"
+            ),
+        }
+    }
+}
+
+/// A configured rule that was applied in the decision
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "rule", rename_all = "kebab-case")]
+pub enum RuleHit {
+    RequiredThreshold {
+        threshold: usize,
+        /// Set if an architecture specific threshold was used
+        #[serde(skip_serializing_if = "Option::is_none")]
+        architecture: Option<String>,
+    },
+    BlindlyTrust {
+        package: String,
+    },
+    BlindlyTrustSource {
+        source: String,
+    },
+    /// An installer component admitted by `allow_udeb`
+    AllowUdeb {
+        package: String,
+    },
+    /// `enforcement = "disabled"` admits everything
+    EnforcementDisabled,
+    GracePeriod {
+        days: u32,
+    },
+    PartialResults,
+    /// None of the trusted rebuilders could be reached
+    Offline {
+        fallback: Offline,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    BlindlyTrusted,
+    Reproduced,
+    /// Below the threshold, but admitted and tracked until the grace period ends
+    GracePeriod,
+    /// Below the threshold because rebuilders didn't answer in time, admitted like the grace period
+    PartialResults,
+    /// No trusted rebuilder could be reached, admitted with a warning until it's verified later
+    Offline,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Ballot {
+    /// The rebuilder signed an attestation for this artifact
+    Confirm,
+    /// The rebuilder only signed attestations for a different artifact
+    Disagree,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Vote {
+    pub key_id: KeyId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rebuilder: Option<String>,
+    pub ballot: Ballot,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Decision {
+    pub outcome: Outcome,
+    pub reasons: Vec<Reason>,
+    pub threshold: usize,
+    /// Votes of trusted keys, confirms are counted once per rebuilder domain
+    pub votes: Vec<Vote>,
+    pub rule_hits: Vec<RuleHit>,
+    /// Where the rules came from, e.g. a config file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+}
+
+impl Decision {
+    /// The artifact is blindly trusted or reached the threshold
+    pub fn verified(&self) -> bool {
+        matches!(self.outcome, Outcome::BlindlyTrusted | Outcome::Reproduced)
+    }
+
+    /// Rejected only because no trusted rebuilder could be reached, to be settled later
+    pub fn is_deferred(&self) -> bool {
+        self.outcome == Outcome::Rejected
+            && self.rule_hits.contains(&RuleHit::Offline {
+                fallback: Offline::Defer,
+            })
+    }
+
+    fn ballots(&self, ballot: Ballot) -> impl Iterator<Item = &Vote> {
+        self.votes.iter().filter(move |vote| vote.ballot == ballot)
+    }
+
+    pub fn confirms(&self) -> usize {
+        self.ballots(Ballot::Confirm).count()
+    }
+
+    /// The rebuilder hosts that voted this way
+    pub fn rebuilders(&self, ballot: Ballot) -> Vec<String> {
+        self.ballots(ballot)
+            .filter_map(|vote| vote.rebuilder.clone())
+            .collect()
+    }
+
+    pub fn disagrees(&self, key_id: &KeyId) -> bool {
+        self.ballots(Ballot::Disagree)
+            .any(|vote| vote.key_id == *key_id)
+    }
+
+    /// A one-line summary, for logs and command output
+    pub fn message(&self) -> String {
+        let (confirms, threshold) = (self.confirms(), self.threshold);
+        match self.outcome {
+            Outcome::BlindlyTrusted => {
+                "Blindly trusted without reproducible builds attestations".to_string()
+            }
+            Outcome::Reproduced => {
+                format!("Reproduced with {confirms}/{threshold} required signatures")
+            }
+            Outcome::GracePeriod => format!(
+                "Admitted under grace period: only {confirms}/{threshold} required signatures"
+            ),
+            Outcome::PartialResults => {
+                let pending = self
+                    .reasons
+                    .iter()
+                    .find_map(|reason| match reason {
+                        Reason::DeadlineReached { count } => Some(*count),
+                        _ => None,
+                    })
+                    .unwrap_or_default();
+                format!(
+                    "Admitted with partial results: {pending} rebuilders did not answer in time, only {confirms}/{threshold} required signatures"
+                )
+            }
+            Outcome::Offline => {
+                let unreachable = self
+                    .reasons
+                    .iter()
+                    .find_map(|reason| match reason {
+                        Reason::RebuildersUnreachable { count } => Some(*count),
+                        _ => None,
+                    })
+                    .unwrap_or_default();
+                format!(
+                    "Admitted while offline: none of the {unreachable} trusted rebuilders could be reached, verification is pending"
+                )
+            }
+            Outcome::Rejected => self
+                .reasons
+                .iter()
+                .map(|reason| reason.to_string())
+                .collect::<Vec<_>>()
+                .join("; "),
+        }
+    }
+}
+
+pub struct Engine<'a> {
+    trusted: DomainTree,
+    threshold: Threshold<'a>,
+    /// The artifact is an installer component (.udeb)
+    udeb: bool,
+}
+
+impl<'a> Engine<'a> {
+    /// Trust the configured rebuilders, with the configured rules
+    pub fn new(config: &'a Config) -> Self {
+        Engine {
+            trusted: DomainTree::from_config(config),
+            threshold: Threshold::Rules(&config.rules),
+            udeb: false,
+        }
+    }
+
+    /// Trust the given keys, each key gets one vote
+    pub fn with_keys(signing_keys: &[PublicKey], threshold: usize) -> Self {
+        Engine {
+            trusted: DomainTree::from_keys(signing_keys),
+            threshold: Threshold::Fixed(threshold),
+            udeb: false,
+        }
+    }
+
+    /// The keys whose attestations are counted
+    pub fn signing_keys(&self) -> impl Iterator<Item = &PublicKey> {
+        self.trusted.signing_keys()
+    }
+
+    /// Decide about an installer component (.udeb), which may be admitted by `allow_udeb`
+    pub fn udeb(mut self, udeb: bool) -> Self {
+        self.udeb = udeb;
+        self
+    }
+
+    fn allows_udeb(&self) -> bool {
+        match &self.threshold {
+            Threshold::Rules(rules) => self.udeb && rules.allow_udeb,
+            Threshold::Fixed(_) => false,
+        }
+    }
+
+    /// The configured threshold, never below 1 while the rules are enforced
+    pub fn threshold(&self, package: Option<&Deb>) -> usize {
+        match (&self.threshold, package) {
+            (Threshold::Rules(rules), _) if rules.is_disabled() => 0,
+            (Threshold::Rules(rules), Some(package)) => {
+                rules.required_threshold_for(&package.architecture).max(1)
+            }
+            (Threshold::Rules(rules), None) => rules.required_threshold.max(1),
+            (Threshold::Fixed(threshold), _) => (*threshold).max(1),
+        }
+    }
+
+    pub fn blindly_trusts(&self, package: &Deb) -> bool {
+        match &self.threshold {
+            Threshold::Rules(rules) => {
+                rules.is_disabled() || self.allows_udeb() || rules.blindly_trusts(package)
+            }
+            Threshold::Fixed(_) => false,
+        }
+    }
+
+    /// Trusted keys that confirmed this sha256, one per rebuilder domain
+    pub fn confirms(&self, sha256: &[u8], attestations: &Tree) -> BTreeSet<KeyId> {
+        let confirms = attestations.verify(sha256, self.trusted.signing_keys());
+        self.trusted.group_by_domain(confirms)
+    }
+
+    /// The attestations behind the confirms that were counted for this decision
+    pub fn counted(
+        &self,
+        decision: &Decision,
+        sha256: &[u8],
+        attestations: &Tree,
+    ) -> BTreeMap<KeyId, Confirm<'_>> {
+        let mut confirms = attestations.confirm(sha256, self.signing_keys());
+        confirms.retain(|key_id, _| {
+            decision
+                .ballots(Ballot::Confirm)
+                .any(|vote| vote.key_id == *key_id)
+        });
+        confirms
+    }
+
+    /// The configured number of confirms from rebuilders verified out-of-band
+    fn min_out_of_band_verified(&self) -> usize {
+        match &self.threshold {
+            Threshold::Rules(rules) => rules.min_out_of_band_verified as usize,
+            Threshold::Fixed(_) => 0,
+        }
+    }
+
+    /// How many of these keys belong to rebuilders verified out-of-band
+    fn out_of_band_verified<'b, I: IntoIterator<Item = &'b KeyId>>(&self, key_ids: I) -> usize {
+        key_ids
+            .into_iter()
+            .filter(|key_id| self.trusted.is_verified(key_id))
+            .count()
+    }
+
+    /// Used to stop waiting for rebuilders early
+    pub fn threshold_reached(&self, package: Option<&Deb>, sha256: &[u8], tree: &Tree) -> bool {
+        let confirms = self.confirms(sha256, tree);
+        confirms.len() >= self.threshold(package)
+            && self.out_of_band_verified(&confirms) >= self.min_out_of_band_verified()
+    }
+
+    /// The rebuilder hosts of these keys
+    pub fn hosts(&self, key_ids: &BTreeSet<KeyId>) -> Vec<String> {
+        key_ids
+            .iter()
+            .filter_map(|key_id| self.trusted.host(key_id))
+            .map(|host| host.to_string())
+            .collect()
+    }
+
+    fn votes(&self, ballot: Ballot, key_ids: BTreeSet<KeyId>) -> impl Iterator<Item = Vote> {
+        key_ids.into_iter().map(move |key_id| Vote {
+            rebuilder: self.trusted.host(&key_id).map(|host| host.to_string()),
+            key_id,
+            ballot,
+        })
+    }
+
+    pub fn decide(&self, package: Option<&Deb>, sha256: &[u8], remote: &Remote) -> Decision {
+        let threshold = self.threshold(package);
+        let mut decision = Decision {
+            outcome: Outcome::Reproduced,
+            reasons: Vec::new(),
+            threshold,
+            votes: Vec::new(),
+            rule_hits: Vec::new(),
+            policy: None,
+        };
+
+        let rules = match &self.threshold {
+            Threshold::Rules(rules) => Some(*rules),
+            Threshold::Fixed(_) => None,
+        };
+        let architecture = package
+            .filter(|package| {
+                rules.is_some_and(|rules| {
+                    rules
+                        .architecture_threshold(&package.architecture)
+                        .is_some()
+                })
+            })
+            .map(|package| package.architecture.clone());
+        decision.rule_hits.push(RuleHit::RequiredThreshold {
+            threshold,
+            architecture,
+        });
+
+        let mut blindly_trusted = false;
+        if rules.is_some_and(Rules::is_disabled) {
+            blindly_trusted = true;
+            decision.rule_hits.push(RuleHit::EnforcementDisabled);
+        }
+        if let (Some(rules), Some(package)) = (rules, package) {
+            if rules.is_blindly_trusted(&package.name) {
+                blindly_trusted = true;
+                decision.rule_hits.push(RuleHit::BlindlyTrust {
+                    package: package.name.clone(),
+                });
+            }
+            if rules.is_source_blindly_trusted(&package.source) {
+                blindly_trusted = true;
+                decision.rule_hits.push(RuleHit::BlindlyTrustSource {
+                    source: package.source.clone(),
+                });
+            }
+            if self.allows_udeb() {
+                blindly_trusted = true;
+                decision.rule_hits.push(RuleHit::AllowUdeb {
+                    package: package.name.clone(),
+                });
+            }
+        }
+        if blindly_trusted {
+            decision.outcome = Outcome::BlindlyTrusted;
+            return decision;
+        }
+
+        let attestations = &remote.attestations;
+        let confirms = self.confirms(sha256, attestations);
+        let disagreeing = attestations.disagreeing(sha256, self.trusted.signing_keys());
+        let conflicting = (!disagreeing.is_empty()).then(|| self.hosts(&disagreeing));
+        decision.votes.extend(self.votes(Ballot::Confirm, confirms));
+        decision
+            .votes
+            .extend(self.votes(Ballot::Disagree, disagreeing));
+
+        let confirms = decision.confirms();
+        let verified =
+            self.out_of_band_verified(decision.ballots(Ballot::Confirm).map(|vote| &vote.key_id));
+        let required = self.min_out_of_band_verified();
+        if confirms >= threshold && verified >= required {
+            decision.reasons.push(Reason::ThresholdReached {
+                confirms,
+                threshold,
+            });
+            return decision;
+        }
+
+        let mut out_of_band =
+            (verified < required).then_some(Reason::OutOfBandVerifiedNotMet { verified, required });
+        if confirms >= threshold {
+            decision.reasons.extend(out_of_band.take());
+        }
+
+        // A trusted rebuilder reproduced a different binary, waiting for more attestations
+        // doesn't make this go away. Neither are packages admitted without the required
+        // rebuilders verified out-of-band.
+        let conflict = conflicting.is_some();
+        let unverified = verified < required;
+        if let Some(rebuilders) = conflicting {
+            decision.reasons.push(Reason::Conflicting { rebuilders });
+        }
+        if !remote.unreachable.is_empty() {
+            decision.reasons.push(Reason::RebuildersUnreachable {
+                count: remote.unreachable.len(),
+            });
+        }
+        if !remote.pending.is_empty() {
+            decision.reasons.push(Reason::DeadlineReached {
+                count: remote.pending.len(),
+            });
+        }
+        if attestations.is_empty() {
+            decision.reasons.push(Reason::NoAttestations);
+        }
+        if confirms < threshold {
+            decision.reasons.push(Reason::ThresholdNotMet {
+                confirms,
+                threshold,
+            });
+        }
+        decision.reasons.extend(out_of_band);
+
+        if conflict || unverified {
+            decision.outcome = Outcome::Rejected;
+            return decision;
+        }
+        decision.outcome = match rules {
+            Some(rules) if rules.grace_period_days > 0 => {
+                decision.rule_hits.push(RuleHit::GracePeriod {
+                    days: rules.grace_period_days,
+                });
+                Outcome::GracePeriod
+            }
+            Some(rules)
+                if rules.partial_results == PartialResults::GracePeriod
+                    && !remote.pending.is_empty() =>
+            {
+                decision.rule_hits.push(RuleHit::PartialResults);
+                Outcome::PartialResults
+            }
+            Some(rules) if rules.offline != Offline::Reject && remote.is_offline() => {
+                decision.rule_hits.push(RuleHit::Offline {
+                    fallback: rules.offline,
+                });
+                match rules.offline {
+                    Offline::WarnOnly => Outcome::Offline,
+                    _ => Outcome::Rejected,
+                }
+            }
+            _ => Outcome::Rejected,
+        };
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use serde_json::json;
+
+    fn decide(config: &Config, remote: &Remote) -> (Outcome, Reason) {
+        let engine = Engine::new(config);
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], remote);
+        (decision.outcome, decision.reasons[0].clone())
+    }
+
+    #[test]
+    fn test_decide() {
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        let remote = Remote::default();
+        assert_eq!(
+            decide(&config, &remote),
+            (Outcome::Rejected, Reason::NoAttestations)
+        );
+
+        let pending = Remote {
+            pending: vec!["https://rebuilder.example.com/".parse().unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(
+            decide(&config, &pending),
+            (Outcome::Rejected, Reason::DeadlineReached { count: 1 })
+        );
+        let unreachable = Remote {
+            unreachable: pending.pending.clone(),
+            ..Default::default()
+        };
+        assert_eq!(
+            decide(&config, &unreachable),
+            (
+                Outcome::Rejected,
+                Reason::RebuildersUnreachable { count: 1 }
+            )
+        );
+
+        config.rules.partial_results = PartialResults::GracePeriod;
+        assert_eq!(decide(&config, &pending).0, Outcome::PartialResults);
+        assert_eq!(decide(&config, &remote).0, Outcome::Rejected);
+
+        config.rules.grace_period_days = 3;
+        assert_eq!(decide(&config, &remote).0, Outcome::GracePeriod);
+    }
+
+    #[test]
+    fn test_conflicting_no_grace_period() {
+        let keyring = include_str!("../test_data/reproducible-archlinux.pub").to_string();
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        config.rules.grace_period_days = 3;
+        config.rules.partial_results = PartialResults::GracePeriod;
+        config.trusted_rebuilders.push(test_server::rebuilder(
+            "https://rebuilder.example.com/".parse().unwrap(),
+            keyring,
+        ));
+
+        // The rebuilder signed an attestation, but for a different binary
+        let mut attestations = Tree::default();
+        attestations.insert(
+            "filesystem".to_string(),
+            crate::attestation::Attestation::parse(include_bytes!(
+                "../test_data/filesystem-2025.10.12-1-any.in-toto.link"
+            ))
+            .unwrap(),
+        );
+        let remote = Remote {
+            attestations,
+            pending: vec!["https://other.example.com/".parse().unwrap()],
+            ..Default::default()
+        };
+        let decision = Engine::new(&config).decide(Some(&test_server::deb()), &[0; 32], &remote);
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert!(matches!(decision.reasons[0], Reason::Conflicting { .. }));
+        assert!(
+            decision
+                .rule_hits
+                .iter()
+                .all(|hit| !matches!(hit, RuleHit::GracePeriod { .. } | RuleHit::PartialResults))
+        );
+
+        // Partial results only cover rebuilders that didn't answer, not ones that disagree
+        config.rules.grace_period_days = 0;
+        let decision = Engine::new(&config).decide(Some(&test_server::deb()), &[0; 32], &remote);
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert!(!decision.rule_hits.contains(&RuleHit::PartialResults));
+    }
+
+    #[test]
+    fn test_offline() {
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        let engine = Engine::new(&config);
+        let unreachable = vec!["https://rebuilder.example.com/".parse().unwrap()];
+        let offline = Remote {
+            unreachable: unreachable.clone(),
+            ..Default::default()
+        };
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], &offline);
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert!(!decision.is_deferred());
+
+        config.rules.offline = Offline::Defer;
+        let engine = Engine::new(&config);
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], &offline);
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert!(decision.is_deferred());
+
+        // Another rebuilder answered, so this isn't an outage of the local network
+        let online = Remote {
+            unreachable,
+            answered: 1,
+            ..Default::default()
+        };
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], &online);
+        assert!(!decision.is_deferred());
+
+        config.rules.offline = Offline::WarnOnly;
+        let engine = Engine::new(&config);
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], &offline);
+        assert_eq!(decision.outcome, Outcome::Offline);
+        assert!(!decision.verified());
+        assert_eq!(
+            decision.message(),
+            "Admitted while offline: none of the 1 trusted rebuilders could be reached, verification is pending"
+        );
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], &online);
+        assert_eq!(decision.outcome, Outcome::Rejected);
+    }
+
+    #[test]
+    fn test_decision_json() {
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        config
+            .rules
+            .blindly_trust_source
+            .insert("rust-as-slice".to_string());
+        let engine = Engine::new(&config);
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], &Remote::default());
+        assert_eq!(
+            serde_json::to_value(&decision).unwrap(),
+            json!({
+                "outcome": "blindly-trusted",
+                "reasons": [],
+                "threshold": 1,
+                "votes": [],
+                "rule_hits": [
+                    { "rule": "required-threshold", "threshold": 1 },
+                    { "rule": "blindly-trust-source", "source": "rust-as-slice" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_min_out_of_band_verified() {
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        config.rules.min_out_of_band_verified = 1;
+        let engine = Engine::new(&config);
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], &Remote::default());
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert_eq!(
+            decision.reasons,
+            [
+                Reason::NoAttestations,
+                Reason::ThresholdNotMet {
+                    confirms: 0,
+                    threshold: 1,
+                },
+                Reason::OutOfBandVerifiedNotMet {
+                    verified: 0,
+                    required: 1,
+                },
+            ]
+        );
+        assert!(!engine.threshold_reached(None, &[0; 32], &Tree::default()));
+
+        // Not even under a grace period
+        config.rules.grace_period_days = 3;
+        let engine = Engine::new(&config);
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], &Remote::default());
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert!(
+            decision
+                .rule_hits
+                .iter()
+                .all(|hit| !matches!(hit, RuleHit::GracePeriod { .. }))
+        );
+    }
+
+    #[test]
+    fn test_allow_udeb() {
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        let deb = test_server::deb();
+        let engine = Engine::new(&config).udeb(true);
+        assert!(!engine.blindly_trusts(&deb));
+        let decision = engine.decide(Some(&deb), &[0; 32], &Remote::default());
+        assert_eq!(decision.outcome, Outcome::Rejected);
+
+        config.rules.allow_udeb = true;
+        assert!(!Engine::new(&config).blindly_trusts(&deb));
+        let engine = Engine::new(&config).udeb(true);
+        assert!(engine.blindly_trusts(&deb));
+        let decision = engine.decide(Some(&deb), &[0; 32], &Remote::default());
+        assert_eq!(decision.outcome, Outcome::BlindlyTrusted);
+        assert_eq!(
+            decision.rule_hits.last(),
+            Some(&RuleHit::AllowUdeb { package: deb.name })
+        );
+    }
+
+    #[test]
+    fn test_enforcement() {
+        // The threshold is never below 1 while enforcing, even if the rules weren't validated
+        let config = Config::default();
+        let decision = Engine::new(&config).decide(None, &[0; 32], &Remote::default());
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert_eq!(decision.threshold, 1);
+
+        let mut config = Config::default();
+        config.rules.enforcement = crate::config::Enforcement::Disabled;
+        let engine = Engine::new(&config);
+        let deb = test_server::deb();
+        assert!(engine.blindly_trusts(&deb));
+        let decision = engine.decide(Some(&deb), &[0; 32], &Remote::default());
+        assert_eq!(decision.outcome, Outcome::BlindlyTrusted);
+        assert!(decision.rule_hits.contains(&RuleHit::EnforcementDisabled));
+    }
+
+    #[test]
+    fn test_fixed_threshold() {
+        // Verification can only be disabled in the config, not with a threshold of 0
+        let engine = Engine::with_keys(&[], 0);
+        let decision = engine.decide(None, &[0; 32], &Remote::default());
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert_eq!(decision.threshold, 1);
+
+        let engine = Engine::with_keys(&[], 1);
+        let decision = engine.decide(Some(&test_server::deb()), &[0; 32], &Remote::default());
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert_eq!(
+            decision.message(),
+            "No attestations found: none of the trusted rebuilders reproduced this package yet; Threshold not met: only 0/1 required signatures"
+        );
+    }
+}
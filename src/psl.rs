@@ -0,0 +1,170 @@
+//! Registrable domains, according to the public suffix list
+//!
+//! The list isn't bundled, the copy maintained by the distribution is used instead
+//! (`publicsuffix` on Debian, `publicsuffix-list` on Arch Linux).
+use crate::errors::*;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use tokio::fs;
+use url::Host;
+
+pub const PUBLIC_SUFFIX_LIST: &str = "/usr/share/publicsuffix/public_suffix_list.dat";
+
+/// Rules are listed in unicode, hosts are compared in punycode
+fn to_ascii(rule: &str) -> String {
+    let (prefix, domain) = if let Some(domain) = rule.strip_prefix("*.") {
+        ("*.", domain)
+    } else if let Some(domain) = rule.strip_prefix('!') {
+        ("!", domain)
+    } else {
+        ("", rule)
+    };
+    match Host::parse(domain) {
+        Ok(Host::Domain(domain)) => format!("{prefix}{domain}"),
+        _ => rule.to_lowercase(),
+    }
+}
+
+#[derive(Default)]
+pub struct List {
+    rules: HashSet<String>,
+    /// `*.ck` is stored as `ck`
+    wildcards: HashSet<String>,
+    /// `!www.ck` is stored as `www.ck`
+    exceptions: HashSet<String>,
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("List")
+            .field("rules", &self.rules.len())
+            .field("wildcards", &self.wildcards.len())
+            .field("exceptions", &self.exceptions.len())
+            .finish()
+    }
+}
+
+impl List {
+    pub fn parse(text: &str) -> Self {
+        let mut list = List::default();
+        for line in text.lines() {
+            // Only the text up to the first whitespace is the rule
+            let Some(rule) = line.split_whitespace().next() else {
+                continue;
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+            let rule = to_ascii(rule);
+            if let Some(exception) = rule.strip_prefix('!') {
+                list.exceptions.insert(exception.to_string());
+            } else if let Some(wildcard) = rule.strip_prefix("*.") {
+                list.wildcards.insert(wildcard.to_string());
+            } else {
+                list.rules.insert(rule);
+            }
+        }
+        list
+    }
+
+    pub async fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read public suffix list: {path:?}"))?;
+        let list = Self::parse(&text);
+        if list.rules.is_empty() {
+            bail!("Public suffix list is empty: {path:?}");
+        }
+        Ok(list)
+    }
+
+    /// Number of labels of the public suffix of a domain, the longest matching rule wins
+    fn suffix_labels(&self, labels: &[&str]) -> usize {
+        for idx in 0..labels.len() {
+            let candidate = labels[idx..].join(".");
+            if self.exceptions.contains(&candidate) {
+                return labels.len() - idx - 1;
+            }
+            if self.rules.contains(&candidate)
+                || (idx + 1 < labels.len() && self.wildcards.contains(&labels[idx + 1..].join(".")))
+            {
+                return labels.len() - idx;
+            }
+        }
+        // Unlisted top level domains are public suffixes too
+        1
+    }
+
+    /// The public suffix plus one label, `None` if the domain is a public suffix itself
+    ///
+    /// The domain is expected in its normalized form, lowercase and punycode.
+    pub fn registrable_domain<'a>(&self, domain: &'a str) -> Option<&'a str> {
+        let labels = domain.split('.').collect::<Vec<_>>();
+        let suffix = self.suffix_labels(&labels);
+        if labels.len() <= suffix {
+            return None;
+        }
+        let skip = labels[..labels.len() - suffix - 1]
+            .iter()
+            .map(|label| label.len() + 1)
+            .sum::<usize>();
+        Some(&domain[skip..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = "// ===BEGIN ICANN DOMAINS===
+com
+org
+uk
+co.uk
+*.ck
+!www.ck
+// Internationalized domains are listed in unicode
+рф
+// ===BEGIN PRIVATE DOMAINS===
+github.io
+";
+
+    #[test]
+    fn test_registrable_domain() {
+        let list = List::parse(LIST);
+        let registrable = |domain| list.registrable_domain(domain);
+        assert_eq!(
+            registrable("amd64.rebuilder.example.org"),
+            Some("example.org")
+        );
+        assert_eq!(registrable("example.org"), Some("example.org"));
+        assert_eq!(registrable("org"), None);
+        assert_eq!(
+            registrable("rebuilder.example.co.uk"),
+            Some("example.co.uk")
+        );
+        assert_eq!(registrable("co.uk"), None);
+        assert_eq!(registrable("a.b.example.ck"), Some("b.example.ck"));
+        assert_eq!(registrable("example.ck"), None);
+        assert_eq!(registrable("a.www.ck"), Some("www.ck"));
+        assert_eq!(registrable("alice.github.io"), Some("alice.github.io"));
+        assert_eq!(registrable("rebuilder.example.test"), Some("example.test"));
+        assert_eq!(
+            registrable("rebuilder.xn--e1afmkfd.xn--p1ai"),
+            Some("xn--e1afmkfd.xn--p1ai")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_system_list() {
+        // Only available if the distribution package is installed
+        let Ok(list) = List::load(Path::new(PUBLIC_SUFFIX_LIST)).await else {
+            return;
+        };
+        assert_eq!(
+            list.registrable_domain("arm64.rebuilder.example.co.uk"),
+            Some("example.co.uk")
+        );
+    }
+}
@@ -1,14 +1,35 @@
+use crate::dns;
 use crate::errors::*;
 use crate::http;
 use crate::signing;
 use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
 use in_toto::crypto::PublicKey;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::sync::LazyLock;
 use url::Url;
 
 const COMMUNITY_URL: &str =
     "https://raw.githubusercontent.com/kpcyrd/rebuilderd-community/refs/heads/main/README.md";
+/// Limits for the rebuilderd-community list, anything beyond this is not a plausible list
+const MAX_COMMUNITY_SIZE: usize = 1024 * 1024;
+const MAX_COMMUNITY_REBUILDERS: usize = 256;
+const MAX_TEXT_LEN: usize = 256;
+/// Rebuilders with pinned signing keys, used until the rebuilderd-community list was fetched
+const BUNDLED: &str = include_str!("../data/rebuilders.toml");
+/// Rebuilders publish the fingerprints of their signing keys as TXT record on this subdomain
+const DNS_PREFIX: &str = "_repro-threshold";
+const DNS_VERSION: &str = "v=rt1";
+/// Warn about keyrings that expire within this many days
+const KEY_EXPIRY_WARNING_DAYS: i64 = 30;
+
+static BUNDLED_REBUILDERS: LazyLock<Vec<Rebuilder>> = LazyLock::new(|| {
+    List::parse(BUNDLED, false)
+        .expect("Failed to parse bundled rebuilders")
+        .rebuilders
+});
 
 #[derive(Debug, Clone)]
 pub struct Selectable<T> {
@@ -34,6 +55,12 @@ pub struct Rebuilder {
     pub contact: Option<String>,
     #[serde(default, skip_serializing_if = "String::is_empty")]
     pub signing_keyring: String,
+    /// Stop counting signatures of the pinned keyring after this time, e.g. a scheduled key rollover
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<DateTime<Utc>>,
+    /// The fingerprint of the signing key, after it was compared with the operator (e.g. in person)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified_fingerprint: Option<String>,
 }
 
 impl Rebuilder {
@@ -43,12 +70,141 @@ impl Rebuilder {
         }
     }
 
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.valid_until
+            .is_some_and(|valid_until| valid_until <= now)
+    }
+
+    /// A notice about the expiry of the pinned keyring, if it's expired or about to
+    pub fn expiry_notice(&self, now: DateTime<Utc>) -> Option<&'static str> {
+        let valid_until = self.valid_until?;
+        if valid_until <= now {
+            Some("signing keyring expired")
+        } else if valid_until - now <= TimeDelta::days(KEY_EXPIRY_WARNING_DAYS) {
+            Some("signing keyring expires soon")
+        } else {
+            None
+        }
+    }
+
+    /// Pin a new keyring, the expiry of the previous keyring doesn't carry over
+    pub fn replace_signing_keyring(&mut self, keyring: String) {
+        if keyring != self.signing_keyring {
+            self.signing_keyring = keyring;
+            self.valid_until = None;
+        }
+    }
+
     pub async fn refresh_signing_keyring(&mut self, http: &http::Client) -> Result<()> {
         let keyring = http.fetch_signing_keyring(&self.url).await?;
-        self.signing_keyring = keyring;
+        self.replace_signing_keyring(keyring);
+
+        // DNS is a second channel for the keyring, but most rebuilders don't publish it (yet)
+        match self.dns_binding(http).await {
+            Ok(DnsBinding::Missing) => {
+                debug!("No signing key fingerprints in DNS for {}", self.url)
+            }
+            Ok(DnsBinding::Match { authenticated }) => debug!(
+                "Signing keyring of {} matches DNS (DNSSEC validated: {authenticated})",
+                self.url
+            ),
+            Ok(DnsBinding::Mismatch { authenticated, .. }) => warn!(
+                "Signing keyring of {} disagrees with the fingerprints published in DNS{}",
+                self.url,
+                if authenticated {
+                    ""
+                } else {
+                    " (not DNSSEC validated)"
+                }
+            ),
+            Err(err) => debug!(
+                "Failed to look up signing key fingerprints of {} in DNS: {err:#}",
+                self.url
+            ),
+        }
         Ok(())
     }
 
+    /// The TXT record that publishes the fingerprints of the signing keyring
+    pub fn dns_record_name(&self) -> Option<String> {
+        let domain = self.url.domain()?;
+        Some(format!("{DNS_PREFIX}.{domain}"))
+    }
+
+    /// Compare the signing keyring with the fingerprints published in DNS
+    ///
+    /// The lookup goes to the system resolver, so it's refused if queries to the rebuilder are
+    /// sent through the relay.
+    pub async fn dns_binding(&self, http: &http::Client) -> Result<DnsBinding> {
+        let Some(name) = self.dns_record_name() else {
+            return Ok(DnsBinding::Missing);
+        };
+        if http.is_relayed(&self.url) {
+            bail!("Not looking up {name:?}, DNS queries would bypass the relay");
+        }
+        let keyring = signing::pem_fingerprints(self.signing_keyring.as_bytes())?;
+        let txt = dns::lookup_txt(&name).await?;
+        Ok(DnsBinding::compare(&keyring, &txt))
+    }
+
+    /// Reject entries that could confuse vote-grouping or the UI
+    pub fn validate(&self) -> Result<()> {
+        for (field, value) in [
+            ("name", Some(&self.name)),
+            ("contact", self.contact.as_ref()),
+        ]
+        .into_iter()
+        .chain(self.distributions.iter().map(|d| ("distribution", Some(d))))
+        {
+            let Some(value) = value else { continue };
+            if value.len() > MAX_TEXT_LEN {
+                bail!("Field `{field}` is longer than {MAX_TEXT_LEN} bytes");
+            }
+            if value.chars().any(char::is_control) {
+                bail!("Field `{field}` contains control characters");
+            }
+        }
+        if self.name.trim().is_empty() {
+            bail!("Rebuilder name is empty");
+        }
+
+        let url = &self.url;
+        if url.scheme() != "https" {
+            bail!("Rebuilder url must use https: {url}");
+        }
+        if url.host_str().is_none_or(str::is_empty) {
+            bail!("Rebuilder url has no host: {url}");
+        }
+        if !url.username().is_empty() || url.password().is_some() {
+            bail!("Rebuilder url must not contain credentials");
+        }
+        if url.query().is_some() || url.fragment().is_some() {
+            bail!("Rebuilder url must not contain a query or fragment: {url}");
+        }
+
+        if let Some(country) = &self.country
+            && !(matches!(country.len(), 2 | 3) && country.chars().all(|c| c.is_ascii_uppercase()))
+        {
+            bail!("Invalid country code: {country:?}");
+        }
+
+        Ok(())
+    }
+
+    /// The fingerprint of the signing key, see `signing_key`
+    pub fn fingerprint(&self) -> Result<String> {
+        signing::pem_fingerprint(self.signing_keyring.as_bytes())
+    }
+
+    /// The current signing key is the one that was verified out-of-band
+    ///
+    /// A rotated key needs to be verified again.
+    pub fn is_verified_out_of_band(&self) -> bool {
+        self.verified_fingerprint
+            .as_ref()
+            .is_some_and(|verified| self.fingerprint().is_ok_and(|current| current == *verified))
+    }
+
     pub fn signing_key(&self) -> Result<PublicKey> {
         let keyring_bytes = self.signing_keyring.as_bytes();
         let mut keys = signing::pem_to_pubkeys(keyring_bytes)?;
@@ -59,51 +215,407 @@ impl Rebuilder {
     }
 }
 
-pub async fn fetch_rebuilderd_community(http: &http::Client) -> Result<Vec<Rebuilder>> {
-    let response = http
-        .get(COMMUNITY_URL)
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
+/// Url templates for rebuilders that don't follow the rebuilderd API layout, e.g. behind a proxy
+///
+/// Placeholders are written as `{name}`, `{base}` is the rebuilder url without trailing slash.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiTemplate {
+    /// Used for all rebuilders with urls starting with this
+    pub url: Url,
+    /// The search query, with `{name}`, `{version}` and `{arch}` (or `{source}` and
+    /// `{source_version}` for source packages)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    /// The attestation of a search result, with `{build_id}`, `{artifact_id}`, `{hash}` (the
+    /// sha256 in the search result), `{name}`, `{version}` and `{arch}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<String>,
+}
+
+impl ApiTemplate {
+    /// Find the most specific template for a rebuilder url
+    pub fn find<'a>(templates: &'a [ApiTemplate], url: &Url) -> Option<&'a ApiTemplate> {
+        templates
+            .iter()
+            .filter(|t| http::is_below(&t.url, url))
+            .max_by_key(|t| t.url.as_str().len())
+    }
+
+    /// Fill in the placeholders, values are percent-encoded
+    pub fn render(template: &str, base: &Url, vars: &BTreeMap<&str, &str>) -> Result<Url> {
+        let mut out = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            let Some(end) = rest[start..].find('}') else {
+                bail!("Unterminated placeholder in url template: {template:?}");
+            };
+            let key = &rest[start + 1..start + end];
+            if key == "base" {
+                out.push_str(base.as_str().trim_end_matches('/'));
+            } else {
+                let value = vars
+                    .get(key)
+                    .with_context(|| format!("Unknown placeholder {{{key}}} in url template"))?;
+                out.extend(url::form_urlencoded::byte_serialize(value.as_bytes()));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        out.push_str(rest);
+        out.parse()
+            .with_context(|| format!("Url template is not a valid url: {out:?}"))
+    }
+}
+
+/// If the signing keyring served over HTTPS agrees with DNS
+#[derive(Debug, PartialEq)]
+pub enum DnsBinding {
+    /// The rebuilder doesn't publish fingerprints in DNS
+    Missing,
+    /// Every key in the keyring is published, the DNS answer was validated if `authenticated`
+    Match { authenticated: bool },
+    Mismatch {
+        authenticated: bool,
+        published: BTreeSet<String>,
+    },
+}
+
+impl DnsBinding {
+    /// Records look like `v=rt1 sha256=<hex>`, keys may be spread over multiple records
+    pub fn parse(txt: &dns::Txt) -> BTreeSet<String> {
+        txt.records
+            .iter()
+            .filter_map(|record| {
+                let mut fields = record.split([' ', ';']).filter(|f| !f.is_empty());
+                (fields.next() == Some(DNS_VERSION)).then_some(fields)
+            })
+            .flatten()
+            .filter_map(|field| field.strip_prefix("sha256="))
+            .map(str::to_lowercase)
+            .collect()
+    }
+
+    pub fn compare(keyring: &BTreeSet<String>, txt: &dns::Txt) -> Self {
+        let published = Self::parse(txt);
+        let authenticated = txt.authenticated;
+        if published.is_empty() {
+            DnsBinding::Missing
+        } else if !keyring.is_empty() && keyring.is_subset(&published) {
+            DnsBinding::Match { authenticated }
+        } else {
+            DnsBinding::Mismatch {
+                authenticated,
+                published,
+            }
+        }
+    }
+}
+
+/// The rebuilders compiled into the binary
+pub fn bundled() -> &'static [Rebuilder] {
+    &BUNDLED_REBUILDERS
+}
+
+pub async fn fetch_rebuilderd_community(http: &http::Client) -> Result<Community> {
+    let mut response = http.get(COMMUNITY_URL).send().await?.error_for_status()?;
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_COMMUNITY_SIZE {
+            bail!("Rebuilderd-community list is larger than {MAX_COMMUNITY_SIZE} bytes");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let response =
+        String::from_utf8(body).context("Rebuilderd-community list is not valid utf-8")?;
     parse(response.as_str())
 }
 
-fn parse(text: &str) -> Result<Vec<Rebuilder>> {
-    let mut start = None;
-    let mut end = None;
+/// Narrow down a list of rebuilders, empty criteria match everything
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Rebuilders for any of these distributions
+    pub distributions: Vec<String>,
+    /// Rebuilders in any of these countries, compared case-insensitively
+    pub countries: Vec<String>,
+    /// Rebuilders with a usable signing keyring
+    pub with_keys_only: bool,
+}
+
+impl Filter {
+    pub fn matches(&self, rebuilder: &Rebuilder) -> bool {
+        let distribution = self.distributions.is_empty()
+            || rebuilder
+                .distributions
+                .iter()
+                .any(|d| self.distributions.contains(d));
+        let country = self.countries.is_empty()
+            || rebuilder.country.as_ref().is_some_and(|country| {
+                self.countries
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(country))
+            });
+        let keys = !self.with_keys_only || rebuilder.fingerprint().is_ok();
+        distribution && country && keys
+    }
+}
+
+/// Rebuilders for a distribution, ordered by preference for automatic selection
+///
+/// With `distinct_countries`, the first rebuilder of each country is listed before any
+/// second rebuilder of a country that was already used.
+pub fn rank<'a>(
+    rebuilders: &'a [Rebuilder],
+    distribution: &str,
+    distinct_countries: bool,
+) -> Vec<&'a Rebuilder> {
+    let candidates = rebuilders
+        .iter()
+        .filter(|r| r.distributions.iter().any(|d| d == distribution));
+    if !distinct_countries {
+        return candidates.collect();
+    }
+
+    let mut seen = HashSet::new();
+    let (first, rest): (Vec<_>, Vec<_>) = candidates.partition(|r| match &r.country {
+        Some(country) => seen.insert(country.as_str()),
+        None => true,
+    });
+    first.into_iter().chain(rest).collect()
+}
+
+/// An entry of the rebuilderd-community list that could not be used
+#[derive(Debug, PartialEq)]
+pub struct EntryError {
+    /// The heading of the section the entry was found in
+    pub section: String,
+    /// The position of the entry within its code block
+    pub index: usize,
+    pub error: String,
+}
 
-    for (idx, line) in text.lines().enumerate() {
-        if line.starts_with("```") {
-            if start.is_none() {
-                start = Some(idx + 1);
-            } else if end.is_none() {
-                end = Some(idx);
-                break;
+impl fmt::Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid rebuilder entry #{} in section {:?}: {}",
+            self.index + 1,
+            self.section,
+            self.error
+        )
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Community {
+    pub rebuilders: Vec<Rebuilder>,
+    pub errors: Vec<EntryError>,
+}
+
+impl Community {
+    /// Log all invalid entries and return the usable rebuilders
+    pub fn into_rebuilders(self) -> Vec<Rebuilder> {
+        for err in &self.errors {
+            warn!("{err}");
+        }
+        self.rebuilders
+    }
+}
+
+struct CodeBlock<'a> {
+    section: &'a str,
+    lang: &'a str,
+    content: String,
+}
+
+/// Find all fenced code blocks, together with the heading they are in
+fn code_blocks(text: &str) -> Vec<CodeBlock<'_>> {
+    let mut blocks = Vec::new();
+    let mut section = "";
+    let mut current: Option<CodeBlock> = None;
+
+    for line in text.lines() {
+        if let Some(fence) = line.strip_prefix("```") {
+            if let Some(block) = current.take() {
+                blocks.push(block);
+            } else {
+                current = Some(CodeBlock {
+                    section,
+                    lang: fence.trim(),
+                    content: String::new(),
+                });
             }
+        } else if let Some(block) = &mut current {
+            block.content.push_str(line);
+            block.content.push('\n');
+        } else if line.starts_with('#') {
+            section = line.trim_start_matches('#').trim();
         }
     }
 
-    let start_line = start.context("Failed to find start of TOML data")?;
-    let end_line = end.context("Failed to find end of TOML data")?;
+    blocks
+}
+
+/// Sections that only document the format, their entries aren't real rebuilders
+fn is_example_section(section: &str) -> bool {
+    let section = section.to_lowercase();
+    section.contains("example") || section.contains("template")
+}
+
+/// Parse the rebuilderd-community README, invalid entries are collected instead of failing
+pub fn parse(text: &str) -> Result<Community> {
+    let blocks = code_blocks(text);
+    if blocks.is_empty() {
+        bail!("Failed to find TOML data");
+    }
+
+    // Prefer blocks that are explicitly tagged as TOML, fall back to untagged ones
+    let has_toml = blocks.iter().any(|b| b.lang == "toml");
+    let blocks = blocks
+        .into_iter()
+        .filter(|b| {
+            if has_toml {
+                b.lang == "toml"
+            } else {
+                b.lang.is_empty()
+            }
+        })
+        .filter(|b| !is_example_section(b.section));
 
-    // Extract the lines between start and end
-    let toml_content: Vec<&str> = text
-        .lines()
-        .skip(start_line)
-        .take(end_line - start_line)
-        .collect();
-    let toml_str = toml_content.join("\n");
+    let mut community = Community::default();
+    let mut seen = HashSet::new();
+    for block in blocks {
+        let table = match toml::from_str::<toml::Table>(&block.content) {
+            Ok(table) => table,
+            Err(err) => {
+                community.errors.push(EntryError {
+                    section: block.section.to_string(),
+                    index: 0,
+                    error: format!("Failed to parse TOML block: {err}"),
+                });
+                continue;
+            }
+        };
+        let Some(entries) = table.get("rebuilder").and_then(|v| v.as_array()) else {
+            continue;
+        };
+
+        for (index, entry) in entries.iter().enumerate() {
+            let mut error = |error| {
+                community.errors.push(EntryError {
+                    section: block.section.to_string(),
+                    index,
+                    error,
+                })
+            };
+            match Rebuilder::deserialize(entry.clone()) {
+                Ok(rebuilder) if community.rebuilders.len() >= MAX_COMMUNITY_REBUILDERS => {
+                    error(format!(
+                        "Too many rebuilders, ignoring {:?} (limit is {MAX_COMMUNITY_REBUILDERS})",
+                        rebuilder.name
+                    ))
+                }
+                Ok(rebuilder) => match rebuilder.validate() {
+                    Err(err) => error(format!("{err:#}")),
+                    Ok(()) if !seen.insert(rebuilder.url.clone()) => {
+                        error(format!("Duplicate rebuilder url: {}", rebuilder.url))
+                    }
+                    Ok(()) => community.rebuilders.push(rebuilder),
+                },
+                Err(err) => error(err.to_string()),
+            }
+        }
+    }
+
+    Ok(community)
+}
+
+/// A list of rebuilders, in the same schema as the rebuilderd-community README
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct List {
+    #[serde(default, rename = "rebuilder")]
+    pub rebuilders: Vec<Rebuilder>,
+}
+
+impl List {
+    /// Parse a TOML list, or json if `json` is set
+    ///
+    /// The entries are validated like the rebuilderd-community list, but any invalid entry
+    /// rejects the whole list.
+    pub fn parse(text: &str, json: bool) -> Result<Self> {
+        let list = if json {
+            serde_json::from_str::<Self>(text).context("Failed to parse rebuilder list as json")?
+        } else {
+            toml::from_str::<Self>(text).context("Failed to parse rebuilder list as TOML")?
+        };
+        let mut seen = HashSet::new();
+        for rebuilder in &list.rebuilders {
+            rebuilder
+                .validate()
+                .with_context(|| format!("Invalid rebuilder {:?}", rebuilder.name))?;
+            if !seen.insert(&rebuilder.url) {
+                bail!("Duplicate rebuilder url: {}", rebuilder.url);
+            }
+        }
+        Ok(list)
+    }
 
-    let mut list = toml::from_str::<HashMap<String, Vec<Rebuilder>>>(&toml_str)?;
-    let list = list.remove("rebuilder").unwrap_or_default();
-    Ok(list)
+    pub fn to_string(&self, json: bool) -> Result<String> {
+        if json {
+            Ok(serde_json::to_string_pretty(self)? + "\n")
+        } else {
+            Ok(toml::to_string_pretty(self)?)
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_server;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_dns_binding() {
+        let keyring = signing::pem_fingerprints(bundled()[0].signing_keyring.as_bytes()).unwrap();
+        let fingerprint = keyring.first().unwrap().clone();
+        let txt = |records: &[&str], authenticated| dns::Txt {
+            records: records.iter().map(|r| r.to_string()).collect(),
+            authenticated,
+        };
+
+        let published = format!("v=rt1 sha256={}", fingerprint.to_uppercase());
+        assert_eq!(
+            DnsBinding::compare(&keyring, &txt(&[&published, "unrelated"], true)),
+            DnsBinding::Match {
+                authenticated: true
+            }
+        );
+        assert_eq!(
+            DnsBinding::compare(&keyring, &txt(&["unrelated"], true)),
+            DnsBinding::Missing
+        );
+        assert!(matches!(
+            DnsBinding::compare(&keyring, &txt(&["v=rt1; sha256=00"], false)),
+            DnsBinding::Mismatch {
+                authenticated: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_bundled() {
+        let bundled = bundled();
+        assert!(!bundled.is_empty());
+        for rebuilder in bundled {
+            rebuilder.validate().unwrap();
+            let keys = signing::pem_to_pubkeys(rebuilder.signing_keyring.as_bytes())
+                .unwrap()
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            assert!(!keys.is_empty(), "{} has no pinned key", rebuilder.url);
+        }
+    }
 
     #[test]
     fn test_parse() {
@@ -127,7 +639,7 @@ distributions = ["archlinux", "debian"]
 ```
 
 "#;
-        let rebuilders = parse(data).unwrap();
+        let rebuilders = parse(data).unwrap().rebuilders;
         assert_eq!(
             rebuilders,
             &[
@@ -138,6 +650,8 @@ distributions = ["archlinux", "debian"]
                     country: Some("DEU".to_string()),
                     contact: Some("Hello!".to_string()),
                     signing_keyring: String::new(),
+                    valid_until: None,
+                    verified_fingerprint: None,
                 },
                 Rebuilder {
                     name: "Rebuilder Two".to_string(),
@@ -146,15 +660,81 @@ distributions = ["archlinux", "debian"]
                     country: None,
                     contact: None,
                     signing_keyring: String::new(),
+                    valid_until: None,
+                    verified_fingerprint: None,
                 },
             ]
         );
     }
 
+    #[test]
+    fn test_rank() {
+        let rebuilder = |name: &str, distribution: &str, country: &str| Rebuilder {
+            name: name.to_string(),
+            distributions: vec![distribution.to_string()],
+            country: Some(country.to_string()),
+            ..test_server::rebuilder(
+                format!("https://{name}.example.com").parse().unwrap(),
+                String::new(),
+            )
+        };
+        let list = [
+            rebuilder("a", "debian", "DEU"),
+            rebuilder("b", "debian", "DEU"),
+            rebuilder("c", "archlinux", "USA"),
+            rebuilder("d", "debian", "NLD"),
+        ];
+
+        let names = |ranked: Vec<&Rebuilder>| {
+            ranked
+                .into_iter()
+                .map(|r| r.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(rank(&list, "debian", false)), ["a", "b", "d"]);
+        assert_eq!(names(rank(&list, "debian", true)), ["a", "d", "b"]);
+        assert_eq!(names(rank(&list, "archlinux", true)), ["c"]);
+    }
+
+    #[test]
+    fn test_list_roundtrip() {
+        let list = List {
+            rebuilders: vec![Rebuilder {
+                name: "Internal".to_string(),
+                contact: Some("ops@example.com".to_string()),
+                ..test_server::rebuilder(
+                    "https://rebuilder.internal.example.com/".parse().unwrap(),
+                    "-----BEGIN PUBLIC KEY-----".to_string(),
+                )
+            }],
+        };
+
+        for json in [false, true] {
+            let text = list.to_string(json).unwrap();
+            let parsed = List::parse(&text, json).unwrap();
+            assert_eq!(parsed.rebuilders, list.rebuilders);
+        }
+
+        let entry = |url: &str| {
+            format!("[[rebuilder]]\nname = \"Internal\"\nurl = \"{url}\"\ndistributions = []\n")
+        };
+        let insecure = entry("http://rebuilder.internal.example.com/");
+        assert!(List::parse(&insecure, false).is_err());
+        let url = "https://rebuilder.internal.example.com/";
+        let duplicate = entry(url) + &entry(url);
+        assert!(List::parse(&duplicate, false).is_err());
+        assert!(List::parse(&entry(url), false).is_ok());
+        assert!(
+            list.to_string(false)
+                .unwrap()
+                .starts_with("[[rebuilder]]\n")
+        );
+    }
+
     #[test]
     fn test_parse_empty() {
         let data = "```\n```";
-        let list = parse(data).unwrap();
+        let list = parse(data).unwrap().rebuilders;
         assert_eq!(list, &[]);
     }
 
@@ -164,4 +744,235 @@ distributions = ["archlinux", "debian"]
         let list = parse(data);
         assert!(list.is_err());
     }
+
+    #[test]
+    fn test_parse_scoped_blocks() {
+        let data = r#"# Rebuilderd Community Rebuilders
+
+```sh
+curl https://example.com
+```
+
+## Template
+
+```toml
+[[rebuilder]]
+name = "Your Rebuilder"
+url = "https://rebuilder.example.com"
+distributions = []
+```
+
+## Debian
+
+```toml
+[[rebuilder]]
+name = "Rebuilder One"
+url = "https://one.example.com"
+distributions = ["debian"]
+
+[[rebuilder]]
+name = "Broken"
+distributions = ["debian"]
+```
+
+## Arch Linux
+
+```toml
+[[rebuilder]]
+name = "Rebuilder Two"
+url = "https://two.example.com"
+distributions = ["archlinux"]
+
+[[rebuilder]]
+name = "Rebuilder One again"
+url = "https://one.example.com"
+distributions = ["archlinux"]
+```
+"#;
+        let community = parse(data).unwrap();
+        let names = community
+            .rebuilders
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Rebuilder One", "Rebuilder Two"]);
+
+        assert_eq!(community.errors.len(), 2);
+        assert_eq!(community.errors[0].section, "Debian");
+        assert_eq!(community.errors[0].index, 1);
+        assert!(community.errors[0].error.contains("url"));
+        assert_eq!(community.errors[1].section, "Arch Linux");
+        assert!(community.errors[1].error.contains("Duplicate"));
+    }
+
+    #[test]
+    fn test_expiry_notice() {
+        let now = Utc::now();
+        let mut rebuilder = bundled()[0].clone();
+        assert_eq!(rebuilder.expiry_notice(now), None);
+        assert!(!rebuilder.is_expired(now));
+
+        rebuilder.valid_until = Some(now + TimeDelta::days(90));
+        assert_eq!(rebuilder.expiry_notice(now), None);
+        rebuilder.valid_until = Some(now + TimeDelta::days(7));
+        assert_eq!(
+            rebuilder.expiry_notice(now),
+            Some("signing keyring expires soon")
+        );
+        assert!(!rebuilder.is_expired(now));
+        rebuilder.valid_until = Some(now - TimeDelta::days(1));
+        assert_eq!(
+            rebuilder.expiry_notice(now),
+            Some("signing keyring expired")
+        );
+        assert!(rebuilder.is_expired(now));
+    }
+
+    #[test]
+    fn test_verified_out_of_band() {
+        let mut rebuilder = bundled()[0].clone();
+        assert!(!rebuilder.is_verified_out_of_band());
+
+        let fingerprint = rebuilder.fingerprint().unwrap();
+        let keyring = signing::pem_fingerprints(rebuilder.signing_keyring.as_bytes()).unwrap();
+        assert!(keyring.contains(&fingerprint));
+        rebuilder.verified_fingerprint = Some(fingerprint);
+        assert!(rebuilder.is_verified_out_of_band());
+
+        // A different key needs to be verified again
+        rebuilder.signing_keyring = bundled()[1].signing_keyring.clone();
+        assert!(!rebuilder.is_verified_out_of_band());
+    }
+
+    #[test]
+    fn test_replace_signing_keyring() {
+        let mut rebuilder = Rebuilder {
+            signing_keyring: "old".to_string(),
+            valid_until: Some(Utc::now()),
+            ..bundled()[0].clone()
+        };
+        rebuilder.replace_signing_keyring("old".to_string());
+        assert!(rebuilder.valid_until.is_some());
+        rebuilder.replace_signing_keyring("new".to_string());
+        assert_eq!(rebuilder.signing_keyring, "new");
+        assert_eq!(rebuilder.valid_until, None);
+    }
+
+    #[test]
+    fn test_filter() {
+        let rebuilder =
+            |distribution: &str, country: Option<&str>, signing_keyring: &str| Rebuilder {
+                distributions: vec![distribution.to_string()],
+                country: country.map(String::from),
+                signing_keyring: signing_keyring.to_string(),
+                ..bundled()[0].clone()
+            };
+        let keyring = &bundled()[0].signing_keyring;
+        let debian = rebuilder("debian", Some("DE"), keyring);
+        let archlinux = rebuilder("archlinux", None, "");
+
+        let all = Filter::default();
+        assert!(all.matches(&debian));
+        assert!(all.matches(&archlinux));
+
+        let filter = Filter {
+            distributions: vec!["archlinux".to_string(), "debian".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.matches(&debian));
+        assert!(filter.matches(&archlinux));
+
+        let filter = Filter {
+            countries: vec!["de".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.matches(&debian));
+        assert!(!filter.matches(&archlinux));
+
+        let filter = Filter {
+            distributions: vec!["archlinux".to_string()],
+            with_keys_only: true,
+            ..Default::default()
+        };
+        assert!(!filter.matches(&debian));
+        assert!(!filter.matches(&archlinux));
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = Rebuilder {
+            country: Some("DE".to_string()),
+            ..test_server::rebuilder(
+                "https://rebuilder.example.com/".parse().unwrap(),
+                String::new(),
+            )
+        };
+        valid.validate().unwrap();
+
+        let invalid = |f: fn(&mut Rebuilder)| {
+            let mut rebuilder = valid.clone();
+            f(&mut rebuilder);
+            rebuilder.validate().is_err()
+        };
+        assert!(invalid(
+            |r| r.url = "http://rebuilder.example.com/".parse().unwrap()
+        ));
+        assert!(invalid(
+            |r| r.url = "https://user:pw@rebuilder.example.com/".parse().unwrap()
+        ));
+        assert!(invalid(
+            |r| r.url = "https://rebuilder.example.com/#x".parse().unwrap()
+        ));
+        assert!(invalid(
+            |r| r.url = "https://rebuilder.example.com/?a=b".parse().unwrap()
+        ));
+        assert!(invalid(|r| r.country = Some("Germany".to_string())));
+        assert!(invalid(|r| r.country = Some("de".to_string())));
+        assert!(invalid(|r| r.name = "Evil\u{1b}[2J".to_string()));
+        assert!(invalid(|r| r.name = " ".to_string()));
+        assert!(invalid(|r| r.contact = Some("x".repeat(MAX_TEXT_LEN + 1))));
+    }
+
+    #[test]
+    fn test_api_template() {
+        let base = "https://proxy.example.com/rebuilderd/".parse().unwrap();
+        let vars = BTreeMap::from([("name", "librust-as-slice-dev"), ("version", "0.2.1-1+b2")]);
+        let url = ApiTemplate::render("{base}/search/{name}/{version}", &base, &vars).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://proxy.example.com/rebuilderd/search/librust-as-slice-dev/0.2.1-1%2Bb2"
+        );
+        assert!(ApiTemplate::render("{base}/search/{arch}", &base, &vars).is_err());
+        assert!(ApiTemplate::render("{base}/search/{name", &base, &vars).is_err());
+
+        let template = |url: &str| ApiTemplate {
+            url: url.parse().unwrap(),
+            search: None,
+            attestation: None,
+        };
+        let templates = [
+            template("https://proxy.example.com/"),
+            template("https://proxy.example.com/rebuilderd/"),
+        ];
+        let found = ApiTemplate::find(&templates, &base).unwrap();
+        assert_eq!(found.url, base);
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_parse_arbitrary(text in "\\PC*") {
+            let _ = parse(&text);
+        }
+
+        #[test]
+        fn proptest_parse_toml_block(section in "[^\n]*", content in "\\PC*") {
+            let text = format!("# {section}\n```toml\n{content}\n```\n");
+            if let Ok(community) = parse(&text) {
+                prop_assert!(community.rebuilders.len() <= MAX_COMMUNITY_REBUILDERS);
+                for rebuilder in &community.rebuilders {
+                    prop_assert!(rebuilder.validate().is_ok());
+                }
+            }
+        }
+    }
 }
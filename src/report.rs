@@ -0,0 +1,175 @@
+use crate::errors::*;
+use crate::http;
+use crate::inspect::deb::Deb;
+use crate::rebuilder::Rebuilder;
+use std::fmt::Write;
+use std::slice;
+
+/// What a single rebuilder had to say about the package
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Confirmed,
+    Mismatch,
+    NotFound,
+    Error(String),
+}
+
+impl Outcome {
+    fn as_str(&self) -> &str {
+        match self {
+            Outcome::Confirmed => "reproduced",
+            Outcome::Mismatch => "attestation does not match",
+            Outcome::NotFound => "no attestation",
+            Outcome::Error(_) => "error",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RebuilderResult {
+    pub rebuilder: Rebuilder,
+    pub outcome: Outcome,
+    /// The attestation url and its products section
+    pub excerpts: Vec<(String, String)>,
+}
+
+pub async fn check(
+    http: &http::Client,
+    rebuilder: Rebuilder,
+    inspect: &Deb,
+    sha256: &[u8],
+) -> RebuilderResult {
+    let mut result = RebuilderResult {
+        rebuilder,
+        outcome: Outcome::NotFound,
+        excerpts: vec![],
+    };
+
+    let signing_key = match result.rebuilder.signing_key() {
+        Ok(key) => key,
+        Err(err) => {
+            result.outcome = Outcome::Error(format!("{err:#}"));
+            return result;
+        }
+    };
+
+    let attestations = match http
+        .fetch_attestations_for_pkg(&result.rebuilder.url, inspect)
+        .await
+    {
+        Ok(attestations) => attestations,
+        Err(err) => {
+            result.outcome = Outcome::Error(format!("{err:#}"));
+            return result;
+        }
+    };
+
+    for (label, attestation) in attestations.iter() {
+        let products = attestation
+            .signed_json()
+            .ok()
+            .and_then(|signed| signed.get("products").cloned())
+            .and_then(|products| serde_json::to_string_pretty(&products).ok())
+            .unwrap_or_default();
+        result.excerpts.push((label.clone(), products));
+    }
+
+    if !attestations
+        .verify(sha256, slice::from_ref(&signing_key))
+        .is_empty()
+    {
+        result.outcome = Outcome::Confirmed;
+    } else if !result.excerpts.is_empty() {
+        result.outcome = Outcome::Mismatch;
+    }
+
+    result
+}
+
+/// Render a Markdown report that can be attached to a bug report
+pub fn render(inspect: &Deb, sha256: &[u8], results: &[RebuilderResult]) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Verification report for {} {}\n",
+        inspect.name, inspect.version
+    )?;
+
+    writeln!(out, "## Package\n")?;
+    writeln!(out, "- Name: `{}`", inspect.name)?;
+    writeln!(out, "- Version: `{}`", inspect.version)?;
+    writeln!(out, "- Architecture: `{}`", inspect.architecture)?;
+    writeln!(out, "- Source: `{}`", inspect.source)?;
+    writeln!(
+        out,
+        "- SHA256: `{}`\n",
+        data_encoding::HEXLOWER.encode(sha256)
+    )?;
+
+    writeln!(out, "## Rebuilders\n")?;
+    writeln!(out, "| Rebuilder | URL | Result |")?;
+    writeln!(out, "|-----------|-----|--------|")?;
+    for result in results {
+        writeln!(
+            out,
+            "| {} | {} | {} |",
+            result.rebuilder.name,
+            result.rebuilder.url,
+            result.outcome.as_str()
+        )?;
+    }
+
+    for result in results {
+        if result.excerpts.is_empty() && !matches!(result.outcome, Outcome::Error(_)) {
+            continue;
+        }
+        writeln!(out, "\n### {}\n", result.rebuilder.name)?;
+        if let Outcome::Error(err) = &result.outcome {
+            writeln!(out, "Error: `{err}`\n")?;
+        }
+        for (url, products) in &result.excerpts {
+            writeln!(out, "Products of <{url}>:\n")?;
+            writeln!(out, "```json\n{products}\n```\n")?;
+        }
+    }
+
+    writeln!(
+        out,
+        "\n---\nGenerated by {} {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )?;
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn test_report() {
+        let url = test_server::spawn_rebuilder().await;
+        let rebuilder = test_server::rebuilder(url.clone(), test_server::DEB_KEYRING.to_string());
+        let http = http::client();
+
+        let inspect = test_server::deb();
+        let confirmed = check(
+            &http,
+            rebuilder.clone(),
+            &inspect,
+            &test_server::deb_sha256(),
+        )
+        .await;
+        assert_eq!(confirmed.outcome, Outcome::Confirmed);
+        assert_eq!(confirmed.excerpts.len(), 1);
+
+        let mismatch = check(&http, rebuilder, &inspect, &[0; 32]).await;
+        assert_eq!(mismatch.outcome, Outcome::Mismatch);
+
+        let report = render(&inspect, &test_server::deb_sha256(), &[confirmed, mismatch]).unwrap();
+        assert!(report.contains(&format!("| mock | {url} | reproduced |")));
+        assert!(report.contains("librust-as-slice-dev_0.2.1-1+b2_amd64.deb"));
+    }
+}
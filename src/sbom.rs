@@ -0,0 +1,282 @@
+use crate::errors::*;
+use crate::inspect::deb::Deb;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs;
+use url::Url;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Sbom {
+    /// Write an SBOM fragment for each apt run into this directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// CycloneDX 1.5 json
+    #[default]
+    CycloneDx,
+    /// SPDX 2.3 json
+    Spdx,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::CycloneDx => "cdx.json",
+            Format::Spdx => "spdx.json",
+        }
+    }
+}
+
+/// Why a package was admitted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// Enough trusted rebuilders confirmed the package
+    Reproduced,
+    /// The package is in the blindly-trust set
+    BlindlyTrusted,
+    /// The package was admitted before reaching the threshold
+    GracePeriod,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Reproduced => "reproduced",
+            Status::BlindlyTrusted => "blindly-trusted",
+            Status::GracePeriod => "grace-period",
+        }
+    }
+}
+
+/// A package that was admitted by the transport
+#[derive(Debug, Clone, PartialEq)]
+pub struct Component {
+    pub package: Deb,
+    pub url: Url,
+    pub sha256: String,
+    pub status: Status,
+    /// The hosts of the rebuilders that confirmed the package
+    pub rebuilders: Vec<String>,
+}
+
+impl Component {
+    /// The package url, e.g. `pkg:deb/debian/curl@7.74.0-1?arch=amd64`
+    fn purl(&self) -> String {
+        let version = self.package.version.replace(':', "%3A");
+        format!(
+            "pkg:deb/debian/{}@{version}?arch={}",
+            self.package.name, self.package.architecture
+        )
+    }
+
+    fn summary(&self) -> String {
+        match self.status {
+            Status::Reproduced => format!("Reproduced by {}", self.rebuilders.join(", ")),
+            Status::BlindlyTrusted => "Blindly trusted, not verified".to_string(),
+            Status::GracePeriod if self.rebuilders.is_empty() => {
+                "Admitted under grace period, not reproduced yet".to_string()
+            }
+            Status::GracePeriod => format!(
+                "Admitted under grace period, only reproduced by {}",
+                self.rebuilders.join(", ")
+            ),
+        }
+    }
+}
+
+fn cyclonedx(components: &[Component], now: DateTime<Utc>) -> serde_json::Value {
+    let components = components
+        .iter()
+        .map(|component| {
+            let mut properties = vec![serde_json::json!({
+                "name": "repro-threshold:status",
+                "value": component.status.as_str(),
+            })];
+            properties.extend(component.rebuilders.iter().map(|host| {
+                serde_json::json!({
+                    "name": "repro-threshold:rebuilder",
+                    "value": host,
+                })
+            }));
+            serde_json::json!({
+                "type": "library",
+                "bom-ref": component.purl(),
+                "name": component.package.name,
+                "version": component.package.version,
+                "purl": component.purl(),
+                "hashes": [{ "alg": "SHA-256", "content": component.sha256 }],
+                "externalReferences": [{ "type": "distribution", "url": component.url }],
+                "properties": properties,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": now.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+        },
+        "components": components,
+    })
+}
+
+fn spdx(components: &[Component], now: DateTime<Utc>) -> serde_json::Value {
+    let created = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let tool = format!(
+        "Tool: {}-{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+
+    let packages = components
+        .iter()
+        .enumerate()
+        .map(|(i, component)| {
+            serde_json::json!({
+                "SPDXID": format!("SPDXRef-Package-{i}"),
+                "name": component.package.name,
+                "versionInfo": component.package.version,
+                "downloadLocation": component.url,
+                "checksums": [{ "algorithm": "SHA256", "checksumValue": component.sha256 }],
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": component.purl(),
+                }],
+                "annotations": [{
+                    "annotationType": "OTHER",
+                    "annotator": tool,
+                    "annotationDate": created,
+                    "comment": component.summary(),
+                }],
+            })
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": "repro-threshold transaction",
+        "documentNamespace": format!(
+            "urn:repro-threshold:sbom:{}:{}",
+            now.timestamp(),
+            std::process::id()
+        ),
+        "creationInfo": {
+            "created": created,
+            "creators": [tool],
+        },
+        "packages": packages,
+    })
+}
+
+pub fn render(format: Format, components: &[Component], now: DateTime<Utc>) -> serde_json::Value {
+    match format {
+        Format::CycloneDx => cyclonedx(components, now),
+        Format::Spdx => spdx(components, now),
+    }
+}
+
+/// Write the packages admitted in this transaction, if configured
+pub async fn write(config: &Sbom, components: &[Component]) -> Result<Option<PathBuf>> {
+    let Some(dir) = &config.dir else {
+        return Ok(None);
+    };
+    if components.is_empty() {
+        return Ok(None);
+    }
+
+    let now = Utc::now();
+    let sbom = render(config.format, components, now);
+
+    fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create SBOM directory: {dir:?}"))?;
+    let path = dir.join(format!(
+        "{}-{}.{}",
+        now.format("%Y%m%dT%H%M%SZ"),
+        std::process::id(),
+        config.format.extension()
+    ));
+    let json = serde_json::to_vec_pretty(&sbom)?;
+    fs::write(&path, json)
+        .await
+        .with_context(|| format!("Failed to write SBOM: {path:?}"))?;
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn component() -> Component {
+        Component {
+            package: Deb {
+                name: "curl".to_string(),
+                version: "1:7.74.0-1".to_string(),
+                architecture: "amd64".to_string(),
+                source: "curl".to_string(),
+                ..Default::default()
+            },
+            url: "http://deb.debian.org/debian/pool/main/c/curl/curl_7.74.0-1_amd64.deb"
+                .parse()
+                .unwrap(),
+            sha256: "00".repeat(32),
+            status: Status::Reproduced,
+            rebuilders: vec!["rebuilder.example.com".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let now = DateTime::from_timestamp(1700000000, 0).unwrap();
+        let sbom = render(Format::CycloneDx, &[component()], now);
+        assert_eq!(sbom["metadata"]["timestamp"], "2023-11-14T22:13:20Z");
+        let component = &sbom["components"][0];
+        assert_eq!(
+            component["purl"],
+            "pkg:deb/debian/curl@1%3A7.74.0-1?arch=amd64"
+        );
+        assert_eq!(component["hashes"][0]["content"], "00".repeat(32));
+        assert_eq!(
+            component["properties"],
+            serde_json::json!([
+                { "name": "repro-threshold:status", "value": "reproduced" },
+                { "name": "repro-threshold:rebuilder", "value": "rebuilder.example.com" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_spdx() {
+        let now = DateTime::from_timestamp(1700000000, 0).unwrap();
+        let mut component = component();
+        component.status = Status::GracePeriod;
+        component.rebuilders.clear();
+        let sbom = render(Format::Spdx, &[component], now);
+        let package = &sbom["packages"][0];
+        assert_eq!(package["SPDXID"], "SPDXRef-Package-0");
+        assert_eq!(package["checksums"][0]["algorithm"], "SHA256");
+        assert_eq!(
+            package["annotations"][0]["comment"],
+            "Admitted under grace period, not reproduced yet"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use crate::errors::*;
+use crate::inspect::deb::Deb;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Where apt keeps downloaded packages
+pub const APT_ARCHIVES: &str = "/var/cache/apt/archives";
+
+async fn dpkg_query(args: &[&std::ffi::OsStr]) -> Result<String> {
+    let output = Command::new("dpkg-query")
+        .args(args)
+        .output()
+        .await
+        .context("Failed to execute dpkg-query")?;
+    if !output.status.success() {
+        bail!(
+            "dpkg-query failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).context("dpkg-query output is not valid utf-8")
+}
+
+/// Find the package name in the output of `dpkg-query -S`, e.g. `repro-threshold: /usr/bin/repro-threshold`
+fn parse_search(output: &str, path: &Path) -> Option<String> {
+    output.lines().find_map(|line| {
+        // Diversions are reported as separate lines, they are not owned by any package
+        let (packages, file) = line.rsplit_once(": ")?;
+        if Path::new(file) != path || packages.starts_with("diversion by ") {
+            return None;
+        }
+        let package = packages.split(", ").next()?;
+        Some(package.to_string())
+    })
+}
+
+const QUERY_FORMAT: &str = "${Package}\\t${Version}\\t${Architecture}\\t${source:Package}\\n";
+
+fn parse_query(output: &str) -> Result<Deb> {
+    let line = output
+        .lines()
+        .next()
+        .context("dpkg-query returned no package")?;
+    let mut fields = line.split('\t');
+    let mut next = || {
+        fields
+            .next()
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .with_context(|| format!("Unexpected dpkg-query output: {line:?}"))
+    };
+    Ok(Deb {
+        name: next()?,
+        version: next()?,
+        architecture: next()?,
+        source: next()?,
+        ..Default::default()
+    })
+}
+
+/// The installed package that contains this file
+pub async fn owning_package(path: &Path) -> Result<Deb> {
+    let output = dpkg_query(&["-S".as_ref(), path.as_os_str()]).await?;
+    let package = parse_search(&output, path)
+        .with_context(|| format!("No installed package contains {path:?}"))?;
+    debug!("File {path:?} is provided by package {package:?}");
+
+    let output = dpkg_query(&[
+        "-W".as_ref(),
+        "-f".as_ref(),
+        QUERY_FORMAT.as_ref(),
+        package.as_ref(),
+    ])
+    .await?;
+    parse_query(&output)
+}
+
+/// The filename apt uses for a downloaded package, the `:` of an epoch is url-encoded
+pub fn archive_filename(deb: &Deb) -> String {
+    let version = deb.version.replace(':', "%3a");
+    format!("{}_{}_{}.deb", deb.name, version, deb.architecture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_search() {
+        let path = Path::new("/usr/bin/repro-threshold");
+        let output = "repro-threshold: /usr/bin/repro-threshold\n";
+        assert_eq!(
+            parse_search(output, path),
+            Some("repro-threshold".to_string())
+        );
+
+        let output = "diversion by foo from: /usr/bin/repro-threshold\ndiversion by foo to: /usr/bin/repro-threshold.real\nrepro-threshold:amd64: /usr/bin/repro-threshold\n";
+        assert_eq!(
+            parse_search(output, path),
+            Some("repro-threshold:amd64".to_string())
+        );
+
+        let output = "repro-threshold: /usr/bin\n";
+        assert_eq!(parse_search(output, path), None);
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let deb = parse_query("repro-threshold\t1:0.1.0-1\tamd64\trepro-threshold\n").unwrap();
+        assert_eq!(
+            deb,
+            Deb {
+                name: "repro-threshold".to_string(),
+                version: "1:0.1.0-1".to_string(),
+                architecture: "amd64".to_string(),
+                source: "repro-threshold".to_string(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            archive_filename(&deb),
+            "repro-threshold_1%3a0.1.0-1_amd64.deb"
+        );
+
+        assert!(parse_query("repro-threshold\t0.1.0-1\n").is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use crate::errors::*;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+
+const LINK_EXTENSION: &str = ".in-toto.link";
+/// Longest request line or header line that is accepted
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADER_LINES: usize = 100;
+/// Clients that don't send their request headers in time are disconnected
+pub const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// The public key of a `file://` rebuilder, stored next to the attestations
+const SIGNING_KEY_FILENAME: &str = "signing-key.pub";
+
+/// Attestations available in the served directory
+pub struct Index {
+    keyring: String,
+    /// Files named `<name>_<version>_<architecture>.in-toto.link`
+    links: Vec<(String, PathBuf)>,
+}
+
+impl Index {
+    pub async fn scan(dir: &Path, keyring: String) -> Result<Self> {
+        let mut links = Vec::new();
+
+        let mut entries = fs::read_dir(dir)
+            .await
+            .with_context(|| format!("Failed to read directory: {dir:?}"))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let Some(filename) = path.file_name().and_then(|f| f.to_str()) else {
+                continue;
+            };
+            let Some(stem) = filename.strip_suffix(LINK_EXTENSION) else {
+                continue;
+            };
+            debug!("Indexed attestation: {path:?}");
+            links.push((stem.to_string(), path));
+        }
+        links.sort();
+
+        Ok(Index { keyring, links })
+    }
+
+    /// Index a local attestation dump, the signing key is read from `signing-key.pub` if present
+    pub async fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join(SIGNING_KEY_FILENAME);
+        let keyring = match fs::read_to_string(&path).await {
+            Ok(keyring) => keyring,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read signing key: {path:?}"));
+            }
+        };
+        Self::scan(dir, keyring).await
+    }
+
+    /// Ids are 1-based, build and artifact ids are the same
+    fn search(&self, name: &str, version: &str, architecture: &str) -> Vec<usize> {
+        let key = format!("{name}_{version}_{architecture}");
+        self.links
+            .iter()
+            .enumerate()
+            .filter(|(_, (stem, _))| *stem == key)
+            .map(|(idx, _)| idx + 1)
+            .collect()
+    }
+
+    fn get(&self, id: usize) -> Option<&Path> {
+        let idx = id.checked_sub(1)?;
+        self.links.get(idx).map(|(_, path)| path.as_path())
+    }
+}
+
+enum Response {
+    Json(serde_json::Value),
+    Bytes(Vec<u8>),
+    NotFound,
+    BadRequest,
+}
+
+async fn handle(index: &Index, target: &str) -> Result<Response> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+
+    let response = match segments.as_slice() {
+        ["api", "v1", "meta", "public-keys"] if !index.keyring.is_empty() => {
+            Response::Json(json!({
+                "current": [index.keyring],
+            }))
+        }
+        ["api", "v1", "packages", "binary"] => {
+            let query = url::form_urlencoded::parse(query.as_bytes()).collect::<BTreeMap<_, _>>();
+            let (Some(name), Some(version), Some(architecture)) = (
+                query.get("name"),
+                query.get("version"),
+                query.get("architecture"),
+            ) else {
+                return Ok(Response::BadRequest);
+            };
+
+            let records = index
+                .search(name, version, architecture)
+                .into_iter()
+                .map(|id| json!({"build_id": id, "artifact_id": id}))
+                .collect::<Vec<_>>();
+            Response::Json(json!({ "records": records }))
+        }
+        [
+            "api",
+            "v1",
+            "builds",
+            build_id,
+            "artifacts",
+            artifact_id,
+            "attestation",
+        ] => {
+            let (Ok(build_id), Ok(artifact_id)) =
+                (build_id.parse::<usize>(), artifact_id.parse::<usize>())
+            else {
+                return Ok(Response::BadRequest);
+            };
+            if build_id != artifact_id {
+                return Ok(Response::NotFound);
+            }
+            let Some(path) = index.get(artifact_id) else {
+                return Ok(Response::NotFound);
+            };
+            let bytes = fs::read(path)
+                .await
+                .with_context(|| format!("Failed to read attestation: {path:?}"))?;
+            Response::Bytes(bytes)
+        }
+        _ => Response::NotFound,
+    };
+
+    Ok(response)
+}
+
+/// Answer a request without a network connection, returns the HTTP status and body
+pub async fn respond(index: &Index, target: &str) -> Result<(u16, Vec<u8>)> {
+    let response = match handle(index, target).await? {
+        Response::Json(value) => (200, serde_json::to_vec(&value)?),
+        Response::Bytes(body) => (200, body),
+        Response::NotFound => (404, vec![]),
+        Response::BadRequest => (400, vec![]),
+    };
+    Ok(response)
+}
+
+pub async fn write_response<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<()> {
+    let header = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// The request line and headers of an HTTP request
+#[derive(Debug, PartialEq)]
+pub struct Head {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Head {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Read a single line, lines longer than `MAX_LINE_LENGTH` are an error
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, line: &mut String) -> Result<usize> {
+    line.clear();
+    let n = (&mut *reader)
+        .take(MAX_LINE_LENGTH + 1)
+        .read_line(line)
+        .await?;
+    if n as u64 > MAX_LINE_LENGTH {
+        bail!("Request line exceeds {MAX_LINE_LENGTH} bytes");
+    }
+    Ok(n)
+}
+
+/// Read the request line and headers, `None` if the request line is invalid
+///
+/// The size of the headers is bounded, and they have to arrive within `READ_TIMEOUT`.
+pub async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Head>> {
+    time::timeout(READ_TIMEOUT, async {
+        let mut request_line = String::new();
+        read_line(reader, &mut request_line).await?;
+
+        let mut headers = Vec::new();
+        let mut line = String::new();
+        loop {
+            let n = read_line(reader, &mut line).await?;
+            if n == 0 || line.trim_end().is_empty() {
+                break;
+            }
+            if headers.len() >= MAX_HEADER_LINES {
+                bail!("Request has more than {MAX_HEADER_LINES} headers");
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                headers.push((key.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Ok(None);
+        };
+        Ok(Some(Head {
+            method: method.to_string(),
+            target: target.to_string(),
+            headers,
+        }))
+    })
+    .await
+    .context("Timed out reading request")?
+}
+
+/// Read the request line and skip over the request headers, returns method and target
+pub async fn read_request<R: AsyncBufRead + Unpin>(
+    mut reader: R,
+) -> Result<Option<(String, String)>> {
+    let head = read_head(&mut reader).await?;
+    Ok(head.map(|head| (head.method, head.target)))
+}
+
+async fn serve_connection(index: &Index, stream: TcpStream, addr: SocketAddr) -> Result<()> {
+    let (reader, writer) = stream.into_split();
+
+    let Some((method, target)) = read_request(BufReader::new(reader)).await? else {
+        return write_response(writer, "400 Bad Request", "text/plain", b"Bad Request\n").await;
+    };
+    info!("{addr} - {method} {target:?}");
+
+    if method != "GET" {
+        return write_response(
+            writer,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Method Not Allowed\n",
+        )
+        .await;
+    }
+
+    match handle(index, &target).await? {
+        Response::Json(value) => {
+            let body = serde_json::to_vec(&value)?;
+            write_response(writer, "200 OK", "application/json", &body).await
+        }
+        Response::Bytes(body) => {
+            write_response(writer, "200 OK", "application/octet-stream", &body).await
+        }
+        Response::NotFound => {
+            write_response(writer, "404 Not Found", "text/plain", b"Not Found\n").await
+        }
+        Response::BadRequest => {
+            write_response(writer, "400 Bad Request", "text/plain", b"Bad Request\n").await
+        }
+    }
+}
+
+pub async fn run(bind: SocketAddr, index: Index) -> Result<()> {
+    let listener = TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("Failed to bind to address: {bind}"))?;
+    serve(listener, index).await
+}
+
+pub async fn serve(listener: TcpListener, index: Index) -> Result<()> {
+    info!(
+        "Serving {} attestations on http://{}",
+        index.links.len(),
+        listener.local_addr()?
+    );
+
+    let index = Arc::new(index);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let index = Arc::clone(&index);
+        tokio::spawn(async move {
+            if let Err(err) = serve_connection(&index, stream, addr).await {
+                warn!("Failed to serve request from {addr}: {err:#}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http, test_server};
+
+    #[tokio::test]
+    async fn test_serve_index() {
+        let index = Index::scan(Path::new("test_data"), "keyring".to_string())
+            .await
+            .unwrap();
+
+        let Response::Json(value) = handle(
+            &index,
+            "/api/v1/packages/binary?name=librust-as-slice-dev&version=0.2.1-1%2Bb2&architecture=amd64",
+        )
+        .await
+        .unwrap() else {
+            panic!("Expected json response");
+        };
+        let id = value["records"][0]["artifact_id"].as_u64().unwrap();
+
+        let Response::Bytes(bytes) = handle(
+            &index,
+            &format!("/api/v1/builds/{id}/artifacts/{id}/attestation"),
+        )
+        .await
+        .unwrap() else {
+            panic!("Expected attestation response");
+        };
+        assert_eq!(
+            bytes,
+            include_bytes!("../test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.in-toto.link")
+        );
+
+        let Response::Json(value) = handle(&index, "/api/v1/meta/public-keys").await.unwrap()
+        else {
+            panic!("Expected json response");
+        };
+        assert_eq!(value, json!({"current": ["keyring"]}));
+    }
+
+    #[tokio::test]
+    async fn test_read_head() {
+        let raw = b"GET /api/v1/meta/public-keys HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let head = read_head(&mut &raw[..]).await.unwrap().unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.target, "/api/v1/meta/public-keys");
+        assert_eq!(head.header("host"), Some("localhost"));
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+        assert!(read_head(&mut long.as_bytes()).await.is_err());
+
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X-Foo: bar\r\n".repeat(101));
+        assert!(read_head(&mut many.as_bytes()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serve_attestations_e2e() {
+        let keyring = fs::read_to_string("test_data/reproduce-debian-net-amd64.pub")
+            .await
+            .unwrap();
+        let index = Index::scan(Path::new("test_data"), keyring).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(serve(listener, index));
+
+        let http = http::client();
+        let keyring = http.fetch_signing_keyring(&url).await.unwrap();
+        let rebuilder = test_server::rebuilder(url, keyring);
+        let signing_key = rebuilder.signing_key().unwrap();
+
+        let attestations = http
+            .fetch_attestations_for_pkg(&rebuilder.url, &test_server::deb())
+            .await
+            .unwrap();
+        let confirms = attestations.verify(&test_server::deb_sha256(), [&signing_key]);
+        assert_eq!(confirms.len(), 1);
+    }
+}
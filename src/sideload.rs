@@ -0,0 +1,418 @@
+//! Packages that were installed without going through a transport
+//!
+//! The logs of dpkg and pacman are followed from where the last scan stopped, every installed
+//! package the transports didn't admit (e.g. `dpkg -i` or `pacman -U` of a local file, or a
+//! source that doesn't use repro-threshold) is recorded. If the package manager still has the
+//! file in its cache it's verified retroactively, an alert is sent if that's not possible or the
+//! package isn't reproduced.
+use crate::config::Config;
+use crate::cron::{self, Status};
+use crate::errors::*;
+use crate::http;
+use crate::inspect::deb::Deb;
+use crate::notify::{self, Event};
+use crate::state::{Admitted, SideLoaded, SideLoadedPackage, Store};
+use crate::transport::alpm;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+
+pub const DPKG_LOG: &str = "/var/log/dpkg.log";
+pub const PACMAN_LOG: &str = "/var/log/pacman.log";
+pub const APT_ARCHIVES: &str = "/var/cache/apt/archives";
+const PACMAN_LOCAL_DB: &str = "/var/lib/pacman/local";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Manager {
+    Dpkg,
+    Pacman,
+}
+
+/// A package manager log to follow
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log {
+    pub manager: Manager,
+    pub path: PathBuf,
+}
+
+impl Log {
+    /// The default logs of all package managers installed on this system
+    pub async fn detect() -> Vec<Log> {
+        let mut logs = Vec::new();
+        for (manager, path) in [(Manager::Dpkg, DPKG_LOG), (Manager::Pacman, PACMAN_LOG)] {
+            if fs::try_exists(path).await.unwrap_or(false) {
+                logs.push(Log {
+                    manager,
+                    path: PathBuf::from(path),
+                });
+            }
+        }
+        logs
+    }
+
+    fn parse(&self, content: &str) -> Vec<Deb> {
+        match self.manager {
+            Manager::Dpkg => parse_dpkg_log(content),
+            Manager::Pacman => parse_pacman_log(content),
+        }
+    }
+}
+
+/// Packages installed or upgraded according to lines of the dpkg log
+///
+/// `2025-01-01 12:00:00 upgrade curl:amd64 7.74.0-1 7.74.0-2`
+pub fn parse_dpkg_log(content: &str) -> Vec<Deb> {
+    let mut packages = BTreeMap::new();
+    for line in content.lines() {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let [_date, _time, action, package, _old, version] = fields[..] else {
+            continue;
+        };
+        if action != "install" && action != "upgrade" {
+            continue;
+        }
+        let Some((name, architecture)) = package.split_once(':') else {
+            continue;
+        };
+        // The last action wins, if a package was upgraded twice
+        packages.insert(
+            (name.to_string(), architecture.to_string()),
+            version.to_string(),
+        );
+    }
+    packages
+        .into_iter()
+        .map(|((name, architecture), version)| Deb {
+            source: name.clone(),
+            name,
+            version,
+            architecture,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Packages installed, upgraded or downgraded according to lines of the pacman log
+///
+/// `[2025-01-01T12:00:00+0000] [ALPM] upgraded curl (8.11.1-2 -> 8.11.1-3)`
+///
+/// The log doesn't contain the architecture, it's left empty.
+pub fn parse_pacman_log(content: &str) -> Vec<Deb> {
+    let mut packages = BTreeMap::new();
+    for line in content.lines() {
+        let Some((_, line)) = line.split_once("] [ALPM] ") else {
+            continue;
+        };
+        let Some((action, rest)) = line.split_once(' ') else {
+            continue;
+        };
+        if !matches!(
+            action,
+            "installed" | "upgraded" | "downgraded" | "reinstalled"
+        ) {
+            continue;
+        }
+        let Some((name, versions)) = rest.split_once(" (") else {
+            continue;
+        };
+        let versions = versions.trim_end_matches(')');
+        let version = versions.rsplit(" -> ").next().unwrap_or(versions);
+        packages.insert(name.to_string(), version.to_string());
+    }
+    packages
+        .into_iter()
+        .map(|(name, version)| Deb {
+            source: name.clone(),
+            name,
+            version,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// The path apt downloads a package to
+pub fn archive_path(archives: &Path, package: &Deb) -> PathBuf {
+    let version = package.version.replace(':', "%3a");
+    archives.join(format!(
+        "{}_{version}_{}.deb",
+        package.name, package.architecture
+    ))
+}
+
+/// Look up the architecture of an installed pacman package
+async fn pacman_architecture(package: &Deb) -> Option<String> {
+    let path = Path::new(PACMAN_LOCAL_DB)
+        .join(format!("{}-{}", package.name, package.version))
+        .join("desc");
+    let desc = fs::read_to_string(path).await.ok()?;
+    let mut lines = desc.lines();
+    lines.find(|line| *line == "%ARCH%")?;
+    lines.next().map(String::from)
+}
+
+/// Find the file of a package in the cache of its package manager
+async fn find_cached(manager: Manager, archives: &Path, package: &mut Deb) -> Option<PathBuf> {
+    match manager {
+        Manager::Dpkg => {
+            let path = archive_path(archives, package);
+            fs::try_exists(&path).await.unwrap_or(false).then_some(path)
+        }
+        Manager::Pacman => {
+            let cache_dirs = [PathBuf::from(alpm::PACMAN_CACHE_DIR)];
+            let cached = alpm::find_cached(&cache_dirs, std::slice::from_ref(package))
+                .await
+                .ok()
+                .and_then(|found| found.into_iter().next());
+            if let Some((cached, path)) = cached {
+                package.architecture = cached.architecture;
+                return Some(path);
+            }
+            if let Some(architecture) = pacman_architecture(package).await {
+                package.architecture = architecture;
+            }
+            None
+        }
+    }
+}
+
+/// Verify a cached package file, returns its sha256 and if it's reproduced
+async fn verify(
+    http: &http::Client,
+    config: &Config,
+    manager: Manager,
+    package: &Deb,
+    path: &Path,
+) -> Result<(String, bool)> {
+    let pkg = match manager {
+        Manager::Dpkg => cron::Package::load(path, Duration::ZERO).await?,
+        // pacman packages are identified by their filename
+        Manager::Pacman => cron::Package {
+            age: Duration::ZERO,
+            inspect: package.clone(),
+            sha256: crate::attestation::sha256_path(path).await?,
+        },
+    };
+    let sha256 = data_encoding::HEXLOWER.encode(&pkg.sha256);
+    let verified = match cron::check(http, config, &pkg).await {
+        Status::Verified | Status::BlindlyTrusted => true,
+        Status::Pending {
+            confirms,
+            threshold,
+        } => {
+            warn!(
+                "Only {confirms}/{threshold} required signatures for {} {}",
+                pkg.inspect.name, pkg.inspect.version
+            );
+            false
+        }
+    };
+    Ok((sha256, verified))
+}
+
+/// Check the packages installed since the last scan of each log
+pub async fn scan(
+    http: &http::Client,
+    config: &Config,
+    logs: &[Log],
+    archives: &Path,
+) -> Result<()> {
+    let state = SideLoaded::load().await?;
+    let admitted = Admitted::load().await?;
+
+    let mut offsets = BTreeMap::new();
+    let mut through_transport = Vec::new();
+    let mut side_loaded = Vec::new();
+    for log in logs {
+        let content = fs::read(&log.path)
+            .await
+            .with_context(|| format!("Failed to read log: {:?}", log.path))?;
+        let end = content.len() as u64;
+        let key = log.path.display().to_string();
+        offsets.insert(key.clone(), end);
+
+        let Some(&offset) = state.offsets.get(&key) else {
+            info!(
+                "Following {:?} for the first time, only checking future transactions",
+                log.path
+            );
+            continue;
+        };
+        // The log was rotated since the last scan
+        let offset = if offset > end { 0 } else { offset };
+
+        for mut package in log.parse(&String::from_utf8_lossy(&content[offset as usize..])) {
+            let cached = find_cached(log.manager, archives, &mut package).await;
+            if admitted.contains(&package) {
+                debug!(
+                    "Installed through transport: {} {}",
+                    package.name, package.version
+                );
+                through_transport.push(package);
+                continue;
+            }
+            warn!(
+                "Installed {} {} ({}) without going through the transport",
+                package.name, package.version, package.architecture
+            );
+
+            let (sha256, verified) = if let Some(path) = cached {
+                match verify(http, config, log.manager, &package, &path).await {
+                    Ok((sha256, verified)) => (Some(sha256), verified),
+                    Err(err) => {
+                        warn!("Failed to verify {path:?}: {err:#}");
+                        (None, false)
+                    }
+                }
+            } else {
+                debug!("Package file is not in the cache anymore");
+                (None, false)
+            };
+            if verified {
+                info!(
+                    "Verified {} {} retroactively",
+                    package.name, package.version
+                );
+            } else {
+                let event = Event::Bypassed {
+                    package: package.clone(),
+                    sha256: sha256.clone(),
+                };
+                notify::dispatch(http, &config.notify, &event).await;
+            }
+            side_loaded.push(SideLoadedPackage {
+                package,
+                sha256,
+                verified,
+                detected_at: Utc::now(),
+            });
+        }
+    }
+
+    if !through_transport.is_empty() {
+        Admitted::update(|admitted| {
+            for package in &through_transport {
+                admitted.remove(package);
+            }
+        })
+        .await;
+    }
+    SideLoaded::update(|state| {
+        state.offsets.extend(offsets);
+        // A newer version replaces the side-loaded one, whichever way it was installed
+        for package in &through_transport {
+            state.remove(package);
+        }
+        for entry in side_loaded {
+            state.insert(entry);
+        }
+    })
+    .await;
+    Ok(())
+}
+
+/// Re-check side-loaded packages with a known hash, in case they were reproduced since
+pub async fn recheck(http: &http::Client, config: &Config) -> Result<()> {
+    let state = SideLoaded::load().await?;
+    let mut verified = Vec::new();
+    for entry in state.unverified() {
+        let Some(sha256) = &entry.sha256 else {
+            continue;
+        };
+        let pkg = cron::Package {
+            age: Duration::ZERO,
+            inspect: entry.package.clone(),
+            sha256: data_encoding::HEXLOWER.decode(sha256.as_bytes())?,
+        };
+        if cron::check(http, config, &pkg).await == Status::Verified {
+            info!(
+                "Verified side-loaded package {} {}",
+                entry.package.name, entry.package.version
+            );
+            verified.push(entry.package.clone());
+        }
+    }
+    if !verified.is_empty() {
+        SideLoaded::update(|state| {
+            for package in &verified {
+                state.mark_verified(package);
+            }
+        })
+        .await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dpkg_log() {
+        let log = "2025-01-01 12:00:00 startup archives unpack
+2025-01-01 12:00:00 upgrade curl:amd64 7.74.0-1 7.74.0-2
+2025-01-01 12:00:01 status half-configured curl:amd64 7.74.0-2
+2025-01-01 12:00:01 status installed curl:amd64 7.74.0-2
+2025-01-01 12:00:01 install tzdata:all <none> 2025a-1
+2025-01-01 12:00:02 trigproc man-db:amd64 2.11.2-2 <none>
+2025-01-01 12:00:02 upgrade curl:amd64 7.74.0-2 1:7.74.0-3
+";
+        let packages = parse_dpkg_log(log)
+            .into_iter()
+            .map(|p| (p.name, p.version, p.architecture))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            packages,
+            [
+                (
+                    "curl".to_string(),
+                    "1:7.74.0-3".to_string(),
+                    "amd64".to_string()
+                ),
+                (
+                    "tzdata".to_string(),
+                    "2025a-1".to_string(),
+                    "all".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_pacman_log() {
+        let log = "[2025-01-01T12:00:00+0000] [PACMAN] Running 'pacman -Syu'
+[2025-01-01T12:00:01+0000] [ALPM] transaction started
+[2025-01-01T12:00:01+0000] [ALPM] upgraded curl (8.11.1-2 -> 8.11.1-3)
+[2025-01-01T12:00:01+0000] [ALPM] installed python-foo (1:1.0-1)
+[2025-01-01T12:00:01+0000] [ALPM] removed zlib (1.3.1-2)
+[2025-01-01T12:00:02+0000] [ALPM-SCRIPTLET] installed something (1.0)
+[2025-01-01T12:00:02+0000] [ALPM] transaction completed
+";
+        let packages = parse_pacman_log(log)
+            .into_iter()
+            .map(|p| (p.name, p.version))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            packages,
+            [
+                ("curl".to_string(), "8.11.1-3".to_string()),
+                ("python-foo".to_string(), "1:1.0-1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_archive_path() {
+        let package = Deb {
+            name: "curl".to_string(),
+            version: "1:7.74.0-3".to_string(),
+            architecture: "amd64".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            archive_path(Path::new(APT_ARCHIVES), &package),
+            Path::new("/var/cache/apt/archives/curl_1%3a7.74.0-3_amd64.deb")
+        );
+    }
+}
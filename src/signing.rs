@@ -1,23 +1,107 @@
-use crate::config::Config;
+use crate::clock;
+use crate::config::{Config, Rules, VoteGrouping};
 use crate::errors::*;
+use crate::pkcs11;
 use in_toto::crypto::{KeyId, PublicKey, SignatureScheme};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    ECDSA_P256_SHA256_FIXED, ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey,
+};
+use sha2::{Digest, Sha256};
 use std::collections::{BTreeMap, BTreeSet};
 use std::path::Path;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 use url::Host;
 
 const PEM_PUBLIC_KEY: &str = "PUBLIC KEY";
+const PEM_PRIVATE_KEY: &str = "PRIVATE KEY";
+// SubjectPublicKeyInfo header for a raw Ed25519 public key
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+// SubjectPublicKeyInfo header for an uncompressed P-256 public key
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// Normalize a host before grouping, so the same domain always gets the same vote
+///
+/// Internationalized domains are converted to punycode, domains are lowercased and a trailing dot
+/// is removed, `Bücher.example.`, `bücher.example` and `xn--bcher-kva.example` are the same host.
+pub fn normalize_host(host: Host<&str>) -> Host<String> {
+    match host {
+        Host::Domain(domain) => {
+            let domain = domain.strip_suffix('.').unwrap_or(domain);
+            Host::parse(domain).unwrap_or_else(|_| Host::Domain(domain.to_ascii_lowercase()))
+        }
+        Host::Ipv4(addr) => Host::Ipv4(addr),
+        Host::Ipv6(addr) => Host::Ipv6(addr),
+    }
+}
+
+/// Rebuilders with the same key share a vote
+fn vote_group(rules: &Rules, host: &Host<String>) -> String {
+    if let (VoteGrouping::RegistrableDomain, Host::Domain(domain)) = (rules.group_votes_by, host) {
+        match &rules.public_suffixes {
+            Some(list) => {
+                if let Some(registrable) = list.registrable_domain(domain) {
+                    return registrable.to_string();
+                }
+            }
+            None => warn!("Public suffix list isn't loaded, grouping votes by host"),
+        }
+    }
+    host.to_string()
+}
+
+/// Where a signing key was configured
+struct Origin {
+    host: Host<String>,
+    /// Keys in the same group share a vote
+    group: String,
+    /// The key was verified out-of-band
+    verified: bool,
+}
 
 // Ensure each domain only gets one vote, until we don't have per-architecture rebuilders anymore
-pub struct DomainTree<'a> {
-    map: BTreeMap<KeyId, (Host<&'a str>, PublicKey)>,
+pub struct DomainTree {
+    /// Keys without an origin each get their own vote
+    map: BTreeMap<KeyId, (Option<Origin>, PublicKey)>,
 }
 
-impl<'a> DomainTree<'a> {
-    pub fn from_config(config: &'a Config) -> Self {
+impl DomainTree {
+    pub fn from_config(config: &Config) -> Self {
         let mut map = BTreeMap::new();
 
+        // Keyrings are accepted until the clock is past their expiry by more than the allowed skew
+        let now = match clock::now() {
+            Ok(now) => Some(now - config.rules.clock_skew()),
+            Err(problem) => {
+                warn!("Can't tell if signing keyrings expired, {problem}");
+                None
+            }
+        };
         for rebuilder in &config.trusted_rebuilders {
+            if config.rules.require_out_of_band_verified && !rebuilder.is_verified_out_of_band() {
+                debug!(
+                    "Ignoring signatures of {}, its signing key wasn't verified out-of-band",
+                    rebuilder.url
+                );
+                continue;
+            }
+            let expired = match now {
+                Some(now) => rebuilder.is_expired(now),
+                None => rebuilder.valid_until.is_some(),
+            };
+            if expired {
+                warn!(
+                    "Ignoring signatures of {}, its signing keyring expired",
+                    rebuilder.url
+                );
+                continue;
+            }
             let Ok(signing_key) = rebuilder.signing_key() else {
                 continue;
             };
@@ -27,26 +111,72 @@ impl<'a> DomainTree<'a> {
                 continue;
             };
 
-            map.insert(key_id, (host, signing_key));
+            let host = normalize_host(host);
+            let group = vote_group(&config.rules, &host);
+            let verified = rebuilder.is_verified_out_of_band();
+            map.insert(
+                key_id,
+                (
+                    Some(Origin {
+                        host,
+                        group,
+                        verified,
+                    }),
+                    signing_key,
+                ),
+            );
         }
 
         DomainTree { map }
     }
 
+    /// Signing keys that were given explicitly, e.g. on the command line
+    pub fn from_keys(signing_keys: &[PublicKey]) -> Self {
+        let map = signing_keys
+            .iter()
+            .map(|key| (key.key_id().to_owned(), (None, key.clone())))
+            .collect();
+        DomainTree { map }
+    }
+
+    /// The rebuilder host a signing key belongs to
+    pub fn host(&self, key_id: &KeyId) -> Option<&Host<String>> {
+        self.map
+            .get(key_id)
+            .and_then(|(origin, _)| origin.as_ref())
+            .map(|origin| &origin.host)
+    }
+
     pub fn signing_keys(&self) -> impl Iterator<Item = &PublicKey> {
         self.map.values().map(|(_, key)| key)
     }
 
+    /// The key belongs to a rebuilder that was verified out-of-band
+    pub fn is_verified(&self, key_id: &KeyId) -> bool {
+        self.map
+            .get(key_id)
+            .and_then(|(origin, _)| origin.as_ref())
+            .is_some_and(|origin| origin.verified)
+    }
+
     pub fn group_by_domain(&self, confirms: BTreeSet<KeyId>) -> BTreeSet<KeyId> {
         let mut voted = BTreeSet::new();
 
+        // If a group has a key verified out-of-band, that's the one casting the vote
+        let (verified, unverified): (Vec<_>, Vec<_>) = confirms
+            .into_iter()
+            .partition(|key_id| self.is_verified(key_id));
+
         let mut new = BTreeSet::new();
-        for key_id in confirms {
-            let Some((host, _)) = self.map.get(&key_id) else {
+        for key_id in verified.into_iter().chain(unverified) {
+            let Some((origin, _)) = self.map.get(&key_id) else {
                 continue;
             };
 
-            if voted.insert(host) {
+            if origin
+                .as_ref()
+                .is_none_or(|origin| voted.insert(&origin.group))
+            {
                 new.insert(key_id);
             }
         }
@@ -67,6 +197,186 @@ pub fn pem_to_pubkeys(buf: &[u8]) -> Result<impl Iterator<Item = Result<PublicKe
     Ok(iter)
 }
 
+/// The sha256 of each public key in a pem file, as published by rebuilders in DNS
+pub fn pem_fingerprints(buf: &[u8]) -> Result<BTreeSet<String>> {
+    let pems = pem::parse_many(buf).context("Failed to parse pem file")?;
+    let fingerprints = pems
+        .iter()
+        .filter(|pem| pem.tag() == PEM_PUBLIC_KEY)
+        .map(|pem| data_encoding::HEXLOWER.encode(&Sha256::digest(pem.contents())))
+        .collect();
+    Ok(fingerprints)
+}
+
+/// The sha256 of the first public key in a pem file, the one that's used for verification
+pub fn pem_fingerprint(buf: &[u8]) -> Result<String> {
+    let pems = pem::parse_many(buf).context("Failed to parse pem file")?;
+    let pem = pems
+        .iter()
+        .find(|pem| pem.tag() == PEM_PUBLIC_KEY)
+        .context("No public keys found in signing keyring")?;
+    Ok(data_encoding::HEXLOWER.encode(&Sha256::digest(pem.contents())))
+}
+
+/// Uppercase hex in groups of four, for comparing fingerprints by eye or reading them out loud
+pub fn format_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .as_bytes()
+        .chunks(4)
+        .map(|group| String::from_utf8_lossy(group).to_uppercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Accept fingerprints as published in DNS (`sha256=...`) or as displayed (grouped hex)
+pub fn parse_fingerprint(input: &str) -> Result<String> {
+    let input = input.trim();
+    let input = input
+        .strip_prefix("sha256=")
+        .or_else(|| input.strip_prefix("SHA256="))
+        .unwrap_or(input);
+    let fingerprint = input
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let bytes = data_encoding::HEXLOWER
+        .decode(fingerprint.as_bytes())
+        .with_context(|| format!("Invalid fingerprint: {input:?}"))?;
+    if bytes.len() != 32 {
+        bail!("Invalid fingerprint, expected 32 bytes of sha256: {input:?}");
+    }
+    Ok(fingerprint)
+}
+
+pub fn pubkey_to_pem(key: &PublicKey) -> Result<String> {
+    let spki = key.as_spki().context("Failed to encode signing key")?;
+    let pem = pem::Pem::new(PEM_PUBLIC_KEY, spki);
+    Ok(pem::encode(&pem))
+}
+
+/// Ed25519 key held by this host, used to countersign verification results
+pub struct LocalKey {
+    keypair: Ed25519KeyPair,
+}
+
+impl LocalKey {
+    pub async fn generate(path: &Path) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng)
+            .map_err(|_| anyhow!("Failed to generate Ed25519 key"))?;
+        let pem = pem::encode(&pem::Pem::new(PEM_PRIVATE_KEY, pkcs8.as_ref()));
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to create private key file: {path:?}"))?;
+        file.write_all(pem.as_bytes())
+            .await
+            .with_context(|| format!("Failed to write private key: {path:?}"))?;
+
+        Self::from_pkcs8(pkcs8.as_ref())
+    }
+
+    pub async fn load(path: &Path) -> Result<Self> {
+        let buf = fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read private key: {path:?}"))?;
+        Self::from_pem(&buf).with_context(|| format!("Failed to load private key: {path:?}"))
+    }
+
+    pub fn from_pem(buf: &[u8]) -> Result<Self> {
+        let pem = pem::parse(buf).context("Failed to parse pem file")?;
+        if pem.tag() != PEM_PRIVATE_KEY {
+            bail!("Unexpected pem type, expected {PEM_PRIVATE_KEY:?}");
+        }
+        Self::from_pkcs8(pem.contents())
+    }
+
+    pub(crate) fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        let keypair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+            .map_err(|err| anyhow!("Failed to load Ed25519 private key: {err}"))?;
+        Ok(LocalKey { keypair })
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Vec<u8> {
+        self.keypair.sign(msg).as_ref().to_vec()
+    }
+
+    pub fn public_key_pem(&self) -> String {
+        let mut spki = ED25519_SPKI_PREFIX.to_vec();
+        spki.extend_from_slice(self.keypair.public_key().as_ref());
+        pem::encode(&pem::Pem::new(PEM_PUBLIC_KEY, spki))
+    }
+}
+
+/// A countersigning key, either in a file or on a PKCS#11 token
+pub enum Signer {
+    Local(LocalKey),
+    Pkcs11(pkcs11::Key),
+}
+
+impl Signer {
+    /// Load a key from a file, or from a token if this is a `pkcs11:` URI
+    pub async fn load(key: &str) -> Result<Self> {
+        if key.starts_with(pkcs11::SCHEME) {
+            Ok(Signer::Pkcs11(pkcs11::Key::load(key).await?))
+        } else {
+            Ok(Signer::Local(LocalKey::load(Path::new(key)).await?))
+        }
+    }
+
+    pub async fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Signer::Local(key) => Ok(key.sign(msg)),
+            Signer::Pkcs11(key) => key.sign(msg).await,
+        }
+    }
+
+    pub fn public_key_pem(&self) -> String {
+        match self {
+            Signer::Local(key) => key.public_key_pem(),
+            Signer::Pkcs11(key) => key.public_key_pem(),
+        }
+    }
+}
+
+/// The type of a DER encoded SubjectPublicKeyInfo, if it can be used for countersigning
+pub fn spki_key_type(spki: &[u8]) -> Result<pkcs11::KeyType> {
+    if spki.len() == ED25519_SPKI_PREFIX.len() + 32 && spki.starts_with(&ED25519_SPKI_PREFIX) {
+        Ok(pkcs11::KeyType::Ed25519)
+    } else if spki.len() == P256_SPKI_PREFIX.len() + 65 && spki.starts_with(&P256_SPKI_PREFIX) {
+        Ok(pkcs11::KeyType::P256)
+    } else {
+        bail!("Unsupported public key, expected Ed25519 or P-256")
+    }
+}
+
+pub fn spki_to_pem(spki: &[u8]) -> String {
+    pem::encode(&pem::Pem::new(PEM_PUBLIC_KEY, spki))
+}
+
+/// Verify a signature created by a `Signer`, given its public key in pem format
+///
+/// P-256 signatures are expected as fixed size `r || s`, like PKCS#11 tokens create them.
+pub fn verify_local_signature(public_key_pem: &str, msg: &[u8], sig: &[u8]) -> Result<()> {
+    let pem = pem::parse(public_key_pem).context("Failed to parse pem file")?;
+    let spki = pem.contents();
+    let result = match spki_key_type(spki)? {
+        pkcs11::KeyType::Ed25519 => {
+            UnparsedPublicKey::new(&ED25519, &spki[ED25519_SPKI_PREFIX.len()..]).verify(msg, sig)
+        }
+        pkcs11::KeyType::P256 => {
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, &spki[P256_SPKI_PREFIX.len()..])
+                .verify(msg, sig)
+        }
+    };
+    result.map_err(|_| anyhow!("Signature verification failed"))
+}
+
 pub async fn load_all_signing_keys<I: IntoIterator<Item = P>, P: AsRef<Path>>(
     paths: I,
 ) -> Result<Vec<PublicKey>> {
@@ -92,9 +402,12 @@ mod tests {
     use super::*;
     use crate::{
         attestation::{self, Attestation},
+        psl,
         rebuilder::Rebuilder,
+        test_server,
     };
     use std::str::FromStr;
+    use std::sync::Arc;
 
     #[test]
     fn test_parse_signing_key() {
@@ -136,6 +449,8 @@ mod tests {
                     country: None,
                     contact: None,
                     signing_keyring: "-----BEGIN PUBLIC KEY-----\r\nMCwwBwYDK2VwBQADIQAO2E6IRl1NbzFuNQ8tDeii85GknnvibBj+AmQDSiYVkg==\r\n-----END PUBLIC KEY-----\r\n".to_string(),
+                    valid_until: None,
+                    verified_fingerprint: None,
                 },
                 Rebuilder {
                     name: "B".to_string(),
@@ -144,6 +459,8 @@ mod tests {
                     country: None,
                     contact: None,
                     signing_keyring: "-----BEGIN PUBLIC KEY-----\r\nMCwwBwYDK2VwBQADIQC+uldtf6F9pI5IYY3p0IzzQSnh/uRZS8c1NmxW3/zP/g==\r\n-----END PUBLIC KEY-----\r\n".to_string(),
+                    valid_until: None,
+                    verified_fingerprint: None,
                 },
                 Rebuilder {
                     name: "C".to_string(),
@@ -152,6 +469,8 @@ mod tests {
                     country: None,
                     contact: None,
                     signing_keyring: "-----BEGIN PUBLIC KEY-----\r\nMCwwBwYDK2VwBQADIQCjiKUEanhTIjz+VDQ22bEWiMVSgDvsqwSAr1zqAuUKlw==\r\n-----END PUBLIC KEY-----\r\n".to_string(),
+                    valid_until: None,
+                    verified_fingerprint: None,
                 },
             ],
             ..Default::default()
@@ -178,7 +497,7 @@ mod tests {
             ])
         );
 
-        let filtered = trusted.group_by_domain(confirms);
+        let filtered = trusted.group_by_domain(confirms.clone());
         assert_eq!(
             filtered,
             BTreeSet::from_iter([
@@ -188,5 +507,157 @@ mod tests {
                     .unwrap(),
             ])
         );
+
+        // Within a group, the key verified out-of-band casts the vote
+        for idx in 0..2 {
+            let mut config = Config {
+                trusted_rebuilders: config.trusted_rebuilders.clone(),
+                ..Default::default()
+            };
+            let rebuilder = &mut config.trusted_rebuilders[idx];
+            rebuilder.verified_fingerprint = Some(rebuilder.fingerprint().unwrap());
+            let trusted = DomainTree::from_config(&config);
+            let filtered = trusted.group_by_domain(confirms.clone());
+            assert_eq!(filtered.len(), 2);
+            assert_eq!(
+                filtered
+                    .iter()
+                    .filter(|key_id| trusted.is_verified(key_id))
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_format() {
+        let fingerprint = "3a7f91c2".repeat(8);
+        let formatted = format_fingerprint(&fingerprint);
+        assert_eq!(
+            formatted,
+            "3A7F 91C2 3A7F 91C2 3A7F 91C2 3A7F 91C2 3A7F 91C2 3A7F 91C2 3A7F 91C2 3A7F 91C2"
+        );
+        assert_eq!(parse_fingerprint(&formatted).unwrap(), fingerprint);
+        assert_eq!(
+            parse_fingerprint(&format!("sha256={}", fingerprint.to_uppercase())).unwrap(),
+            fingerprint
+        );
+        assert_eq!(
+            parse_fingerprint(&format!(" {fingerprint}\n")).unwrap(),
+            fingerprint
+        );
+        assert!(parse_fingerprint("3A7F 91C2").is_err());
+        assert!(parse_fingerprint(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_verify_p256_signature() {
+        use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair};
+
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let keypair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+        let mut spki = P256_SPKI_PREFIX.to_vec();
+        spki.extend_from_slice(keypair.public_key().as_ref());
+        assert_eq!(spki_key_type(&spki).unwrap(), pkcs11::KeyType::P256);
+        let public_key = spki_to_pem(&spki);
+
+        let sig = keypair.sign(&rng, b"statement").unwrap();
+        verify_local_signature(&public_key, b"statement", sig.as_ref()).unwrap();
+        assert!(verify_local_signature(&public_key, b"other statement", sig.as_ref()).is_err());
+        assert!(spki_key_type(&spki[..spki.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        let normalize = |host| normalize_host(Host::Domain(host)).to_string();
+        assert_eq!(normalize("bücher.example"), "xn--bcher-kva.example");
+        assert_eq!(normalize("Bücher.Example."), "xn--bcher-kva.example");
+        assert_eq!(normalize("XN--BCHER-KVA.example"), "xn--bcher-kva.example");
+        assert_eq!(normalize("rebuilder.example.com."), "rebuilder.example.com");
+        assert_eq!(
+            normalize_host(Host::Ipv4([127, 0, 0, 1].into())),
+            Host::<String>::Ipv4([127, 0, 0, 1].into())
+        );
+    }
+
+    #[test]
+    fn test_vote_group() {
+        let host = |domain: &str| Host::Domain(domain.to_string());
+        let mut rules = Rules::default();
+        assert_eq!(
+            vote_group(&rules, &host("amd64.rebuilder.example.org")),
+            "amd64.rebuilder.example.org"
+        );
+
+        rules.group_votes_by = VoteGrouping::RegistrableDomain;
+        rules.public_suffixes = Some(Arc::new(psl::List::parse("org\nco.uk\n")));
+        assert_eq!(
+            vote_group(&rules, &host("amd64.rebuilder.example.org")),
+            "example.org"
+        );
+        assert_eq!(
+            vote_group(&rules, &host("arm64.rebuilder.example.org")),
+            "example.org"
+        );
+        assert_eq!(
+            vote_group(&rules, &host("rebuilder.example.co.uk")),
+            "example.co.uk"
+        );
+        assert_eq!(vote_group(&rules, &host("co.uk")), "co.uk");
+        assert_eq!(
+            vote_group(&rules, &Host::Ipv4([10, 0, 0, 1].into())),
+            "10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn test_domain_tree_idn() {
+        let rebuilder = |url: &str, signing_keyring: &str| {
+            test_server::rebuilder(url.parse().unwrap(), signing_keyring.to_string())
+        };
+        let config = Config {
+            trusted_rebuilders: vec![
+                rebuilder(
+                    "https://Bücher.example./",
+                    "-----BEGIN PUBLIC KEY-----\r\nMCwwBwYDK2VwBQADIQAO2E6IRl1NbzFuNQ8tDeii85GknnvibBj+AmQDSiYVkg==\r\n-----END PUBLIC KEY-----\r\n",
+                ),
+                rebuilder(
+                    "https://xn--bcher-kva.example/",
+                    "-----BEGIN PUBLIC KEY-----\r\nMCwwBwYDK2VwBQADIQC+uldtf6F9pI5IYY3p0IzzQSnh/uRZS8c1NmxW3/zP/g==\r\n-----END PUBLIC KEY-----\r\n",
+                ),
+                rebuilder(
+                    "https://bücher.example.org/",
+                    "-----BEGIN PUBLIC KEY-----\r\nMCwwBwYDK2VwBQADIQCjiKUEanhTIjz+VDQ22bEWiMVSgDvsqwSAr1zqAuUKlw==\r\n-----END PUBLIC KEY-----\r\n",
+                ),
+            ],
+            ..Default::default()
+        };
+        let trusted = DomainTree::from_config(&config);
+
+        let confirms = BTreeSet::from_iter([
+            KeyId::from_str("1752ad72d6f07622d66da9676f5084385ab4e7a8af08bbe137d88dba5d0848f2")
+                .unwrap(),
+            KeyId::from_str("931cf71e1a72729f5d41957671508ffba5effe950aa7e7e2af4e99ec9dcde2ba")
+                .unwrap(),
+            KeyId::from_str("c2b6844adec1b4debbdeb606a42b8ed93444344326afad4af20f53bc1068e6e9")
+                .unwrap(),
+        ]);
+        for key_id in &confirms {
+            let host = trusted.host(key_id).unwrap().to_string();
+            assert!(host.starts_with("xn--bcher-kva.example"), "{host}");
+        }
+        let filtered = trusted.group_by_domain(confirms);
+        assert_eq!(filtered.len(), 2);
+        assert!(
+            filtered.contains(
+                &KeyId::from_str(
+                    "c2b6844adec1b4debbdeb606a42b8ed93444344326afad4af20f53bc1068e6e9"
+                )
+                .unwrap()
+            )
+        );
     }
 }
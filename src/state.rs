@@ -0,0 +1,615 @@
+use crate::errors::*;
+use crate::inspect::deb::Deb;
+use crate::lock::Lock;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::Permissions;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::{fs, io, task};
+use url::Url;
+
+const STATE_DIR: &str = "/var/lib/repro-threshold";
+const CACHE_DIR: &str = "/var/cache/repro-threshold";
+
+struct Dirs {
+    state: PathBuf,
+    cache: PathBuf,
+}
+
+static DIRS: OnceLock<Dirs> = OnceLock::new();
+
+/// Use the directories of the system, or the ones from `REPRO_THRESHOLD_STATE_DIR` and
+/// `REPRO_THRESHOLD_CACHE_DIR`
+///
+/// Until this is called, directories private to the process are used.
+pub fn init() {
+    let dir = |name, default| {
+        std::env::var_os(name)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(default))
+    };
+    let dirs = Dirs {
+        state: dir("REPRO_THRESHOLD_STATE_DIR", STATE_DIR),
+        cache: dir("REPRO_THRESHOLD_CACHE_DIR", CACHE_DIR),
+    };
+    if DIRS.set(dirs).is_err() {
+        warn!("State directories were already in use before they were configured");
+    }
+}
+
+fn dirs() -> &'static Dirs {
+    DIRS.get_or_init(|| {
+        let dir = |name| {
+            std::env::temp_dir().join(format!("repro-threshold-{name}-{}", std::process::id()))
+        };
+        Dirs {
+            state: dir("state"),
+            cache: dir("cache"),
+        }
+    })
+}
+
+pub fn state_dir() -> PathBuf {
+    dirs().state.clone()
+}
+
+/// Data that can be recomputed at any time, like parsed package metadata
+pub fn cache_dir() -> PathBuf {
+    dirs().cache.clone()
+}
+
+/// If this process can write the state directory, checked once
+///
+/// The apt method runs as `_apt`, which can only write the state directory if it was set up
+/// for it. A missing directory is created on the first write, so its closest existing parent
+/// is checked instead.
+pub fn is_writable() -> bool {
+    static WRITABLE: OnceLock<bool> = OnceLock::new();
+    *WRITABLE.get_or_init(|| {
+        let dir = state_dir();
+        let Some(existing) = dir.ancestors().find(|path| path.exists()) else {
+            return false;
+        };
+        let writable = nix::unistd::access(existing, nix::unistd::AccessFlags::W_OK).is_ok();
+        if !writable {
+            debug!("State directory {dir:?} is not writable, statistics and missing builds are not recorded");
+        }
+        writable
+    })
+}
+
+/// A json file in the state directory
+pub trait Store: Default + Serialize + DeserializeOwned {
+    const FILENAME: &'static str;
+
+    fn path() -> PathBuf {
+        state_dir().join(Self::FILENAME)
+    }
+
+    async fn load_file(path: &Path) -> Result<Self> {
+        match fs::read(path).await {
+            Ok(buf) => serde_json::from_slice(&buf)
+                .with_context(|| format!("Failed to parse state file: {path:?}")),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => {
+                Err(Error::from(err).context(format!("Failed to read state file: {path:?}")))
+            }
+        }
+    }
+
+    async fn load() -> Result<Self> {
+        Self::load_file(&Self::path()).await
+    }
+
+    async fn save_file(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create state directory: {parent:?}"))?;
+        }
+
+        // Write to a temporary file first, so the state is never left truncated. The state
+        // directory belongs to the apt method's user, so the temporary file must not be a
+        // predictable path that could already exist as a symlink.
+        let json = serde_json::to_vec_pretty(self)?;
+        let path = path.to_path_buf();
+        task::spawn_blocking(move || {
+            let dir = path.parent().unwrap_or(Path::new("."));
+            let mut tmp = tempfile::NamedTempFile::new_in(dir)
+                .with_context(|| format!("Failed to create temporary file in {dir:?}"))?;
+            tmp.write_all(&json)
+                .with_context(|| format!("Failed to write state file: {:?}", tmp.path()))?;
+            // Readable by the apt method and root alike, like the files it replaces
+            tmp.as_file()
+                .set_permissions(Permissions::from_mode(0o644))?;
+            tmp.persist(&path)
+                .with_context(|| format!("Failed to write state file: {path:?}"))?;
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Modify the file in place
+    ///
+    /// The file is locked while it's modified, so concurrent processes don't lose updates.
+    async fn try_update<F: FnOnce(&mut Self)>(f: F) -> Result<()> {
+        let path = Self::path();
+        async {
+            let _lock = Lock::acquire(Self::FILENAME).await?;
+            let mut state = Self::load_file(&path).await?;
+            f(&mut state);
+            state.save_file(&path).await
+        }
+        .await
+        .with_context(|| format!("Failed to update state file {path:?}"))
+    }
+
+    /// Like `try_update`, but failures are logged and not fatal
+    async fn update<F: FnOnce(&mut Self)>(f: F) {
+        if let Err(err) = Self::try_update(f).await {
+            warn!("{err:#}");
+        }
+    }
+
+    /// Like `update`, for data that's nice to have, skipped if the state directory isn't writable
+    async fn update_if_writable<F: FnOnce(&mut Self)>(f: F) {
+        if is_writable() {
+            Self::update(f).await;
+        }
+    }
+}
+
+/// How often the transports made a final decision about a package
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counters {
+    #[serde(default)]
+    pub verified: u64,
+    #[serde(default)]
+    pub failed: u64,
+}
+
+impl Store for Counters {
+    const FILENAME: &'static str = "counters.json";
+}
+
+/// Why a package was admitted without reaching the threshold
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    BlindlyTrusted,
+    GracePeriod,
+    /// Admitted because the deadline was reached before all trusted rebuilders answered
+    PartialResults,
+    /// Admitted with a warning because no trusted rebuilder could be reached
+    Offline,
+    /// Refused because no trusted rebuilder could be reached, not installed
+    Deferred,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub package: Deb,
+    pub reason: Reason,
+    pub admitted_at: DateTime<Utc>,
+    /// When attestations are expected to have shown up, blindly trusted packages have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<DateTime<Utc>>,
+    /// The deadline has passed and this has been reported
+    #[serde(default)]
+    pub escalated: bool,
+}
+
+impl Entry {
+    /// A package admitted without reaching the threshold, it's due after the grace period
+    pub fn pending(
+        package: Deb,
+        reason: Reason,
+        now: DateTime<Utc>,
+        grace_period_days: u32,
+    ) -> Self {
+        Entry {
+            package,
+            reason,
+            admitted_at: now,
+            deadline: Some(now + TimeDelta::days(grace_period_days.max(1).into())),
+            escalated: false,
+        }
+    }
+
+    pub fn is_overdue(&self, now: DateTime<Utc>) -> bool {
+        self.deadline.is_some_and(|deadline| deadline <= now)
+    }
+}
+
+/// Packages that were admitted but are still waiting for attestations, keyed by sha256
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Pending {
+    #[serde(default)]
+    pub packages: BTreeMap<String, Entry>,
+}
+
+impl Store for Pending {
+    const FILENAME: &'static str = "pending.json";
+}
+
+impl Pending {
+    pub fn insert(&mut self, sha256: &[u8], entry: Entry) {
+        let sha256 = data_encoding::HEXLOWER.encode(sha256);
+        // Keep the original admission time if the package is downloaded again
+        self.packages.entry(sha256).or_insert(entry);
+    }
+
+    /// Record an admission in the persistent store
+    ///
+    /// Packages admitted below the threshold have to be tracked, so failures are returned.
+    pub async fn record(sha256: &[u8], entry: Entry) -> Result<()> {
+        Self::try_update(|pending| pending.insert(sha256, entry)).await
+    }
+
+    pub fn overdue(&self, now: DateTime<Utc>) -> impl Iterator<Item = (&String, &Entry)> {
+        self.packages
+            .iter()
+            .filter(move |(_, entry)| entry.is_overdue(now))
+    }
+}
+
+/// A rebuilder served a different keyring than the one trusted on first use
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyChange {
+    /// Fingerprints of the pinned keys
+    pub pinned: BTreeSet<String>,
+    /// Fingerprints of the keys the rebuilder served instead
+    pub served: BTreeSet<String>,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Rebuilders with a changed keyring, keyed by url, until the user accepts the new keys
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KeyChanges {
+    #[serde(default)]
+    pub rebuilders: BTreeMap<String, KeyChange>,
+}
+
+impl Store for KeyChanges {
+    const FILENAME: &'static str = "key-changes.json";
+}
+
+impl KeyChanges {
+    /// Record a changed keyring, failures are logged but not fatal
+    pub async fn record(url: &Url, change: KeyChange) {
+        Self::update(|changes| {
+            changes.rebuilders.insert(url.to_string(), change);
+        })
+        .await;
+    }
+}
+
+/// Keyrings trusted on first use, keyed by rebuilder url
+///
+/// These are kept in the state directory instead of the config file, since the apt method
+/// can't write the config.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Pins {
+    #[serde(default)]
+    pub keyrings: BTreeMap<String, String>,
+}
+
+impl Store for Pins {
+    const FILENAME: &'static str = "pins.json";
+}
+
+/// The time of the last notification email and the number of events suppressed since then
+///
+/// Every apt run is a new process, so the rate limit has to be kept in the state directory.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmailLimit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sent: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub suppressed: usize,
+}
+
+impl Store for EmailLimit {
+    const FILENAME: &'static str = "email.json";
+}
+
+/// Rebuilders that had no build of a package, keyed by rebuilder url and package
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Misses {
+    #[serde(default)]
+    pub queries: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl Store for Misses {
+    const FILENAME: &'static str = "misses.json";
+}
+
+impl Misses {
+    fn key(rebuilder: &Url, package: &Deb) -> String {
+        format!(
+            "{rebuilder} {}_{}_{}",
+            package.name, package.version, package.architecture
+        )
+    }
+
+    pub fn is_fresh(
+        &self,
+        rebuilder: &Url,
+        package: &Deb,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> bool {
+        self.queries
+            .get(&Self::key(rebuilder, package))
+            // Misses from the future are from a clock that was off, they're not trusted
+            .is_some_and(|at| {
+                now.signed_duration_since(*at)
+                    .to_std()
+                    .is_ok_and(|age| age < ttl)
+            })
+    }
+
+    pub fn insert(&mut self, rebuilder: &Url, package: &Deb, now: DateTime<Utc>, ttl: Duration) {
+        // Forget expired misses, so the file doesn't grow forever
+        self.queries.retain(|_, at| {
+            now.signed_duration_since(*at)
+                .to_std()
+                .is_ok_and(|age| age < ttl)
+        });
+        self.queries.insert(Self::key(rebuilder, package), now);
+    }
+
+    /// Check if the rebuilder recently had no build of this package
+    pub async fn contains(rebuilder: &Url, package: &Deb, ttl: Duration) -> bool {
+        match Self::load().await {
+            Ok(misses) => misses.is_fresh(rebuilder, package, Utc::now(), ttl),
+            Err(err) => {
+                debug!("Failed to load negative cache: {err:#}");
+                false
+            }
+        }
+    }
+
+    /// Record a rebuilder had no build of this package, failures are logged but not fatal
+    pub async fn record(rebuilder: &Url, package: &Deb, ttl: Duration) {
+        Self::update_if_writable(|misses| misses.insert(rebuilder, package, Utc::now(), ttl)).await;
+    }
+}
+
+/// How long the post-invoke hook can still match an installed package to its admission
+const ADMITTED_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Packages recently admitted by the apt transport, so the post-invoke hook can tell which
+/// installed packages bypassed it
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Admitted {
+    #[serde(default)]
+    pub packages: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl Store for Admitted {
+    const FILENAME: &'static str = "admitted.json";
+}
+
+impl Admitted {
+    fn key(package: &Deb) -> String {
+        format!(
+            "{}_{}_{}",
+            package.name, package.version, package.architecture
+        )
+    }
+
+    pub fn contains(&self, package: &Deb) -> bool {
+        self.packages.contains_key(&Self::key(package))
+    }
+
+    pub fn insert(&mut self, package: &Deb, now: DateTime<Utc>) {
+        // Forget old admissions, so the file doesn't grow forever
+        self.packages.retain(|_, at| {
+            now.signed_duration_since(*at).to_std().unwrap_or_default() < ADMITTED_TTL
+        });
+        self.packages.insert(Self::key(package), now);
+    }
+
+    pub fn remove(&mut self, package: &Deb) {
+        self.packages.remove(&Self::key(package));
+    }
+
+    /// Record a package was admitted, failures are logged but not fatal
+    ///
+    /// Without the record the post-invoke hook can only tell the package went through the
+    /// transport if it's still in the package cache, otherwise it's reported as side-loaded.
+    pub async fn record(package: &Deb) {
+        if let Err(err) = Self::try_update(|admitted| admitted.insert(package, Utc::now())).await {
+            error!(
+                "Failed to record admission of {} {}, it's reported as side-loaded unless it's still in the package cache: {err:#}",
+                package.name, package.version
+            );
+        }
+    }
+}
+
+/// An installed package that didn't go through a transport
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SideLoadedPackage {
+    pub package: Deb,
+    /// Only known if the file was still in the cache of the package manager
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// The package was verified after it was installed
+    pub verified: bool,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Side-loaded packages that are still installed, keyed by name and architecture
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SideLoaded {
+    /// How much of each package manager log has been processed, keyed by path
+    #[serde(default)]
+    pub offsets: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub packages: BTreeMap<String, SideLoadedPackage>,
+}
+
+impl Store for SideLoaded {
+    const FILENAME: &'static str = "side-loaded.json";
+}
+
+impl SideLoaded {
+    fn key(package: &Deb) -> String {
+        format!("{}:{}", package.name, package.architecture)
+    }
+
+    /// Record a package, replacing an older version
+    pub fn insert(&mut self, entry: SideLoadedPackage) {
+        self.packages.insert(Self::key(&entry.package), entry);
+    }
+
+    /// Forget the package, e.g. because a newer version was installed through a transport
+    pub fn remove(&mut self, package: &Deb) {
+        self.packages.remove(&Self::key(package));
+    }
+
+    pub fn mark_verified(&mut self, package: &Deb) {
+        if let Some(entry) = self.packages.get_mut(&Self::key(package))
+            && entry.package.version == package.version
+        {
+            entry.verified = true;
+        }
+    }
+
+    pub fn unverified(&self) -> impl Iterator<Item = &SideLoadedPackage> {
+        self.packages.values().filter(|entry| !entry.verified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use chrono::TimeDelta;
+    use std::env;
+
+    fn entry(deadline: Option<DateTime<Utc>>) -> Entry {
+        Entry {
+            package: test_server::deb(),
+            reason: Reason::GracePeriod,
+            admitted_at: DateTime::UNIX_EPOCH,
+            deadline,
+            escalated: false,
+        }
+    }
+
+    #[test]
+    fn test_overdue() {
+        let now = Utc::now();
+        let mut pending = Pending::default();
+        pending.insert(&[1], entry(Some(now - TimeDelta::days(1))));
+        pending.insert(&[2], entry(Some(now + TimeDelta::days(1))));
+        pending.insert(&[3], entry(None));
+
+        let overdue = pending
+            .overdue(now)
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(overdue, ["01"]);
+    }
+
+    #[test]
+    fn test_insert_keeps_first_admission() {
+        let mut pending = Pending::default();
+        pending.insert(&[1], entry(None));
+        let mut later = entry(None);
+        later.admitted_at = Utc::now();
+        pending.insert(&[1], later);
+        assert_eq!(pending.packages["01"].admitted_at, DateTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_misses_ttl() {
+        let url = "https://rebuilder.example.com/".parse().unwrap();
+        let deb = test_server::deb();
+        let ttl = Duration::from_secs(300);
+        let now = Utc::now();
+
+        let mut misses = Misses::default();
+        misses.insert(&url, &deb, now - TimeDelta::hours(1), ttl);
+        assert!(!misses.is_fresh(&url, &deb, now, ttl));
+        misses.insert(&url, &deb, now - TimeDelta::minutes(1), ttl);
+        assert!(misses.is_fresh(&url, &deb, now, ttl));
+        assert!(!misses.is_fresh(&url, &deb, now, Duration::ZERO));
+
+        let other = "https://other.example.com/".parse().unwrap();
+        assert!(!misses.is_fresh(&other, &deb, now, ttl));
+
+        misses.insert(&url, &deb, now + TimeDelta::days(1), ttl);
+        assert!(!misses.is_fresh(&url, &deb, now, ttl));
+    }
+
+    #[test]
+    fn test_admitted_ttl() {
+        let deb = test_server::deb();
+        let now = Utc::now();
+
+        let mut admitted = Admitted::default();
+        admitted.insert(&deb, now - TimeDelta::days(8));
+        assert!(admitted.contains(&deb));
+
+        let mut other = test_server::deb();
+        other.version = "0.2.2-1".to_string();
+        admitted.insert(&other, now);
+        assert!(!admitted.contains(&deb));
+        assert!(admitted.contains(&other));
+
+        admitted.remove(&other);
+        assert!(admitted.packages.is_empty());
+    }
+
+    #[test]
+    fn test_side_loaded() {
+        let entry = |version: &str| SideLoadedPackage {
+            package: Deb {
+                version: version.to_string(),
+                ..test_server::deb()
+            },
+            sha256: None,
+            verified: false,
+            detected_at: DateTime::UNIX_EPOCH,
+        };
+
+        let mut side_loaded = SideLoaded::default();
+        side_loaded.insert(entry("1.0-1"));
+        side_loaded.insert(entry("1.1-1"));
+        assert_eq!(side_loaded.unverified().count(), 1);
+
+        side_loaded.mark_verified(&entry("1.0-1").package);
+        assert_eq!(side_loaded.unverified().count(), 1);
+        side_loaded.mark_verified(&entry("1.1-1").package);
+        assert_eq!(side_loaded.unverified().count(), 0);
+
+        side_loaded.remove(&entry("1.2-1").package);
+        assert!(side_loaded.packages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_save_load() {
+        let path = env::temp_dir()
+            .join(format!("repro-threshold-test-state-{}", std::process::id()))
+            .join("pending.json");
+
+        assert!(Pending::load_file(&path).await.unwrap().packages.is_empty());
+
+        let mut pending = Pending::default();
+        pending.insert(&[1], entry(Some(DateTime::UNIX_EPOCH)));
+        pending.save_file(&path).await.unwrap();
+
+        let loaded = Pending::load_file(&path).await.unwrap();
+        assert_eq!(loaded.packages, pending.packages);
+        fs::remove_dir_all(path.parent().unwrap()).await.unwrap();
+    }
+}
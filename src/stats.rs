@@ -0,0 +1,170 @@
+use crate::state::Store;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+use url::Url;
+
+/// Only the most recent latencies are kept for the median
+const MAX_LATENCIES: usize = 100;
+
+/// The result of querying a rebuilder for a package
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Found,
+    NotFound,
+    Error,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RebuilderStats {
+    #[serde(default)]
+    pub queries: u64,
+    #[serde(default)]
+    pub found: u64,
+    #[serde(default)]
+    pub errors: u64,
+    /// Attestations signed by this rebuilder that didn't match the package
+    #[serde(default)]
+    pub disagreements: u64,
+    #[serde(default)]
+    pub latencies_ms: VecDeque<u64>,
+}
+
+impl RebuilderStats {
+    pub fn record(&mut self, outcome: Outcome, latency: Duration) {
+        self.queries += 1;
+        match outcome {
+            Outcome::Found => self.found += 1,
+            Outcome::NotFound => (),
+            Outcome::Error => self.errors += 1,
+        }
+        if self.latencies_ms.len() >= MAX_LATENCIES {
+            self.latencies_ms.pop_front();
+        }
+        self.latencies_ms
+            .push_back(latency.as_millis().try_into().unwrap_or(u64::MAX));
+    }
+
+    fn rate(&self, n: u64) -> f64 {
+        if self.queries == 0 {
+            0.0
+        } else {
+            n as f64 / self.queries as f64
+        }
+    }
+
+    pub fn found_rate(&self) -> f64 {
+        self.rate(self.found)
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        self.rate(self.errors)
+    }
+
+    pub fn median_latency(&self) -> Option<Duration> {
+        let mut latencies = self.latencies_ms.iter().copied().collect::<Vec<_>>();
+        latencies.sort_unstable();
+        let median = latencies.get(latencies.len() / 2)?;
+        Some(Duration::from_millis(*median))
+    }
+
+    /// A short summary for the TUI
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{:.0}% found, {:.0}% errors",
+            self.found_rate() * 100.0,
+            self.error_rate() * 100.0
+        );
+        if self.disagreements > 0 {
+            summary.push_str(&format!(", {} disagreements", self.disagreements));
+        }
+        if let Some(latency) = self.median_latency() {
+            summary.push_str(&format!(", {}ms", latency.as_millis()));
+        }
+        summary
+    }
+}
+
+/// Historical outcomes of rebuilder queries, keyed by rebuilder url
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    #[serde(default)]
+    pub rebuilders: BTreeMap<String, RebuilderStats>,
+}
+
+impl Store for Stats {
+    const FILENAME: &'static str = "rebuilder-stats.json";
+}
+
+impl Stats {
+    pub fn get(&self, url: &Url) -> Option<&RebuilderStats> {
+        self.rebuilders.get(url.as_str())
+    }
+
+    pub fn entry(&mut self, url: &Url) -> &mut RebuilderStats {
+        self.rebuilders.entry(url.to_string()).or_default()
+    }
+
+    /// Add query outcomes to the persistent stats, failures are logged but not fatal
+    pub async fn record_queries(outcomes: Vec<(Url, Outcome, Duration)>) {
+        if outcomes.is_empty() {
+            return;
+        }
+        Self::update_if_writable(|stats| {
+            for (url, outcome, latency) in outcomes {
+                stats.entry(&url).record(outcome, latency);
+            }
+        })
+        .await;
+    }
+
+    pub async fn record_disagreements<'a, I: IntoIterator<Item = &'a Url>>(urls: I) {
+        let urls = urls.into_iter().collect::<Vec<_>>();
+        if urls.is_empty() {
+            return;
+        }
+        Self::update_if_writable(|stats| {
+            for url in urls {
+                stats.entry(url).disagreements += 1;
+            }
+        })
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut stats = RebuilderStats::default();
+        stats.record(Outcome::Found, Duration::from_millis(300));
+        stats.record(Outcome::Found, Duration::from_millis(100));
+        stats.record(Outcome::NotFound, Duration::from_millis(200));
+        stats.record(Outcome::Error, Duration::from_millis(10_000));
+
+        assert_eq!(stats.queries, 4);
+        assert_eq!(stats.found_rate(), 0.5);
+        assert_eq!(stats.error_rate(), 0.25);
+        assert_eq!(stats.median_latency(), Some(Duration::from_millis(300)));
+        assert_eq!(stats.summary(), "50% found, 25% errors, 300ms");
+    }
+
+    #[test]
+    fn test_latency_window() {
+        let mut stats = RebuilderStats::default();
+        for i in 0..(MAX_LATENCIES as u64 + 10) {
+            stats.record(Outcome::Found, Duration::from_millis(i));
+        }
+        assert_eq!(stats.latencies_ms.len(), MAX_LATENCIES);
+        assert_eq!(stats.latencies_ms.front(), Some(&10));
+    }
+
+    #[test]
+    fn test_empty() {
+        let stats = RebuilderStats::default();
+        assert_eq!(stats.found_rate(), 0.0);
+        assert_eq!(stats.median_latency(), None);
+    }
+}
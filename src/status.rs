@@ -0,0 +1,308 @@
+use crate::clock;
+use crate::config::Config;
+use crate::errors::*;
+use crate::http;
+use crate::state::{Counters, Pending, Reason, SideLoaded, SideLoadedPackage, Store};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tokio::task::JoinSet;
+use url::Url;
+
+/// A summary of this machine for configuration management and fleet monitoring
+#[derive(Debug, Serialize)]
+pub struct Status {
+    /// The policy is enforceable and no pending package is overdue
+    pub compliant: bool,
+    pub policy: Policy,
+    pub rebuilders: Vec<RebuilderStatus>,
+    pub packages: Packages,
+    /// Installed packages that didn't go through a transport and couldn't be verified
+    pub side_loaded: Vec<SideLoadedPackage>,
+    /// Why the system clock can't be trusted for key expiry and deadlines
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock: Option<String>,
+    pub community_refreshed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Policy {
+    /// All packages are admitted without checking attestations
+    pub disabled: bool,
+    pub required_threshold: usize,
+    pub architecture: BTreeMap<String, usize>,
+    pub blindly_trust: usize,
+    pub blindly_trust_source: usize,
+    pub grace_period_days: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RebuilderStatus {
+    pub name: String,
+    pub url: Url,
+    pub reachable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<DateTime<Utc>>,
+    /// Signatures of an expired keyring don't count towards the threshold
+    pub expired: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Packages {
+    pub verified: u64,
+    pub failed: u64,
+    pub grace: usize,
+    /// Admitted before all trusted rebuilders answered
+    pub partial: usize,
+    pub blind: usize,
+    /// Admitted while no trusted rebuilder could be reached
+    pub offline: usize,
+    /// Refused while no trusted rebuilder could be reached, waiting to be settled
+    pub deferred: usize,
+    pub overdue: usize,
+}
+
+impl Packages {
+    pub fn new(counters: &Counters, pending: &Pending, now: DateTime<Utc>) -> Self {
+        let count = |reason| {
+            pending
+                .packages
+                .values()
+                .filter(|entry| entry.reason == reason)
+                .count()
+        };
+        Packages {
+            verified: counters.verified,
+            failed: counters.failed,
+            grace: count(Reason::GracePeriod),
+            partial: count(Reason::PartialResults),
+            blind: count(Reason::BlindlyTrusted),
+            offline: count(Reason::Offline),
+            deferred: count(Reason::Deferred),
+            overdue: pending.overdue(now).count(),
+        }
+    }
+}
+
+async fn probe_rebuilders(http: &http::Client, config: &Config) -> Vec<RebuilderStatus> {
+    let mut tasks = JoinSet::new();
+    for (idx, rebuilder) in config.trusted_rebuilders.iter().enumerate() {
+        let http = http.clone();
+        let url = rebuilder.url.clone();
+        tasks.spawn(async move { (idx, http.fetch_signing_keyring(&url).await) });
+    }
+
+    let mut results = BTreeMap::new();
+    while let Some(res) = tasks.join_next().await {
+        match res {
+            Ok((idx, result)) => {
+                results.insert(idx, result.err().map(|err| format!("{err:#}")));
+            }
+            Err(err) => warn!("Rebuilder task panicked: {err:#}"),
+        }
+    }
+
+    let now = Utc::now();
+    config
+        .trusted_rebuilders
+        .iter()
+        .enumerate()
+        .map(|(idx, rebuilder)| {
+            let error = results
+                .remove(&idx)
+                .unwrap_or_else(|| Some("Rebuilder was not probed".to_string()));
+            RebuilderStatus {
+                name: rebuilder.name.clone(),
+                url: rebuilder.url.clone(),
+                reachable: error.is_none(),
+                valid_until: rebuilder.valid_until,
+                expired: rebuilder.is_expired(now),
+                error,
+            }
+        })
+        .collect()
+}
+
+pub async fn collect(http: &http::Client, config: &Config) -> Result<Status> {
+    let (counters, pending, side_loaded) =
+        tokio::try_join!(Counters::load(), Pending::load(), SideLoaded::load())?;
+    let side_loaded = side_loaded.unverified().cloned().collect::<Vec<_>>();
+    let packages = Packages::new(&counters, &pending, Utc::now());
+
+    let rules = &config.rules;
+    let policy = Policy {
+        disabled: rules.is_disabled(),
+        required_threshold: rules.required_threshold,
+        architecture: rules
+            .architecture
+            .iter()
+            .filter_map(|(arch, rules)| Some((arch.clone(), rules.required_threshold?)))
+            .collect(),
+        blindly_trust: rules.blindly_trust.len(),
+        blindly_trust_source: rules.blindly_trust_source.len(),
+        grace_period_days: rules.grace_period_days,
+    };
+
+    let rebuilders = probe_rebuilders(http, config).await;
+    let reachable = rebuilders
+        .iter()
+        .filter(|r| r.reachable && !r.expired)
+        .count();
+    let max_threshold = policy
+        .architecture
+        .values()
+        .copied()
+        .chain([policy.required_threshold])
+        .max()
+        .unwrap_or_default();
+
+    let clock = clock::now().err().map(|problem| problem.to_string());
+
+    Ok(Status {
+        compliant: !policy.disabled
+            && packages.overdue == 0
+            // Admitted without asking any rebuilder, until cron verified them
+            && packages.offline == 0
+            && side_loaded.is_empty()
+            && clock.is_none()
+            && reachable >= max_threshold,
+        policy,
+        rebuilders,
+        packages,
+        side_loaded,
+        clock,
+        community_refreshed_at: config.cached_rebuilderd_community_at,
+    })
+}
+
+impl Status {
+    pub fn print(&self) {
+        let compliant = if self.compliant { "yes" } else { "no" };
+        println!("compliant: {compliant}");
+        if self.policy.disabled {
+            println!("enforcement: disabled");
+        }
+        println!("required threshold: {}", self.policy.required_threshold);
+        for (arch, threshold) in &self.policy.architecture {
+            println!("required threshold ({arch}): {threshold}");
+        }
+        println!(
+            "blindly trusted: {} packages, {} source packages",
+            self.policy.blindly_trust, self.policy.blindly_trust_source
+        );
+        for rebuilder in &self.rebuilders {
+            let status = if rebuilder.reachable { "[x]" } else { "[ ]" };
+            let expiry = match rebuilder.valid_until {
+                Some(at) if rebuilder.expired => format!(" (signing keyring expired {at})"),
+                Some(at) => format!(" (signing keyring valid until {at})"),
+                None => String::new(),
+            };
+            println!(
+                "{status} {:?} - {:?}{expiry}",
+                rebuilder.name,
+                rebuilder.url.as_str()
+            );
+        }
+        let packages = &self.packages;
+        println!(
+            "packages: {} verified, {} failed, {} grace period, {} partial results, {} blindly trusted, {} admitted offline, {} deferred, {} overdue",
+            packages.verified,
+            packages.failed,
+            packages.grace,
+            packages.partial,
+            packages.blind,
+            packages.offline,
+            packages.deferred,
+            packages.overdue
+        );
+        for entry in &self.side_loaded {
+            let package = &entry.package;
+            println!(
+                "unverified side-loaded package: {} {} ({}), installed {}",
+                package.name, package.version, package.architecture, entry.detected_at
+            );
+        }
+        if let Some(problem) = &self.clock {
+            println!("clock: {problem}");
+        }
+        match self.community_refreshed_at {
+            Some(at) => println!("rebuilderd-community refreshed: {at}"),
+            None => println!("rebuilderd-community refreshed: never"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Entry;
+    use crate::test_server;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn test_packages() {
+        let now = Utc::now();
+        let entry = |reason, deadline| Entry {
+            package: test_server::deb(),
+            reason,
+            admitted_at: now,
+            deadline,
+            escalated: false,
+        };
+        let mut pending = Pending::default();
+        pending.insert(&[1], entry(Reason::BlindlyTrusted, None));
+        pending.insert(
+            &[2],
+            entry(Reason::GracePeriod, Some(now + TimeDelta::days(1))),
+        );
+        pending.insert(
+            &[3],
+            entry(Reason::GracePeriod, Some(now - TimeDelta::days(1))),
+        );
+        pending.insert(
+            &[4],
+            entry(Reason::Deferred, Some(now + TimeDelta::days(1))),
+        );
+        pending.insert(
+            &[5],
+            entry(Reason::PartialResults, Some(now + TimeDelta::days(1))),
+        );
+        let counters = Counters {
+            verified: 5,
+            failed: 1,
+        };
+
+        assert_eq!(
+            Packages::new(&counters, &pending, now),
+            Packages {
+                verified: 5,
+                failed: 1,
+                grace: 2,
+                partial: 1,
+                blind: 1,
+                offline: 0,
+                deferred: 1,
+                overdue: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_rebuilders() {
+        let url = test_server::spawn_rebuilder().await;
+        let config = Config {
+            trusted_rebuilders: vec![
+                test_server::rebuilder(url, String::new()),
+                test_server::rebuilder("http://127.0.0.1:1/".parse().unwrap(), String::new()),
+            ],
+            ..Default::default()
+        };
+
+        let status = probe_rebuilders(&http::client(), &config).await;
+        assert!(status[0].reachable);
+        assert!(!status[1].reachable);
+        assert!(status[1].error.is_some());
+    }
+}
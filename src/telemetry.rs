@@ -0,0 +1,311 @@
+//! Tracing spans for the transports, exported to an OpenTelemetry collector (OTLP/HTTP json)
+//!
+//! Spans are only recorded if an endpoint is configured, and are sent in batches with `flush`.
+use crate::errors::*;
+use crate::http;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time;
+use url::Url;
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Drop spans instead of growing without bounds if the collector is unreachable
+const MAX_BUFFERED_SPANS: usize = 10_000;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Telemetry {
+    /// Send tracing spans to this OTLP/HTTP collector, e.g. `http://localhost:4318`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<Url>,
+    /// The `service.name` of the spans (default: `repro-threshold`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+}
+
+struct Exporter {
+    endpoint: Url,
+    service_name: String,
+    spans: Mutex<Vec<Finished>>,
+}
+
+static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+/// Start recording spans, if an endpoint is configured
+pub fn init(config: &Telemetry) -> Result<()> {
+    let Some(base) = &config.otlp_endpoint else {
+        return Ok(());
+    };
+    let mut endpoint = base.clone();
+    endpoint
+        .path_segments_mut()
+        .map_err(|_| anyhow!("Invalid OTLP endpoint: {base}"))?
+        .pop_if_empty()
+        .push("v1")
+        .push("traces");
+    let service_name = config
+        .service_name
+        .clone()
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string());
+
+    debug!("Exporting tracing spans to {endpoint}");
+    let _ = EXPORTER.set(Exporter {
+        endpoint,
+        service_name,
+        spans: Mutex::new(Vec::new()),
+    });
+    Ok(())
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut buf = [0; N];
+    SystemRandom::new()
+        .fill(&mut buf)
+        .expect("Failed to read random bytes");
+    buf
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    nanos.to_string()
+}
+
+/// The span new spans are started in, this is passed into spawned tasks with `scope`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Context {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+}
+
+tokio::task_local! {
+    static CURRENT: Context;
+}
+
+pub fn current() -> Option<Context> {
+    CURRENT.try_with(|context| *context).ok()
+}
+
+/// Run a future in the given span context, e.g. inside of a spawned task
+pub async fn scope<F: Future>(context: Option<Context>, future: F) -> F::Output {
+    match context {
+        Some(context) => CURRENT.scope(context, future).await,
+        None => future.await,
+    }
+}
+
+#[derive(Debug)]
+struct Finished {
+    name: &'static str,
+    context: Context,
+    parent: Option<[u8; 8]>,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, serde_json::Value)>,
+    error: Option<String>,
+}
+
+impl Finished {
+    fn to_json(&self) -> serde_json::Value {
+        let attributes = self
+            .attributes
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    serde_json::Value::Bool(value) => json!({ "boolValue": value }),
+                    serde_json::Value::Number(value) if value.is_i64() || value.is_u64() => {
+                        json!({ "intValue": value.to_string() })
+                    }
+                    serde_json::Value::Number(value) => json!({ "doubleValue": value }),
+                    serde_json::Value::String(value) => json!({ "stringValue": value }),
+                    value => json!({ "stringValue": value.to_string() }),
+                };
+                json!({ "key": key, "value": value })
+            })
+            .collect::<Vec<_>>();
+
+        let status = match &self.error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 0 }),
+        };
+
+        let mut span = json!({
+            "traceId": data_encoding::HEXLOWER.encode(&self.context.trace_id),
+            "spanId": data_encoding::HEXLOWER.encode(&self.context.span_id),
+            "name": self.name,
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(self.end),
+            "attributes": attributes,
+            "status": status,
+        });
+        if let Some(parent) = &self.parent {
+            span["parentSpanId"] = json!(data_encoding::HEXLOWER.encode(parent));
+        }
+        span
+    }
+}
+
+/// A span that ends when it's dropped, this does nothing if no exporter is configured
+#[must_use]
+pub struct Span(Option<Finished>);
+
+pub fn span(name: &'static str) -> Span {
+    if EXPORTER.get().is_none() {
+        return Span(None);
+    }
+    Span::start(name)
+}
+
+impl Span {
+    fn start(name: &'static str) -> Self {
+        let parent = current();
+        let context = Context {
+            trace_id: parent.map_or_else(random_id, |parent| parent.trace_id),
+            span_id: random_id(),
+        };
+        Span(Some(Finished {
+            name,
+            context,
+            parent: parent.map(|parent| parent.span_id),
+            start: SystemTime::now(),
+            end: SystemTime::now(),
+            attributes: Vec::new(),
+            error: None,
+        }))
+    }
+
+    pub fn attr<V: Into<serde_json::Value>>(&mut self, key: &'static str, value: V) {
+        if let Some(span) = &mut self.0 {
+            span.attributes.push((key, value.into()));
+        }
+    }
+
+    pub fn error(&mut self, err: &Error) {
+        if let Some(span) = &mut self.0 {
+            span.error = Some(format!("{err:#}"));
+        }
+    }
+
+    pub fn context(&self) -> Option<Context> {
+        self.0.as_ref().map(|span| span.context)
+    }
+
+    /// Run a future with this span as parent of all spans started inside of it
+    pub async fn instrument<F: Future>(&self, future: F) -> F::Output {
+        scope(self.context(), future).await
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let (Some(mut span), Some(exporter)) = (self.0.take(), EXPORTER.get()) else {
+            return;
+        };
+        span.end = SystemTime::now();
+        let mut spans = exporter.spans.lock().unwrap_or_else(|err| err.into_inner());
+        if spans.len() < MAX_BUFFERED_SPANS {
+            spans.push(span);
+        }
+    }
+}
+
+fn export_request(service_name: &str, spans: &[Finished]) -> serde_json::Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": service_name },
+                }],
+            },
+            "scopeSpans": [{
+                "scope": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "spans": spans.iter().map(Finished::to_json).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+/// Send all finished spans to the collector, failures are logged but not fatal
+pub async fn flush(http: &http::Client) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let spans = std::mem::take(&mut *exporter.spans.lock().unwrap_or_else(|err| err.into_inner()));
+    if spans.is_empty() {
+        return;
+    }
+
+    let body = export_request(&exporter.service_name, &spans);
+    let request = http.post(exporter.endpoint.clone()).json(&body).send();
+    let result = match time::timeout(EXPORT_TIMEOUT, request).await {
+        Ok(Ok(response)) => response.error_for_status().map(|_| ()).map_err(Error::from),
+        Ok(Err(err)) => Err(err.into()),
+        Err(_) => Err(anyhow!("Timeout after {EXPORT_TIMEOUT:?}")),
+    };
+    match result {
+        Ok(()) => trace!("Exported {} tracing spans", spans.len()),
+        Err(err) => warn!(
+            "Failed to export tracing spans to {}: {err:#}",
+            exporter.endpoint
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_span_parent() {
+        let parent = Span::start("acquire");
+        let context = parent.context().unwrap();
+
+        let child = parent
+            .instrument(async { Span::start("fetch_remote") })
+            .await;
+        let child = child.0.as_ref().unwrap();
+        assert_eq!(child.context.trace_id, context.trace_id);
+        assert_eq!(child.parent, Some(context.span_id));
+
+        let root = Span::start("acquire");
+        assert_ne!(root.context().unwrap().trace_id, context.trace_id);
+        assert_eq!(root.0.as_ref().unwrap().parent, None);
+    }
+
+    #[test]
+    fn test_export_request() {
+        let span = Finished {
+            name: "rebuilder_query",
+            context: Context {
+                trace_id: [0x11; 16],
+                span_id: [0x22; 8],
+            },
+            parent: Some([0x33; 8]),
+            start: UNIX_EPOCH + Duration::from_secs(1),
+            end: UNIX_EPOCH + Duration::from_millis(1500),
+            attributes: vec![("attestations", json!(2)), ("rebuilder", json!("x"))],
+            error: Some("Timeout".to_string()),
+        };
+        let request = export_request("repro-threshold", &[span]);
+        let span = &request["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "11".repeat(16));
+        assert_eq!(span["parentSpanId"], "33".repeat(8));
+        assert_eq!(span["startTimeUnixNano"], "1000000000");
+        assert_eq!(span["endTimeUnixNano"], "1500000000");
+        assert_eq!(
+            span["attributes"][0],
+            json!({ "key": "attestations", "value": { "intValue": "2" } })
+        );
+        assert_eq!(span["status"], json!({ "code": 2, "message": "Timeout" }));
+    }
+}
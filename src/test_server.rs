@@ -0,0 +1,101 @@
+//! Canned rebuilder and mirror responses for end-to-end tests
+use crate::inspect::deb::Deb;
+use crate::rebuilder::Rebuilder;
+use crate::serve;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::io::BufReader;
+use tokio::net::TcpListener;
+use url::Url;
+
+pub const DEB_PATH: &str = "test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.deb";
+pub const DEB_KEYRING: &str = include_str!("../test_data/reproduce-debian-net-amd64.pub");
+const DEB_ATTESTATION: &[u8] =
+    include_bytes!("../test_data/librust-as-slice-dev_0.2.1-1+b2_amd64.in-toto.link");
+
+pub fn deb() -> Deb {
+    Deb {
+        name: "librust-as-slice-dev".to_string(),
+        version: "0.2.1-1+b2".to_string(),
+        architecture: "amd64".to_string(),
+        source: "rust-as-slice".to_string(),
+        ..Default::default()
+    }
+}
+
+pub fn deb_sha256() -> Vec<u8> {
+    data_encoding::HEXLOWER
+        .decode(b"32be954941cdb42bce44c19ece910af04ece3a1acc9b79fa3e8ff735ffb511cd")
+        .unwrap()
+}
+
+pub fn rebuilder(url: Url, signing_keyring: String) -> Rebuilder {
+    Rebuilder {
+        name: "mock".to_string(),
+        url,
+        distributions: vec!["debian".to_string()],
+        country: None,
+        contact: None,
+        signing_keyring,
+        valid_until: None,
+        verified_fingerprint: None,
+    }
+}
+
+/// Start a server responding with canned bodies, routes are matched by path only
+pub async fn spawn(routes: BTreeMap<String, Vec<u8>>) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let routes = Arc::new(routes);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _addr) = listener.accept().await.unwrap();
+            let routes = Arc::clone(&routes);
+            tokio::spawn(async move {
+                let (reader, writer) = stream.into_split();
+                let Some((_method, target)) =
+                    serve::read_request(BufReader::new(reader)).await.unwrap()
+                else {
+                    return;
+                };
+                let path = target.split_once('?').map(|(p, _)| p).unwrap_or(&target);
+                match routes.get(path) {
+                    Some(body) => serve::write_response(writer, "200 OK", "", body).await,
+                    None => serve::write_response(writer, "404 Not Found", "", b"").await,
+                }
+                .unwrap();
+            });
+        }
+    });
+
+    format!("http://{addr}/").parse().unwrap()
+}
+
+/// A rebuilder that has reproduced the test .deb
+pub async fn spawn_rebuilder() -> Url {
+    let keyring = serde_json::json!({ "current": [DEB_KEYRING] });
+    let search = serde_json::json!({ "records": [{ "build_id": 1, "artifact_id": 1 }] });
+
+    spawn(BTreeMap::from([
+        (
+            "/api/v1/meta/public-keys".to_string(),
+            serde_json::to_vec(&keyring).unwrap(),
+        ),
+        (
+            "/api/v1/packages/binary".to_string(),
+            serde_json::to_vec(&search).unwrap(),
+        ),
+        (
+            "/api/v1/builds/1/artifacts/1/attestation".to_string(),
+            DEB_ATTESTATION.to_vec(),
+        ),
+    ]))
+    .await
+}
+
+/// A package mirror serving the test .deb
+pub async fn spawn_mirror() -> Url {
+    let deb = std::fs::read(DEB_PATH).unwrap();
+    spawn(BTreeMap::from([("/pool/test.deb".to_string(), deb)])).await
+}
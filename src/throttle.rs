@@ -0,0 +1,74 @@
+use crate::config::Network;
+use std::time::{Duration, Instant};
+
+/// A token bucket to limit the download rate, in bytes per second
+///
+/// The bucket holds at most one second worth of data, so short pauses don't allow large bursts.
+#[derive(Debug)]
+pub struct Throttle {
+    rate: u64,
+    available: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    pub fn new(rate: u64) -> Self {
+        Throttle {
+            rate,
+            available: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// The download limit of the config, if any
+    pub fn for_downloads(network: &Network) -> Option<Self> {
+        network
+            .download_limit_kib
+            .filter(|limit| *limit > 0)
+            .map(|limit| Throttle::new(limit.saturating_mul(1024)))
+    }
+
+    /// Take `n` bytes from the bucket, returns how long to wait until they may be used
+    fn take(&mut self, n: usize, now: Instant) -> Duration {
+        let rate = self.rate as f64;
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.available = (self.available + elapsed * rate).min(rate);
+        self.available -= n as f64;
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / rate)
+        }
+    }
+
+    /// Wait until `n` bytes may be used
+    pub async fn consume(&mut self, n: usize) {
+        let delay = self.take(n, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let mut throttle = Throttle::new(1000);
+        let now = throttle.last;
+        // The first second is available immediately
+        assert_eq!(throttle.take(1000, now), Duration::ZERO);
+        assert_eq!(throttle.take(500, now), Duration::from_millis(500));
+        // The debt is paid back over time
+        let now = now + Duration::from_millis(500);
+        assert_eq!(throttle.take(0, now), Duration::ZERO);
+        let now = now + Duration::from_millis(250);
+        assert_eq!(throttle.take(500, now), Duration::from_millis(250));
+        // Idle time doesn't accumulate more than one second
+        let now = now + Duration::from_secs(60);
+        assert_eq!(throttle.take(2000, now), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,216 @@
+//! Trust on first use: pin the keyring a rebuilder serves on first contact, like SSH's known_hosts
+//!
+//! Pins are kept in the state directory, which the apt method can write, unlike the config
+//! file. Once pinned, a different keyring is never accepted silently. It has to be removed with
+//! `plumbing forget-key` first.
+use crate::config::Config;
+use crate::errors::*;
+use crate::http;
+use crate::signing;
+use crate::state::{KeyChange, KeyChanges, Pins, Store};
+use chrono::Utc;
+use futures::future;
+use url::Url;
+
+/// Compare a pinned keyring with the one a rebuilder currently serves
+pub fn compare(pinned: &str, served: &str) -> Result<Option<KeyChange>> {
+    if pinned.is_empty() {
+        return Ok(None);
+    }
+    let pinned =
+        signing::pem_fingerprints(pinned.as_bytes()).context("Failed to parse pinned keyring")?;
+    let served =
+        signing::pem_fingerprints(served.as_bytes()).context("Failed to parse served keyring")?;
+    Ok((pinned != served).then(|| KeyChange {
+        pinned,
+        served,
+        detected_at: Utc::now(),
+    }))
+}
+
+/// Make sure this can't be overlooked in the logs
+fn alarm(url: &Url, change: &KeyChange) {
+    error!("@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@");
+    error!("@        WARNING: REBUILDER SIGNING KEY HAS CHANGED!       @");
+    error!("@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@");
+    error!(
+        "The rebuilder {url} serves a different signing keyring than the one trusted on first use."
+    );
+    error!("Someone could be impersonating the rebuilder, or it rotated its keys.");
+    for fingerprint in &change.pinned {
+        error!("Pinned key: sha256={fingerprint}");
+    }
+    for fingerprint in &change.served {
+        error!("Served key: sha256={fingerprint}");
+    }
+    error!("If this change is expected, run `repro-threshold plumbing forget-key {url}`");
+}
+
+/// Pin the keyrings of trusted rebuilders that have none yet, and check all others
+///
+/// Returns the rebuilders whose keyring changed, they are also recorded in the state directory.
+/// A keyring that can't be pinned isn't used, the rebuilder can't confirm any package then.
+pub async fn check(http: &http::Client, config: &mut Config) -> Vec<Url> {
+    let served = future::join_all(
+        config
+            .trusted_rebuilders
+            .iter()
+            .map(|rebuilder| http.fetch_signing_keyring(&rebuilder.url)),
+    )
+    .await;
+    let pins = match Pins::load().await {
+        Ok(pins) => pins,
+        Err(err) => {
+            warn!("Failed to load keyrings trusted on first use: {err:#}");
+            Pins::default()
+        }
+    };
+
+    let mut changed = Vec::new();
+    for (rebuilder, served) in config.trusted_rebuilders.iter_mut().zip(served) {
+        let url = rebuilder.url.to_string();
+        // Keyrings pinned in the config file take precedence
+        if rebuilder.signing_keyring.is_empty()
+            && let Some(pinned) = pins.keyrings.get(&url)
+        {
+            rebuilder.signing_keyring = pinned.clone();
+        }
+
+        let served = match served {
+            Ok(served) => served,
+            Err(err) => {
+                warn!(
+                    "Failed to fetch signing keyring of {}: {err:#}",
+                    rebuilder.url
+                );
+                continue;
+            }
+        };
+        if rebuilder.signing_keyring.is_empty() {
+            match pin(&url, &served).await {
+                Ok(()) => {
+                    info!("Trusting signing keyring of {} on first use", rebuilder.url);
+                    rebuilder.signing_keyring = served;
+                }
+                Err(err) => warn!(
+                    "Not trusting signing keyring of {} on first use: {err:#}",
+                    rebuilder.url
+                ),
+            }
+            continue;
+        }
+        match compare(&rebuilder.signing_keyring, &served) {
+            Ok(None) => (),
+            Ok(Some(change)) => {
+                alarm(&rebuilder.url, &change);
+                KeyChanges::record(&rebuilder.url, change).await;
+                changed.push(rebuilder.url.clone());
+            }
+            Err(err) => {
+                // Not something that can be compared is not the pinned keyring either
+                error!("Failed to compare signing keyring of {url}: {err:#}");
+                changed.push(rebuilder.url.clone());
+            }
+        }
+    }
+    changed
+}
+
+/// Remember a keyring, only keyrings that were persisted are trusted
+async fn pin(url: &str, keyring: &str) -> Result<()> {
+    let fingerprints = signing::pem_fingerprints(keyring.as_bytes())?;
+    if fingerprints.is_empty() {
+        bail!("The keyring contains no public keys");
+    }
+    Pins::try_update(|pins| {
+        pins.keyrings.insert(url.to_string(), keyring.to_string());
+    })
+    .await
+}
+
+/// Check the keyrings if trust on first use is enabled, and refuse to go on if any changed
+pub async fn enforce(http: &http::Client, config: &mut Config) -> Result<()> {
+    if !config.trust_on_first_use {
+        return Ok(());
+    }
+    let changed = check(http, config).await;
+    if !changed.is_empty() {
+        let urls = changed.iter().map(Url::as_str).collect::<Vec<_>>();
+        bail!(
+            "Signing keyring of trusted rebuilders changed, refusing to verify: {}",
+            urls.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::client;
+    use crate::test_server;
+
+    const OTHER_KEYRING: &str = "-----BEGIN PUBLIC KEY-----\nMCowBQYDK2VwAyEAGb9ECWmEzf6FQbrBZ9w7lshQhqowtrbLDFw4rXAxZuE=\n-----END PUBLIC KEY-----\n";
+
+    #[test]
+    fn test_compare() {
+        let other = OTHER_KEYRING;
+        assert_eq!(compare("", test_server::DEB_KEYRING).unwrap(), None);
+        assert_eq!(
+            compare(test_server::DEB_KEYRING, test_server::DEB_KEYRING).unwrap(),
+            None
+        );
+        let change = compare(test_server::DEB_KEYRING, other).unwrap().unwrap();
+        assert_ne!(change.pinned, change.served);
+        assert_eq!(change.served.len(), 1);
+        assert!(
+            compare(
+                test_server::DEB_KEYRING,
+                "-----BEGIN PUBLIC KEY-----\nAAAA\n-----END PRIVATE KEY-----\n"
+            )
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_key_changed() {
+        let url = test_server::spawn_rebuilder().await;
+        let mut config = Config {
+            trust_on_first_use: true,
+            trusted_rebuilders: vec![test_server::rebuilder(
+                url.clone(),
+                OTHER_KEYRING.to_string(),
+            )],
+            ..Default::default()
+        };
+
+        let changed = check(&client(), &mut config).await;
+        assert_eq!(changed, std::slice::from_ref(&url));
+        // The pinned keyring is kept
+        assert_eq!(config.trusted_rebuilders[0].signing_keyring, OTHER_KEYRING);
+        let changes = KeyChanges::load().await.unwrap();
+        assert!(changes.rebuilders.contains_key(url.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_check_pins_in_state() {
+        let url = test_server::spawn_rebuilder().await;
+        let config = || Config {
+            trust_on_first_use: true,
+            trusted_rebuilders: vec![test_server::rebuilder(url.clone(), String::new())],
+            ..Default::default()
+        };
+
+        let mut first = config();
+        assert!(check(&client(), &mut first).await.is_empty());
+        let pinned = first.trusted_rebuilders[0].signing_keyring.clone();
+        assert!(!pinned.is_empty());
+
+        // The next run starts with the same config file, the pin comes from the state
+        let mut second = config();
+        assert!(enforce(&client(), &mut second).await.is_ok());
+        assert_eq!(second.trusted_rebuilders[0].signing_keyring, pinned);
+        let pins = Pins::load().await.unwrap();
+        assert_eq!(pins.keyrings.get(url.as_str()), Some(&pinned));
+    }
+}
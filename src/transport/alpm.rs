@@ -1,6 +1,591 @@
+//! pacman's `XferCommand`, packages are only written completely once they're verified
+//!
+//! pacman checks the gpg signatures of packages and databases itself, `.sig` files are
+//! downloaded untouched.
+//!
+//! The hook mode runs as a `PreTransaction` hook instead, it checks the files pacman
+//! already downloaded into its cache and fails the hook to abort the transaction.
+use crate::args::TransportOptions;
+use crate::attestation;
+use crate::audit;
+use crate::clock;
 use crate::config::Config;
+use crate::credentials::Redacted;
+use crate::disk;
 use crate::errors::*;
+use crate::http;
+use crate::inspect::deb::Deb;
+use crate::notify::{self, Event};
+use crate::policy;
+use crate::state::{self, Store};
+use crate::throttle::Throttle;
+use crate::tofu;
+use crate::withhold;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use url::Url;
 
-pub async fn run(_config: Config) -> Result<()> {
-    todo!("alpm")
+#[derive(Debug, PartialEq)]
+pub enum Kind {
+    Package(Box<Deb>),
+    /// A detached gpg signature, verified by pacman
+    Signature,
+    /// Databases and anything else pacman downloads
+    Other,
+}
+
+impl Kind {
+    pub fn from_url(url: &Url) -> Self {
+        let filename = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default();
+        if filename.ends_with(".sig") {
+            Kind::Signature
+        } else if let Some(package) = parse_filename(filename) {
+            Kind::Package(Box::new(package))
+        } else {
+            Kind::Other
+        }
+    }
+}
+
+/// Parse a package filename like `filesystem-2025.10.12-1-any.pkg.tar.zst`
+pub fn parse_filename(filename: &str) -> Option<Deb> {
+    // The epoch of a version is percent-encoded in urls
+    let filename = filename.replace("%3A", ":").replace("%3a", ":");
+    let (stem, _compression) = filename.split_once(".pkg.tar")?;
+    let mut parts = stem.rsplitn(4, '-');
+    let architecture = parts.next()?;
+    let pkgrel = parts.next()?;
+    let pkgver = parts.next()?;
+    let name = parts.next().filter(|name| !name.is_empty())?;
+    Some(Deb {
+        name: name.to_string(),
+        version: format!("{pkgver}-{pkgrel}"),
+        architecture: architecture.to_string(),
+        // The pkgbase isn't part of the filename
+        source: name.to_string(),
+        ..Default::default()
+    })
+}
+
+async fn download<W: tokio::io::AsyncWrite + Unpin>(
+    http: &http::Client,
+    config: &Config,
+    url: &Url,
+    path: &Path,
+    file: &mut withhold::Writer<W>,
+) -> Result<()> {
+    let mut response = http
+        .get(url.clone())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Failed to download {url}"))?;
+    http::log_redirect(url, &response);
+    if let Some(size) = response.content_length() {
+        disk::check_space(path, size, config.storage.min_free())?;
+    }
+    let mut throttle = Throttle::for_downloads(&config.network);
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .with_context(|| format!("Failed to download {url}"))?
+    {
+        if let Some(throttle) = &mut throttle {
+            throttle.consume(chunk.len()).await;
+        }
+        file.write_all(chunk).await?;
+    }
+    Ok(())
+}
+
+async fn verify(
+    http: &http::Client,
+    config: &Config,
+    rebuilders: Vec<Url>,
+    package: Deb,
+    sha256: &[u8],
+    origin: &str,
+) -> Result<()> {
+    let record = |outcome| audit::Record {
+        package: Some(package.clone()),
+        sha256: Some(data_encoding::HEXLOWER.encode(sha256)),
+        ..audit::Record::new(origin, outcome)
+    };
+    let engine = policy::Engine::new(config);
+    if engine.blindly_trusts(&package) {
+        info!("Blindly trusting {} {}", package.name, package.version);
+        state::Admitted::record(&package).await;
+        audit::append(&config.audit, &record(audit::Outcome::BlindlyTrusted)).await;
+        return Ok(());
+    }
+
+    let deadline = config
+        .rules
+        .max_verification_time
+        .map(|budget| Instant::now() + budget.0);
+    let remote =
+        attestation::fetch_remote_until(http, rebuilders, package.clone(), deadline, |tree| {
+            engine.threshold_reached(Some(&package), sha256, tree)
+        })
+        .await;
+    let decision = engine.decide(Some(&package), sha256, &remote);
+    debug!("Decision: {}", serde_json::to_string(&decision)?);
+
+    if decision.outcome == policy::Outcome::Rejected {
+        let event = Event::Rejected {
+            package: package.clone(),
+            sha256: data_encoding::HEXLOWER.encode(sha256),
+            confirms: decision.confirms(),
+            threshold: decision.threshold,
+        };
+        notify::dispatch(http, &config.notify, &event).await;
+        if decision.is_deferred() {
+            // Not installed, but recorded so cron reports once it can be verified
+            if let Ok(now) = clock::now() {
+                let entry = state::Entry::pending(
+                    package.clone(),
+                    state::Reason::Deferred,
+                    now,
+                    config.rules.grace_period_days,
+                );
+                if let Err(err) = state::Pending::record(sha256, entry).await {
+                    warn!("{err:#}");
+                }
+            }
+        }
+        state::Counters::update(|counters| counters.failed += 1).await;
+        audit::append(
+            &config.audit,
+            &audit::Record {
+                rebuilders: decision.rebuilders(policy::Ballot::Confirm),
+                message: Some(decision.message()),
+                ..record(audit::Outcome::Rejected)
+            },
+        )
+        .await;
+        bail!(
+            "Refusing {} {}: {}",
+            package.name,
+            package.version,
+            decision.message()
+        );
+    }
+    let reason = match decision.outcome {
+        policy::Outcome::GracePeriod => Some(state::Reason::GracePeriod),
+        policy::Outcome::PartialResults => Some(state::Reason::PartialResults),
+        policy::Outcome::Offline => Some(state::Reason::Offline),
+        _ => None,
+    };
+    if let Some(reason) = reason {
+        if decision.outcome == policy::Outcome::Offline {
+            warn!("{}", decision.message());
+        }
+        // Without a record, cron can't re-verify the package later, so refuse it instead
+        let now = clock::now()
+            .map_err(|problem| anyhow!("Refusing to admit a package, the {problem}"))?;
+        let entry =
+            state::Entry::pending(package.clone(), reason, now, config.rules.grace_period_days);
+        state::Pending::record(sha256, entry)
+            .await
+            .context("Refusing to admit a package that can't be tracked for re-verification")?;
+    } else {
+        state::Counters::update(|counters| counters.verified += 1).await;
+    }
+    state::Admitted::record(&package).await;
+    let outcome = match decision.outcome {
+        policy::Outcome::GracePeriod
+        | policy::Outcome::PartialResults
+        | policy::Outcome::Offline => audit::Outcome::GracePeriod,
+        policy::Outcome::BlindlyTrusted => audit::Outcome::BlindlyTrusted,
+        _ => audit::Outcome::Reproduced,
+    };
+    audit::append(
+        &config.audit,
+        &audit::Record {
+            rebuilders: decision.rebuilders(policy::Ballot::Confirm),
+            message: Some(decision.message()),
+            ..record(outcome)
+        },
+    )
+    .await;
+    info!(
+        "Verified {} {}: {}",
+        package.name,
+        package.version,
+        decision.message()
+    );
+    Ok(())
+}
+
+/// Check the package with pacman's keyring, so the signed package is the one that was reproduced
+async fn check_signature(
+    http: &http::Client,
+    config: &Config,
+    url: &Url,
+    path: &Path,
+) -> Result<()> {
+    let url = format!("{url}.sig")
+        .parse::<Url>()
+        .context("Invalid signature url")?;
+    let sig = PathBuf::from(format!("{}.repro-threshold.sig", path.display()));
+
+    let result = async {
+        let file = File::create(&sig)
+            .await
+            .with_context(|| format!("Failed to create file: {sig:?}"))?;
+        let mut file = withhold::Writer::new(file);
+        download(http, config, &url, &sig, &mut file).await?;
+        file.finalize().await?;
+
+        let status = Command::new("pacman-key")
+            .arg("--verify")
+            .arg(&sig)
+            .arg(path)
+            .status()
+            .await
+            .context("Failed to execute pacman-key")?;
+        if !status.success() {
+            bail!("The signature {url} doesn't match the reproduced package: {status}");
+        }
+        Ok(())
+    }
+    .await;
+    let _ = fs::remove_file(&sig).await;
+    result
+}
+
+pub async fn run(
+    mut config: Config,
+    http: http::Client,
+    options: &TransportOptions,
+    url: Url,
+    output: &Path,
+    verify_signature: bool,
+) -> Result<()> {
+    let file = File::create(output)
+        .await
+        .with_context(|| format!("Failed to create file: {output:?}"))?;
+    let mut file = withhold::Writer::new(file);
+
+    let result = async {
+        info!("Downloading {}", Redacted(&url));
+        download(&http, &config, &url, output, &mut file).await?;
+        if let Kind::Package(package) = Kind::from_url(&url) {
+            tofu::enforce(&http, &mut config).await?;
+            let rebuilders = config.rebuilder_urls_or(options.rebuilders.clone());
+            verify(
+                &http,
+                &config,
+                rebuilders,
+                *package,
+                &file.sha256(),
+                url.as_str(),
+            )
+            .await?;
+            // Only write the final chunk once verified
+            file.finalize().await?;
+            if verify_signature {
+                check_signature(&http, &config, &url, output).await?;
+            }
+        } else {
+            file.finalize().await?;
+        }
+        Ok(())
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = fs::remove_file(output).await;
+    }
+    result
+}
+
+/// The default `CacheDir` of pacman
+pub const PACMAN_CACHE_DIR: &str = "/var/cache/pacman/pkg";
+
+/// Parse the output of `pacman -Sp --print-format '%n %v %a'`
+fn parse_print(output: &str) -> Vec<Deb> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(version), Some(architecture), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return None;
+            };
+            Some(Deb {
+                name: name.to_string(),
+                version: version.to_string(),
+                architecture: architecture.to_string(),
+                source: name.to_string(),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Ask pacman which version of each target the transaction installs
+///
+/// Printing doesn't take the database lock, so this works while the transaction is running.
+async fn transaction_targets(targets: &[String]) -> Result<Vec<Deb>> {
+    let output = Command::new("pacman")
+        .args(["-Sp", "--print-format", "%n %v %a", "--"])
+        .args(targets)
+        .output()
+        .await
+        .context("Failed to execute pacman")?;
+    if !output.status.success() {
+        bail!(
+            "pacman failed to resolve the transaction ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let output = String::from_utf8(output.stdout).context("pacman output is not valid utf-8")?;
+    // Dependencies are printed too, only keep what the hook was asked about
+    Ok(parse_print(&output)
+        .into_iter()
+        .filter(|package| targets.contains(&package.name))
+        .collect())
+}
+
+/// Find the package file of each target, with the same name and version
+pub async fn find_cached(cache_dirs: &[PathBuf], targets: &[Deb]) -> Result<Vec<(Deb, PathBuf)>> {
+    let mut candidates = Vec::new();
+    for dir in cache_dirs {
+        let mut entries = match fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read cache dir: {dir:?}"));
+            }
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let filename = entry.file_name();
+            let Some(filename) = filename.to_str() else {
+                continue;
+            };
+            if filename.ends_with(".sig") || filename.ends_with(".part") {
+                continue;
+            }
+            let Some(package) = parse_filename(filename) else {
+                continue;
+            };
+            if !targets
+                .iter()
+                .any(|target| target.name == package.name && target.version == package.version)
+            {
+                continue;
+            }
+            candidates.push((package, entry.path()));
+        }
+    }
+
+    let mut found = Vec::new();
+    for target in targets {
+        let Some((package, path)) = candidates
+            .iter()
+            .find(|(p, _)| p.name == target.name && p.version == target.version)
+        else {
+            bail!(
+                "Failed to find package file for {:?} {:?} in {cache_dirs:?}",
+                target.name,
+                target.version
+            );
+        };
+        found.push((package.clone(), path.clone()));
+    }
+    Ok(found)
+}
+
+/// Verify the package files of a transaction, the package names are read from stdin
+pub async fn hook(
+    mut config: Config,
+    http: http::Client,
+    options: &TransportOptions,
+    cache_dirs: &[PathBuf],
+) -> Result<()> {
+    // Once per transaction, the XferCommand runs for every single file
+    disk::prune_cache(&config).await;
+    let mut targets = Vec::new();
+    let mut lines = BufReader::new(io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if !line.is_empty() {
+            targets.push(line.to_string());
+        }
+    }
+
+    tofu::enforce(&http, &mut config).await?;
+    let targets = transaction_targets(&targets).await?;
+    let rebuilders = config.rebuilder_urls_or(options.rebuilders.clone());
+
+    let mut failed = 0;
+    for (package, path) in find_cached(cache_dirs, &targets).await? {
+        let sha256 = attestation::sha256_path(&path).await?;
+        let origin = path.to_string_lossy();
+        if let Err(err) = verify(
+            &http,
+            &config,
+            rebuilders.clone(),
+            package,
+            &sha256,
+            &origin,
+        )
+        .await
+        {
+            error!("{err:#}");
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "{failed} of {} packages failed verification, aborting transaction",
+            targets.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[test]
+    fn test_kind_from_url() {
+        let url =
+            "https://geo.mirror.pkgbuild.com/core/os/x86_64/python-1%3A3.13.1-1-x86_64.pkg.tar.zst"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            Kind::from_url(&url),
+            Kind::Package(Box::new(Deb {
+                name: "python".to_string(),
+                version: "1:3.13.1-1".to_string(),
+                architecture: "x86_64".to_string(),
+                source: "python".to_string(),
+                ..Default::default()
+            }))
+        );
+
+        let url = "https://geo.mirror.pkgbuild.com/core/os/x86_64/lib32-gcc-libs-14.2.1-1-x86_64.pkg.tar.zst.sig"
+            .parse()
+            .unwrap();
+        assert_eq!(Kind::from_url(&url), Kind::Signature);
+
+        let url = "https://geo.mirror.pkgbuild.com/core/os/x86_64/core.db"
+            .parse()
+            .unwrap();
+        assert_eq!(Kind::from_url(&url), Kind::Other);
+        assert_eq!(parse_filename("foo-1-x86_64.pkg.tar.zst"), None);
+    }
+
+    #[tokio::test]
+    async fn test_signature_passthrough() {
+        let sig = b"-----BEGIN PGP SIGNATURE-----".to_vec();
+        let mirror = test_server::spawn(
+            [
+                ("/foo.pkg.tar.zst.sig".to_string(), sig.clone()),
+                ("/foo-1.0-1-x86_64.pkg.tar.zst".to_string(), b"pkg".to_vec()),
+            ]
+            .into(),
+        )
+        .await;
+        let dir =
+            std::env::temp_dir().join(format!("repro-threshold-test-alpm-{}", std::process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let output = dir.join("foo.pkg.tar.zst.sig.part");
+
+        let url = mirror.join("foo.pkg.tar.zst.sig").unwrap();
+        let config = Config::default();
+        let options = TransportOptions::default();
+        run(config, http::client(), &options, url, &output, true)
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&output).await.unwrap(), sig);
+
+        // Packages nobody reproduced are removed again
+        let url = mirror.join("foo-1.0-1-x86_64.pkg.tar.zst").unwrap();
+        let output = dir.join("foo-1.0-1-x86_64.pkg.tar.zst.part");
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        assert!(
+            run(config, http::client(), &options, url, &output, false)
+                .await
+                .is_err()
+        );
+        assert!(!fs::try_exists(&output).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_find_cached() {
+        let dir = std::env::temp_dir().join(format!(
+            "repro-threshold-test-alpm-cache-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).await.unwrap();
+        for filename in [
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            "foo-1.1-1-x86_64.pkg.tar.zst",
+            "foo-1.1-1-x86_64.pkg.tar.zst.sig",
+            "bar-2.0-1-any.pkg.tar.zst",
+        ] {
+            std::fs::File::create(dir.join(filename)).unwrap();
+        }
+        let target = |name: &str, version: &str| Deb {
+            name: name.to_string(),
+            version: version.to_string(),
+            ..Default::default()
+        };
+
+        // Not the newest file, the version of the transaction
+        let found = find_cached(std::slice::from_ref(&dir), &[target("foo", "1.0-1")])
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.version, "1.0-1");
+        assert_eq!(found[0].0.architecture, "x86_64");
+        assert_eq!(found[0].1, dir.join("foo-1.0-1-x86_64.pkg.tar.zst"));
+
+        assert!(
+            find_cached(std::slice::from_ref(&dir), &[target("foo", "1.2-1")])
+                .await
+                .is_err()
+        );
+        assert!(
+            find_cached(
+                std::slice::from_ref(&dir),
+                &[target("foo", "1.1-1"), target("baz", "1.0-1")]
+            )
+            .await
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_print() {
+        let packages = parse_print("python 1:3.13.1-1 x86_64\nbar 2.0-1 any\n\n")
+            .into_iter()
+            .map(|p| (p.name, p.version, p.architecture))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            packages,
+            [
+                (
+                    "python".to_string(),
+                    "1:3.13.1-1".to_string(),
+                    "x86_64".to_string()
+                ),
+                ("bar".to_string(), "2.0-1".to_string(), "any".to_string()),
+            ]
+        );
+    }
 }
@@ -1,13 +1,38 @@
 use crate::attestation;
+use crate::audit;
+use crate::clock;
 use crate::config::Config;
+use crate::credentials::Redacted;
+use crate::disk;
 use crate::errors::*;
 use crate::http;
-use crate::inspect;
-use crate::signing::DomainTree;
+use crate::i18n::tr;
+use crate::inspect::{self, deb::Deb};
+use crate::layout;
+use crate::notify::{self, Event};
+use crate::policy;
+use crate::sbom;
+use crate::state::{self, Store};
+use crate::stats::Stats;
+use crate::telemetry;
+use crate::throttle::Throttle;
+use crate::tofu;
+use crate::transport::worker;
 use crate::withhold;
+use bytes::Bytes;
+use chrono::Utc;
+use reqwest::StatusCode;
+use reqwest::header::{IF_RANGE, RANGE};
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use tokio::fs::File;
-use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, BufReader};
+use std::fmt;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncSeekExt, BufReader};
+use tokio::sync::{mpsc, watch};
+use tokio::time;
 use url::Url;
 
 #[derive(Debug, Default)]
@@ -41,9 +66,42 @@ impl Request {
         }
     }
 
+    /// The checksums apt expects from the `Expected-*` headers
+    fn expected(&self) -> Result<Expected> {
+        let sha256 = self
+            .headers
+            .get("Expected-SHA256")
+            .map(|hex| {
+                data_encoding::HEXLOWER_PERMISSIVE
+                    .decode(hex.as_bytes())
+                    .with_context(|| format!("Invalid `Expected-SHA256` header: {hex:?}"))
+            })
+            .transpose()?;
+        let size = self
+            .headers
+            .get("Expected-Checksum-FileSize")
+            .map(|size| {
+                size.parse().with_context(|| {
+                    format!("Invalid `Expected-Checksum-FileSize` header: {size:?}")
+                })
+            })
+            .transpose()?;
+        Ok(Expected { sha256, size })
+    }
+
+    /// Debian installer components, they're verified like .debs
+    fn is_udeb(&self) -> bool {
+        self.headers.get("Target-Type").is_some_and(|t| t == "udeb")
+            || ["URI", "Filename"].iter().any(|key| {
+                self.headers
+                    .get(*key)
+                    .is_some_and(|value| value.ends_with(".udeb"))
+            })
+    }
+
     fn needs_verification(&self) -> bool {
         match self.headers.get("Target-Type").map(String::as_str) {
-            Some("deb") | None => true,
+            Some("deb") | Some("udeb") | None => true,
             Some("index") => false,
             // We don't recognize this type, but it doesn't seem to be a .deb so should be fine
             Some(_other) => false,
@@ -51,14 +109,295 @@ impl Request {
     }
 }
 
+/// How long to wait for rebuilders, unless configured otherwise
+const VERIFICATION_TIMEOUT_SECS: u64 = 300;
+
+/// Save the hashing state of large downloads every 64 MiB
+const CHECKPOINT_INTERVAL: u64 = 64 * 1024 * 1024;
+
+/// Stored next to a partial download, so an interrupted download doesn't start over from byte zero
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Resume {
+    url: Url,
+    /// Sent as `If-Range`, so the download starts over if the file has changed on the mirror
+    last_modified: String,
+    #[serde(flatten)]
+    checkpoint: withhold::Checkpoint,
+}
+
+impl Resume {
+    fn path(filename: &str) -> PathBuf {
+        PathBuf::from(format!("{filename}.checkpoint"))
+    }
+
+    /// Load the checkpoint of a previous download of this url, if any
+    async fn load(path: &Path, url: &Url) -> Option<Self> {
+        let buf = fs::read(path).await.ok()?;
+        let resume = serde_json::from_slice::<Self>(&buf)
+            .inspect_err(|err| debug!("Ignoring invalid checkpoint {path:?}: {err:#}"))
+            .ok()?;
+        (resume.url == *url).then_some(resume)
+    }
+
+    async fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec(self)?;
+        fs::write(path, json)
+            .await
+            .with_context(|| format!("Failed to write checkpoint: {path:?}"))
+    }
+
+    async fn remove(path: &Path) {
+        if let Err(err) = fs::remove_file(path).await
+            && err.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove checkpoint {path:?}: {err:#}");
+        }
+    }
+}
+
+/// Checksums provided by apt from the signed repository metadata
+#[derive(Debug, Default, PartialEq)]
+struct Expected {
+    sha256: Option<Vec<u8>>,
+    size: Option<u64>,
+}
+
+impl Expected {
+    /// Fail early if the mirror sends more data than expected
+    fn check_size(&self, size: u64) -> Result<(), Failure> {
+        if let Some(expected) = self.size
+            && size > expected
+        {
+            return Err(Failure::HashMismatch(format!(
+                "expected {expected} bytes, received more"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Make sure the download matches, before spending any time on rebuilder checks
+    fn check(&self, size: u64, sha256: &[u8]) -> Result<(), Failure> {
+        if let Some(expected) = self.size
+            && size != expected
+        {
+            return Err(Failure::HashMismatch(format!(
+                "expected {expected} bytes, received {size}"
+            )));
+        }
+        if let Some(expected) = &self.sha256
+            && sha256 != expected.as_slice()
+        {
+            return Err(Failure::HashMismatch(format!(
+                "expected sha256 {}, received {}",
+                data_encoding::HEXLOWER.encode(expected),
+                data_encoding::HEXLOWER.encode(sha256)
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Why a package was not admitted, shown by apt
+#[derive(Debug, PartialEq)]
+enum Failure {
+    Download(String),
+    HashMismatch(String),
+    RebuildersUnreachable {
+        unreachable: usize,
+        confirms: usize,
+        threshold: usize,
+    },
+    Conflicting {
+        rebuilders: Vec<String>,
+        confirms: usize,
+        threshold: usize,
+    },
+    DeadlineReached {
+        pending: usize,
+        confirms: usize,
+        threshold: usize,
+    },
+    NoAttestations {
+        threshold: usize,
+    },
+    ThresholdNotMet {
+        confirms: usize,
+        threshold: usize,
+    },
+    OutOfBandVerifiedNotMet {
+        verified: usize,
+        required: usize,
+    },
+    /// None of the trusted rebuilders could be reached, recorded for `repro-threshold cron`
+    Deferred {
+        unreachable: usize,
+    },
+    Timeout {
+        secs: u64,
+    },
+    Layout(String),
+    NoSpace(disk::NoSpace),
+    /// A grace period was needed, but the system clock is implausible
+    Clock(clock::Problem),
+    /// A grace period was needed, but the package couldn't be recorded for `repro-threshold cron`
+    Untracked(String),
+    /// Trusted rebuilders serve a different keyring than the one trusted on first use
+    KeyChanged {
+        rebuilders: Vec<String>,
+    },
+}
+
+impl Failure {
+    /// The most specific reason of a rejection
+    fn from_decision(decision: &policy::Decision) -> Self {
+        let (confirms, threshold) = (decision.confirms(), decision.threshold);
+        if decision.is_deferred() {
+            let unreachable = decision
+                .reasons
+                .iter()
+                .find_map(|reason| match reason {
+                    policy::Reason::RebuildersUnreachable { count } => Some(*count),
+                    _ => None,
+                })
+                .unwrap_or_default();
+            return Failure::Deferred { unreachable };
+        }
+        match decision.reasons.first() {
+            Some(policy::Reason::Conflicting { rebuilders }) => Failure::Conflicting {
+                rebuilders: rebuilders.clone(),
+                confirms,
+                threshold,
+            },
+            Some(policy::Reason::RebuildersUnreachable { count }) => {
+                Failure::RebuildersUnreachable {
+                    unreachable: *count,
+                    confirms,
+                    threshold,
+                }
+            }
+            Some(policy::Reason::DeadlineReached { count }) => Failure::DeadlineReached {
+                pending: *count,
+                confirms,
+                threshold,
+            },
+            Some(policy::Reason::NoAttestations) => Failure::NoAttestations { threshold },
+            Some(policy::Reason::OutOfBandVerifiedNotMet { verified, required }) => {
+                Failure::OutOfBandVerifiedNotMet {
+                    verified: *verified,
+                    required: *required,
+                }
+            }
+            _ => Failure::ThresholdNotMet {
+                confirms,
+                threshold,
+            },
+        }
+    }
+
+    /// The `FailReason` field of the apt method protocol
+    fn reason(&self) -> &'static str {
+        match self {
+            Failure::Download(_) => "DownloadFailed",
+            // apt recognizes this one and may retry with a different mirror
+            Failure::HashMismatch(_) => "HashSumMismatch",
+            Failure::RebuildersUnreachable { .. } => "RebuildersUnreachable",
+            Failure::Conflicting { .. } => "ConflictingAttestations",
+            Failure::DeadlineReached { .. } => "DeadlineReached",
+            Failure::NoAttestations { .. } => "NoAttestations",
+            Failure::ThresholdNotMet { .. } => "ThresholdNotMet",
+            Failure::OutOfBandVerifiedNotMet { .. } => "OutOfBandVerifiedNotMet",
+            Failure::Deferred { .. } => "RebuildersOffline",
+            Failure::Timeout { .. } => "Timeout",
+            Failure::Layout(_) => "LayoutVerificationFailed",
+            Failure::NoSpace(_) => "NoSpaceLeft",
+            Failure::Clock(_) => "ClockImplausible",
+            Failure::Untracked(_) => "PendingNotRecorded",
+            Failure::KeyChanged { .. } => "RebuilderKeyChanged",
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Failure::Download(err) => tr!("fail-download", err = err),
+            Failure::HashMismatch(err) => tr!("fail-hash-mismatch", err = err),
+            Failure::RebuildersUnreachable {
+                unreachable,
+                confirms,
+                threshold,
+            } => tr!(
+                "fail-rebuilders-unreachable",
+                unreachable = unreachable,
+                confirms = confirms,
+                threshold = threshold,
+            ),
+            Failure::Conflicting {
+                rebuilders,
+                confirms,
+                threshold,
+            } => tr!(
+                "fail-conflicting-attestations",
+                rebuilders = rebuilders.join(", "),
+                confirms = confirms,
+                threshold = threshold,
+            ),
+            Failure::DeadlineReached {
+                pending,
+                confirms,
+                threshold,
+            } => tr!(
+                "fail-deadline-reached",
+                pending = pending,
+                confirms = confirms,
+                threshold = threshold,
+            ),
+            Failure::NoAttestations { threshold } => {
+                tr!("fail-no-attestations", threshold = threshold,)
+            }
+            Failure::ThresholdNotMet {
+                confirms,
+                threshold,
+            } => tr!(
+                "fail-threshold-not-met",
+                confirms = confirms,
+                threshold = threshold,
+            ),
+            Failure::OutOfBandVerifiedNotMet { verified, required } => tr!(
+                "fail-not-enough-verified",
+                verified = verified,
+                required = required,
+            ),
+            Failure::Deferred { unreachable } => tr!("fail-deferred", unreachable = unreachable,),
+            Failure::Timeout { secs } => tr!("fail-timeout", secs = secs,),
+            Failure::Layout(err) => tr!("fail-layout", err = err),
+            Failure::Clock(problem) => tr!("fail-grace-period-refused", problem = problem),
+            Failure::Untracked(err) => tr!("fail-grace-period-refused", problem = err),
+            Failure::NoSpace(disk::NoSpace { needed, available }) => {
+                tr!("fail-disk-space", needed = needed, available = available)
+            }
+            Failure::KeyChanged { rebuilders } => {
+                tr!("fail-key-changed", rebuilders = rebuilders.join(", "),)
+            }
+        };
+        f.write_str(&msg)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Track a package that was admitted or deferred without reaching the threshold
 /// For safety reasons, make sure we absolutely do not have newlines in the messages
 fn truncate_newline(s: &str) -> &str {
     s.split_once('\n').map(|(line, _)| line).unwrap_or(s)
 }
 
-fn uri_failure(uri: Option<&str>, message: &str) {
+fn uri_failure(uri: Option<&str>, message: &str, reason: Option<&str>) {
     println!("400 URI Failure");
     println!("Message: {}", truncate_newline(message));
+    if let Some(reason) = reason {
+        println!("FailReason: {reason}");
+    }
     if let Some(uri) = uri {
         println!("URI: {}", truncate_newline(uri));
     }
@@ -72,7 +411,110 @@ fn send_status(uri: &str, message: &str) {
     println!();
 }
 
-async fn acquire(http: &http::Client, config: &Config, req: &Request) -> Result<()> {
+/// Where the package is downloaded and parsed
+enum Source {
+    /// In this process
+    Direct(reqwest::Response),
+    /// In a privilege separated worker process
+    Worker(worker::Worker),
+}
+
+impl Source {
+    async fn connect(
+        http: &http::Client,
+        config: &Config,
+        url: &Url,
+        resume: Option<&Resume>,
+    ) -> Result<(Self, worker::Started)> {
+        if config.sandbox.privilege_separation {
+            let mut worker = worker::Worker::spawn(config).await?;
+            let range = resume.map(|resume| worker::Range {
+                offset: resume.checkpoint.size,
+                last_modified: resume.last_modified.clone(),
+            });
+            let started = worker
+                .download(url.clone(), range)
+                .await
+                .map_err(Failure::Download)?;
+            return Ok((Source::Worker(worker), started));
+        }
+
+        let mut request = http.get(url.clone());
+        if let Some(resume) = resume {
+            request = request
+                .header(RANGE, format!("bytes={}-", resume.checkpoint.size))
+                .header(IF_RANGE, &resume.last_modified);
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| Failure::Download(format!("{err:#}")))?;
+        http::log_redirect(url, &response);
+
+        let started = worker::Started {
+            partial: resume.is_some() && response.status() == StatusCode::PARTIAL_CONTENT,
+            last_modified: response
+                .headers()
+                .get("Last-Modified")
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+            content_length: response.content_length(),
+        };
+        Ok((Source::Direct(response), started))
+    }
+
+    async fn chunk(&mut self) -> Result<Option<Bytes>, Failure> {
+        match self {
+            Source::Direct(response) => response
+                .chunk()
+                .await
+                .map_err(|err| Failure::Download(format!("{err:#}"))),
+            Source::Worker(worker) => worker.chunk().await.map_err(Failure::Download),
+        }
+    }
+
+    async fn inspect<R: AsyncRead + Unpin>(&mut self, reader: R) -> Result<Deb> {
+        match self {
+            Source::Direct(_) => inspect::deb::inspect(reader).await,
+            Source::Worker(worker) => worker.inspect(reader).await,
+        }
+        .context("Failed to parse .deb metadata")
+    }
+
+    async fn fetch_remote_until<F>(
+        &mut self,
+        http: &http::Client,
+        rebuilders: Vec<Url>,
+        inspect: Deb,
+        deadline: Option<Instant>,
+        done: F,
+    ) -> Result<attestation::Remote>
+    where
+        F: FnMut(&attestation::Tree) -> bool,
+    {
+        match self {
+            Source::Direct(_) => Ok(attestation::fetch_remote_until(
+                http, rebuilders, inspect, deadline, done,
+            )
+            .await),
+            Source::Worker(worker) => {
+                let mut span = telemetry::span("fetch_remote");
+                span.attr("worker", true);
+                let answers = worker.fetch(rebuilders.clone(), inspect).await?;
+                Ok(attestation::collect_remote(answers, rebuilders, deadline, done).await)
+            }
+        }
+    }
+}
+
+/// Download and verify a file, returns the package if it's recorded in the SBOM
+async fn acquire(
+    http: &http::Client,
+    config: &Config,
+    req: &Request,
+    notifications: &mut notify::Background,
+) -> Result<Option<sbom::Component>> {
     let uri = req.headers.get("URI").context("Missing `URI` header")?;
 
     let filename = req
@@ -80,31 +522,64 @@ async fn acquire(http: &http::Client, config: &Config, req: &Request) -> Result<
         .get("Filename")
         .context("Missing `Filename` header")?;
 
+    let expected = req.expected()?;
+
     let url = uri.strip_prefix("reproduced+").unwrap_or(uri);
     let url = url.parse::<Url>().context("Invalid URI")?;
-    let domain = url.domain().context("URI missing domain")?;
+    let domain = url.host_str().context("URI missing host")?;
 
-    // Open file for writing
-    let file = File::options()
+    // Open file for writing, it's only truncated once we know the download can't be resumed
+    let mut file = File::options()
         .create(true)
         .read(true)
         .write(true)
-        .truncate(true)
+        .truncate(false)
         .open(filename)
         .await
         .with_context(|| format!("Failed to open file: {}", filename))?;
 
-    let mut file = withhold::Writer::new(file);
+    let checkpoint_path = Resume::path(filename);
+    let resume = Resume::load(&checkpoint_path, &url).await;
 
     // Start sending request
     send_status(uri, &format!("Connecting to {}", domain));
-    let mut response = http.get(url).send().await?.error_for_status()?;
+    let (mut source, started) = Source::connect(http, config, &url, resume.as_ref()).await?;
+
+    // Refuse downloads that don't fit, before the file is truncated
+    let offset = match &resume {
+        Some(resume) if started.partial => resume.checkpoint.size,
+        _ => 0,
+    };
+    let needed = expected
+        .size
+        .map(|size| size.saturating_sub(offset))
+        .or(started.content_length);
+    if let Some(needed) = needed {
+        disk::check_space(Path::new(filename), needed, config.storage.min_free())
+            .map_err(Failure::NoSpace)?;
+    }
 
-    let last_modified = response
-        .headers()
-        .get("Last-Modified")
-        .and_then(|v| v.to_str().ok())
-        .map(String::from);
+    let mut file = match resume {
+        Some(resume) if started.partial => {
+            let offset = resume.checkpoint.size;
+            debug!("Resuming download of {} at byte {offset}", Redacted(&url));
+            file.set_len(offset)
+                .await
+                .with_context(|| format!("Failed to truncate file: {}", filename))?;
+            file.seek(SeekFrom::Start(offset))
+                .await
+                .with_context(|| format!("Failed to seek in file: {}", filename))?;
+            withhold::Writer::resume(file, &resume.checkpoint)?
+        }
+        _ => {
+            file.set_len(0)
+                .await
+                .with_context(|| format!("Failed to truncate file: {}", filename))?;
+            withhold::Writer::new(file)
+        }
+    };
+
+    let last_modified = started.last_modified;
 
     println!("200 URI Start");
     if let Some(last_modified) = &last_modified {
@@ -113,41 +588,246 @@ async fn acquire(http: &http::Client, config: &Config, req: &Request) -> Result<
     println!("URI: {}", truncate_newline(uri));
     println!();
 
-    while let Some(chunk) = response.chunk().await.transpose() {
-        file.write_all(chunk?).await?;
+    let mut throttle = Throttle::for_downloads(&config.network);
+    let mut next_checkpoint = file.size() + CHECKPOINT_INTERVAL;
+    let mut download = telemetry::span("download");
+    while let Some(chunk) = source.chunk().await? {
+        if let Some(throttle) = &mut throttle {
+            throttle.consume(chunk.len()).await;
+        }
+        file.write_all(chunk).await?;
+        expected.check_size(file.size())?;
+
+        // Without Last-Modified we can't tell if the file changed in between
+        if let Some(last_modified) = &last_modified
+            && file.size() >= next_checkpoint
+        {
+            let resume = Resume {
+                url: url.clone(),
+                last_modified: last_modified.clone(),
+                checkpoint: file.checkpoint().await?,
+            };
+            if let Err(err) = resume.save(&checkpoint_path).await {
+                warn!("{err:#}");
+            }
+            next_checkpoint = file.size() + CHECKPOINT_INTERVAL;
+        }
     }
+    Resume::remove(&checkpoint_path).await;
+    download.attr("bytes", file.size());
+    drop(download);
 
     let sha256 = file.sha256();
+    expected.check(file.size(), &sha256)?;
 
     // Verify reproducible builds attestations
+    let mut component = None;
     if req.needs_verification() {
         send_status(uri, "Verifying download");
         let mut reader = file.into_reader().await?;
 
         // Parse deb metadata
-        let inspect = inspect::deb::inspect(&mut reader)
-            .await
-            .context("Failed to parse .deb metadata")?;
+        let inspect = source.inspect(&mut reader).await?;
         file = reader.into_writer().await?;
 
-        if !config.rules.blindly_trust.contains(&inspect.name) {
-            // Fetch attestations
-            let rebuilders = config.trusted_rebuilders.iter().map(|r| r.url.clone());
-            let attestations = attestation::fetch_remote(http, rebuilders, inspect).await;
-
-            // Ensure each domain only gets one vote, until we don't have per-architecture rebuilders anymore
-            let trusted = DomainTree::from_config(config);
-            let confirms = attestations.verify(&sha256, trusted.signing_keys());
-            let confirms = trusted.group_by_domain(confirms);
-
-            if confirms.len() < config.rules.required_threshold {
+        // The worker is not trusted with the identity of the package, it decides if the
+        // package is blindly trusted, so it's derived again from the hashed file
+        if let Source::Worker(_) = source {
+            let mut reader = file.into_reader().await?;
+            let parsed = inspect::deb::inspect(&mut reader)
+                .await
+                .context("Failed to parse .deb metadata")?;
+            file = reader.into_writer().await?;
+            if parsed != inspect {
                 bail!(
-                    "Not enough reproducible builds attestations: only {}/{} required signatures",
-                    confirms.len(),
-                    config.rules.required_threshold
+                    "Worker reported different package metadata than the downloaded file: {inspect:?} != {parsed:?}"
                 );
             }
         }
+
+        let hex = data_encoding::HEXLOWER.encode(&sha256);
+        let mut admitted = |package: &Deb, status, rebuilders| {
+            component = Some(sbom::Component {
+                package: package.clone(),
+                url: url.clone(),
+                sha256: hex.clone(),
+                status,
+                rebuilders,
+            });
+        };
+        let udeb = req.is_udeb();
+        if udeb {
+            debug!("Verifying installer component: {inspect:?}");
+        }
+        let engine = policy::Engine::new(config).udeb(udeb);
+        if engine.blindly_trusts(&inspect) {
+            admitted(&inspect, sbom::Status::BlindlyTrusted, vec![]);
+            let entry = state::Entry {
+                package: inspect.clone(),
+                reason: state::Reason::BlindlyTrusted,
+                admitted_at: Utc::now(),
+                deadline: None,
+                escalated: false,
+            };
+            if let Err(err) = state::Pending::record(&sha256, entry).await {
+                warn!("{err:#}");
+            }
+
+            let event = Event::BlindlyTrusted {
+                package: inspect,
+                sha256: hex,
+            };
+            notifications.dispatch(http, &config.notify, event);
+        } else if let Some(layout) = &config.rules.layout {
+            // The layout decides which attestations are needed, so wait for all rebuilders
+            let rebuilders = config
+                .trusted_rebuilders
+                .iter()
+                .map(|r| r.url.clone())
+                .collect();
+            let timeout = Duration::from_secs(
+                config
+                    .network
+                    .verification_timeout_secs
+                    .unwrap_or(VERIFICATION_TIMEOUT_SECS),
+            );
+            let remote = time::timeout(
+                timeout,
+                source.fetch_remote_until(http, rebuilders, inspect.clone(), None, |_| false),
+            )
+            .await
+            .map_err(|_| Failure::Timeout {
+                secs: timeout.as_secs(),
+            })??;
+
+            send_status(uri, "Verifying in-toto layout");
+            let path = Path::new(filename);
+            if let Err(err) =
+                layout::verify_artifact(layout, &remote.attestations, &sha256, path).await
+            {
+                state::Counters::update(|counters| counters.failed += 1).await;
+                return Err(Failure::Layout(format!("{err:#}")).into());
+            }
+
+            let hosts = engine.hosts(&engine.confirms(&sha256, &remote.attestations));
+            admitted(&inspect, sbom::Status::Reproduced, hosts);
+            state::Counters::update(|counters| counters.verified += 1).await;
+        } else {
+            // Fetch attestations, until the threshold is reached
+            let rebuilders = config
+                .trusted_rebuilders
+                .iter()
+                .map(|r| r.url.clone())
+                .collect();
+            let timeout = Duration::from_secs(
+                config
+                    .network
+                    .verification_timeout_secs
+                    .unwrap_or(VERIFICATION_TIMEOUT_SECS),
+            );
+            let deadline = config
+                .rules
+                .max_verification_time
+                .map(|budget| Instant::now() + budget.0);
+            let remote = time::timeout(
+                timeout,
+                source.fetch_remote_until(
+                    http,
+                    rebuilders,
+                    inspect.clone(),
+                    deadline,
+                    |attestations| engine.threshold_reached(Some(&inspect), &sha256, attestations),
+                ),
+            )
+            .await
+            .map_err(|_| Failure::Timeout {
+                secs: timeout.as_secs(),
+            })??;
+
+            let decision = engine.decide(Some(&inspect), &sha256, &remote);
+            debug!(
+                "Decision for {}: {}",
+                Redacted(&url),
+                serde_json::to_string(&decision)?
+            );
+            let confirming_hosts = decision.rebuilders(policy::Ballot::Confirm);
+            let disagreeing_hosts = decision.rebuilders(policy::Ballot::Disagree);
+            if !disagreeing_hosts.is_empty() {
+                let event = Event::Conflicting {
+                    package: inspect.clone(),
+                    sha256: hex.clone(),
+                    rebuilders: disagreeing_hosts,
+                };
+                notifications.dispatch(http, &config.notify, event);
+
+                let urls = config.trusted_rebuilders.iter().filter_map(|r| {
+                    let key = r.signing_key().ok()?;
+                    decision.disagrees(key.key_id()).then_some(&r.url)
+                });
+                Stats::record_disagreements(urls).await;
+            }
+
+            match decision.outcome {
+                policy::Outcome::GracePeriod
+                | policy::Outcome::PartialResults
+                | policy::Outcome::Offline => {
+                    send_status(uri, &decision.message());
+                    if decision.outcome == policy::Outcome::Offline {
+                        warn!("{}", decision.message());
+                    }
+                    // The deadline would be meaningless with a clock that is way off
+                    let now = clock::now().map_err(Failure::Clock)?;
+                    let reason = match decision.outcome {
+                        policy::Outcome::PartialResults => state::Reason::PartialResults,
+                        policy::Outcome::Offline => state::Reason::Offline,
+                        _ => state::Reason::GracePeriod,
+                    };
+                    // Without a record, cron can't escalate the package if it never verifies
+                    let entry = state::Entry::pending(
+                        inspect.clone(),
+                        reason,
+                        now,
+                        config.rules.grace_period_days,
+                    );
+                    state::Pending::record(&sha256, entry)
+                        .await
+                        .map_err(|err| Failure::Untracked(format!("{err:#}")))?;
+                    admitted(&inspect, sbom::Status::GracePeriod, confirming_hosts);
+                }
+                policy::Outcome::Rejected if decision.is_deferred() => {
+                    warn!("{}", decision.message());
+                    // Not installed, but recorded so cron reports once it can be verified
+                    if let Ok(now) = clock::now() {
+                        let entry = state::Entry::pending(
+                            inspect,
+                            state::Reason::Deferred,
+                            now,
+                            config.rules.grace_period_days,
+                        );
+                        if let Err(err) = state::Pending::record(&sha256, entry).await {
+                            warn!("{err:#}");
+                        }
+                    }
+                    state::Counters::update(|counters| counters.failed += 1).await;
+                    return Err(Failure::from_decision(&decision).into());
+                }
+                policy::Outcome::Rejected => {
+                    let event = Event::Rejected {
+                        package: inspect,
+                        sha256: hex,
+                        confirms: decision.confirms(),
+                        threshold: decision.threshold,
+                    };
+                    notifications.dispatch(http, &config.notify, event);
+                    state::Counters::update(|counters| counters.failed += 1).await;
+                    return Err(Failure::from_decision(&decision).into());
+                }
+                policy::Outcome::BlindlyTrusted | policy::Outcome::Reproduced => {
+                    admitted(&inspect, sbom::Status::Reproduced, confirming_hosts);
+                    state::Counters::update(|counters| counters.verified += 1).await;
+                }
+            }
+        }
     }
 
     // If successfully verified, write final chunk
@@ -163,36 +843,418 @@ async fn acquire(http: &http::Client, config: &Config, req: &Request) -> Result<
     println!("URI: {}", truncate_newline(uri));
     println!();
 
-    Ok(())
+    Ok(component)
 }
 
-pub async fn run(config: Config) -> Result<()> {
+pub async fn run(mut config: Config, http: http::Client) -> Result<()> {
     println!("100 Capabilities");
     println!("Send-URI-Encoded: true");
     // println!("Send-Config: true");
     // println!("Pipeline: true");
     println!("Version: 1.2");
     println!();
+    disk::prune_cache(&config).await;
 
-    let http = http::client();
-    let mut stdin = BufReader::new(io::stdin());
+    // Read stdin in the background, so we notice if apt goes away during an acquire
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    let (closed_tx, closed) = watch::channel(false);
+    tokio::spawn(async move {
+        let mut stdin = BufReader::new(io::stdin());
+        loop {
+            let req = Request::read(&mut stdin).await.transpose();
+            let eof = !matches!(req, Some(Ok(_)));
+            if let Some(req) = req
+                && requests_tx.send(req).is_err()
+            {
+                break;
+            }
+            if eof {
+                break;
+            }
+        }
+        let _ = closed_tx.send(true);
+    });
+
+    let key_changed = if config.trust_on_first_use {
+        tofu::check(&http, &mut config).await
+    } else {
+        Vec::new()
+    };
 
-    while let Some(req) = Request::read(&mut stdin).await? {
-        if req.status.starts_with("600 ") {
+    let mut components = Vec::new();
+    let mut notifications = notify::Background::default();
+    let result = loop {
+        let Some(req) = requests.recv().await else {
+            break Ok(());
+        };
+        let req = match req {
+            Ok(req) => req,
+            Err(err) => break Err(err),
+        };
+        if req.status.starts_with("600 ") && !key_changed.is_empty() {
+            let failure = Failure::KeyChanged {
+                rebuilders: key_changed.iter().map(Url::to_string).collect(),
+            };
+            uri_failure(
+                req.headers.get("URI").map(|s| s.as_str()),
+                &failure.to_string(),
+                Some(failure.reason()),
+            );
+        } else if req.status.starts_with("600 ") {
             debug!("Received acquire request: {req:?}");
             // 600 URI Acquire
-            if let Err(err) = acquire(&http, &config, &req).await {
-                uri_failure(
-                    req.headers.get("URI").map(|s| s.as_str()),
-                    &format!("{err:#}"),
-                );
+            let mut closed = closed.clone();
+            let already_closed = *closed.borrow_and_update();
+            let mut span = telemetry::span("acquire");
+            if let Some(uri) = req.headers.get("URI") {
+                span.attr("url.full", uri.as_str());
+            }
+            let result = tokio::select! {
+                result = span.instrument(acquire(&http, &config, &req, &mut notifications)) => result,
+                // Dropping the acquire future aborts all pending rebuilder queries
+                _ = closed.wait_for(|closed| *closed), if !already_closed => {
+                    info!("apt closed stdin, cancelling verification");
+                    break Ok(());
+                }
+            };
+            if let Err(err) = &result {
+                span.error(err);
+            }
+            drop(span);
+            match result {
+                Ok(Some(component)) => {
+                    state::Admitted::record(&component.package).await;
+                    audit::append(&config.audit, &audit::Record::admitted(&component)).await;
+                    components.push(component);
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    let reason = err.downcast_ref::<Failure>().map(Failure::reason);
+                    let uri = req.headers.get("URI").map(|s| s.as_str());
+                    if let Some(uri) = uri {
+                        let url = uri.strip_prefix("reproduced+").unwrap_or(uri);
+                        audit::append(
+                            &config.audit,
+                            &audit::Record::rejected(url, reason, format!("{err:#}")),
+                        )
+                        .await;
+                    }
+                    uri_failure(uri, &format!("{err:#}"), reason);
+                }
             }
         } else if req.status.starts_with("601 ") {
             // 601 Configuration
         } else {
-            uri_failure(None, &format!("Unsupported command: {}", req.status));
+            uri_failure(None, &format!("Unsupported command: {}", req.status), None);
         }
+    };
+
+    // The packages of this apt run, for compliance tooling
+    match sbom::write(&config.sbom, &components).await {
+        Ok(Some(path)) => debug!("Wrote SBOM to {path:?}"),
+        Ok(None) => (),
+        Err(err) => warn!("{err:#}"),
+    }
+    notifications.finish().await;
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use std::env;
+
+    fn request(mirror: &Url, filename: &std::path::Path) -> Request {
+        Request {
+            status: "600 URI Acquire".to_string(),
+            headers: BTreeMap::from([
+                (
+                    "URI".to_string(),
+                    format!("reproduced+{}", mirror.join("pool/test.deb").unwrap()),
+                ),
+                (
+                    "Filename".to_string(),
+                    filename.to_string_lossy().into_owned(),
+                ),
+            ]),
+        }
+    }
+
+    fn config(rebuilder: Url, required_threshold: usize) -> Config {
+        let mut config = Config::default();
+        config.rules.required_threshold = required_threshold;
+        config.trusted_rebuilders = vec![test_server::rebuilder(
+            rebuilder,
+            test_server::DEB_KEYRING.to_string(),
+        )];
+        config
+    }
+
+    #[tokio::test]
+    async fn test_acquire_verified() {
+        let mirror = test_server::spawn_mirror().await;
+        let rebuilder = test_server::spawn_rebuilder().await;
+        let filename = env::temp_dir().join(format!(
+            "repro-threshold-test-acquire-verified-{}.deb",
+            std::process::id()
+        ));
+
+        let http = http::client();
+        let result = acquire(
+            &http,
+            &config(rebuilder, 1),
+            &request(&mirror, &filename),
+            &mut Default::default(),
+        )
+        .await;
+        let downloaded = tokio::fs::read(&filename).await;
+        let _ = tokio::fs::remove_file(&filename).await;
+
+        result.unwrap();
+        assert_eq!(
+            downloaded.unwrap(),
+            tokio::fs::read(test_server::DEB_PATH).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acquire_below_threshold() {
+        let mirror = test_server::spawn_mirror().await;
+        let rebuilder = test_server::spawn_rebuilder().await;
+        let filename = env::temp_dir().join(format!(
+            "repro-threshold-test-acquire-below-threshold-{}.deb",
+            std::process::id()
+        ));
+
+        let http = http::client();
+        let result = acquire(
+            &http,
+            &config(rebuilder, 2),
+            &request(&mirror, &filename),
+            &mut Default::default(),
+        )
+        .await;
+        let _ = tokio::fs::remove_file(&filename).await;
+
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Failure>(),
+            Some(&Failure::ThresholdNotMet {
+                confirms: 1,
+                threshold: 2
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acquire_rebuilders_unreachable() {
+        let mirror = test_server::spawn_mirror().await;
+        let filename = env::temp_dir().join(format!(
+            "repro-threshold-test-acquire-unreachable-{}.deb",
+            std::process::id()
+        ));
+
+        let config = config("http://127.0.0.1:1/".parse().unwrap(), 1);
+        let result = acquire(
+            &http::client(),
+            &config,
+            &request(&mirror, &filename),
+            &mut Default::default(),
+        )
+        .await;
+        let _ = tokio::fs::remove_file(&filename).await;
+
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Failure>(),
+            Some(&Failure::RebuildersUnreachable {
+                unreachable: 1,
+                confirms: 0,
+                threshold: 1
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "Rebuilders unreachable: 1 trusted rebuilders could not be queried, only 0/1 required signatures"
+        );
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn test_acquire_offline_deferred() {
+        let mirror = test_server::spawn_mirror().await;
+        let filename = env::temp_dir().join(format!(
+            "repro-threshold-test-acquire-deferred-{}.deb",
+            std::process::id()
+        ));
+
+        let mut config = config("http://127.0.0.1:1/".parse().unwrap(), 1);
+        config.rules.offline = crate::config::Offline::Defer;
+        let result = acquire(
+            &http::client(),
+            &config,
+            &request(&mirror, &filename),
+            &mut Default::default(),
+        )
+        .await;
+        let _ = tokio::fs::remove_file(&filename).await;
+
+        let err = result.unwrap_err();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure, &Failure::Deferred { unreachable: 1 });
+        assert_eq!(failure.reason(), "RebuildersOffline");
+    }
+
+    #[tokio::test]
+    async fn test_acquire_expected_hash_mismatch() {
+        let mirror = test_server::spawn_mirror().await;
+        let filename = env::temp_dir().join(format!(
+            "repro-threshold-test-acquire-hash-mismatch-{}.deb",
+            std::process::id()
+        ));
+
+        let mut req = request(&mirror, &filename);
+        req.headers
+            .insert("Expected-SHA256".to_string(), "00".repeat(32));
+        // No rebuilder is needed, the download is rejected before any attestations are fetched
+        let config = config("http://127.0.0.1:1/".parse().unwrap(), 1);
+        let result = acquire(&http::client(), &config, &req, &mut Default::default()).await;
+        let _ = tokio::fs::remove_file(&filename).await;
+
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Failure>().map(Failure::reason),
+            Some("HashSumMismatch")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acquire_timeout() {
+        let mirror = test_server::spawn_mirror().await;
+        // Accepts connections, but never responds
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rebuilder = format!("http://{}/", listener.local_addr().unwrap());
+        let filename = env::temp_dir().join(format!(
+            "repro-threshold-test-acquire-timeout-{}.deb",
+            std::process::id()
+        ));
+
+        let mut config = config(rebuilder.parse().unwrap(), 1);
+        config.network.verification_timeout_secs = Some(1);
+        let result = acquire(
+            &http::client(),
+            &config,
+            &request(&mirror, &filename),
+            &mut Default::default(),
+        )
+        .await;
+        let _ = tokio::fs::remove_file(&filename).await;
+
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Failure>(),
+            Some(&Failure::Timeout { secs: 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acquire_deadline_reached() {
+        let mirror = test_server::spawn_mirror().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rebuilder = format!("http://{}/", listener.local_addr().unwrap());
+        let filename = env::temp_dir().join(format!(
+            "repro-threshold-test-acquire-deadline-{}.deb",
+            std::process::id()
+        ));
+
+        let mut config = config(rebuilder.parse().unwrap(), 1);
+        config.rules.max_verification_time = Some("100ms".parse().unwrap());
+        let result = acquire(
+            &http::client(),
+            &config,
+            &request(&mirror, &filename),
+            &mut Default::default(),
+        )
+        .await;
+        let _ = tokio::fs::remove_file(&filename).await;
+
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Failure>(),
+            Some(&Failure::DeadlineReached {
+                pending: 1,
+                confirms: 0,
+                threshold: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_needs_verification() {
+        let mut req = Request::default();
+        assert!(req.needs_verification());
+        assert!(!req.is_udeb());
+
+        req.headers.insert(
+            "URI".to_string(),
+            "reproduced+http://deb.debian.org/debian/pool/main/b/busybox/busybox-udeb_1.37.0-4_amd64.udeb"
+                .to_string(),
+        );
+        assert!(req.needs_verification());
+        assert!(req.is_udeb());
+
+        req.headers
+            .insert("Target-Type".to_string(), "index".to_string());
+        assert!(!req.needs_verification());
+    }
+
+    #[test]
+    fn test_expected() {
+        let mut req = Request::default();
+        assert_eq!(req.expected().unwrap(), Expected::default());
+
+        req.headers
+            .insert("Expected-SHA256".to_string(), "ab".repeat(32));
+        req.headers
+            .insert("Expected-Checksum-FileSize".to_string(), "3".to_string());
+        let expected = req.expected().unwrap();
+        assert_eq!(expected.size, Some(3));
+
+        assert!(expected.check_size(3).is_ok());
+        assert!(expected.check_size(4).is_err());
+        assert!(expected.check(3, &[0xab; 32]).is_ok());
+        assert!(expected.check(2, &[0xab; 32]).is_err());
+        assert!(expected.check(3, &[0xcd; 32]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resume_checkpoint() {
+        let filename = env::temp_dir().join(format!(
+            "repro-threshold-test-resume-{}.deb",
+            std::process::id()
+        ));
+        let path = Resume::path(filename.to_str().unwrap());
+        let url = "http://deb.debian.org/debian/pool/main/f/foo.deb"
+            .parse::<Url>()
+            .unwrap();
+        let resume = Resume {
+            url: url.clone(),
+            last_modified: "Wed, 21 Oct 2015 07:28:00 GMT".to_string(),
+            checkpoint: withhold::Checkpoint {
+                size: 5,
+                sha256_state: "00".repeat(8),
+            },
+        };
+        resume.save(&path).await.unwrap();
+
+        let loaded = Resume::load(&path, &url).await;
+        let other = Resume::load(&path, &"http://example.com/foo.deb".parse().unwrap()).await;
+        Resume::remove(&path).await;
+        let removed = Resume::load(&path, &url).await;
+
+        assert_eq!(loaded, Some(resume));
+        assert_eq!(other, None);
+        assert_eq!(removed, None);
+    }
 }
@@ -1,15 +1,77 @@
 pub mod alpm;
 pub mod apt;
+pub mod worker;
 
 use crate::args::Transport;
+use crate::audit;
 use crate::config::Config;
 use crate::errors::*;
+use crate::sideload;
+use crate::telemetry;
 
 pub async fn run(transport: Transport) -> Result<()> {
-    let config = Config::load().await?;
-
     match transport {
-        Transport::Alpm { .. } => alpm::run(config).await,
-        Transport::Apt => apt::run(config).await,
+        Transport::Alpm {
+            output,
+            url,
+            verify_signature,
+            options,
+        } => {
+            let mut config = Config::load().await?;
+            options.apply(&mut config);
+            let http = options.http.client(&config)?;
+            telemetry::init(&config.telemetry)?;
+            let result = alpm::run(
+                config,
+                http.clone(),
+                &options,
+                url,
+                &output,
+                verify_signature,
+            )
+            .await;
+            telemetry::flush(&http).await;
+            result
+        }
+        Transport::AlpmHook {
+            cache_dirs,
+            options,
+        } => {
+            let mut config = Config::load().await?;
+            options.apply(&mut config);
+            let http = options.http.client(&config)?;
+            telemetry::init(&config.telemetry)?;
+            let result = alpm::hook(config, http.clone(), &options, &cache_dirs).await;
+            telemetry::flush(&http).await;
+            result
+        }
+        Transport::Apt { http } => {
+            let config = Config::load().await?;
+            let http = http.client(&config)?;
+            telemetry::init(&config.telemetry)?;
+            // Spans are exported once apt is done, so the transfers aren't held up
+            let result = apt::run(config, http.clone()).await;
+            telemetry::flush(&http).await;
+            result
+        }
+        Transport::AptPostInvoke {
+            dpkg_log,
+            archives,
+            http,
+        } => {
+            let config = Config::load().await?;
+            let http = http.client(&config)?;
+            let log = sideload::Log {
+                manager: sideload::Manager::Dpkg,
+                path: dpkg_log,
+            };
+            // The apt method can't use the signing key, its entries are queued until now
+            if let Err(err) = audit::seal(&config.audit).await {
+                warn!("Failed to sign queued audit log entries: {err:#}");
+            }
+            sideload::scan(&http, &config, &[log], &archives).await
+        }
+        // The worker receives its config from the apt transport
+        Transport::AptWorker => worker::run().await,
     }
 }
@@ -0,0 +1,759 @@
+//! Privilege separation for the apt transport
+//!
+//! With `[sandbox] privilege_separation = true` all network access, and parsing of untrusted
+//! input (.deb files and rebuilder responses), happens in a worker process with reduced
+//! privileges. The worker runs as an unprivileged user and a seccomp filter stops it from
+//! executing programs, writing files or tampering with other processes.
+//!
+//! The parent only holds the output file and the policy: it hashes the data it receives
+//! itself and re-parses attestations, so a compromised worker can't make the parent accept a
+//! package that isn't signed by enough rebuilders. The metadata of the package (name, version
+//! and architecture) decides if the package is blindly trusted, so the parent parses it again
+//! from the hashed file and refuses the package if the worker reported something else.
+//!
+//! Both directions use the same framing, a tag byte and a big endian u32 length, followed by
+//! either a JSON message or raw data.
+use crate::attestation::{self, Attestation, MAX_ATTESTATION_SIZE};
+use crate::config::{Config, Sandbox};
+use crate::errors::*;
+use crate::http;
+use crate::inspect::{self, deb::Deb};
+use bytes::Bytes;
+use futures::{Stream, stream};
+use nix::unistd::User;
+use reqwest::StatusCode;
+use reqwest::header::{IF_RANGE, RANGE};
+use seccompiler::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule, TargetArch,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use url::Url;
+
+/// Frames are bounded, so a compromised worker can't exhaust the memory of the parent
+const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+/// Size of the data frames the parent sends for inspection
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const TAG_MESSAGE: u8 = b'm';
+const TAG_DATA: u8 = b'd';
+
+/// Sent from the parent to the worker
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Job {
+    /// Download a file, optionally continuing a previous download
+    Download { url: Url, resume: Option<Range> },
+    /// Parse the .deb that follows as data frames, terminated by an empty data frame
+    Inspect,
+    /// Query rebuilders for attestations of a package
+    Fetch { rebuilders: Vec<Url>, package: Deb },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Range {
+    pub offset: u64,
+    pub last_modified: String,
+}
+
+/// Sent from the worker to the parent
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Message {
+    /// The download has started, data frames follow
+    Started {
+        partial: bool,
+        last_modified: Option<String>,
+        content_length: Option<u64>,
+    },
+    /// All data frames of the download have been sent
+    Downloaded,
+    Failed(String),
+    Inspected(Result<Deb, String>),
+    Attestation {
+        rebuilder: Url,
+        label: String,
+        /// Base64 encoded attestation
+        data: String,
+    },
+    /// All attestations of this rebuilder have been sent
+    Answered {
+        rebuilder: Url,
+        latency_ms: u64,
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug)]
+enum Frame<T> {
+    Message(T),
+    Data(Bytes),
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, tag: u8, payload: &[u8]) -> Result<()> {
+    let len = u32::try_from(payload.len()).context("Frame is too large")?;
+    writer.write_u8(tag).await?;
+    writer.write_u32(len).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn write_message<W: AsyncWrite + Unpin, T: Serialize>(writer: &mut W, msg: &T) -> Result<()> {
+    let json = serde_json::to_vec(msg)?;
+    write_frame(writer, TAG_MESSAGE, &json).await
+}
+
+async fn write_data<W: AsyncWrite + Unpin>(writer: &mut W, data: &[u8]) -> Result<()> {
+    write_frame(writer, TAG_DATA, data).await
+}
+
+/// Read the next frame, `None` if the other side closed the pipe
+async fn read_frame<R: AsyncRead + Unpin, T: DeserializeOwned>(
+    reader: &mut R,
+) -> Result<Option<Frame<T>>> {
+    let tag = match reader.read_u8().await {
+        Ok(tag) => tag,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_FRAME_SIZE {
+        bail!("Frame exceeds {MAX_FRAME_SIZE} bytes: {len}");
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).await?;
+
+    match tag {
+        TAG_MESSAGE => {
+            let msg = serde_json::from_slice(&payload).context("Failed to parse message")?;
+            Ok(Some(Frame::Message(msg)))
+        }
+        TAG_DATA => Ok(Some(Frame::Data(payload.into()))),
+        _ => bail!("Unknown frame type: {tag:#x}"),
+    }
+}
+
+/// The parent side of a worker process
+pub struct Worker<R = ChildStdout, W = ChildStdin> {
+    /// Killed on drop, this also aborts any pending rebuilder queries
+    _child: Option<Child>,
+    reader: BufReader<R>,
+    writer: W,
+}
+
+impl Worker {
+    /// Start a new worker process, and send it the config
+    pub async fn spawn(config: &Config) -> Result<Self> {
+        let exe = env::current_exe().context("Failed to determine path of current executable")?;
+        let mut command = Command::new(exe);
+        command
+            .args(["transport", "apt-worker"])
+            .current_dir("/")
+            // Fewer malloc arenas, so the address space limit isn't hit by accident
+            .env("MALLOC_ARENA_MAX", "2")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            // Forwarded by us, the worker can't write to stderr if it was redirected to a file
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        restrict(&mut command, &config.sandbox)?;
+
+        let mut child = command.spawn().context("Failed to spawn worker process")?;
+        let reader = child.stdout.take().context("Worker has no stdout")?;
+        let writer = child.stdin.take().context("Worker has no stdin")?;
+        let mut stderr = child.stderr.take().context("Worker has no stderr")?;
+        tokio::spawn(async move {
+            let _ = io::copy(&mut stderr, &mut io::stderr()).await;
+        });
+
+        let mut worker = Worker {
+            _child: Some(child),
+            reader: BufReader::new(reader),
+            writer,
+        };
+        write_message(&mut worker.writer, config).await?;
+        Ok(worker)
+    }
+}
+
+/// Reduce what the worker process is able to do, this is applied right before exec
+fn restrict(command: &mut Command, sandbox: &Sandbox) -> Result<()> {
+    let memory_limit = sandbox
+        .memory_limit_mib
+        .map(|mib| (mib * 1024 * 1024) as libc::rlim_t);
+
+    // Started as root, e.g. with `APT::Sandbox::User "root"`, the worker switches to a user
+    // that owns nothing. The user is looked up here, this isn't possible after fork.
+    let credentials = if nix::unistd::geteuid().is_root() {
+        let name = sandbox.user();
+        let user = User::from_name(name)
+            .with_context(|| format!("Failed to look up sandbox user: {name:?}"))?
+            .with_context(|| format!("Sandbox user does not exist: {name:?}"))?;
+        if user.uid.is_root() {
+            bail!("Sandbox user must not be root: {name:?}");
+        }
+        Some((user.uid.as_raw(), user.gid.as_raw()))
+    } else {
+        None
+    };
+
+    // SAFETY: only async-signal-safe functions are called in between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            let setrlimit = |resource, limit| {
+                let rlimit = libc::rlimit {
+                    rlim_cur: limit,
+                    rlim_max: limit,
+                };
+                if libc::setrlimit(resource, &rlimit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            };
+
+            if let Some((uid, gid)) = credentials
+                && (libc::setgroups(0, std::ptr::null()) != 0
+                    || libc::setgid(gid) != 0
+                    || libc::setuid(uid) != 0)
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            // The worker can't gain privileges, e.g. through setuid binaries
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // The worker never writes files, and doesn't dump its memory either
+            setrlimit(libc::RLIMIT_FSIZE, 0)?;
+            setrlimit(libc::RLIMIT_CORE, 0)?;
+            if let Some(limit) = memory_limit {
+                setrlimit(libc::RLIMIT_AS, limit)?;
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Syscalls the worker never needs, calling them fails with `EPERM`
+fn seccomp_filter() -> Result<BpfProgram> {
+    let denied = [
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_reboot,
+        // The flags of these can't be checked by the filter
+        libc::SYS_openat2,
+        libc::SYS_io_uring_setup,
+        libc::SYS_unlinkat,
+        libc::SYS_renameat2,
+        libc::SYS_mkdirat,
+        libc::SYS_fchmodat,
+        libc::SYS_fchownat,
+        libc::SYS_linkat,
+        libc::SYS_symlinkat,
+    ];
+    // Older syscalls that newer architectures only have as `*at` variant
+    #[cfg(target_arch = "x86_64")]
+    let denied = denied.into_iter().chain([
+        libc::SYS_unlink,
+        libc::SYS_rmdir,
+        libc::SYS_rename,
+        libc::SYS_renameat,
+        libc::SYS_mkdir,
+        libc::SYS_chmod,
+        libc::SYS_chown,
+        libc::SYS_lchown,
+        libc::SYS_link,
+        libc::SYS_symlink,
+        libc::SYS_creat,
+    ]);
+    let mut rules = denied
+        .into_iter()
+        .map(|syscall| (syscall, vec![]))
+        .collect::<BTreeMap<_, _>>();
+
+    // Files can only be opened for reading
+    let write_flags = |arg| {
+        [libc::O_WRONLY, libc::O_RDWR, libc::O_CREAT, libc::O_TRUNC]
+            .into_iter()
+            .map(|flag| {
+                let flag = flag as u64;
+                let cond = SeccompCondition::new(
+                    arg,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::MaskedEq(flag),
+                    flag,
+                )?;
+                Ok(SeccompRule::new(vec![cond])?)
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+    rules.insert(libc::SYS_openat, write_flags(2)?);
+    #[cfg(target_arch = "x86_64")]
+    rules.insert(libc::SYS_open, write_flags(1)?);
+
+    let arch = TargetArch::try_from(env::consts::ARCH)
+        .map_err(|err| anyhow!("Unsupported architecture for seccomp: {err:?}"))?;
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        arch,
+    )?;
+    let program = filter.try_into()?;
+    Ok(program)
+}
+
+/// Install the seccomp filter for all threads of the worker
+///
+/// This needs `PR_SET_NO_NEW_PRIVS`, which is set by the parent before exec.
+fn apply_seccomp() -> Result<()> {
+    let program = seccomp_filter()?;
+    seccompiler::apply_filter_all_threads(&program).context("Failed to apply seccomp filter")?;
+    Ok(())
+}
+
+/// The download has been started by the server
+#[derive(Debug, PartialEq)]
+pub struct Started {
+    /// The server continues at the requested offset
+    pub partial: bool,
+    pub last_modified: Option<String>,
+    /// Size of the remaining data, if the server announced it
+    pub content_length: Option<u64>,
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Worker<R, W> {
+    /// Talk to a worker over the given pipes
+    pub fn new(reader: R, writer: W) -> Self {
+        Worker {
+            _child: None,
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    async fn recv(&mut self) -> Result<Frame<Message>> {
+        read_frame(&mut self.reader)
+            .await
+            .context("Failed to read from worker")?
+            .context("Worker process exited unexpectedly")
+    }
+
+    async fn recv_message(&mut self) -> Result<Message> {
+        match self.recv().await? {
+            Frame::Message(msg) => Ok(msg),
+            Frame::Data(_) => bail!("Unexpected data frame from worker"),
+        }
+    }
+
+    /// Ask the worker to start a download, the error is the reason the download failed
+    pub async fn download(&mut self, url: Url, resume: Option<Range>) -> Result<Started, String> {
+        let job = Job::Download { url, resume };
+        write_message(&mut self.writer, &job)
+            .await
+            .map_err(|err| format!("{err:#}"))?;
+        match self
+            .recv_message()
+            .await
+            .map_err(|err| format!("{err:#}"))?
+        {
+            Message::Started {
+                partial,
+                last_modified,
+                content_length,
+            } => Ok(Started {
+                partial,
+                last_modified,
+                content_length,
+            }),
+            Message::Failed(err) => Err(err),
+            msg => Err(format!("Unexpected message from worker: {msg:?}")),
+        }
+    }
+
+    /// The next chunk of the download, `None` once the download is complete
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, String> {
+        match self.recv().await.map_err(|err| format!("{err:#}"))? {
+            Frame::Data(data) => Ok(Some(data)),
+            Frame::Message(Message::Downloaded) => Ok(None),
+            Frame::Message(Message::Failed(err)) => Err(err),
+            Frame::Message(msg) => Err(format!("Unexpected message from worker: {msg:?}")),
+        }
+    }
+
+    /// Send the file to the worker and let it parse the .deb metadata
+    pub async fn inspect<F: AsyncRead + Unpin>(&mut self, mut file: F) -> Result<Deb> {
+        write_message(&mut self.writer, &Job::Inspect).await?;
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = file.read(&mut buf).await?;
+            write_data(&mut self.writer, &buf[..n]).await?;
+            if n == 0 {
+                break;
+            }
+        }
+
+        match self.recv_message().await? {
+            Message::Inspected(result) => result.map_err(|err| anyhow!("{err}")),
+            msg => bail!("Unexpected message from worker: {msg:?}"),
+        }
+    }
+
+    /// Let the worker query rebuilders, and verify their answers as they arrive
+    ///
+    /// Attestations are parsed again, the stream ends if the worker goes away.
+    pub async fn fetch(
+        &mut self,
+        rebuilders: Vec<Url>,
+        package: Deb,
+    ) -> Result<impl Stream<Item = attestation::Answer> + '_> {
+        let pending = rebuilders.iter().cloned().collect::<BTreeSet<_>>();
+        write_message(
+            &mut self.writer,
+            &Job::Fetch {
+                rebuilders,
+                package,
+            },
+        )
+        .await?;
+
+        let answers = stream::unfold(
+            (
+                self,
+                pending,
+                BTreeMap::<Url, Result<attestation::Tree>>::new(),
+            ),
+            |(worker, mut pending, mut trees)| async move {
+                // The worker stays around for the next job, stop once everybody answered
+                while !pending.is_empty() {
+                    let msg = match worker.recv_message().await {
+                        Ok(msg) => msg,
+                        Err(err) => {
+                            warn!("Failed to receive rebuilder answers: {err:#}");
+                            return None;
+                        }
+                    };
+                    match msg {
+                        Message::Attestation {
+                            rebuilder,
+                            label,
+                            data,
+                        } => {
+                            if !pending.contains(&rebuilder) {
+                                continue;
+                            }
+                            let entry = trees
+                                .entry(rebuilder)
+                                .or_insert_with(|| Ok(Default::default()));
+                            let Ok(tree) = entry else {
+                                continue;
+                            };
+                            match data_encoding::BASE64
+                                .decode(data.as_bytes())
+                                .map_err(Error::from)
+                                .and_then(|bytes| {
+                                    if bytes.len() > MAX_ATTESTATION_SIZE {
+                                        bail!("Attestation exceeds {MAX_ATTESTATION_SIZE} bytes");
+                                    }
+                                    Attestation::parse(&bytes)
+                                }) {
+                                Ok(attestation) => tree.insert(label, attestation),
+                                Err(err) => *entry = Err(err),
+                            }
+                        }
+                        Message::Answered {
+                            rebuilder,
+                            latency_ms,
+                            error,
+                        } => {
+                            if !pending.remove(&rebuilder) {
+                                continue;
+                            }
+                            let tree = trees
+                                .remove(&rebuilder)
+                                .unwrap_or_else(|| Ok(Default::default()));
+                            let result = match error {
+                                Some(err) => Err(anyhow!("{err}")),
+                                None => tree,
+                            };
+                            let latency = Duration::from_millis(latency_ms);
+                            let answer = (rebuilder, latency, result);
+                            return Some((answer, (worker, pending, trees)));
+                        }
+                        msg => {
+                            warn!("Unexpected message from worker: {msg:?}");
+                            return None;
+                        }
+                    }
+                }
+                None
+            },
+        );
+        Ok(answers)
+    }
+}
+
+/// The worker side, runs until the parent closes the pipe
+pub async fn serve<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    reader: R,
+    mut writer: W,
+) -> Result<()> {
+    let mut reader = BufReader::new(reader);
+    let Some(Frame::Message(config)) = read_frame::<_, Config>(&mut reader).await? else {
+        bail!("Expected config as first message");
+    };
+    let http = http::client_for_config(&config)?;
+
+    while let Some(frame) = read_frame::<_, Job>(&mut reader).await? {
+        let Frame::Message(job) = frame else {
+            bail!("Unexpected data frame");
+        };
+        debug!("Received job from parent: {job:?}");
+        match job {
+            Job::Download { url, resume } => download(&http, &mut writer, url, resume).await?,
+            Job::Inspect => {
+                let result = inspect(&mut reader).await?;
+                let result = result.map_err(|err| format!("{err:#}"));
+                write_message(&mut writer, &Message::Inspected(result)).await?;
+            }
+            Job::Fetch {
+                rebuilders,
+                package,
+            } => fetch(&http, &mut writer, rebuilders, package).await?,
+        }
+    }
+
+    Ok(())
+}
+
+async fn download<W: AsyncWrite + Unpin>(
+    http: &http::Client,
+    writer: &mut W,
+    url: Url,
+    resume: Option<Range>,
+) -> Result<()> {
+    let mut request = http.get(url.clone());
+    if let Some(resume) = &resume {
+        request = request
+            .header(RANGE, format!("bytes={}-", resume.offset))
+            .header(IF_RANGE, &resume.last_modified);
+    }
+    let mut response = match request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+    {
+        Ok(response) => response,
+        Err(err) => return write_message(writer, &Message::Failed(format!("{err:#}"))).await,
+    };
+    http::log_redirect(&url, &response);
+
+    let started = Message::Started {
+        partial: resume.is_some() && response.status() == StatusCode::PARTIAL_CONTENT,
+        last_modified: response
+            .headers()
+            .get("Last-Modified")
+            .and_then(|v| v.to_str().ok())
+            .map(String::from),
+        content_length: response.content_length(),
+    };
+    write_message(writer, &started).await?;
+
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => write_data(writer, &chunk).await?,
+            Ok(None) => return write_message(writer, &Message::Downloaded).await,
+            Err(err) => return write_message(writer, &Message::Failed(format!("{err:#}"))).await,
+        }
+    }
+}
+
+/// Parse the .deb from the data frames, all frames are read even if parsing finishes early
+async fn inspect<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Result<Deb>> {
+    let (mut tx, rx) = io::duplex(CHUNK_SIZE);
+    let task = tokio::spawn(inspect::deb::inspect(rx));
+
+    loop {
+        match read_frame::<_, Job>(reader).await? {
+            Some(Frame::Data(data)) if data.is_empty() => break,
+            // Once the parser is done the pipe is closed, the remaining data is discarded
+            Some(Frame::Data(data)) => {
+                let _ = tx.write_all(&data).await;
+            }
+            Some(Frame::Message(job)) => bail!("Unexpected job during inspection: {job:?}"),
+            None => bail!("Parent closed the pipe during inspection"),
+        }
+    }
+    drop(tx);
+
+    task.await.context("Failed to join inspection task")
+}
+
+async fn fetch<W: AsyncWrite + Unpin>(
+    http: &http::Client,
+    writer: &mut W,
+    rebuilders: Vec<Url>,
+    package: Deb,
+) -> Result<()> {
+    let mut tasks = attestation::spawn_queries(http, rebuilders, package);
+    while let Some(res) = tasks.join_next().await {
+        let (rebuilder, latency, result) = match res {
+            Ok(res) => res,
+            Err(err) => {
+                warn!("Rebuilder task panicked: {err:#}");
+                continue;
+            }
+        };
+        let error = match result {
+            Ok(tree) => {
+                for (label, attestation) in tree.iter() {
+                    let msg = Message::Attestation {
+                        rebuilder: rebuilder.clone(),
+                        label: label.clone(),
+                        data: data_encoding::BASE64.encode(attestation.as_bytes()),
+                    };
+                    write_message(writer, &msg).await?;
+                }
+                None
+            }
+            Err(err) => Some(format!("{err:#}")),
+        };
+        let msg = Message::Answered {
+            rebuilder,
+            latency_ms: latency.as_millis() as u64,
+            error,
+        };
+        write_message(writer, &msg).await?;
+    }
+    Ok(())
+}
+
+pub async fn run() -> Result<()> {
+    apply_seccomp()?;
+    serve(io::stdin(), io::stdout()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use futures::StreamExt;
+
+    /// Connect a worker to `serve` running in this process
+    async fn connect() -> Worker<io::DuplexStream, io::DuplexStream> {
+        let (parent_tx, worker_rx) = io::duplex(CHUNK_SIZE);
+        let (worker_tx, parent_rx) = io::duplex(CHUNK_SIZE);
+        tokio::spawn(serve(worker_rx, worker_tx));
+
+        let mut worker = Worker::new(parent_rx, parent_tx);
+        write_message(&mut worker.writer, &Config::default())
+            .await
+            .unwrap();
+        worker
+    }
+
+    #[tokio::test]
+    async fn test_frame_limit() {
+        let (mut tx, mut rx) = io::duplex(64);
+        tx.write_u8(TAG_DATA).await.unwrap();
+        tx.write_u32(MAX_FRAME_SIZE as u32 + 1).await.unwrap();
+        let err = read_frame::<_, Message>(&mut rx).await.unwrap_err();
+        assert!(err.to_string().contains("Frame exceeds"));
+    }
+
+    #[test]
+    fn test_seccomp_filter() {
+        let program = seccomp_filter().unwrap();
+        assert!(!program.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_frame_eof() {
+        let (tx, mut rx) = io::duplex(64);
+        drop(tx);
+        assert!(read_frame::<_, Message>(&mut rx).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_worker_download() {
+        let mirror = test_server::spawn_mirror().await;
+        let mut worker = connect().await;
+
+        let url = mirror.join("pool/test.deb").unwrap();
+        let started = worker.download(url, None).await.unwrap();
+        assert_eq!(
+            started,
+            Started {
+                partial: false,
+                last_modified: None,
+                content_length: Some(std::fs::metadata(test_server::DEB_PATH).unwrap().len()),
+            }
+        );
+
+        let mut downloaded = Vec::new();
+        while let Some(chunk) = worker.chunk().await.unwrap() {
+            downloaded.extend_from_slice(&chunk);
+        }
+        assert_eq!(
+            downloaded,
+            tokio::fs::read(test_server::DEB_PATH).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_worker_download_failed() {
+        let mirror = test_server::spawn_mirror().await;
+        let mut worker = connect().await;
+
+        let url = mirror.join("pool/missing.deb").unwrap();
+        let err = worker.download(url, None).await.unwrap_err();
+        assert!(err.contains("404"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_worker_fetch() {
+        let rebuilder = test_server::spawn_rebuilder().await;
+        let unreachable = "http://127.0.0.1:1/".parse::<Url>().unwrap();
+        let mut worker = connect().await;
+
+        let rebuilders = vec![rebuilder.clone(), unreachable.clone()];
+        let answers = worker
+            .fetch(rebuilders, test_server::deb())
+            .await
+            .unwrap()
+            .map(|(url, _, result)| (url, result))
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        assert_eq!(answers.len(), 2);
+        assert!(answers[&unreachable].is_err());
+        let attestations = answers[&rebuilder].as_ref().unwrap();
+        assert!(!attestations.is_empty());
+    }
+}
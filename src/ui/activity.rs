@@ -0,0 +1,133 @@
+use crate::app::App;
+use crate::audit::{Outcome, Record};
+use crate::i18n::tr;
+use crate::ui::{self, Theme};
+use ratatui::{
+    prelude::*,
+    widgets::{
+        Block, BorderType, Clear, HighlightSpacing, List, ListItem, Paragraph, Scrollbar,
+        ScrollbarOrientation, ScrollbarState, Wrap,
+    },
+};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+fn outcome(theme: &Theme, outcome: Outcome) -> Span<'static> {
+    match outcome {
+        Outcome::Reproduced => Span::styled(tr!("outcome-reproduced"), theme.positive),
+        Outcome::BlindlyTrusted => Span::styled(tr!("outcome-blindly-trusted"), theme.warning),
+        Outcome::GracePeriod => Span::styled(tr!("outcome-grace-period"), theme.warning),
+        Outcome::Rejected => Span::styled(tr!("outcome-rejected"), theme.negative),
+    }
+}
+
+fn list_item(theme: &Theme, record: &Record) -> ListItem<'static> {
+    ListItem::new(Line::from_iter([
+        Span::styled(record.at.format(TIME_FORMAT).to_string(), theme.hint),
+        Span::raw(" "),
+        outcome(theme, record.outcome),
+        Span::raw(format!(" {}", record.subject().escape_default())),
+    ]))
+}
+
+fn details(theme: &Theme, record: &Record) -> Vec<Line<'static>> {
+    let mut lines = vec![
+        Line::raw(tr!("detail-time", at = record.at.format(TIME_FORMAT))),
+        Line::from_iter([
+            Span::raw(tr!("detail-outcome")),
+            outcome(theme, record.outcome),
+        ]),
+    ];
+    if let Some(package) = &record.package {
+        lines.push(Line::raw(tr!(
+            "detail-package",
+            package = record.subject().escape_default()
+        )));
+        lines.push(Line::raw(tr!(
+            "detail-source",
+            source = package.source.escape_default(),
+            version = package.source_version().escape_default()
+        )));
+    }
+    lines.push(Line::raw(tr!(
+        "detail-url",
+        url = record.url.escape_default()
+    )));
+    if let Some(sha256) = &record.sha256 {
+        lines.push(Line::raw(tr!("detail-sha256", sha256 = sha256)));
+    }
+    if !record.rebuilders.is_empty() {
+        lines.push(Line::raw(tr!(
+            "detail-confirmed-by",
+            rebuilders = record.rebuilders.join(", ")
+        )));
+    }
+    if let Some(reason) = &record.reason {
+        lines.push(Line::raw(tr!("detail-reason", reason = reason)));
+    }
+    if let Some(message) = &record.message {
+        lines.push(Line::raw(""));
+        lines.push(Line::raw(message.escape_default().to_string()));
+    }
+    lines.push(Line::raw(""));
+    lines.push(Line::styled(tr!("activity-close-hint"), theme.hint));
+    lines
+}
+
+impl App {
+    pub fn render_activity(&mut self, area: Rect, buf: &mut Buffer) {
+        let block = ui::container(&self.theme);
+
+        let items = if self.activity.is_empty() {
+            vec![ListItem::new(Span::styled(
+                tr!("activity-empty"),
+                Style::new().italic(),
+            ))]
+        } else {
+            self.activity
+                .iter()
+                .map(|record| list_item(&self.theme, record))
+                .collect::<Vec<_>>()
+        };
+
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(self.theme.selected)
+            .highlight_symbol("> ")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        StatefulWidget::render(&list, area, buf, self.scroll());
+
+        Scrollbar::new(ScrollbarOrientation::VerticalRight)
+            .begin_symbol(None)
+            .end_symbol(None)
+            .track_symbol(None)
+            .render(
+                area.inner(Margin {
+                    horizontal: 0,
+                    vertical: 1,
+                }),
+                buf,
+                &mut ScrollbarState::new(list.len())
+                    .position(self.scroll().selected().unwrap_or_default()),
+            );
+
+        if let Some(crate::app::View::Activity { details: true, .. }) = self.view
+            && let Some(record) = self
+                .scroll()
+                .selected()
+                .and_then(|idx| self.activity.get(idx))
+        {
+            let popup = Paragraph::new(details(&self.theme, record))
+                .wrap(Wrap { trim: false })
+                .block(
+                    Block::bordered()
+                        .title(tr!("activity-details-title"))
+                        .border_type(BorderType::Rounded),
+                );
+            let popup_area = ui::centered_area(area, 80, 60);
+            Clear.render(popup_area, buf);
+            popup.render(popup_area, buf);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::app::App;
-use crate::ui::{self, SELECTED_STYLE};
+use crate::i18n::tr;
+use crate::ui;
 use ratatui::{
     prelude::*,
     widgets::{HighlightSpacing, List, ListItem, Scrollbar, ScrollbarOrientation, ScrollbarState},
@@ -8,24 +9,31 @@ use std::iter;
 
 impl App {
     pub fn render_blindly_trust(&mut self, area: Rect, buf: &mut Buffer) {
-        let block = ui::container();
+        let block = ui::container(&self.theme);
 
         let items = iter::once(ListItem::from(Span::styled(
-                "Use `repro-threshold plumbing [add-blindly-trust|remove-blindly-trust] <package>` to update",
-                Style::new().italic()
-            )))
-            .chain(
-                self.config
-                    .rules
-                    .blindly_trust
-                    .iter()
-                    .map(|s| ListItem::from(format!("Always blindly trust: {s}"))),
-            )
-            .collect::<Vec<_>>();
+            tr!("blindly-hint"),
+            Style::new().italic(),
+        )))
+        .chain(
+            self.config
+                .rules
+                .blindly_trust
+                .iter()
+                .map(|s| ListItem::from(tr!("blindly-package", package = s))),
+        )
+        .chain(
+            self.config
+                .rules
+                .blindly_trust_source
+                .iter()
+                .map(|s| ListItem::from(tr!("blindly-source", package = s))),
+        )
+        .collect::<Vec<_>>();
 
         let list = List::new(items)
             .block(block)
-            .highlight_style(SELECTED_STYLE)
+            .highlight_style(self.theme.selected)
             .highlight_symbol("> ")
             .highlight_spacing(HighlightSpacing::Always);
 
@@ -1,5 +1,6 @@
 use crate::app::App;
-use crate::ui::{self, COLOR_NEGATIVE, COLOR_POSITIVE, COLOR_WARNING, SELECTED_STYLE};
+use crate::i18n::tr;
+use crate::ui;
 use ratatui::{
     prelude::*,
     widgets::{HighlightSpacing, List, ListItem},
@@ -7,39 +8,66 @@ use ratatui::{
 
 impl App {
     pub fn render_home(&mut self, area: Rect, buf: &mut Buffer) {
-        let block = ui::container();
+        let block = ui::container(&self.theme);
 
         let required_threshold = self.config.rules.required_threshold;
         let trusted_rebuilders = self.config.trusted_rebuilders.len();
 
-        let items = vec![
-            ListItem::new(Line::from_iter([
-                Span::raw("Required reproduction threshold: "),
+        let threshold = if self.config.rules.is_disabled() {
+            Line::from_iter([
+                Span::raw(tr!("home-threshold")),
+                Span::styled(tr!("home-threshold-disabled"), self.theme.negative),
+            ])
+        } else {
+            Line::from_iter([
+                Span::raw(tr!("home-threshold")),
                 Span::styled(
                     required_threshold.to_string(),
                     match required_threshold {
-                        0 => COLOR_NEGATIVE,
-                        1 => COLOR_WARNING,
-                        num if num <= trusted_rebuilders => COLOR_POSITIVE,
-                        _ => COLOR_NEGATIVE,
+                        1 => self.theme.warning,
+                        num if num <= trusted_rebuilders => self.theme.positive,
+                        _ => self.theme.negative,
                     },
                 ),
                 Span::raw("/"),
                 Span::raw(format!("{trusted_rebuilders}")),
-            ])),
-            ListItem::new(format!(
-                "Configure trusted rebuilders ({trusted_rebuilders} selected)"
-            )),
-            ListItem::new(format!(
-                "Add/remove packages from 'blindly-trust' set ({} entries)",
-                self.config.rules.blindly_trust.len()
+            ])
+        };
+
+        let profile = match &self.config.active_profile {
+            _ if self.config.profiles.is_empty() => Line::from_iter([
+                Span::raw(tr!("home-profile")),
+                Span::styled(tr!("home-profile-none-hint"), self.theme.hint),
+            ]),
+            Some(name) => Line::from_iter([
+                Span::raw(tr!("home-profile")),
+                Span::styled(name.escape_default().to_string(), self.theme.positive),
+                Span::styled(" (+/-)", self.theme.hint),
+            ]),
+            None => Line::from_iter([
+                Span::raw(tr!("home-profile")),
+                Span::styled(tr!("home-profile-none"), self.theme.hint),
+                Span::styled(" (+/-)", self.theme.hint),
+            ]),
+        };
+
+        let items = vec![
+            ListItem::new(threshold),
+            ListItem::new(profile),
+            ListItem::new(tr!("home-menu-rebuilders", count = trusted_rebuilders)),
+            ListItem::new(tr!(
+                "home-menu-blindly",
+                count = self.config.rules.blindly_trust.len()
+                    + self.config.rules.blindly_trust_source.len()
             )),
-            ListItem::new("Quit"),
+            ListItem::new(tr!("home-menu-activity", count = self.activity.len())),
+            ListItem::new(tr!("home-menu-verify")),
+            ListItem::new(tr!("home-menu-quit")),
         ];
 
         let list = List::new(items)
             .block(block)
-            .highlight_style(SELECTED_STYLE)
+            .highlight_style(self.theme.selected)
             .highlight_symbol("> ")
             .highlight_spacing(HighlightSpacing::Always);
 
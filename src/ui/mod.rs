@@ -1,54 +1,100 @@
+mod activity;
 mod blindly;
 mod home;
 mod rebuilders;
+mod theme;
+mod verify;
 
-use crate::app::App;
+pub use theme::Theme;
+
+use crate::app::{App, Confirm};
+use crate::i18n::tr;
 use ratatui::{
     layout::Flex,
     prelude::*,
-    widgets::{Block, BorderType, Clear},
+    widgets::{Block, BorderType, Clear, Paragraph, Wrap},
 };
 
-const SELECTED_STYLE: Style = Style::new().bg(Color::Reset).add_modifier(Modifier::BOLD);
-const COLOR_POSITIVE: Color = Color::Green;
-const COLOR_WARNING: Color = Color::Yellow;
-const COLOR_NEGATIVE: Color = Color::Red;
-
 const TITLE: &str = concat!(
     "repro-threshold ",
     env!("CARGO_PKG_VERSION"),
     " (experimental)"
 );
-const TITLE_STYLE: Style = Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD);
 
-fn container() -> Block<'static> {
+fn container(theme: &Theme) -> Block<'static> {
     Block::bordered()
         .title(TITLE)
         .title_alignment(Alignment::Center)
-        .title_style(TITLE_STYLE)
+        .title_style(theme.title)
         .border_type(BorderType::Rounded)
 }
 
 impl Widget for &mut App {
     fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = if self.key_changes.rebuilders.is_empty() {
+            area
+        } else {
+            let [banner, area] = area.layout(&Layout::vertical([
+                Constraint::Length(1),
+                Constraint::Fill(1),
+            ]));
+            let rebuilders = self
+                .key_changes
+                .rebuilders
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            Line::styled(
+                tr!("banner-key-changed", rebuilders = rebuilders),
+                self.theme
+                    .negative
+                    .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            )
+            .render(banner, buf);
+            area
+        };
+
+        let area = match &self.status {
+            Some(status) => {
+                let [area, line] = area.layout(&Layout::vertical([
+                    Constraint::Fill(1),
+                    Constraint::Length(1),
+                ]));
+                Line::styled(status.clone(), self.theme.hint).render(line, buf);
+                area
+            }
+            None => area,
+        };
+
         match self.view {
             Some(crate::app::View::Home) => self.render_home(area, buf),
             Some(crate::app::View::Rebuilders { .. }) => self.render_rebuilders(area, buf),
             Some(crate::app::View::BlindlyTrust { .. }) => self.render_blindly_trust(area, buf),
+            Some(crate::app::View::Activity { .. }) => self.render_activity(area, buf),
+            Some(crate::app::View::Verify { .. }) => self.render_verify(area, buf),
             None => {}
         }
 
-        if self.confirm {
-            let popup = Block::bordered().title("Are you sure?");
+        if let Some(confirm) = self.confirm {
+            let popup = Block::bordered().title(tr!("confirm-title"));
+            let question = match confirm {
+                Confirm::DisableEnforcement => tr!("confirm-disable-enforcement"),
+            };
             let popup_area = centered_area(area, 60, 40);
             // clears out any background in the area before rendering the popup
             Clear.render(popup_area, buf);
-            popup.render(popup_area, buf);
+            Paragraph::new(question)
+                .wrap(Wrap { trim: true })
+                .block(popup)
+                .render(popup_area, buf);
         }
+
+        self.render_prompt(area, buf);
     }
 }
 
-fn centered_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+pub(super) fn centered_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
     let vertical = Layout::vertical([Constraint::Percentage(percent_y)]).flex(Flex::Center);
     let horizontal = Layout::horizontal([Constraint::Percentage(percent_x)]).flex(Flex::Center);
     let [area] = area.layout(&vertical);
@@ -1,6 +1,10 @@
 use crate::app::App;
+use crate::i18n::tr;
 use crate::rebuilder::{Rebuilder, Selectable};
-use crate::ui::{self, COLOR_POSITIVE, SELECTED_STYLE};
+use crate::signing;
+use crate::stats::RebuilderStats;
+use crate::ui::{self, Theme};
+use chrono::Utc;
 use ratatui::{
     prelude::*,
     widgets::{HighlightSpacing, List, ListItem, Scrollbar, ScrollbarOrientation, ScrollbarState},
@@ -8,23 +12,35 @@ use ratatui::{
 
 impl App {
     pub fn render_rebuilders(&mut self, area: Rect, buf: &mut Buffer) {
-        let block = ui::container();
+        let block = ui::container(&self.theme);
 
         let items = if self.rebuilders.is_empty() {
             vec![ListItem::new(Span::styled(
-                "No rebuilders configured, press ctrl-R to load community set, or run `repro-threshold plumbing add-rebuilder <url>` to add one",
+                tr!("rebuilders-empty"),
                 Style::new().italic(),
             ))]
         } else {
+            let now = Utc::now();
             self.rebuilders
                 .iter()
-                .map(ListItem::from)
+                .map(|rebuilder| {
+                    let notice = rebuilder
+                        .item
+                        .expiry_notice(now)
+                        .or_else(|| self.config.upstream_status(&rebuilder.item).notice());
+                    list_item(
+                        &self.theme,
+                        rebuilder,
+                        notice,
+                        self.stats.get(&rebuilder.item.url),
+                    )
+                })
                 .collect::<Vec<_>>()
         };
 
         let list = List::new(items)
             .block(block)
-            .highlight_style(SELECTED_STYLE)
+            .highlight_style(self.theme.selected)
             .highlight_symbol("> ")
             .highlight_spacing(HighlightSpacing::Always);
 
@@ -46,36 +62,56 @@ impl App {
     }
 }
 
-impl From<&Selectable<Rebuilder>> for ListItem<'_> {
-    fn from(value: &Selectable<Rebuilder>) -> Self {
-        let mut line = Line::from_iter([
-            if value.active {
-                Span::styled("✓", COLOR_POSITIVE)
-            } else {
-                Span::raw("☐")
-            },
-            Span::raw(format!(
-                " {} - {}",
-                value.item.name.escape_default(),
-                value.item.url
-            )),
-        ]);
+fn list_item(
+    theme: &Theme,
+    value: &Selectable<Rebuilder>,
+    notice: Option<&str>,
+    stats: Option<&RebuilderStats>,
+) -> ListItem<'static> {
+    let mut line = Line::from_iter([
+        if value.active {
+            Span::styled(theme.checked, theme.positive)
+        } else {
+            Span::raw(theme.unchecked)
+        },
+        Span::raw(format!(
+            " {} - {}",
+            value.item.name.escape_default(),
+            value.item.url
+        )),
+    ]);
 
-        if !value.item.distributions.is_empty() {
-            line.push_span(Span::raw(" ["));
-            for (i, dist) in value.item.distributions.iter().enumerate() {
-                if i > 0 {
-                    line.push_span(Span::raw(", "));
-                }
-                line.push_span(Span::raw(dist.escape_default().to_string()));
+    if !value.item.distributions.is_empty() {
+        line.push_span(Span::raw(" ["));
+        for (i, dist) in value.item.distributions.iter().enumerate() {
+            if i > 0 {
+                line.push_span(Span::raw(", "));
             }
-            line.push_span(Span::raw("]"));
+            line.push_span(Span::raw(dist.escape_default().to_string()));
         }
+        line.push_span(Span::raw("]"));
+    }
 
-        if let Ok(key) = value.item.signing_key() {
-            line.push_span(Span::raw(format!(" - {:?}", key.key_id())));
+    if let Ok(fingerprint) = value.item.fingerprint() {
+        line.push_span(Span::raw(format!(
+            " - {}",
+            signing::format_fingerprint(&fingerprint)
+        )));
+        if value.item.is_verified_out_of_band() {
+            line.push_span(Span::styled(
+                format!(" {} {}", theme.checked, tr!("rebuilders-verified")),
+                theme.positive,
+            ));
         }
+    }
 
-        ListItem::new(line)
+    if let Some(stats) = stats {
+        line.push_span(Span::styled(format!(" ({})", stats.summary()), theme.hint));
     }
+
+    if let Some(notice) = notice {
+        line.push_span(Span::styled(format!(" ({notice})"), theme.warning));
+    }
+
+    ListItem::new(line)
 }
@@ -0,0 +1,102 @@
+use crate::config::{ThemeName, Ui};
+use ratatui::prelude::*;
+use std::env;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub title: Style,
+    pub selected: Style,
+    pub positive: Style,
+    pub warning: Style,
+    pub negative: Style,
+    pub hint: Style,
+    pub checked: &'static str,
+    pub unchecked: &'static str,
+}
+
+impl Theme {
+    /// The theme from the config, `NO_COLOR` overrides the configured colors
+    pub fn new(ui: &Ui) -> Self {
+        // https://no-color.org/
+        let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let name = if no_color {
+            ThemeName::NoColor
+        } else {
+            ui.theme
+        };
+        Self::build(name, ui.ascii)
+    }
+
+    fn build(name: ThemeName, ascii: bool) -> Self {
+        let (checked, unchecked) = if ascii {
+            ("[x]", "[ ]")
+        } else {
+            ("✓", "☐")
+        };
+        let bold = Style::new().add_modifier(Modifier::BOLD);
+        let (title, selected, positive, warning, negative, hint) = match name {
+            ThemeName::Default => (
+                bold.fg(Color::Yellow),
+                bold.bg(Color::Reset),
+                Style::new().fg(Color::Green),
+                Style::new().fg(Color::Yellow),
+                Style::new().fg(Color::Red),
+                Style::new().dim(),
+            ),
+            ThemeName::NoColor => (
+                bold,
+                bold.add_modifier(Modifier::REVERSED),
+                Style::new(),
+                Style::new(),
+                Style::new(),
+                Style::new().italic(),
+            ),
+            ThemeName::HighContrast => (
+                bold.fg(Color::White),
+                bold.add_modifier(Modifier::REVERSED),
+                bold.fg(Color::LightGreen),
+                bold.fg(Color::LightYellow),
+                bold.fg(Color::LightRed),
+                Style::new().italic(),
+            ),
+        };
+        Theme {
+            title,
+            selected,
+            positive,
+            warning,
+            negative,
+            hint,
+            checked,
+            unchecked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_color() {
+        let theme = Theme::build(ThemeName::NoColor, false);
+        for style in [
+            theme.title,
+            theme.selected,
+            theme.positive,
+            theme.warning,
+            theme.negative,
+        ] {
+            assert_eq!(style.fg, None);
+            assert_eq!(style.bg, None);
+        }
+    }
+
+    #[test]
+    fn test_ascii() {
+        let theme = Theme::build(ThemeName::Default, true);
+        assert!(theme.checked.is_ascii());
+        assert!(theme.unchecked.is_ascii());
+        assert_ne!(theme.checked, theme.unchecked);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::app::{App, Verification, View};
+use crate::i18n::tr;
+use crate::policy::{Ballot, Outcome};
+use crate::ui::{self, Theme};
+use ratatui::{
+    prelude::*,
+    widgets::{Block, BorderType, Clear, Paragraph, Wrap},
+};
+
+fn outcome(theme: &Theme, outcome: Outcome) -> Span<'static> {
+    match outcome {
+        Outcome::Reproduced => Span::styled(tr!("outcome-reproduced"), theme.positive),
+        Outcome::BlindlyTrusted => Span::styled(tr!("outcome-blindly-trusted"), theme.warning),
+        Outcome::GracePeriod | Outcome::PartialResults | Outcome::Offline => {
+            Span::styled(tr!("outcome-not-reproduced-yet"), theme.warning)
+        }
+        Outcome::Rejected => Span::styled(tr!("outcome-rejected"), theme.negative),
+    }
+}
+
+fn verification(theme: &Theme, verification: &Verification) -> Vec<Line<'static>> {
+    let package = &verification.package;
+    let decision = &verification.decision;
+    let mut lines = vec![
+        Line::from_iter([
+            Span::raw(tr!("detail-outcome")),
+            outcome(theme, decision.outcome),
+        ]),
+        Line::raw(tr!(
+            "detail-package",
+            package = format!(
+                "{} {} ({})",
+                package.name, package.version, package.architecture
+            )
+            .escape_default()
+        )),
+        Line::raw(tr!(
+            "detail-source",
+            source = package.source.escape_default(),
+            version = package.source_version().escape_default()
+        )),
+        Line::raw(tr!(
+            "detail-sha256",
+            sha256 = data_encoding::HEXLOWER.encode(&verification.sha256)
+        )),
+        Line::raw(tr!(
+            "detail-signatures",
+            confirms = decision.confirms(),
+            threshold = decision.threshold
+        )),
+    ];
+    let confirmed = decision.rebuilders(Ballot::Confirm);
+    if !confirmed.is_empty() {
+        lines.push(Line::raw(tr!(
+            "detail-confirmed-by",
+            rebuilders = confirmed.join(", ")
+        )));
+    }
+    let disagreed = decision.rebuilders(Ballot::Disagree);
+    if !disagreed.is_empty() {
+        lines.push(Line::styled(
+            tr!("detail-disagreed", rebuilders = disagreed.join(", ")),
+            theme.negative,
+        ));
+    }
+    if !verification.unreachable.is_empty() {
+        let unreachable = verification
+            .unreachable
+            .iter()
+            .map(|url| url.as_str())
+            .collect::<Vec<_>>();
+        lines.push(Line::styled(
+            tr!("detail-unreachable", rebuilders = unreachable.join(", ")),
+            theme.warning,
+        ));
+    }
+    lines.push(Line::raw(""));
+    lines.push(Line::raw(decision.message()));
+    lines
+}
+
+impl App {
+    pub fn render_verify(&mut self, area: Rect, buf: &mut Buffer) {
+        let Some(View::Verify { path, result }) = &self.view else {
+            return;
+        };
+
+        let mut lines = vec![
+            Line::raw(tr!(
+                "detail-file",
+                path = path.to_string_lossy().escape_default()
+            )),
+            Line::raw(""),
+        ];
+        match result {
+            None => lines.push(Line::styled(tr!("verify-checking"), Style::new().italic())),
+            Some(Ok(result)) => lines.extend(verification(&self.theme, result)),
+            Some(Err(err)) => lines.push(Line::styled(
+                format!("{err:#}").escape_default().to_string(),
+                self.theme.negative,
+            )),
+        }
+        lines.push(Line::raw(""));
+        lines.push(Line::styled(tr!("verify-result-hint"), self.theme.hint));
+
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(ui::container(&self.theme))
+            .render(area, buf);
+    }
+
+    /// The path prompt, drawn on top of the current view
+    pub fn render_prompt(&self, area: Rect, buf: &mut Buffer) {
+        let Some(prompt) = &self.prompt else {
+            return;
+        };
+        let popup = Paragraph::new(vec![
+            Line::raw(format!("{}_", prompt.escape_default())),
+            Line::raw(""),
+            Line::styled(tr!("verify-input-hint"), self.theme.hint),
+        ])
+        .wrap(Wrap { trim: false })
+        .block(
+            Block::bordered()
+                .title(tr!("verify-input-title"))
+                .border_type(BorderType::Rounded),
+        );
+        let popup_area = ui::centered_area(area, 70, 30);
+        Clear.render(popup_area, buf);
+        popup.render(popup_area, buf);
+    }
+}
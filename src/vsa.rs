@@ -0,0 +1,311 @@
+//! SLSA Verification Summary Attestations, so other systems can rely on our decision
+//!
+//! The in-toto statement is wrapped in a DSSE envelope and signed with a `Signer`.
+use crate::attestation::Confirm;
+use crate::errors::*;
+use crate::policy::{Decision, Outcome};
+use crate::signing::{self, Signer};
+use chrono::{DateTime, SecondsFormat, Utc};
+use in_toto::crypto::{KeyId, PublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+pub const PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+const PREDICATE_TYPE: &str = "https://slsa.dev/verification_summary/v1";
+const VERIFIER_ID: &str = "https://github.com/kpcyrd/repro-threshold";
+const POLICY_URI: &str = "https://github.com/kpcyrd/repro-threshold#threshold";
+
+/// The policy that was evaluated
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Policy {
+    pub threshold: usize,
+    /// Key ids of the signing keys that were accepted
+    pub signing_keys: Vec<KeyId>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    /// The name of the artifact, e.g. its filename
+    pub artifact: String,
+    pub sha256: String,
+    pub policy: Policy,
+    /// The attestations that confirmed the artifact, with their sha256
+    pub inputs: Vec<(String, String)>,
+    /// The artifact was reproduced, blindly trusted artifacts are not verified
+    pub passed: bool,
+    pub verified_at: DateTime<Utc>,
+}
+
+impl Summary {
+    /// Summarize a decision, `confirms` are the attestations that were counted for it
+    pub fn new<'a, I: IntoIterator<Item = &'a PublicKey>>(
+        artifact: String,
+        sha256: &[u8],
+        decision: &Decision,
+        signing_keys: I,
+        confirms: &BTreeMap<KeyId, Confirm>,
+    ) -> Self {
+        let inputs = confirms
+            .values()
+            .map(|confirm| {
+                let (label, attestation) = confirm.attestation.as_ref();
+                let sha256 = Sha256::digest(attestation.as_bytes());
+                (label.clone(), data_encoding::HEXLOWER.encode(&sha256))
+            })
+            .collect();
+        Summary {
+            artifact,
+            sha256: data_encoding::HEXLOWER.encode(sha256),
+            policy: Policy {
+                threshold: decision.threshold,
+                signing_keys: signing_keys
+                    .into_iter()
+                    .map(|key| key.key_id().clone())
+                    .collect(),
+            },
+            inputs,
+            passed: decision.outcome == Outcome::Reproduced,
+            verified_at: Utc::now(),
+        }
+    }
+
+    pub fn statement(&self) -> Result<serde_json::Value> {
+        let policy = serde_json::to_value(&self.policy)?;
+        let policy_sha256 = Sha256::digest(serde_json::to_vec(&policy)?);
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|(uri, sha256)| serde_json::json!({ "uri": uri, "digest": { "sha256": sha256 } }))
+            .collect::<Vec<_>>();
+
+        Ok(serde_json::json!({
+            "_type": STATEMENT_TYPE,
+            "subject": [{ "name": self.artifact, "digest": { "sha256": self.sha256 } }],
+            "predicateType": PREDICATE_TYPE,
+            "predicate": {
+                "verifier": {
+                    "id": VERIFIER_ID,
+                    "version": { env!("CARGO_PKG_NAME"): env!("CARGO_PKG_VERSION") },
+                },
+                "timeVerified": self.verified_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "resourceUri": self.artifact,
+                "policy": {
+                    "uri": POLICY_URI,
+                    "digest": { "sha256": data_encoding::HEXLOWER.encode(&policy_sha256) },
+                    "annotations": policy,
+                },
+                "inputAttestations": inputs,
+                "verificationResult": if self.passed { "PASSED" } else { "FAILED" },
+                // Reproducible builds are not a SLSA track, so no levels are claimed
+                "verifiedLevels": [],
+                "slsaVersion": "1.0",
+            },
+        }))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Signature {
+    pub keyid: String,
+    pub sig: String,
+}
+
+/// A DSSE envelope
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub payload_type: String,
+    /// Base64 encoded payload
+    pub payload: String,
+    pub signatures: Vec<Signature>,
+}
+
+/// The DSSE pre-authentication encoding, this is what's signed
+fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut buf = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// The sha256 of the public key, in the same format as in-toto key ids
+fn key_id(public_key_pem: &str) -> Result<String> {
+    let pem = pem::parse(public_key_pem).context("Failed to parse pem file")?;
+    Ok(data_encoding::HEXLOWER.encode(&Sha256::digest(pem.contents())))
+}
+
+impl Envelope {
+    pub async fn sign(key: &Signer, statement: &serde_json::Value) -> Result<Self> {
+        let payload = serde_json::to_vec(statement)?;
+        let sig = key.sign(&pae(PAYLOAD_TYPE, &payload)).await?;
+        Ok(Envelope {
+            payload_type: PAYLOAD_TYPE.to_string(),
+            payload: data_encoding::BASE64.encode(&payload),
+            signatures: vec![Signature {
+                keyid: key_id(&key.public_key_pem())?,
+                sig: data_encoding::BASE64.encode(&sig),
+            }],
+        })
+    }
+
+    /// Verify the envelope was signed by this public key and return the statement
+    pub fn verify(&self, public_key_pem: &str) -> Result<serde_json::Value> {
+        if self.payload_type != PAYLOAD_TYPE {
+            bail!("Unexpected payload type: {:?}", self.payload_type);
+        }
+        let payload = data_encoding::BASE64
+            .decode(self.payload.as_bytes())
+            .context("Failed to decode payload")?;
+        let msg = pae(&self.payload_type, &payload);
+
+        let key_id = key_id(public_key_pem)?;
+        let verified = self
+            .signatures
+            .iter()
+            .filter(|sig| sig.keyid == key_id)
+            .filter_map(|sig| data_encoding::BASE64.decode(sig.sig.as_bytes()).ok())
+            .any(|sig| signing::verify_local_signature(public_key_pem, &msg, &sig).is_ok());
+        if !verified {
+            bail!("No valid signature by the expected key");
+        }
+
+        let statement = serde_json::from_slice(&payload).context("Failed to parse statement")?;
+        Ok(statement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation::{Attestation, Remote, Tree};
+    use crate::config::Config;
+    use crate::policy::Engine;
+    use crate::signing::LocalKey;
+    use crate::test_server;
+
+    const PRIVATE_KEY: &[u8] = &[
+        0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04,
+        0x20, 0x9d, 0x61, 0xb1, 0x9d, 0xef, 0xfd, 0x5a, 0x60, 0xba, 0x84, 0x4a, 0xf4, 0x92, 0xec,
+        0x2c, 0xc4, 0x44, 0x49, 0xc5, 0x69, 0x7b, 0x32, 0x69, 0x19, 0x70, 0x3b, 0xac, 0x03, 0x1c,
+        0xae, 0x7f, 0x60,
+    ];
+
+    fn summary() -> Summary {
+        Summary {
+            artifact: "filesystem-2025.10.12-1-any.pkg.tar.zst".to_string(),
+            sha256: "6b6c3fee7432204840d3b6afc9bc1a68c28f591a47fb220071715c40cca956df".to_string(),
+            policy: Policy {
+                threshold: 2,
+                signing_keys: vec![],
+            },
+            inputs: vec![(
+                "https://rebuilder.example.com/api/v1/builds/1/artifacts/1/attestation".to_string(),
+                "00".repeat(32),
+            )],
+            passed: false,
+            verified_at: "2025-10-20T12:00:00Z".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_pae() {
+        assert_eq!(
+            pae(PAYLOAD_TYPE, b"{}"),
+            b"DSSEv1 28 application/vnd.in-toto+json 2 {}"
+        );
+    }
+
+    #[test]
+    fn test_statement() {
+        let statement = summary().statement().unwrap();
+        assert_eq!(statement["predicateType"], PREDICATE_TYPE);
+        assert_eq!(
+            statement["subject"][0]["digest"]["sha256"],
+            summary().sha256
+        );
+        let predicate = &statement["predicate"];
+        assert_eq!(predicate["verificationResult"], "FAILED");
+        assert_eq!(predicate["timeVerified"], "2025-10-20T12:00:00Z");
+        assert_eq!(predicate["policy"]["annotations"]["threshold"], 2);
+        assert_eq!(
+            predicate["inputAttestations"][0]["digest"]["sha256"],
+            "00".repeat(32)
+        );
+    }
+
+    #[test]
+    fn test_summary_of_decision() {
+        let keyring = include_str!("../test_data/reproducible-archlinux.pub").to_string();
+        let mut config = Config::default();
+        config.rules.required_threshold = 1;
+        config.rules.min_out_of_band_verified = 1;
+        config.trusted_rebuilders.push(test_server::rebuilder(
+            "https://rebuilder.example.com/".parse().unwrap(),
+            keyring,
+        ));
+        let mut attestations = Tree::default();
+        attestations.insert(
+            "filesystem".to_string(),
+            Attestation::parse(include_bytes!(
+                "../test_data/filesystem-2025.10.12-1-any.in-toto.link"
+            ))
+            .unwrap(),
+        );
+        let remote = Remote {
+            attestations,
+            ..Default::default()
+        };
+        let sha256 = data_encoding::HEXLOWER
+            .decode(summary().sha256.as_bytes())
+            .unwrap();
+
+        // The threshold is reached, but the rebuilder wasn't verified out-of-band
+        let engine = Engine::new(&config);
+        let decision = engine.decide(None, &sha256, &remote);
+        let confirms = engine.counted(&decision, &sha256, &remote.attestations);
+        assert_eq!(decision.outcome, Outcome::Rejected);
+        assert_eq!(confirms.len(), decision.threshold);
+        let summary = Summary::new(
+            "filesystem".to_string(),
+            &sha256,
+            &decision,
+            engine.signing_keys(),
+            &confirms,
+        );
+        assert!(!summary.passed);
+        assert_eq!(summary.inputs.len(), 1);
+
+        config.rules.min_out_of_band_verified = 0;
+        let engine = Engine::new(&config);
+        let decision = engine.decide(None, &sha256, &remote);
+        let confirms = engine.counted(&decision, &sha256, &remote.attestations);
+        let summary = Summary::new(
+            "filesystem".to_string(),
+            &sha256,
+            &decision,
+            engine.signing_keys(),
+            &confirms,
+        );
+        assert!(summary.passed);
+    }
+
+    #[tokio::test]
+    async fn test_envelope_roundtrip() {
+        let key = Signer::Local(LocalKey::from_pkcs8(PRIVATE_KEY).unwrap());
+        let statement = summary().statement().unwrap();
+        let envelope = Envelope::sign(&key, &statement).await.unwrap();
+        assert_eq!(envelope.verify(&key.public_key_pem()).unwrap(), statement);
+
+        let mut tampered = envelope;
+        tampered.payload = data_encoding::BASE64.encode(b"{}");
+        assert!(tampered.verify(&key.public_key_pem()).is_err());
+    }
+}
@@ -1,9 +1,19 @@
 use crate::errors::*;
 use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::digest::common::hazmat::{SerializableState, SerializedState};
 use sha2::{Digest, Sha256};
 use std::{io::SeekFrom, pin::Pin, task::Poll};
 use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 
+/// The hashing progress of all data written so far, withheld data is not included
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub size: u64,
+    /// The serialized sha256 state, hex encoded
+    pub sha256_state: String,
+}
+
 pub struct Writer<W> {
     inner: W,
     withheld: Option<Bytes>,
@@ -21,6 +31,32 @@ impl<W: AsyncWrite + Unpin> Writer<W> {
         }
     }
 
+    /// Continue writing after `checkpoint`, `inner` needs to be positioned right after the checkpointed data
+    pub fn resume(inner: W, checkpoint: &Checkpoint) -> Result<Self> {
+        let state = data_encoding::HEXLOWER
+            .decode(checkpoint.sha256_state.as_bytes())
+            .context("Failed to decode sha256 state")?;
+        let state = SerializedState::<Sha256>::try_from(state.as_slice())
+            .map_err(|_| anyhow!("Invalid length of sha256 state"))?;
+        let sha256 =
+            Sha256::deserialize(&state).map_err(|_| anyhow!("Failed to restore sha256 state"))?;
+        Ok(Self {
+            inner,
+            withheld: None,
+            size: checkpoint.size,
+            sha256,
+        })
+    }
+
+    /// Flush the data written so far and return a checkpoint to resume from
+    pub async fn checkpoint(&mut self) -> Result<Checkpoint> {
+        self.inner.flush().await?;
+        Ok(Checkpoint {
+            size: self.size,
+            sha256_state: data_encoding::HEXLOWER.encode(&self.sha256.serialize()),
+        })
+    }
+
     async fn apply(&mut self, chunk: &[u8]) -> Result<()> {
         self.inner.write_all(chunk).await?;
         self.size += chunk.len() as u64;
@@ -185,6 +221,33 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_withhold_checkpoint_resume() -> Result<()> {
+        let data = b"Hello, world!";
+
+        let mut buf = Vec::new();
+        let mut writer = Writer::new(&mut buf);
+        writer.write_all(Bytes::from(&data[..5])).await?;
+        writer.write_all(Bytes::from(&data[5..7])).await?;
+        let checkpoint = writer.checkpoint().await?;
+        // The withheld chunk is not part of the checkpoint
+        assert_eq!(checkpoint.size, 5);
+
+        let mut buf = buf[..5].to_vec();
+        let mut writer = Writer::resume(&mut buf, &checkpoint)?;
+        writer.write_all(Bytes::from(&data[5..])).await?;
+        writer.finalize().await?;
+
+        assert_eq!(writer.size(), data.len() as u64);
+        assert_eq!(
+            data_encoding::HEXLOWER.encode(&writer.sha256()),
+            "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3"
+        );
+        assert_eq!(&buf[..], data);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_withhold_writer_reader() -> Result<()> {
         let data = b"Hello, world!";
@@ -0,0 +1,135 @@
+use repro_threshold::config::Config;
+use repro_threshold::test_server;
+use std::path::Path;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::time;
+use url::Url;
+
+const SIGNING_KEY: &str = "test_data/reproduce-debian-net-amd64.pub";
+
+/// The binary with its config, state and cache in `dir`
+fn command(dir: &Path) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_repro-threshold"));
+    cmd.env("REPRO_THRESHOLD_CONFIG", dir.join("repro-threshold.conf"))
+        .env("REPRO_THRESHOLD_STATE_DIR", dir.join("state"))
+        .env("REPRO_THRESHOLD_CACHE_DIR", dir.join("cache"))
+        .kill_on_drop(true);
+    cmd
+}
+
+async fn write_config(dir: &Path, rebuilder: Url, required_threshold: usize) {
+    let mut config = Config::default();
+    config.rules.required_threshold = required_threshold;
+    config.trusted_rebuilders = vec![test_server::rebuilder(
+        rebuilder,
+        test_server::DEB_KEYRING.to_string(),
+    )];
+    let toml = toml::to_string_pretty(&config).unwrap();
+    fs::write(dir.join("repro-threshold.conf"), toml)
+        .await
+        .unwrap();
+}
+
+async fn verify(dir: &Path, args: &[&str]) -> Output {
+    command(dir)
+        .args(["plumbing", "verify"])
+        .args(args)
+        .arg(test_server::DEB_PATH)
+        .output()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_verify_signing_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let rebuilder = test_server::spawn_rebuilder().await;
+
+    let args = |threshold| {
+        [
+            "--no-config",
+            "-S",
+            SIGNING_KEY,
+            "-R",
+            rebuilder.as_str(),
+            "-t",
+            threshold,
+        ]
+    };
+    assert!(verify(dir.path(), &args("1")).await.status.success());
+    assert!(!verify(dir.path(), &args("2")).await.status.success());
+}
+
+#[tokio::test]
+async fn test_verify_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let rebuilder = test_server::spawn_rebuilder().await;
+
+    write_config(dir.path(), rebuilder.clone(), 1).await;
+    let output = verify(dir.path(), &["--json"]).await;
+    assert!(output.status.success());
+    let decision = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    assert_eq!(decision["outcome"], "reproduced");
+
+    write_config(dir.path(), rebuilder, 2).await;
+    assert!(!verify(dir.path(), &[]).await.status.success());
+}
+
+/// Acquire the test .deb through the apt transport, returns the status line of the result
+async fn apt_acquire(dir: &Path, filename: &Path) -> String {
+    let mirror = test_server::spawn_mirror().await;
+    let mut child = command(dir)
+        .args(["transport", "apt"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+    let request = format!(
+        "600 URI Acquire\nURI: reproduced+{}\nFilename: {}\n\n",
+        mirror.join("pool/test.deb").unwrap(),
+        filename.display()
+    );
+    stdin.write_all(request.as_bytes()).await.unwrap();
+
+    // apt keeps stdin open until it got a result
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let status = time::timeout(Duration::from_secs(60), async {
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if line.starts_with("201 ") || line.starts_with("400 ") {
+                return line;
+            }
+        }
+        panic!("The apt transport exited without a result");
+    })
+    .await
+    .unwrap();
+
+    drop(stdin);
+    child.wait().await.unwrap();
+    status
+}
+
+#[tokio::test]
+async fn test_apt_transport() {
+    let dir = tempfile::tempdir().unwrap();
+    let rebuilder = test_server::spawn_rebuilder().await;
+    let filename = dir.path().join("test.deb");
+
+    write_config(dir.path(), rebuilder.clone(), 1).await;
+    assert_eq!(apt_acquire(dir.path(), &filename).await, "201 URI Done");
+    assert_eq!(
+        fs::read(&filename).await.unwrap(),
+        fs::read(test_server::DEB_PATH).await.unwrap()
+    );
+    fs::remove_file(&filename).await.unwrap();
+
+    write_config(dir.path(), rebuilder, 2).await;
+    assert_eq!(apt_acquire(dir.path(), &filename).await, "400 URI Failure");
+    assert!(!filename.exists());
+}